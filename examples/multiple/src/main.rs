@@ -0,0 +1,73 @@
+use core::mem::MaybeUninit;
+use trayicon::*;
+use winapi::um::winuser;
+
+fn main() {
+    // Each tray icon can have it's own event type
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum FirstEvents {
+        ClickTrayIcon,
+        Exit,
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum SecondEvents {
+        ClickTrayIcon,
+        Item1,
+    }
+
+    let (s1, r1) = std::sync::mpsc::channel::<FirstEvents>();
+    let (s2, r2) = std::sync::mpsc::channel::<SecondEvents>();
+    let icon = include_bytes!("../../../src/testresource/icon1.ico");
+    let icon2 = include_bytes!("../../../src/testresource/icon2.ico");
+
+    // Tray icons stay in the thread which built them, and run by its message
+    // loop, only the events are received in other threads
+    let _first_tray_icon = TrayIconBuilder::new()
+        .sender(s1)
+        .icon_from_buffer(icon)
+        .tooltip("First Tray Icon")
+        .on_click(FirstEvents::ClickTrayIcon)
+        .menu(MenuBuilder::new().item("E&xit", FirstEvents::Exit))
+        .build()
+        .unwrap();
+
+    let _second_tray_icon = TrayIconBuilder::new()
+        .sender(s2)
+        .icon_from_buffer(icon2)
+        .tooltip("Second Tray Icon")
+        .on_click(SecondEvents::ClickTrayIcon)
+        .menu(MenuBuilder::new().item("Item 1", SecondEvents::Item1))
+        .build()
+        .unwrap();
+
+    std::thread::spawn(move || {
+        r1.iter().for_each(|m| match m {
+            FirstEvents::Exit => {
+                println!("Please exit");
+            }
+            e => {
+                println!("First: {:?}", e);
+            }
+        })
+    });
+
+    std::thread::spawn(move || {
+        r2.iter().for_each(|m| println!("Second: {:?}", m))
+    });
+
+    // Your applications message loop. Because all applications require an
+    // application loop, you are best served using an `winit` crate.
+    loop {
+        unsafe {
+            let mut msg = MaybeUninit::uninit();
+            let bret = winuser::GetMessageA(msg.as_mut_ptr(), 0 as _, 0, 0);
+            if bret > 0 {
+                winuser::TranslateMessage(msg.as_ptr());
+                winuser::DispatchMessageA(msg.as_ptr());
+            } else {
+                break;
+            }
+        }
+    }
+}
@@ -26,7 +26,9 @@ fn main() {
     let first_icon = Icon::from_buffer(icon, None, None).unwrap();
 
     // Needlessly complicated tray icon with all the whistles and bells
-    let mut tray_icon = TrayIconBuilder::new()
+    // Changed from the thread receiving the events through the handle, the
+    // tray icon is run by the message loop of this thread
+    let tray_icon = TrayIconBuilder::new()
         .sender(s)
         .icon_from_buffer(icon)
         .tooltip("Cool Tray 👀 Icon")
@@ -51,11 +53,20 @@ fn main() {
                     disabled: true, // Disabled entry example
                     id: Events::Item4,
                     icon: None,
+                    item_id: None,
+                    command_id: None,
+                    is_default: false,
+                    accelerator: None,
+                    description: None,
+                    style: None,
+                    mnemonics: false,
                 })
                 .separator()
                 .item("E&xit", Events::Exit),
         )
         .build()
+        .unwrap()
+        .into_handle()
         .unwrap();
 
     std::thread::spawn(move || {
@@ -59,6 +59,13 @@ fn main() {
                     disabled: true, // Disabled entry example
                     id: Events::DisabledItem1,
                     icon: Result::ok(Icon::from_buffer(icon, None, None)),
+                    item_id: None,
+                    command_id: None,
+                    is_default: false,
+                    accelerator: None,
+                    description: None,
+                    style: None,
+                    mnemonics: false,
                 })
                 .separator()
                 .item("E&xit", Events::Exit),
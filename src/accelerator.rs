@@ -0,0 +1,127 @@
+/// Key of the accelerator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Key {
+    /// Uppercase letter, digit or punctuation
+    Char(char),
+
+    /// Function key, F1 to F24
+    F(u8),
+    Space,
+    Enter,
+    Escape,
+    Tab,
+    Backspace,
+    Delete,
+    Insert,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// Parsed accelerator text, e.g. "Ctrl+Shift+Q"
+///
+/// Modifiers and the named keys are case insensitive, the key is the last
+/// part. The text is shown as given, this is for registering the key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Accelerator {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+
+    /// Windows key, or Command in macOS
+    pub meta: bool,
+    pub key: Key,
+}
+
+impl Accelerator {
+    pub fn parse(text: &str) -> Option<Accelerator> {
+        let mut accelerator = Accelerator {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            meta: false,
+            key: Key::Space,
+        };
+        let mut parts = text.split('+').map(str::trim).peekable();
+        while let Some(part) = parts.next() {
+            if parts.peek().is_none() {
+                accelerator.key = parse_key(part)?;
+                return Some(accelerator);
+            }
+            match part.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => accelerator.ctrl = true,
+                "alt" | "option" => accelerator.alt = true,
+                "shift" => accelerator.shift = true,
+                "win" | "super" | "meta" | "cmd" | "command" => accelerator.meta = true,
+                _ => return None,
+            }
+        }
+        None
+    }
+}
+
+fn parse_key(key: &str) -> Option<Key> {
+    let mut chars = key.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return if c.is_ascii_graphic() {
+            Some(Key::Char(c.to_ascii_uppercase()))
+        } else {
+            None
+        };
+    }
+    let key = key.to_ascii_lowercase();
+    let named = match key.as_str() {
+        "space" => Key::Space,
+        "enter" | "return" => Key::Enter,
+        "esc" | "escape" => Key::Escape,
+        "tab" => Key::Tab,
+        "backspace" => Key::Backspace,
+        "del" | "delete" => Key::Delete,
+        "ins" | "insert" => Key::Insert,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pgup" | "pageup" => Key::PageUp,
+        "pgdn" | "pagedown" => Key::PageDown,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        _ => match key.strip_prefix('f')?.parse::<u8>() {
+            Ok(n) if (1..=24).contains(&n) => Key::F(n),
+            _ => return None,
+        },
+    };
+    Some(named)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accelerator_parse() {
+        assert_eq!(
+            Accelerator::parse("Ctrl+Shift+q"),
+            Some(Accelerator {
+                ctrl: true,
+                alt: false,
+                shift: true,
+                meta: false,
+                key: Key::Char('Q'),
+            })
+        );
+        assert_eq!(
+            Accelerator::parse("alt + F4").map(|a| (a.alt, a.key)),
+            Some((true, Key::F(4)))
+        );
+        assert_eq!(Accelerator::parse("Ctrl++"), None);
+        assert_eq!(Accelerator::parse("Ctrl+F25"), None);
+        assert_eq!(Accelerator::parse("Hyper+Q"), None);
+        assert_eq!(Accelerator::parse(""), None);
+    }
+}
@@ -0,0 +1,20 @@
+use crate::Error;
+
+/// Start the app when the user signs in, or stop starting it
+///
+/// For the "Start with Windows" checkable of the menu. The current executable
+/// is started with the arguments, e.g. `--minimized`. The name identifies the
+/// entry, and it's shown in the startup apps of Task Manager. Writes the Run
+/// key of the current user in the registry, and enabling clears the disabling
+/// done in Task Manager. Only on Windows.
+pub fn set_autostart(enabled: bool, app_name: &str, args: &[String]) -> Result<(), Error> {
+    crate::set_autostart_sys(enabled, app_name, args)
+}
+
+/// App with the name is started when the user signs in
+///
+/// False if the entry is disabled in Task Manager, e.g. for the checked state
+/// of the "Start with Windows" checkable. Only on Windows.
+pub fn is_autostart_enabled(app_name: &str) -> bool {
+    crate::is_autostart_enabled_sys(app_name)
+}
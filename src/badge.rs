@@ -0,0 +1,262 @@
+use crate::Icon;
+#[cfg(all(any(target_os = "windows", target_os = "linux"), not(feature = "mock")))]
+use crate::IconBase;
+
+/// Badge drawn at the bottom right corner of the tray icon
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Badge {
+    /// Icon in the quarter of the tray icon
+    Icon(Icon),
+
+    /// Count in a red circle, counts over 99 are shown as 9+
+    Count(u32),
+}
+
+/// Badge and progress drawn on the tray icon, each is optional
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct Decoration {
+    pub badge: Option<Badge>,
+
+    /// From 0.0 to 1.0
+    pub progress: Option<f32>,
+}
+
+/// Color of the count circle, straight RGB
+#[cfg(all(any(target_os = "windows", target_os = "linux"), not(feature = "mock")))]
+const COUNT_COLOR: [u8; 3] = [0xe8, 0x11, 0x23];
+
+/// Color of the filled part of the progress bar
+#[cfg(all(any(target_os = "windows", target_os = "linux"), not(feature = "mock")))]
+const PROGRESS_COLOR: [u8; 3] = [0x06, 0xb0, 0x25];
+
+/// Opacity of the black track under the progress bar
+#[cfg(all(any(target_os = "windows", target_os = "linux"), not(feature = "mock")))]
+const PROGRESS_TRACK_ALPHA: f32 = 0.6;
+
+/// Glyphs of 3x5 pixels, rows from the top and the bits from the left
+#[cfg(all(any(target_os = "windows", target_os = "linux"), not(feature = "mock")))]
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+#[cfg(all(any(target_os = "windows", target_os = "linux"), not(feature = "mock")))]
+const PLUS: [u8; 5] = [0b000, 0b010, 0b111, 0b010, 0b000];
+
+/// Icon with the progress and the badge drawn on it, rendered at the size
+///
+/// None without a decoration, or if the platform can't render the icons to
+/// pixels.
+#[cfg(all(any(target_os = "windows", target_os = "linux"), not(feature = "mock")))]
+pub(crate) fn decorate(
+    icon: &crate::IconSys,
+    width: u32,
+    height: u32,
+    decoration: &Decoration,
+) -> Option<crate::IconSys> {
+    if decoration.badge.is_none() && decoration.progress.is_none() {
+        return None;
+    }
+    let mut rgba = icon.to_rgba(width, height)?;
+    if let Some(progress) = decoration.progress {
+        draw_progress(&mut rgba, width, height, progress);
+    }
+    match &decoration.badge {
+        None => {}
+        Some(Badge::Count(count)) => draw_count(&mut rgba, width, height, *count),
+        Some(Badge::Icon(overlay)) => {
+            let (overlay_width, overlay_height) = (width / 2, height / 2);
+            let overlay_rgba = overlay.sys.to_rgba(overlay_width, overlay_height)?;
+            draw_rgba(
+                &mut rgba,
+                (width, height),
+                &overlay_rgba,
+                (overlay_width, overlay_height),
+                (width - overlay_width, height - overlay_height),
+            );
+        }
+    }
+    crate::IconSys::from_rgba(&rgba, width, height).ok()
+}
+
+/// Draw a bar along the bottom edge, the end of the fill is antialiased
+#[cfg(all(any(target_os = "windows", target_os = "linux"), not(feature = "mock")))]
+fn draw_progress(rgba: &mut [u8], width: u32, height: u32, progress: f32) {
+    let bar_height = (height / 8).max(2).min(height);
+    let filled = progress.clamp(0.0, 1.0) * width as f32;
+    for y in height - bar_height..height {
+        for x in 0..width {
+            blend(rgba, width, x, y, [0, 0, 0], PROGRESS_TRACK_ALPHA);
+            let coverage = (filled - x as f32).clamp(0.0, 1.0);
+            blend(rgba, width, x, y, PROGRESS_COLOR, coverage);
+        }
+    }
+}
+
+/// Draw the count in an antialiased circle at the bottom right corner
+#[cfg(all(any(target_os = "windows", target_os = "linux"), not(feature = "mock")))]
+fn draw_count(rgba: &mut [u8], width: u32, height: u32, count: u32) {
+    let glyphs = count_glyphs(count);
+    let size = width.min(height);
+    let scale = (size / 16).max(1);
+    let diameter = (size * 5 / 8).max(scale * 7 + 2);
+    let radius = diameter as f32 / 2.0;
+    let (cx, cy) = (width as f32 - radius, height as f32 - radius);
+    fill_circle(rgba, (width, height), (cx, cy), radius, COUNT_COLOR, 1.0);
+
+    let text_width = (glyphs.len() as u32 * 4 - 1) * scale;
+    let text_height = 5 * scale;
+    let left = (cx - text_width as f32 / 2.0).round().max(0.0) as u32;
+    let top = (cy - text_height as f32 / 2.0).round().max(0.0) as u32;
+    for (n, glyph) in glyphs.iter().enumerate() {
+        for (row, bits) in glyph.iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                for sy in 0..scale {
+                    for sx in 0..scale {
+                        let x = left + (n as u32 * 4 + column) * scale + sx;
+                        let y = top + row as u32 * scale + sy;
+                        if x < width && y < height {
+                            blend(rgba, width, x, y, [0xff, 0xff, 0xff], 1.0);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Draw the pixels over the rectangle at the position, clipped to the size
+pub(crate) fn draw_rgba(
+    rgba: &mut [u8],
+    (width, height): (u32, u32),
+    src: &[u8],
+    (src_width, src_height): (u32, u32),
+    (left, top): (u32, u32),
+) {
+    for y in 0..src_height.min(height.saturating_sub(top)) {
+        for x in 0..src_width.min(width.saturating_sub(left)) {
+            let i = ((y * src_width + x) * 4) as usize;
+            let pixel = &src[i..i + 4];
+            blend(
+                rgba,
+                width,
+                left + x,
+                top + y,
+                [pixel[0], pixel[1], pixel[2]],
+                pixel[3] as f32 / 255.0,
+            );
+        }
+    }
+}
+
+/// Draw an antialiased circle, clipped to the size
+pub(crate) fn fill_circle(
+    rgba: &mut [u8],
+    (width, height): (u32, u32),
+    (cx, cy): (f32, f32),
+    radius: f32,
+    color: [u8; 3],
+    alpha: f32,
+) {
+    let top = (cy - radius).floor().max(0.0) as u32;
+    let left = (cx - radius).floor().max(0.0) as u32;
+    let bottom = ((cy + radius).ceil().max(0.0) as u32).min(height);
+    let right = ((cx + radius).ceil().max(0.0) as u32).min(width);
+    for y in top..bottom {
+        for x in left..right {
+            let dx = x as f32 + 0.5 - cx;
+            let dy = y as f32 + 0.5 - cy;
+            let coverage = (radius - (dx * dx + dy * dy).sqrt() + 0.5).clamp(0.0, 1.0);
+            blend(rgba, width, x, y, color, coverage * alpha);
+        }
+    }
+}
+
+/// At most two glyphs fit the circle
+#[cfg(all(any(target_os = "windows", target_os = "linux"), not(feature = "mock")))]
+fn count_glyphs(count: u32) -> Vec<[u8; 5]> {
+    match count {
+        0..=9 => vec![DIGITS[count as usize]],
+        10..=99 => vec![DIGITS[count as usize / 10], DIGITS[count as usize % 10]],
+        _ => vec![DIGITS[9], PLUS],
+    }
+}
+
+/// Draw the color over the pixel, both with straight alpha
+pub(crate) fn blend(rgba: &mut [u8], width: u32, x: u32, y: u32, color: [u8; 3], alpha: f32) {
+    if alpha <= 0.0 {
+        return;
+    }
+    let i = ((y * width + x) * 4) as usize;
+    let pixel = &mut rgba[i..i + 4];
+    let dst_alpha = pixel[3] as f32 / 255.0;
+    let out_alpha = alpha + dst_alpha * (1.0 - alpha);
+    for c in 0..3 {
+        let value =
+            (color[c] as f32 * alpha + pixel[c] as f32 * dst_alpha * (1.0 - alpha)) / out_alpha;
+        pixel[c] = value.round() as u8;
+    }
+    pixel[3] = (out_alpha * 255.0).round() as u8;
+}
+
+#[cfg(all(
+    test,
+    any(target_os = "windows", target_os = "linux"),
+    not(feature = "mock")
+))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw_count() {
+        let mut rgba = vec![0u8; 16 * 16 * 4];
+        draw_count(&mut rgba, 16, 16, 7);
+        let pixel = |x: u32, y: u32| {
+            let i = ((y * 16 + x) * 4) as usize;
+            [rgba[i], rgba[i + 1], rgba[i + 2], rgba[i + 3]]
+        };
+
+        // Outside of the circle, inside it, and the top row of the glyph
+        assert_eq!(pixel(0, 0), [0, 0, 0, 0]);
+        assert_eq!(pixel(14, 11), [0xe8, 0x11, 0x23, 0xff]);
+        assert_eq!(pixel(11, 10), [0xe8, 0x11, 0x23, 0xff]);
+        assert_eq!(pixel(10, 9), [0xff, 0xff, 0xff, 0xff]);
+
+        // Edge of the circle is antialiased
+        let edge = pixel(15, 8)[3];
+        assert!(edge > 0 && edge < 0xff);
+    }
+
+    #[test]
+    fn test_draw_progress() {
+        let mut rgba = vec![0u8; 16 * 16 * 4];
+        draw_progress(&mut rgba, 16, 16, 0.5);
+        let pixel = |x: u32, y: u32| {
+            let i = ((y * 16 + x) * 4) as usize;
+            [rgba[i], rgba[i + 1], rgba[i + 2], rgba[i + 3]]
+        };
+
+        // Above the bar, the filled part, and the track
+        assert_eq!(pixel(4, 13), [0, 0, 0, 0]);
+        assert_eq!(pixel(4, 14), [0x06, 0xb0, 0x25, 0xff]);
+        assert_eq!(pixel(7, 15), [0x06, 0xb0, 0x25, 0xff]);
+        assert_eq!(pixel(8, 15), [0, 0, 0, 0x99]);
+    }
+
+    #[test]
+    fn test_count_glyphs() {
+        assert_eq!(count_glyphs(5), vec![DIGITS[5]]);
+        assert_eq!(count_glyphs(42), vec![DIGITS[4], DIGITS[2]]);
+        assert_eq!(count_glyphs(100), vec![DIGITS[9], PLUS]);
+    }
+}
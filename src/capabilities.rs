@@ -0,0 +1,86 @@
+/// Behavior of the notification icon in Windows
+///
+/// Versions differ in the messages the shell sends, newer versions fall back
+/// to the older ones if the shell doesn't support them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum NotifyIconVersion {
+    /// Windows 2000 behavior, there are no keyboard selection messages, and
+    /// the menu is shown on the right button up
+    Legacy,
+
+    /// `NOTIFYICON_VERSION`, the default, adds the keyboard selection and the
+    /// context menu messages
+    #[default]
+    V3,
+
+    /// `NOTIFYICON_VERSION_4`, adds the rich popup messages and the anchor
+    /// position of the keyboard messages
+    V4,
+}
+
+/// What the current desktop supports
+///
+/// Returned by `capabilities`, unsupported features are ignored, e.g. the
+/// events are not sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Notification area or the status bar exists to show the tray icon
+    ///
+    /// In Windows there is no shell e.g. in a service, or when explorer.exe is
+    /// not running. On Linux the StatusNotifierItem host is not checked.
+    pub tray: bool,
+
+    /// Highest notification icon version of the Windows shell, `None` on
+    /// other platforms
+    pub notify_icon_version: Option<NotifyIconVersion>,
+
+    /// Running under Wine, the shell may not send all the messages
+    pub wine: bool,
+
+    /// Running on ReactOS, the shell may not send all the messages
+    pub reactos: bool,
+
+    /// `show_notification` is supported
+    pub notifications: bool,
+
+    /// `rich_popup` events are sent
+    pub rich_popup: bool,
+
+    /// `TrayIcon::rect` is supported
+    pub rect: bool,
+
+    /// `on_double_click` is sent
+    pub double_click: bool,
+
+    /// `on_mouse_enter` and `on_mouse_leave` are sent
+    pub hover: bool,
+
+    /// `TrayIcon::show_menu` is supported
+    pub show_menu: bool,
+}
+
+/// Query what the current desktop supports
+///
+/// Useful for falling back, e.g. for opening the app window from the menu when
+/// double clicks are not reported.
+pub fn capabilities() -> Capabilities {
+    crate::capabilities_sys()
+}
+
+/// Light or dark theme of the taskbar or the menu bar
+///
+/// Icons are usually drawn dark on a light theme, and light on a dark one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SystemTheme {
+    Light,
+    Dark,
+}
+
+/// Theme of the taskbar or the menu bar, where the tray icon is shown
+///
+/// In Windows this is the theme of the taskbar, which can differ from the
+/// theme of the apps. `None` on Linux, the theme of the host is not known, and
+/// in Windows versions without the theme setting.
+pub fn system_theme() -> Option<SystemTheme> {
+    crate::system_theme_sys()
+}
@@ -0,0 +1,158 @@
+use crate::{MenuCommandId, MenuItemId};
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    MenuItemNotFound,
+    IconLoadingFailed,
+    SenderMissing,
+    IconMissing,
+    OsError(OsError),
+    Unsupported,
+
+    /// Event is set, e.g. `on_click`, but there is no sender for it
+    EventWithoutSender(&'static str),
+
+    /// Same stable identifier is given for many menu items
+    DuplicateMenuItemId(MenuItemId),
+
+    /// Command identifier of a menu item is zero, or given for many items
+    InvalidMenuCommandId(MenuCommandId),
+
+    /// Icon size is zero or over the maximum of 256x256
+    IconSizeUnsupported(u32, u32),
+
+    /// State is not in the `IconSet`
+    StateNotFound,
+
+    /// Event handler panicked while the tray icon handled a message, the
+    /// message was handled by the default handler instead
+    HandlerPanicked,
+
+    /// Tray icon of the handle was removed, or its thread stopped
+    TrayIconStopped,
+}
+
+impl Error {
+    /// Operating system call failed, with the error code of the thread
+    ///
+    /// Call right after the failed call, before anything else resets the
+    /// code, e.g. `GetLastError` in Windows.
+    #[cfg(all(target_os = "windows", not(feature = "mock")))]
+    pub(crate) fn last_os_error(operation: &'static str) -> Error {
+        let error = OsError {
+            operation,
+            code: std::io::Error::last_os_error().raw_os_error(),
+        };
+        trace_event!(DEBUG, "{}", error);
+        Error::OsError(error)
+    }
+
+    /// Operating system call failed, with the error code it returned, e.g.
+    /// the registry functions in Windows
+    pub(crate) fn os_error_code(operation: &'static str, code: i32) -> Error {
+        let error = OsError {
+            operation,
+            code: Some(code),
+        };
+        trace_event!(DEBUG, "{}", error);
+        Error::OsError(error)
+    }
+
+    /// Operating system call failed, without an error code
+    pub(crate) fn os_error(operation: &'static str) -> Error {
+        let error = OsError {
+            operation,
+            code: None,
+        };
+        trace_event!(DEBUG, "{}", error);
+        Error::OsError(error)
+    }
+}
+
+// Why do I need to do this, can't Rust do this automatically?
+impl From<&Error> for Error {
+    fn from(e: &Error) -> Self {
+        *e
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::MenuItemNotFound => write!(f, "menu item not found"),
+            Error::IconLoadingFailed => write!(f, "icon loading failed"),
+            Error::SenderMissing => write!(f, "sender missing"),
+            Error::IconMissing => write!(f, "icon missing"),
+            Error::OsError(err) => write!(f, "{}", err),
+            Error::Unsupported => write!(f, "unsupported on this platform"),
+            Error::EventWithoutSender(event) => write!(f, "{} is set without a sender", event),
+            Error::DuplicateMenuItemId(id) => write!(f, "duplicate menu item id {}", id.0),
+            Error::InvalidMenuCommandId(id) => {
+                write!(f, "zero or duplicate menu command id {}", id.0)
+            }
+            Error::IconSizeUnsupported(width, height) => {
+                write!(f, "icon size {}x{} unsupported", width, height)
+            }
+            Error::StateNotFound => write!(f, "icon state not found"),
+            Error::HandlerPanicked => write!(f, "event handler panicked"),
+            Error::TrayIconStopped => write!(f, "tray icon stopped"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Failed operating system call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OsError {
+    /// Operation that failed, e.g. `CreateWindowExW`
+    pub operation: &'static str,
+
+    /// Error code, `GetLastError` in Windows, if the operation sets it
+    pub code: Option<i32>,
+}
+
+impl Display for OsError {
+    /// Message of the error code is formatted by the operating system, e.g.
+    /// with `FormatMessageW` in Windows
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.code {
+            Some(code) => write!(
+                f,
+                "{} failed: {}",
+                self.operation,
+                std::io::Error::from_raw_os_error(code)
+            ),
+            None => write!(f, "{} failed", self.operation),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_display() {
+        assert_eq!(Error::IconMissing.to_string(), "icon missing");
+        assert_eq!(Error::HandlerPanicked.to_string(), "event handler panicked");
+        assert_eq!(
+            Error::os_error("CreatePopupMenu").to_string(),
+            "CreatePopupMenu failed"
+        );
+    }
+
+    #[test]
+    fn test_os_error_display_with_code() {
+        let err = OsError {
+            operation: "CreateWindowExW",
+            code: Some(5),
+        };
+        let message = std::io::Error::from_raw_os_error(5).to_string();
+        assert_eq!(
+            err.to_string(),
+            format!("CreateWindowExW failed: {}", message)
+        );
+    }
+}
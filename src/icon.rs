@@ -1,10 +1,89 @@
 use crate::{Error, IconBase};
+use std::borrow::Cow;
 use std::fmt::Debug;
+#[cfg(all(target_os = "windows", not(feature = "mock")))]
+use std::sync::{Arc, Mutex};
+
+/// Icons loaded for the sizes, with the width and the height
+#[cfg(all(target_os = "windows", not(feature = "mock")))]
+type SizedIcons = Vec<((u32, u32), crate::IconSys)>;
 
 #[derive(Clone)]
 pub struct Icon {
-    buffer: Option<&'static [u8]>,
+    source: Source,
     pub(crate) sys: crate::IconSys,
+
+    /// Loaded without the size, so it can be loaded again for another size
+    #[cfg(all(target_os = "windows", not(feature = "mock")))]
+    scalable: bool,
+
+    /// Size given when loading
+    size: Option<(u32, u32)>,
+
+    /// Loaded again for the sizes it's shown at, shared by the clones
+    #[cfg(all(target_os = "windows", not(feature = "mock")))]
+    sized: Arc<Mutex<SizedIcons>>,
+}
+
+/// Where the icon was loaded from, icons are compared by this
+#[derive(Clone, PartialEq)]
+enum Source {
+    Buffer(Cow<'static, [u8]>),
+
+    /// Boxed, as the menu items contain the icons
+    Text(String, Box<FontOptions>),
+    #[cfg(target_os = "windows")]
+    Resource(u16, Option<u32>, Option<u32>),
+    #[cfg(target_os = "windows")]
+    Stock(StockIcon),
+}
+
+/// Font and colors of `Icon::from_text`
+///
+/// Colors are straight RGBA.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontOptions {
+    /// Font family, e.g. "Segoe UI", otherwise the system font
+    pub family: Option<String>,
+    pub bold: bool,
+    pub color: [u8; 4],
+
+    /// Transparent without it
+    pub background: Option<[u8; 4]>,
+
+    /// Width and height of the icon in pixels
+    pub icon_size: u32,
+}
+
+impl Default for FontOptions {
+    fn default() -> Self {
+        FontOptions {
+            family: None,
+            bold: true,
+            color: [0xff, 0xff, 0xff, 0xff],
+            background: None,
+            icon_size: 32,
+        }
+    }
+}
+
+/// Standard icons of the system
+///
+/// Windows only, these are the shell stock icons.
+#[cfg(target_os = "windows")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StockIcon {
+    Application,
+    Delete,
+    Error,
+    Find,
+    Folder,
+    Help,
+    Info,
+    Lock,
+    Settings,
+    Shield,
+    Warning,
 }
 
 impl Debug for Icon {
@@ -14,20 +93,191 @@ impl Debug for Icon {
 }
 
 impl Icon {
+    fn new(sys: crate::IconSys, source: Source, size: Option<(u32, u32)>) -> Icon {
+        Icon {
+            #[cfg(all(target_os = "windows", not(feature = "mock")))]
+            scalable: size.is_none() && matches!(source, Source::Buffer(_) | Source::Resource(..)),
+            source,
+            sys,
+            size,
+            #[cfg(all(target_os = "windows", not(feature = "mock")))]
+            sized: Arc::default(),
+        }
+    }
+
     pub fn from_buffer(
         buffer: &'static [u8],
         width: Option<u32>,
         height: Option<u32>,
     ) -> Result<Icon, Error> {
-        Ok(Icon {
-            buffer: Some(buffer),
-            sys: crate::IconSys::from_buffer(buffer, width, height)?,
-        })
+        Ok(Icon::new(
+            crate::IconSys::from_buffer(buffer, width, height)?,
+            Source::Buffer(Cow::Borrowed(buffer)),
+            width.zip(height),
+        ))
+    }
+
+    /// Icon from owned buffer
+    ///
+    /// Use this for icons loaded from disk or generated at runtime.
+    pub fn from_vec(
+        buffer: Vec<u8>,
+        width: Option<u32>,
+        height: Option<u32>,
+    ) -> Result<Icon, Error> {
+        Ok(Icon::new(
+            crate::IconSys::from_buffer(&buffer, width, height)?,
+            Source::Buffer(Cow::Owned(buffer)),
+            width.zip(height),
+        ))
+    }
+
+    /// Icon from raw 32-bit RGBA pixels
+    ///
+    /// Use this for dynamically rendered icons, e.g. badges or counters.
+    pub fn from_rgba(width: u32, height: u32, rgba: Vec<u8>) -> Result<Icon, Error> {
+        if width == 0 || height == 0 || rgba.len() != width as usize * height as usize * 4 {
+            return Err(Error::IconLoadingFailed);
+        }
+        Ok(Icon::new(
+            crate::IconSys::from_rgba(&rgba, width, height)?,
+            Source::Buffer(Cow::Owned(rgba)),
+            Some((width, height)),
+        ))
+    }
+
+    /// Icon with the short text drawn on it, e.g. "42%"
+    ///
+    /// Font is made smaller until the text fits the width of the icon, so
+    /// keep it to a few characters. Rendered with GDI in Windows, and AppKit
+    /// in macOS. Not supported on Linux.
+    pub fn from_text(text: &str, options: FontOptions) -> Result<Icon, Error> {
+        if text.is_empty() || options.icon_size == 0 {
+            return Err(Error::IconLoadingFailed);
+        }
+        let size = options.icon_size;
+        Ok(Icon::new(
+            crate::IconSys::from_text(text, &options)?,
+            Source::Text(text.to_string(), Box::new(options)),
+            Some((size, size)),
+        ))
+    }
+
+    /// Icon from the resources of the executable
+    ///
+    /// Without the size the icon is loaded again for the DPI scaling, same as
+    /// with `from_buffer`. Windows only.
+    #[cfg(target_os = "windows")]
+    pub fn from_resource(
+        resource_id: u16,
+        width: Option<u32>,
+        height: Option<u32>,
+    ) -> Result<Icon, Error> {
+        Ok(Icon::new(
+            crate::IconSys::from_resource(resource_id, width, height)?,
+            Source::Resource(resource_id, width, height),
+            width.zip(height),
+        ))
+    }
+
+    /// Standard icon of the system, in the small icon size
+    ///
+    /// Windows only.
+    #[cfg(target_os = "windows")]
+    pub fn from_system(stock_icon: StockIcon) -> Result<Icon, Error> {
+        Ok(Icon::new(
+            crate::IconSys::from_stock(stock_icon)?,
+            Source::Stock(stock_icon),
+            None,
+        ))
+    }
+
+    /// Icon from PNG buffer
+    #[cfg(feature = "image")]
+    pub fn from_png(buffer: &[u8]) -> Result<Icon, Error> {
+        let image = image::load_from_memory_with_format(buffer, image::ImageFormat::Png)
+            .map_err(|_| Error::IconLoadingFailed)?;
+        Icon::from_image(image)
+    }
+
+    /// Icon from decoded image of any format supported by the `image` crate
+    #[cfg(feature = "image")]
+    pub fn from_image(image: image::DynamicImage) -> Result<Icon, Error> {
+        let rgba = image.into_rgba8();
+        let (width, height) = rgba.dimensions();
+        Icon::from_rgba(width, height, rgba.into_raw())
+    }
+
+    /// Icon from SVG buffer, rendered at the given size
+    ///
+    /// Render at the size of the tray icon, e.g. 16x16 or 32x32 depending on
+    /// the scaling, the image is not scaled again after this.
+    #[cfg(feature = "svg")]
+    pub fn from_svg(buffer: &[u8], width: u32, height: u32) -> Result<Icon, Error> {
+        let options = usvg::Options::default();
+        let tree = usvg::Tree::from_data(buffer, &options.to_ref())
+            .map_err(|_| Error::IconLoadingFailed)?;
+        let mut pixmap = tiny_skia::Pixmap::new(width, height).ok_or(Error::IconLoadingFailed)?;
+        resvg::render(
+            &tree,
+            usvg::FitTo::Size(width, height),
+            tiny_skia::Transform::default(),
+            pixmap.as_mut(),
+        )
+        .ok_or(Error::IconLoadingFailed)?;
+
+        // Pixmap is premultiplied
+        let rgba = pixmap
+            .pixels()
+            .iter()
+            .flat_map(|p| {
+                let c = p.demultiply();
+                [c.red(), c.green(), c.blue(), c.alpha()]
+            })
+            .collect();
+        Icon::from_rgba(width, height, rgba)
+    }
+
+    /// Size given when loading, none if the system chooses the size
+    pub fn size(&self) -> Option<(u32, u32)> {
+        self.size
+    }
+
+    /// Icon at the size it's shown at, e.g. in the tray with the DPI scaling,
+    /// in the menu, or in the notification
+    ///
+    /// Buffers and resources loaded without the size are loaded again, and
+    /// multi-size .ico files have a frame for each size, the closest frame is
+    /// chosen. These are cached per size. Other icons are scaled when drawn.
+    #[cfg(all(target_os = "windows", not(feature = "mock")))]
+    pub(crate) fn sys_for_size(&self, width: u32, height: u32) -> crate::IconSys {
+        if !self.scalable {
+            return self.sys.clone();
+        }
+        let mut sized = match self.sized.lock() {
+            Ok(sized) => sized,
+            Err(_) => return self.sys.clone(),
+        };
+        if let Some((_, sys)) = sized.iter().find(|(size, _)| *size == (width, height)) {
+            return sys.clone();
+        }
+        let sys = match &self.source {
+            Source::Buffer(buffer) => {
+                crate::IconSys::from_buffer(buffer, Some(width), Some(height)).ok()
+            }
+            Source::Resource(resource_id, ..) => {
+                crate::IconSys::from_resource(*resource_id, Some(width), Some(height)).ok()
+            }
+            Source::Stock(_) | Source::Text(..) => None,
+        }
+        .unwrap_or_else(|| self.sys.clone());
+        sized.push(((width, height), sys.clone()));
+        sys
     }
 }
 
 impl PartialEq for Icon {
     fn eq(&self, other: &Self) -> bool {
-        self.buffer == other.buffer
+        self.source == other.source
     }
 }
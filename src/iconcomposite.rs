@@ -0,0 +1,156 @@
+use crate::badge::{draw_rgba, fill_circle};
+use crate::{Error, Icon, IconBase};
+
+/// Corner of the icon where a layer is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Corner {
+    /// Top left position of the layer in the icon
+    fn position(
+        self,
+        (width, height): (u32, u32),
+        (layer_width, layer_height): (u32, u32),
+    ) -> (u32, u32) {
+        let right = width.saturating_sub(layer_width);
+        let bottom = height.saturating_sub(layer_height);
+        match self {
+            Corner::TopLeft => (0, 0),
+            Corner::TopRight => (right, 0),
+            Corner::BottomLeft => (0, bottom),
+            Corner::BottomRight => (right, bottom),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Layer {
+    Icon(Icon, Corner),
+    Dot([u8; 4], Corner),
+}
+
+/// Icon composited from the base icon and the layers drawn over it
+///
+/// E.g. for the status indicators, like a red dot when disconnected or a
+/// dimmed icon when paused. Layers are drawn in the order they are added.
+///
+/// ```ignore
+/// let icon = IconComposite::new(&base)
+///     .opacity(0.5)
+///     .dot([0xe8, 0x11, 0x23, 0xff], Corner::BottomRight)
+///     .build(32, 32)?;
+/// tray_icon.set_icon(&icon)?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct IconComposite {
+    base: Icon,
+    opacity: f32,
+    layers: Vec<Layer>,
+}
+
+impl IconComposite {
+    pub fn new(base: &Icon) -> IconComposite {
+        IconComposite {
+            base: base.clone(),
+            opacity: 1.0,
+            layers: vec![],
+        }
+    }
+
+    /// Opacity of the base icon, from 0.0 to 1.0, the layers are not dimmed
+    pub fn opacity(mut self, opacity: f32) -> Self {
+        self.opacity = opacity.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Icon at half the size of the base icon, in the corner
+    pub fn overlay(mut self, icon: &Icon, corner: Corner) -> Self {
+        self.layers.push(Layer::Icon(icon.clone(), corner));
+        self
+    }
+
+    /// Antialiased dot in the corner, the color is straight RGBA
+    pub fn dot(mut self, color: [u8; 4], corner: Corner) -> Self {
+        self.layers.push(Layer::Dot(color, corner));
+        self
+    }
+
+    /// Draw the layers at the size, e.g. the size of the tray icon
+    ///
+    /// Not supported on macOS, the images are not rendered to pixels.
+    pub fn build(&self, width: u32, height: u32) -> Result<Icon, Error> {
+        if width == 0 || height == 0 {
+            return Err(Error::IconLoadingFailed);
+        }
+        let size = (width, height);
+        let mut rgba = self
+            .base
+            .sys
+            .to_rgba(width, height)
+            .ok_or(Error::Unsupported)?;
+        dim(&mut rgba, self.opacity);
+        for layer in &self.layers {
+            match layer {
+                Layer::Icon(icon, corner) => {
+                    let layer_size = (width / 2, height / 2);
+                    let layer_rgba = icon
+                        .sys
+                        .to_rgba(layer_size.0, layer_size.1)
+                        .ok_or(Error::Unsupported)?;
+                    let position = corner.position(size, layer_size);
+                    draw_rgba(&mut rgba, size, &layer_rgba, layer_size, position);
+                }
+                Layer::Dot([r, g, b, a], corner) => {
+                    let diameter = (width.min(height) * 3 / 8).max(1);
+                    let (left, top) = corner.position(size, (diameter, diameter));
+                    let radius = diameter as f32 / 2.0;
+                    let center = (left as f32 + radius, top as f32 + radius);
+                    fill_circle(
+                        &mut rgba,
+                        size,
+                        center,
+                        radius,
+                        [*r, *g, *b],
+                        *a as f32 / 255.0,
+                    );
+                }
+            }
+        }
+        Icon::from_rgba(width, height, rgba)
+    }
+}
+
+/// Multiply the alpha of the pixels
+fn dim(rgba: &mut [u8], opacity: f32) {
+    if opacity >= 1.0 {
+        return;
+    }
+    for pixel in rgba.chunks_exact_mut(4) {
+        pixel[3] = (pixel[3] as f32 * opacity).round() as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_corner_position() {
+        assert_eq!(Corner::TopLeft.position((32, 32), (16, 16)), (0, 0));
+        assert_eq!(Corner::TopRight.position((32, 32), (16, 16)), (16, 0));
+        assert_eq!(Corner::BottomLeft.position((32, 24), (12, 9)), (0, 15));
+        assert_eq!(Corner::BottomRight.position((32, 24), (12, 9)), (20, 15));
+    }
+
+    #[test]
+    fn test_dim() {
+        let mut rgba = vec![10, 20, 30, 200, 10, 20, 30, 0];
+        dim(&mut rgba, 0.5);
+        assert_eq!(rgba, vec![10, 20, 30, 100, 10, 20, 30, 0]);
+    }
+}
@@ -0,0 +1,63 @@
+use crate::Icon;
+
+/// Icon and the tooltip of a named state
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct IconState {
+    pub name: String,
+    pub icon: Icon,
+
+    /// Tooltip is kept if none
+    pub tooltip: Option<String>,
+}
+
+/// Named states of the tray icon, e.g. "online", "offline" and "syncing"
+///
+/// Give to `TrayIconBuilder::icon_set`, and switch the icon and the tooltip
+/// together with `TrayIcon::set_state`. Icons are loaded once when they are
+/// created, switching doesn't load them again.
+///
+/// This is defined as consuming builder.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IconSet {
+    states: Vec<IconState>,
+}
+
+impl IconSet {
+    pub fn new() -> IconSet {
+        IconSet::default()
+    }
+
+    /// State with the icon, the tooltip is not changed when switching to it
+    ///
+    /// Same name replaces the state.
+    pub fn state(self, name: &str, icon: Icon) -> Self {
+        self.insert(name, icon, None)
+    }
+
+    /// State with the icon and the tooltip
+    pub fn state_with_tooltip(self, name: &str, icon: Icon, tooltip: &str) -> Self {
+        self.insert(name, icon, Some(tooltip.to_string()))
+    }
+
+    fn insert(mut self, name: &str, icon: Icon, tooltip: Option<String>) -> Self {
+        let state = IconState {
+            name: name.to_string(),
+            icon,
+            tooltip,
+        };
+        match self.states.iter_mut().find(|s| s.name == name) {
+            Some(existing) => *existing = state,
+            None => self.states.push(state),
+        }
+        self
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&IconState> {
+        self.states.iter().find(|s| s.name == name)
+    }
+
+    /// Names of the states, in the order they were added
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.states.iter().map(|s| s.name.as_str())
+    }
+}
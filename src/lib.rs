@@ -2,30 +2,117 @@
 // ## Example
 // [Open full example with winit here 🢅](https://github.com/Ciantic/trayicon-rs/blob/master/examples/winit/src/main.rs)
 
-#[cfg_attr(target_os = "windows", path = "./sys/windows/mod.rs")]
+#[macro_use]
+mod trace;
+
+#[cfg_attr(
+    all(target_os = "windows", not(feature = "mock")),
+    path = "./sys/windows/mod.rs"
+)]
+#[cfg_attr(
+    all(target_os = "linux", not(feature = "mock")),
+    path = "./sys/linux/mod.rs"
+)]
+#[cfg_attr(
+    all(target_os = "macos", not(feature = "mock")),
+    path = "./sys/macos/mod.rs"
+)]
+#[cfg_attr(
+    any(
+        feature = "mock",
+        not(any(target_os = "windows", target_os = "linux", target_os = "macos"))
+    ),
+    path = "./sys/mock/mod.rs"
+)]
 mod sys;
 
+#[cfg(all(
+    any(target_os = "windows", target_os = "linux", target_os = "macos"),
+    not(feature = "mock")
+))]
+mod accelerator;
+#[cfg(target_os = "windows")]
+mod autostart;
+mod badge;
+mod capabilities;
+mod error;
 mod icon;
+mod iconcomposite;
+mod iconset;
 mod menubuilder;
+mod recentitems;
+#[cfg(target_os = "windows")]
+mod singleinstance;
 mod trayicon;
 mod trayiconbuilder;
+mod trayiconevent;
+mod trayiconhandle;
 mod trayiconsender;
 
 // Public api
-pub use crate::icon::Icon;
-pub use crate::menubuilder::{MenuBuilder, MenuItem};
+#[cfg(target_os = "windows")]
+pub use crate::autostart::{is_autostart_enabled, set_autostart};
+pub use crate::capabilities::{
+    capabilities, system_theme, Capabilities, NotifyIconVersion, SystemTheme,
+};
+pub use crate::error::{Error, OsError};
+#[cfg(target_os = "windows")]
+pub use crate::icon::StockIcon;
+pub use crate::icon::{FontOptions, Icon};
+pub use crate::iconcomposite::{Corner, IconComposite};
+pub use crate::iconset::IconSet;
+pub use crate::menubuilder::{
+    AppInfo, EventFn, HorizontalAlign, MenuBuilder, MenuCommandId, MenuItem, MenuItemId,
+    MenuItemStyle, MenuOptions, MenuTheme, MenuUpdater, VerticalAlign,
+};
+pub use crate::recentitems::RecentItems;
+#[cfg(target_os = "windows")]
+pub use crate::singleinstance::SingleInstance;
+#[cfg(feature = "mock")]
+pub use crate::sys::MockCall;
 pub use crate::trayicon::TrayIcon;
-pub use crate::trayiconbuilder::Error;
 pub use crate::trayiconbuilder::TrayIconBuilder;
+pub use crate::trayiconevent::{
+    ClickBehavior, Modifiers, MouseButton, PhysicalPosition, TrayIconEvent,
+};
+pub use crate::trayiconhandle::{instances, TrayIconHandle, TrayIconInstance};
+
+use crate::badge::Decoration;
+use crate::trayicon::TooltipFn;
+use std::time::Duration;
 
 // Each OS specific implementation must export following:
-pub(crate) use super::{
+pub(crate) use crate::sys::{
+    // Handler -> Result<Waker, Error>, run by the current thread
+    attach_handler,
+
     // MenuBuilder<T> -> Result<MenuSys<T>, Error>
     build_menu,
 
     // TrayIconBuilder<T> -> Result<Box<TrayIconSys<T>>, Error>
     build_trayicon,
 
+    // () -> Capabilities
+    capabilities as capabilities_sys,
+
+    // () -> (), callable from any thread
+    quit_event_loop as quit_event_loop_sys,
+
+    // () -> (), runs until quit
+    run_event_loop as run_event_loop_sys,
+
+    // &AppInfo -> (), blocks until closed
+    show_about as show_about_sys,
+
+    // TrayIconSys<T> -> Result<(), Error>, callable from any thread
+    shutdown_trayicon,
+
+    // FnOnce() -> Result<Handler, Error> -> Result<Waker, Error>
+    spawn_detached,
+
+    // () -> Option<SystemTheme>
+    system_theme as system_theme_sys,
+
     // Struct that must implement IconBase + Clone
     IconSys,
 
@@ -36,21 +123,141 @@ pub(crate) use super::{
     TrayIconSys,
 };
 
+// Struct with new(&str), is_primary() and forward(&str, &[String])
+#[cfg(target_os = "windows")]
+pub(crate) use crate::sys::SingleInstanceSys;
+
+// Option<u128> -> Result<(), Error>
+#[cfg(target_os = "windows")]
+pub(crate) use crate::sys::cleanup_stale_icons as cleanup_stale_icons_sys;
+
+// () -> usize
+#[cfg(target_os = "windows")]
+pub(crate) use crate::sys::gdi_handle_count as gdi_handle_count_sys;
+
+// (bool, &str, &[String]) -> Result<(), Error>, and &str -> bool
+#[cfg(target_os = "windows")]
+pub(crate) use crate::sys::{
+    is_autostart_enabled as is_autostart_enabled_sys, set_autostart as set_autostart_sys,
+};
+
+/// Run the event loop on this thread until `quit_event_loop` is called
+///
+/// For apps without a GUI event loop, e.g. command line apps and daemons. In
+/// Windows this pumps the messages of the thread, so it must run on the
+/// thread which built the tray icons. In macOS this runs the application on
+/// the main thread. On Linux the tray icons run in their own threads, so this
+/// only waits.
+pub fn run_event_loop() {
+    run_event_loop_sys()
+}
+
+/// Quit the running event loops, from any thread
+///
+/// If the event loop is not running yet, it quits right away when started.
+pub fn quit_event_loop() {
+    quit_event_loop_sys()
+}
+
+/// Remove the ghost icons left in the notification area by a crashed instance
+///
+/// Call at the start before building the tray icon. With the guid given to
+/// `TrayIconBuilder::guid` the icon of the crashed instance is removed, the
+/// tray icon does this too if adding the icon fails. Icons without a guid are
+/// removed by moving the mouse over the notification area, the shell removes
+/// the icons of the closed windows then. Only on Windows.
+#[cfg(target_os = "windows")]
+pub fn cleanup_stale_icons(guid: Option<u128>) -> Result<(), Error> {
+    cleanup_stale_icons_sys(guid)
+}
+
+/// Number of the GDI objects of the process, for finding leaks
+///
+/// Icons decoded from the same buffer at the same size share the handle, so
+/// switching between the same icons should not grow the count. Only on
+/// Windows.
+#[cfg(target_os = "windows")]
+pub fn gdi_handle_count() -> usize {
+    gdi_handle_count_sys()
+}
+
 /// TrayIconSys must implement this
+///
+/// Only the methods finding menu items by the event need `T: PartialEq`.
 pub(crate) trait TrayIconBase<T>
 where
-    T: PartialEq + Clone + 'static,
+    T: Clone + 'static,
 {
     fn set_icon(&mut self, icon: &Icon) -> Result<(), Error>;
     fn set_menu(&mut self, menu: &MenuBuilder<T>) -> Result<(), Error>;
     fn set_tooltip(&mut self, tooltip: &str) -> Result<(), Error>;
+
+    /// Tooltip is set from the function now and on each interval, none stops
+    /// the refreshing
+    fn set_tooltip_fn(
+        &mut self,
+        tooltip_fn: Option<TooltipFn>,
+        interval: Duration,
+    ) -> Result<(), Error>;
+    fn set_menu_item_checkable(&mut self, id: &T, checked: bool) -> Result<(), Error>
+    where
+        T: PartialEq;
+    fn set_menu_item_disabled(&mut self, id: &T, disabled: bool) -> Result<(), Error>
+    where
+        T: PartialEq;
+    fn set_menu_item_name(&mut self, id: &T, name: &str) -> Result<(), Error>
+    where
+        T: PartialEq;
+    fn show_notification(
+        &mut self,
+        title: &str,
+        body: &str,
+        icon: Option<&Icon>,
+        timeout: Option<Duration>,
+    ) -> Result<(), Error>;
+
+    /// Screen rectangle as x, y, width and height in physical pixels
+    fn rect(&self) -> Result<(i32, i32, u32, u32), Error>;
+
+    /// Frames are not empty, the icon is shown again after stopping
+    fn set_animation(&mut self, frames: Vec<Icon>, interval: Duration) -> Result<(), Error>;
+    fn stop_animation(&mut self) -> Result<(), Error>;
+    fn set_visible(&mut self, visible: bool) -> Result<(), Error>;
+
+    /// Without the position the menu is shown at the tray icon
+    fn show_menu(&mut self, position: Option<PhysicalPosition>) -> Result<(), Error>;
+
+    /// Blocks until the menu is closed, the chosen event is returned instead
+    /// of sent
+    fn show_menu_sync(&mut self, position: Option<PhysicalPosition>) -> Result<Option<T>, Error>;
+
+    /// Badge and progress are drawn on the icon, and on the animation frames
+    /// in Windows
+    fn set_decoration(&mut self, decoration: Decoration) -> Result<(), Error>;
+
+    /// Replace the click events given to the builder
+    fn set_click_events(
+        &mut self,
+        on_click: Option<T>,
+        on_double_click: Option<T>,
+        on_right_click: Option<T>,
+    ) -> Result<(), Error>;
 }
 
 /// IconSys must implement this
 pub(crate) trait IconBase {
     fn from_buffer(
-        buffer: &'static [u8],
+        buffer: &[u8],
         width: Option<u32>,
         height: Option<u32>,
     ) -> Result<IconSys, Error>;
+
+    /// Buffer is 32-bit RGBA pixels, and it's length is validated
+    fn from_rgba(rgba: &[u8], width: u32, height: u32) -> Result<IconSys, Error>;
+
+    /// Options are validated, the icon is square
+    fn from_text(text: &str, options: &FontOptions) -> Result<IconSys, Error>;
+
+    /// Render at the size as 32-bit RGBA pixels, none if not supported
+    fn to_rgba(&self, width: u32, height: u32) -> Option<Vec<u8>>;
 }
@@ -1,52 +1,533 @@
-use crate::{Error, Icon};
+use crate::{Error, Icon, RecentItems};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::iter::FromIterator;
+use std::sync::{Arc, Mutex};
 
+/// Stable identifier of a menu item
+///
+/// Event `T` may be shared by many items, the identifier is for referencing a
+/// specific item later for updates, removal or insertion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MenuItemId(pub u32);
+
+/// Command identifier of a menu item in Windows
+///
+/// By default the items are numbered from 1 in the order of the menu. Give
+/// the item a fixed identifier with `MenuBuilder::command_id`, e.g. when the
+/// commands are shared with accelerators or other Win32 menus of the app. The
+/// items without one are numbered around the fixed identifiers. Zero is never
+/// used, it means no command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MenuCommandId(pub u16);
+
+/// Numbers the items for their command identifiers in Windows
+///
+/// Fixed identifiers are used as is, the other items are numbered from 1 in
+/// the order of the menu, skipping the fixed ones.
+#[cfg(target_os = "windows")]
+pub(crate) struct CommandNumbering {
+    next: usize,
+    fixed: HashSet<usize>,
+}
+
+#[cfg(target_os = "windows")]
+impl CommandNumbering {
+    pub(crate) fn new<T>(builder: &MenuBuilder<T>) -> CommandNumbering
+    where
+        T: Clone + 'static,
+    {
+        fn collect<T: Clone>(menu: &MenuBuilder<T>, fixed: &mut HashSet<usize>) {
+            for item in &menu.menu_items {
+                if let Some(command_id) = item.command_id() {
+                    fixed.insert(command_id.0 as usize);
+                }
+                if let MenuItem::Submenu { children, .. } = item {
+                    collect(children, fixed);
+                }
+            }
+        }
+        let mut fixed = HashSet::new();
+        collect(builder, &mut fixed);
+        CommandNumbering { next: 0, fixed }
+    }
+
+    /// Identifier of the next item with a command
+    pub(crate) fn next<T>(&mut self, item: &MenuItem<T>) -> usize
+    where
+        T: Clone + 'static,
+    {
+        if let Some(command_id) = item.command_id() {
+            return command_id.0 as usize;
+        }
+        self.next += 1;
+        while self.fixed.contains(&self.next) {
+            self.next += 1;
+        }
+        self.next
+    }
+}
+
+/// Item of the menu
+///
+/// With the `serde` feature the items can be serialized, except the icons
+/// which are skipped, and the `Mapped` items which fail to serialize.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum MenuItem<T>
 where
-    T: PartialEq + Clone + 'static,
+    T: Clone + 'static,
 {
     Separator,
+
+    /// Following items are shown in a new column, e.g. for long lists
+    ColumnBreak,
     Item {
         id: T,
         name: String,
+        #[cfg_attr(feature = "serde", serde(default))]
         disabled: bool,
+        #[cfg_attr(feature = "serde", serde(skip))]
         icon: Option<Icon>,
+        item_id: Option<MenuItemId>,
+        command_id: Option<MenuCommandId>,
+        #[cfg_attr(feature = "serde", serde(default))]
+        is_default: bool,
+
+        /// Shortcut shown after the name, e.g. "Ctrl+Q"
+        accelerator: Option<String>,
+
+        /// Longer text of the item, see `MenuBuilder::description`
+        description: Option<String>,
+
+        /// Bold or normal text, see `MenuBuilder::style`
+        #[cfg_attr(feature = "serde", serde(skip))]
+        style: Option<MenuItemStyle>,
+
+        /// `&` in the name marks the mnemonic, see `MenuBuilder::item_raw`
+        #[cfg_attr(feature = "serde", serde(default))]
+        mnemonics: bool,
     },
     Checkable {
         id: T,
         name: String,
         is_checked: bool,
+        #[cfg_attr(feature = "serde", serde(default))]
         disabled: bool,
+        #[cfg_attr(feature = "serde", serde(skip))]
         icon: Option<Icon>,
+        item_id: Option<MenuItemId>,
+        command_id: Option<MenuCommandId>,
+        description: Option<String>,
+
+        /// Bold text and the check icons, see `MenuBuilder::style`
+        #[cfg_attr(feature = "serde", serde(skip))]
+        style: Option<MenuItemStyle>,
     },
     Submenu {
         id: Option<T>,
         name: String,
         children: MenuBuilder<T>,
+        #[cfg_attr(feature = "serde", serde(default))]
+        disabled: bool,
+        #[cfg_attr(feature = "serde", serde(skip))]
+        icon: Option<Icon>,
+        item_id: Option<MenuItemId>,
+        command_id: Option<MenuCommandId>,
+
+        /// Events sent when the submenu is opened and closed
+        on_open: Option<T>,
+        on_close: Option<T>,
+    },
+
+    /// Item with the event built when it's clicked
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Mapped {
+        map: EventFn<T>,
+        name: String,
         disabled: bool,
         icon: Option<Icon>,
+        item_id: Option<MenuItemId>,
+        command_id: Option<MenuCommandId>,
     },
 }
 
+impl<T> MenuItem<T>
+where
+    T: Clone + 'static,
+{
+    /// Stable identifier of the item, if given
+    pub fn item_id(&self) -> Option<MenuItemId> {
+        match self {
+            MenuItem::Item { item_id, .. }
+            | MenuItem::Checkable { item_id, .. }
+            | MenuItem::Submenu { item_id, .. }
+            | MenuItem::Mapped { item_id, .. } => *item_id,
+            MenuItem::Separator | MenuItem::ColumnBreak => None,
+        }
+    }
+
+    /// Fixed command identifier of the item, if given
+    pub fn command_id(&self) -> Option<MenuCommandId> {
+        match self {
+            MenuItem::Item { command_id, .. }
+            | MenuItem::Checkable { command_id, .. }
+            | MenuItem::Submenu { command_id, .. }
+            | MenuItem::Mapped { command_id, .. } => *command_id,
+            MenuItem::Separator | MenuItem::ColumnBreak => None,
+        }
+    }
+
+    /// Set checked state, only checkable items can be checked
+    pub fn set_checked(&mut self, checked: bool) -> Result<(), Error> {
+        if let MenuItem::Checkable { is_checked, .. } = self {
+            *is_checked = checked;
+            Ok(())
+        } else {
+            Err(Error::MenuItemNotFound)
+        }
+    }
+
+    /// Set the description, only items and checkables can be described
+    pub fn set_description(&mut self, description: Option<String>) -> Result<(), Error> {
+        match self {
+            MenuItem::Item { description: d, .. } | MenuItem::Checkable { description: d, .. } => {
+                *d = description;
+                Ok(())
+            }
+            _ => Err(Error::MenuItemNotFound),
+        }
+    }
+
+    /// Set the style, only items and checkables can be styled
+    pub fn set_style(&mut self, style: Option<MenuItemStyle>) -> Result<(), Error> {
+        match self {
+            MenuItem::Item { style: s, .. } | MenuItem::Checkable { style: s, .. } => {
+                *s = style;
+                Ok(())
+            }
+            _ => Err(Error::MenuItemNotFound),
+        }
+    }
+
+    /// Set disabled state, separators can't be disabled
+    pub fn set_disabled(&mut self, disabled: bool) -> Result<(), Error> {
+        match self {
+            MenuItem::Item { disabled: d, .. }
+            | MenuItem::Checkable { disabled: d, .. }
+            | MenuItem::Submenu { disabled: d, .. }
+            | MenuItem::Mapped { disabled: d, .. } => {
+                *d = disabled;
+                Ok(())
+            }
+            MenuItem::Separator | MenuItem::ColumnBreak => Err(Error::MenuItemNotFound),
+        }
+    }
+}
+
+/// Function building the event of a menu item when it's clicked
+///
+/// Events are compared by the function, clones are equal but two functions
+/// are never equal even if they build the same event.
+pub struct EventFn<T>(Arc<dyn Fn() -> T + Send + Sync>)
+where
+    T: Clone + 'static;
+
+impl<T> EventFn<T>
+where
+    T: Clone + 'static,
+{
+    pub fn new<F>(f: F) -> EventFn<T>
+    where
+        F: Fn() -> T + Send + Sync + 'static,
+    {
+        EventFn(Arc::new(f))
+    }
+
+    /// Build the event
+    pub fn call(&self) -> T {
+        (self.0)()
+    }
+}
+
+impl<T> Clone for EventFn<T>
+where
+    T: Clone + 'static,
+{
+    fn clone(&self) -> Self {
+        EventFn(self.0.clone())
+    }
+}
+
+impl<T> PartialEq for EventFn<T>
+where
+    T: Clone + 'static,
+{
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T> Debug for EventFn<T>
+where
+    T: Clone + 'static,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EventFn")
+    }
+}
+
+/// Function building the menu each time it's opened
+#[derive(Clone)]
+pub(crate) struct MenuFn<T>(pub(crate) Arc<dyn Fn() -> MenuBuilder<T> + Send + Sync>)
+where
+    T: Clone + 'static;
+
+impl<T> MenuFn<T>
+where
+    T: Clone + 'static,
+{
+    pub(crate) fn build(&self) -> MenuBuilder<T> {
+        (self.0)()
+    }
+}
+
+impl<T> Debug for MenuFn<T>
+where
+    T: Clone + 'static,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MenuFn")
+    }
+}
+
+/// Checkables toggled by the tray icon, for the retained menu builder
+pub(crate) type Toggled<T> = Arc<Mutex<Vec<(T, bool)>>>;
+
+/// Sets the menu from any thread, it's applied before the menu is shown
+///
+/// Give a clone to `TrayIconBuilder::menu_updater`, and set the menu e.g. when
+/// the `on_menu_open` event is received. With the `on_event` callback the
+/// event is handled in the thread of the tray icon before the menu is shown,
+/// so the updated menu is shown right away. With a channel the menu is
+/// usually set after it's shown, and it's shown the next time.
+pub struct MenuUpdater<T>
+where
+    T: Clone + 'static,
+{
+    menu: Arc<Mutex<Option<MenuBuilder<T>>>>,
+}
+
+impl<T> MenuUpdater<T>
+where
+    T: Clone + 'static,
+{
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> MenuUpdater<T> {
+        MenuUpdater {
+            menu: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Set the menu shown next, replaces the menu set before it's applied
+    pub fn set_menu(&self, menu: MenuBuilder<T>) {
+        if let Ok(mut pending) = self.menu.lock() {
+            *pending = Some(menu);
+        }
+    }
+
+    /// Menu set after the last time it was taken
+    pub(crate) fn take(&self) -> Option<MenuBuilder<T>> {
+        self.menu.lock().ok().and_then(|mut pending| pending.take())
+    }
+}
+
+impl<T> Clone for MenuUpdater<T>
+where
+    T: Clone + 'static,
+{
+    fn clone(&self) -> Self {
+        MenuUpdater {
+            menu: self.menu.clone(),
+        }
+    }
+}
+
+impl<T> Debug for MenuUpdater<T>
+where
+    T: Clone + 'static,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MenuUpdater")
+    }
+}
+
+/// Colors of the menu
+///
+/// In Windows the dark menu uses the undocumented dark mode of uxtheme.dll,
+/// which exists since Windows 10 1903, older versions show the light menu.
+/// The mode is set for the whole process when the menu is shown, so it
+/// affects the other menus of the process too. Ignored on Linux, the host
+/// draws the menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MenuTheme {
+    /// Follow the system setting for the apps
+    System,
+    Light,
+    Dark,
+}
+
+/// Horizontal position of the menu relative to the point it's shown at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum HorizontalAlign {
+    /// Left edge of the menu is at the point
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// Vertical position of the menu relative to the point it's shown at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum VerticalAlign {
+    /// Top edge of the menu is at the point
+    #[default]
+    Top,
+    Bottom,
+}
+
+/// How the menu is shown, only on Windows
+///
+/// These are the flags of `TrackPopupMenuEx`. The menu is still moved to fit
+/// the monitor, the alignment is where it's placed when it fits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MenuOptions {
+    pub align: HorizontalAlign,
+    pub vertical: VerticalAlign,
+
+    /// Fade or slide the menu in, as set in the system settings
+    pub animation: bool,
+
+    /// Items can be clicked with the right button too
+    pub allow_right_button: bool,
+}
+
+impl Default for MenuOptions {
+    fn default() -> Self {
+        MenuOptions {
+            align: HorizontalAlign::Left,
+            vertical: VerticalAlign::Top,
+            animation: true,
+            allow_right_button: false,
+        }
+    }
+}
+
+/// Styling of an item or a checkable, only on Windows
+///
+/// Set with `MenuBuilder::style` after the item. Check icons replace the
+/// checkmark of a checkable, e.g. a green dot for checked and a grey dot for
+/// unchecked in a status menu. They're drawn in the size of the checkmark.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MenuItemStyle {
+    /// Bold or normal text, by default only the default item is bold
+    ///
+    /// Windows draws the default item of the menu bold, so bold makes the
+    /// item the default one, and only the last bold item of a menu is bold.
+    /// Event of the double click is not changed.
+    pub bold: Option<bool>,
+
+    /// Shown instead of the checkmark when checked
+    pub checked_icon: Option<Icon>,
+
+    /// Shown when not checked, by default nothing is
+    pub unchecked_icon: Option<Icon>,
+}
+
+impl MenuItemStyle {
+    pub fn new() -> MenuItemStyle {
+        MenuItemStyle::default()
+    }
+
+    pub fn bold(mut self, bold: bool) -> Self {
+        self.bold = Some(bold);
+        self
+    }
+
+    /// Icons of the checked and the unchecked states, instead of the checkmark
+    pub fn check_icons(mut self, checked: Option<Icon>, unchecked: Option<Icon>) -> Self {
+        self.checked_icon = checked;
+        self.unchecked_icon = unchecked;
+        self
+    }
+}
+
+/// Name, version and license shown by `MenuBuilder::standard_about`
+///
+/// E.g. from the manifest with `env!("CARGO_PKG_NAME")`,
+/// `env!("CARGO_PKG_VERSION")` and `env!("CARGO_PKG_LICENSE")`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AppInfo {
+    pub name: String,
+    pub version: String,
+    pub license: Option<String>,
+}
+
+impl AppInfo {
+    /// Text of the message box, a line for each field
+    #[cfg(all(any(target_os = "windows", target_os = "macos"), not(feature = "mock")))]
+    pub(crate) fn text(&self) -> String {
+        let mut text = format!("{}\nVersion {}", self.name, self.version);
+        if let Some(license) = &self.license {
+            text.push_str(&format!("\nLicense {}", license));
+        }
+        text
+    }
+}
+
 #[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MenuBuilder<T>
 where
-    T: PartialEq + Clone + 'static,
+    T: Clone + 'static,
 {
+    #[cfg_attr(feature = "serde", serde(rename = "items"))]
     pub(crate) menu_items: Vec<MenuItem<T>>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub(crate) theme: Option<MenuTheme>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub(crate) options: Option<MenuOptions>,
 }
 
 /// Menu Builder
 ///
-/// This is defined as consuming builder, could be converted to non-consuming
-/// one. This builder includes conditional helper `when` for composing
-/// conditionally some items.
+/// This is defined as consuming builder, with the `push` functions and `apply`
+/// for building it in loops through a mutable reference. This builder
+/// includes conditional helper `when` for composing conditionally some items.
 impl<T> MenuBuilder<T>
 where
-    T: PartialEq + Clone + 'static,
+    T: Clone + 'static,
 {
     pub fn new() -> MenuBuilder<T> {
-        MenuBuilder { menu_items: vec![] }
+        MenuBuilder {
+            menu_items: vec![],
+            theme: None,
+            options: None,
+        }
     }
 
     /// Conditionally include items, poor mans function composition
@@ -57,52 +538,710 @@ where
         f(self)
     }
 
+    /// Use the consuming functions through a mutable reference
+    ///
+    /// `menu.apply(|m| m.item("Open", Events::Open).disabled())`
+    pub fn apply<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnOnce(Self) -> Self,
+    {
+        let menu = std::mem::replace(self, MenuBuilder::new());
+        *self = f(menu);
+        self
+    }
+
     pub fn with(mut self, item: MenuItem<T>) -> Self {
+        self.push(item);
+        self
+    }
+
+    /// Add the item, e.g. when building the menu from a config file
+    pub fn push(&mut self, item: MenuItem<T>) -> &mut Self {
         self.menu_items.push(item);
         self
     }
 
-    pub fn separator(mut self) -> Self {
-        self.menu_items.push(MenuItem::Separator);
+    pub fn push_separator(&mut self) -> &mut Self {
+        self.push(MenuItem::Separator)
+    }
+
+    pub fn push_column_break(&mut self) -> &mut Self {
+        self.push(MenuItem::ColumnBreak)
+    }
+
+    pub fn push_item(&mut self, name: &str, id: T) -> &mut Self {
+        self.push(MenuItem::Item {
+            id,
+            name: name.to_string(),
+            disabled: false,
+            icon: None,
+            item_id: None,
+            command_id: None,
+            is_default: false,
+            accelerator: None,
+            description: None,
+            style: None,
+            mnemonics: false,
+        })
+    }
+
+    pub fn push_checkable(&mut self, name: &str, is_checked: bool, id: T) -> &mut Self {
+        self.push(MenuItem::Checkable {
+            id,
+            name: name.to_string(),
+            is_checked,
+            disabled: false,
+            icon: None,
+            item_id: None,
+            command_id: None,
+            description: None,
+            style: None,
+        })
+    }
+
+    pub fn push_submenu(&mut self, name: &str, menu: MenuBuilder<T>) -> &mut Self {
+        self.push(MenuItem::Submenu {
+            id: None,
+            name: name.to_string(),
+            children: menu,
+            disabled: false,
+            icon: None,
+            item_id: None,
+            command_id: None,
+            on_open: None,
+            on_close: None,
+        })
+    }
+
+    /// Add many items, e.g. generated from a list of recent files
+    pub fn items<I>(mut self, items: I) -> Self
+    where
+        I: IntoIterator<Item = MenuItem<T>>,
+    {
+        self.menu_items.extend(items);
+        self
+    }
+
+    /// Add many items, at most `page_size` at each level, the rest are in
+    /// chained "More…" submenus
+    ///
+    /// E.g. for a list of windows or a playlist, which would not fit the
+    /// screen. See `paginated_with_label` for translating the label.
+    pub fn paginated<I>(self, items: I, page_size: usize) -> Self
+    where
+        I: IntoIterator<Item = MenuItem<T>>,
+    {
+        self.paginated_with_label(items, page_size, "More…")
+    }
+
+    /// Same as `paginated`, with the label of the submenus
+    pub fn paginated_with_label<I>(mut self, items: I, page_size: usize, label: &str) -> Self
+    where
+        I: IntoIterator<Item = MenuItem<T>>,
+    {
+        let items = items.into_iter().collect::<Vec<_>>();
+        let mut pages = items.chunks(page_size.max(1)).rev();
+        let mut menu = match pages.next() {
+            Some(last) => MenuBuilder::new().items(last.iter().cloned()),
+            None => return self,
+        };
+        for page in pages {
+            menu = MenuBuilder::new()
+                .items(page.iter().cloned())
+                .submenu(label, menu);
+        }
+        self.menu_items.extend(menu.menu_items);
+        self
+    }
+
+    /// Quit item at the end of the menu, after a separator
+    ///
+    /// Named "Exit" in Windows, and "Quit" elsewhere, as in the menus of the
+    /// platforms.
+    pub fn standard_quit(mut self, id: T) -> Self {
+        if !matches!(self.menu_items.last(), None | Some(MenuItem::Separator)) {
+            self.push_separator();
+        }
+        let name = if cfg!(target_os = "windows") {
+            "Exit"
+        } else {
+            "Quit"
+        };
+        self.item(name, id)
+    }
+
+    /// "About" item showing the name, the version and the license of the app
+    /// in a message box
+    ///
+    /// The event is sent after the message box is closed. The message box is
+    /// native in Windows and macOS, on Linux only the event is sent.
+    pub fn standard_about(self, app_info: AppInfo, id: T) -> Self
+    where
+        T: Send + Sync,
+    {
+        let name = format!("About {}", app_info.name);
+        self.item_map(&name, move || {
+            crate::show_about_sys(&app_info);
+            id.clone()
+        })
+    }
+
+    /// Add an item for each of the recent entries, the latest first
+    ///
+    /// Event is built from the index and the entry, e.g. the path of the
+    /// file. Nothing is added if the list is empty.
+    pub fn recent_items<F>(mut self, recent: &RecentItems, f: F) -> Self
+    where
+        F: Fn(usize, &str) -> T,
+    {
+        for (index, entry) in recent.entries().iter().enumerate() {
+            self.push_item(entry, f(index, entry));
+        }
+        self
+    }
+
+    /// Disable the previously added item, e.g. `.item("Save", Events::Save).disabled()`
+    ///
+    /// Separators can't be disabled, nothing happens after one. Disabled
+    /// submenu disables all of its items too, so their hotkeys don't work
+    /// either.
+    pub fn disabled(mut self) -> Self {
+        if let Some(item) = self.menu_items.last_mut() {
+            let _ = item.set_disabled(true);
+        }
+        self
+    }
+
+    /// Send the event when the previously added submenu is opened
+    ///
+    /// E.g. for refreshing the items of the submenu only while it's shown.
+    /// Ignored after other items. Only sent on Windows.
+    pub fn on_submenu_open(mut self, id: T) -> Self {
+        if let Some(MenuItem::Submenu { on_open, .. }) = self.menu_items.last_mut() {
+            *on_open = Some(id);
+        }
+        self
+    }
+
+    /// Send the event when the previously added submenu is closed
+    ///
+    /// Ignored after other items. Only sent on Windows.
+    pub fn on_submenu_close(mut self, id: T) -> Self {
+        if let Some(MenuItem::Submenu { on_close, .. }) = self.menu_items.last_mut() {
+            *on_close = Some(id);
+        }
+        self
+    }
+
+    /// Describe the previously added item, e.g. for a short or cryptic name
+    ///
+    /// In macOS the description is the tooltip of the item. In Windows
+    /// there are no tooltips in the menus, `TrayIconEvent::MenuHover` is sent
+    /// with the description when the item is highlighted, e.g. for showing it
+    /// in a status bar. Only items and checkables can be described, ignored
+    /// on Linux.
+    pub fn description(mut self, description: &str) -> Self {
+        if let Some(item) = self.menu_items.last_mut() {
+            let _ = item.set_description(Some(description.to_string()));
+        }
+        self
+    }
+
+    /// Fixed command identifier of the previously added item, only on Windows
+    ///
+    /// Identifiers must be unique in the menu and its submenus, and not zero,
+    /// `TrayIconBuilder::build` fails otherwise. Submenus use the identifier
+    /// only for their event. Ignored after separators and column breaks.
+    pub fn command_id(mut self, command_id: MenuCommandId) -> Self {
+        match self.menu_items.last_mut() {
+            Some(MenuItem::Item { command_id: c, .. })
+            | Some(MenuItem::Checkable { command_id: c, .. })
+            | Some(MenuItem::Submenu { command_id: c, .. })
+            | Some(MenuItem::Mapped { command_id: c, .. }) => *c = Some(command_id),
+            _ => {}
+        }
+        self
+    }
+
+    /// Style the previously added item or checkable, only on Windows
+    ///
+    /// E.g. `.checkable("Online", true, Events::Online).style(status_style)`
+    /// with colored dots as the check icons. Ignored after other items.
+    pub fn style(mut self, style: MenuItemStyle) -> Self {
+        if let Some(item) = self.menu_items.last_mut() {
+            let _ = item.set_style(Some(style));
+        }
+        self
+    }
+
+    /// Colors of the menu, by default the platform decides
+    ///
+    /// Only the theme of the top level menu is used, submenus follow it.
+    pub fn theme(mut self, theme: MenuTheme) -> Self {
+        self.theme = Some(theme);
+        self
+    }
+
+    /// Alignment and the animation of the menu, only on Windows
+    ///
+    /// Only the options of the top level menu are used.
+    pub fn options(mut self, options: MenuOptions) -> Self {
+        self.options = Some(options);
+        self
+    }
+
+    pub fn separator(mut self) -> Self {
+        self.push_separator();
+        self
+    }
+
+    /// Show the following items in a new column
+    ///
+    /// Long menus, e.g. a list of windows, can be wrapped to columns instead
+    /// of scrolling. In Windows the columns are separated by a vertical line.
+    /// Other platforms show a separator instead.
+    pub fn column_break(mut self) -> Self {
+        self.push_column_break();
+        self
+    }
+
+    pub fn item(mut self, name: &str, id: T) -> Self {
+        self.push_item(name, id);
+        self
+    }
+
+    /// Item with the `&` in the name marking the keyboard mnemonic, e.g.
+    /// "&Open" is opened with the O key
+    ///
+    /// `&&` is shown as a single `&`. In the names of the other items the `&`
+    /// is shown as it is, e.g. "Files & Folders". Menus in macOS have no
+    /// mnemonics, there the marker is removed.
+    pub fn item_raw(mut self, name: &str, id: T) -> Self {
+        self.menu_items.push(MenuItem::Item {
+            id,
+            name: name.to_string(),
+            disabled: false,
+            icon: None,
+            item_id: None,
+            command_id: None,
+            is_default: false,
+            accelerator: None,
+            description: None,
+            style: None,
+            mnemonics: true,
+        });
+        self
+    }
+
+    /// Item with the event built when it's clicked, e.g. to carry data
+    ///
+    /// `.item_map("Open file", move || Events::Open(path.clone()))`
+    pub fn item_map<F>(mut self, name: &str, f: F) -> Self
+    where
+        F: Fn() -> T + Send + Sync + 'static,
+    {
+        self.menu_items.push(MenuItem::Mapped {
+            map: EventFn::new(f),
+            name: name.to_string(),
+            disabled: false,
+            icon: None,
+            item_id: None,
+            command_id: None,
+        });
+        self
+    }
+
+    /// Item with the shortcut shown after the name, e.g. "Ctrl+Q"
+    ///
+    /// The shortcut is a `+` separated list of the modifiers Ctrl, Alt, Shift
+    /// and Win, and the key, e.g. a letter, F1 or Delete. In macOS Ctrl is the
+    /// Command key, and the shortcut works when the app is active. In Windows
+    /// the shortcut is shown only, unless `TrayIconBuilder::menu_hotkeys` is
+    /// enabled.
+    pub fn item_with_accelerator(mut self, name: &str, accelerator: &str, id: T) -> Self {
+        self.menu_items.push(MenuItem::Item {
+            id,
+            name: name.to_string(),
+            disabled: false,
+            icon: None,
+            item_id: None,
+            command_id: None,
+            is_default: false,
+            accelerator: Some(accelerator.to_string()),
+            description: None,
+            style: None,
+            mnemonics: false,
+        });
+        self
+    }
+
+    /// Default item of the menu, e.g. "Open"
+    ///
+    /// In Windows the default item is shown in bold, and double click on the
+    /// tray icon activates it, unless `on_double_click` is set. Other platforms
+    /// show it as a regular item.
+    pub fn default_item(mut self, name: &str, id: T) -> Self {
+        self.menu_items.push(MenuItem::Item {
+            id,
+            name: name.to_string(),
+            disabled: false,
+            icon: None,
+            item_id: None,
+            command_id: None,
+            is_default: true,
+            accelerator: None,
+            description: None,
+            style: None,
+            mnemonics: false,
+        });
+        self
+    }
+
+    pub fn checkable(mut self, name: &str, is_checked: bool, id: T) -> Self {
+        self.push_checkable(name, is_checked, id);
+        self
+    }
+
+    pub fn submenu(mut self, name: &str, menu: MenuBuilder<T>) -> Self {
+        self.push_submenu(name, menu);
+        self
+    }
+
+    /// Item with an icon shown next to the name
+    pub fn item_with_icon(mut self, name: &str, icon: Icon, id: T) -> Self {
+        self.menu_items.push(MenuItem::Item {
+            id,
+            name: name.to_string(),
+            disabled: false,
+            icon: Some(icon),
+            item_id: None,
+            command_id: None,
+            is_default: false,
+            accelerator: None,
+            description: None,
+            style: None,
+            mnemonics: false,
+        });
+        self
+    }
+
+    /// Checkable with an icon shown next to the name
+    ///
+    /// In Windows the icon replaces the check mark, checked state is shown by
+    /// highlighting the icon.
+    pub fn checkable_with_icon(mut self, name: &str, is_checked: bool, icon: Icon, id: T) -> Self {
+        self.menu_items.push(MenuItem::Checkable {
+            id,
+            name: name.to_string(),
+            is_checked,
+            disabled: false,
+            icon: Some(icon),
+            item_id: None,
+            command_id: None,
+            description: None,
+            style: None,
+        });
+        self
+    }
+
+    /// Submenu with an icon shown next to the name
+    pub fn submenu_with_icon(mut self, name: &str, icon: Icon, menu: MenuBuilder<T>) -> Self {
+        self.menu_items.push(MenuItem::Submenu {
+            id: None,
+            name: name.to_string(),
+            children: menu,
+            disabled: false,
+            icon: Some(icon),
+            item_id: None,
+            command_id: None,
+            on_open: None,
+            on_close: None,
+        });
         self
     }
 
-    pub fn item(mut self, name: &str, id: T) -> Self {
+    /// Item with stable identifier
+    pub fn item_with_id(mut self, item_id: MenuItemId, name: &str, id: T) -> Self {
         self.menu_items.push(MenuItem::Item {
             id,
             name: name.to_string(),
             disabled: false,
             icon: None,
+            item_id: Some(item_id),
+            command_id: None,
+            is_default: false,
+            accelerator: None,
+            description: None,
+            style: None,
+            mnemonics: false,
         });
         self
     }
 
-    pub fn checkable(mut self, name: &str, is_checked: bool, id: T) -> Self {
+    /// Checkable with stable identifier
+    pub fn checkable_with_id(
+        mut self,
+        item_id: MenuItemId,
+        name: &str,
+        is_checked: bool,
+        id: T,
+    ) -> Self {
         self.menu_items.push(MenuItem::Checkable {
             id,
             name: name.to_string(),
             is_checked,
             disabled: false,
             icon: None,
+            item_id: Some(item_id),
+            command_id: None,
+            description: None,
+            style: None,
         });
         self
     }
 
-    pub fn submenu(mut self, name: &str, menu: MenuBuilder<T>) -> Self {
+    /// Submenu with stable identifier
+    pub fn submenu_with_id(
+        mut self,
+        item_id: MenuItemId,
+        name: &str,
+        menu: MenuBuilder<T>,
+    ) -> Self {
         self.menu_items.push(MenuItem::Submenu {
             id: None,
             name: name.to_string(),
             children: menu,
             disabled: false,
             icon: None,
+            item_id: Some(item_id),
+            command_id: None,
+            on_open: None,
+            on_close: None,
         });
         self
     }
 
+    /// Get item by the stable identifier, searches submenus too
+    pub fn get_by_id(&self, item_id: MenuItemId) -> Option<&MenuItem<T>> {
+        self.menu_items.iter().find_map(|item| {
+            if item.item_id() == Some(item_id) {
+                Some(item)
+            } else if let MenuItem::Submenu { children, .. } = item {
+                children.get_by_id(item_id)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Get mutable item by the stable identifier, searches submenus too
+    pub fn get_by_id_mut(&mut self, item_id: MenuItemId) -> Option<&mut MenuItem<T>> {
+        self.menu_items.iter_mut().find_map(|item| {
+            if item.item_id() == Some(item_id) {
+                Some(item)
+            } else if let MenuItem::Submenu { children, .. } = item {
+                children.get_by_id_mut(item_id)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Remove item by the stable identifier, searches submenus too
+    pub fn remove_by_id(&mut self, item_id: MenuItemId) -> Option<MenuItem<T>> {
+        if let Some(pos) = self
+            .menu_items
+            .iter()
+            .position(|i| i.item_id() == Some(item_id))
+        {
+            return Some(self.menu_items.remove(pos));
+        }
+        self.menu_items.iter_mut().find_map(|item| match item {
+            MenuItem::Submenu { children, .. } => children.remove_by_id(item_id),
+            _ => None,
+        })
+    }
+
+    /// Insert item after the item with the stable identifier, searches
+    /// submenus too
+    ///
+    /// Item is given back if the identifier is not found.
+    #[allow(clippy::result_large_err)]
+    pub fn insert_after_id(
+        &mut self,
+        item_id: MenuItemId,
+        item: MenuItem<T>,
+    ) -> Result<(), MenuItem<T>> {
+        if let Some(pos) = self
+            .menu_items
+            .iter()
+            .position(|i| i.item_id() == Some(item_id))
+        {
+            self.menu_items.insert(pos + 1, item);
+            return Ok(());
+        }
+        let mut item = item;
+        for child in self.menu_items.iter_mut() {
+            if let MenuItem::Submenu { children, .. } = child {
+                match children.insert_after_id(item_id, item) {
+                    Ok(()) => return Ok(()),
+                    Err(i) => item = i,
+                }
+            }
+        }
+        Err(item)
+    }
+
+    /// Iterate the items, submenus are not entered
+    pub fn iter(&self) -> impl Iterator<Item = &MenuItem<T>> {
+        self.menu_items.iter()
+    }
+
+    /// Iterate the items mutably, submenus are not entered
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut MenuItem<T>> {
+        self.menu_items.iter_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.menu_items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.menu_items.is_empty()
+    }
+
+    /// Insert item at the index
+    ///
+    /// Panics if the index is greater than the number of items.
+    pub fn insert(&mut self, index: usize, item: MenuItem<T>) {
+        self.menu_items.insert(index, item);
+    }
+
+    /// Remove item at the index, if it exists
+    pub fn remove(&mut self, index: usize) -> Option<MenuItem<T>> {
+        if index < self.menu_items.len() {
+            Some(self.menu_items.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// Set checked state by the stable identifier, searches submenus too
+    pub fn set_checked_by_id(&mut self, item_id: MenuItemId, checked: bool) -> Result<(), Error> {
+        self.get_by_id_mut(item_id)
+            .ok_or(Error::MenuItemNotFound)?
+            .set_checked(checked)
+    }
+
+    /// Set disabled state by the stable identifier, searches submenus too
+    pub fn set_disabled_by_id(&mut self, item_id: MenuItemId, disabled: bool) -> Result<(), Error> {
+        self.get_by_id_mut(item_id)
+            .ok_or(Error::MenuItemNotFound)?
+            .set_disabled(disabled)
+    }
+
+    /// Check that the stable identifiers are unique, submenus included
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        fn collect<T: Clone>(
+            menu: &MenuBuilder<T>,
+            seen: &mut HashSet<MenuItemId>,
+            commands: &mut HashSet<MenuCommandId>,
+        ) -> Result<(), Error> {
+            for item in &menu.menu_items {
+                if let Some(item_id) = item.item_id() {
+                    if !seen.insert(item_id) {
+                        return Err(Error::DuplicateMenuItemId(item_id));
+                    }
+                }
+                if let Some(command_id) = item.command_id() {
+                    if command_id.0 == 0 || !commands.insert(command_id) {
+                        return Err(Error::InvalidMenuCommandId(command_id));
+                    }
+                }
+                if let MenuItem::Submenu { children, .. } = item {
+                    collect(children, seen, commands)?;
+                }
+            }
+            Ok(())
+        }
+        collect(self, &mut HashSet::new(), &mut HashSet::new())
+    }
+
     pub(crate) fn build(&self) -> Result<crate::MenuSys<T>, Error> {
-        crate::build_menu(self)
+        crate::build_menu(&self.with_disabled_submenus())
+    }
+
+    /// Copy with the items of the disabled submenus disabled too
+    ///
+    /// Some hosts open the disabled submenus, and the hotkeys of the items
+    /// don't know about the submenu.
+    pub(crate) fn with_disabled_submenus(&self) -> MenuBuilder<T> {
+        let mut builder = self.clone();
+        builder.propagate_disabled(false);
+        builder
+    }
+
+    fn propagate_disabled(&mut self, disabled: bool) {
+        for item in &mut self.menu_items {
+            if disabled {
+                let _ = item.set_disabled(true);
+            }
+            if let MenuItem::Submenu {
+                children, disabled, ..
+            } = item
+            {
+                children.propagate_disabled(*disabled);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T> MenuBuilder<T>
+where
+    T: Clone + serde::de::DeserializeOwned + 'static,
+{
+    /// Menu from JSON, e.g. a layout stored in a config file
+    ///
+    /// `{"items": [{"Item": {"id": "Open", "name": "Open"}}, "Separator"]}`,
+    /// the events are deserialized as `T`.
+    pub fn from_json(json: &str) -> Result<MenuBuilder<T>, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+impl<T> FromIterator<MenuItem<T>> for MenuBuilder<T>
+where
+    T: Clone + 'static,
+{
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = MenuItem<T>>,
+    {
+        MenuBuilder {
+            menu_items: iter.into_iter().collect(),
+            theme: None,
+            options: None,
+        }
+    }
+}
+
+impl<T> Extend<MenuItem<T>> for MenuBuilder<T>
+where
+    T: Clone + 'static,
+{
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = MenuItem<T>>,
+    {
+        self.menu_items.extend(iter)
     }
+}
 
+impl<T> MenuBuilder<T>
+where
+    T: PartialEq + Clone + 'static,
+{
     /// Get checkable state, if found.
     ///
     /// Prefer maintaining proper application state instead of getting checkable
@@ -124,34 +1263,29 @@ where
     ///
     /// Prefer building a new menu instead of mutating it with this method.
     pub(crate) fn set_checkable(&mut self, id: T, checked: bool) -> Result<(), Error> {
-        self.mutate_item(id, |i| {
-            if let MenuItem::Checkable { is_checked, .. } = i {
-                *is_checked = checked;
-                Ok(())
-            } else {
-                Err(Error::MenuItemNotFound)
-            }
-        })
+        self.mutate_item(id, |i| i.set_checked(checked))
     }
 
     /// Set disabled state
     ///
     /// Prefer building a new menu instead of mutating it with this method.
     pub(crate) fn set_disabled(&mut self, id: T, disabled: bool) -> Result<(), Error> {
+        self.mutate_item(id, |i| i.set_disabled(disabled))
+    }
+
+    /// Set name
+    ///
+    /// Prefer building a new menu instead of mutating it with this method.
+    pub(crate) fn set_name(&mut self, id: T, new_name: &str) -> Result<(), Error> {
         self.mutate_item(id, |i| match i {
-            MenuItem::Item { disabled: d, .. } => {
-                *d = disabled;
-                Ok(())
-            }
-            MenuItem::Checkable { disabled: d, .. } => {
-                *d = disabled;
+            MenuItem::Item { name, .. }
+            | MenuItem::Checkable { name, .. }
+            | MenuItem::Submenu { name, .. }
+            | MenuItem::Mapped { name, .. } => {
+                *name = new_name.to_string();
                 Ok(())
             }
-            MenuItem::Submenu { disabled: d, .. } => {
-                *d = disabled;
-                Ok(())
-            }
-            MenuItem::Separator => Err(Error::MenuItemNotFound),
+            MenuItem::Separator | MenuItem::ColumnBreak => Err(Error::MenuItemNotFound),
         })
     }
 
@@ -164,37 +1298,97 @@ where
     where
         F: FnOnce(&mut MenuItem<T>) -> Result<(), Error>,
     {
-        self._mutate_item_recurse_ref(id, f)
+        match self._find_item_recurse_mut(&id) {
+            Some(item) => f(item),
+            None => Err(Error::MenuItemNotFound),
+        }
     }
 
-    fn _mutate_item_recurse_ref<F>(&mut self, find_id: T, f: F) -> Result<(), Error>
-    where
-        F: FnOnce(&mut MenuItem<T>) -> Result<(), Error>,
-    {
-        let found_item = self.menu_items.iter_mut().find(|f| match f {
-            MenuItem::Item { id, .. } if id == &find_id => true,
-            MenuItem::Checkable { id, .. } if id == &find_id => true,
-            MenuItem::Submenu { id, .. } if id.as_ref() == Some(&find_id) => true,
+    /// Items of this menu are searched first, then each of the submenus until
+    /// the item is found
+    fn _find_item_recurse_mut(&mut self, find_id: &T) -> Option<&mut MenuItem<T>> {
+        let found = self.menu_items.iter().position(|i| match i {
+            MenuItem::Item { id, .. } => id == find_id,
+            MenuItem::Checkable { id, .. } => id == find_id,
+            MenuItem::Submenu { id, .. } => id.as_ref() == Some(find_id),
             _ => false,
         });
+        if let Some(j) = found {
+            return self.menu_items.get_mut(j);
+        }
+        self.menu_items.iter_mut().find_map(|i| match i {
+            MenuItem::Submenu { children, .. } => children._find_item_recurse_mut(find_id),
+            _ => None,
+        })
+    }
+}
 
-        if let Some(item) = found_item {
-            f(item)
-        } else {
-            // Try to find submenu
-            let maybe_found_submenu = self
-                .menu_items
-                .iter_mut()
-                .find(|i| matches!(i, MenuItem::Submenu { .. }));
-
-            // Reurse
-            if let Some(MenuItem::Submenu { children, .. }) = maybe_found_submenu {
-                return children._mutate_item_recurse_ref(find_id, f);
-            }
-
-            Err(Error::MenuItemNotFound)
+/// Name as shown, with the mnemonic marker of the platform
+///
+/// The marker is doubled where it's in the text, e.g. `&` in Windows and `_`
+/// in Linux. With `mnemonics` a single `&` in the name is the marker, and
+/// `&&` is the text. Without the marker the mnemonics are removed.
+#[cfg(all(
+    any(target_os = "windows", target_os = "linux", target_os = "macos"),
+    not(feature = "mock")
+))]
+pub(crate) fn menu_label(name: &str, mnemonics: bool, marker: Option<char>) -> String {
+    let mut label = String::with_capacity(name.len());
+    let mut chars = name.chars().peekable();
+    while let Some(c) = chars.next() {
+        if mnemonics && c == '&' && chars.next_if_eq(&'&').is_none() {
+            label.extend(marker);
+            continue;
+        }
+        label.push(c);
+        if Some(c) == marker {
+            label.push(c);
         }
     }
+    label
+}
+
+/// Build a `MenuBuilder` declaratively
+///
+/// Items are separated with commas, submenus list their items in brackets:
+///
+/// ```ignore
+/// let menu = tray_menu![
+///     item "Open" => Events::Open,
+///     checkable "Autostart", true => Events::Autostart,
+///     separator,
+///     submenu "Help" => [
+///         item "About" => Events::About,
+///     ],
+///     item "Quit" => Events::Quit,
+/// ];
+/// ```
+#[macro_export]
+macro_rules! tray_menu {
+    (@items $menu:expr;) => {
+        $menu
+    };
+    (@items $menu:expr; separator $(, $($rest:tt)*)?) => {
+        $crate::tray_menu!(@items $menu.separator(); $($($rest)*)?)
+    };
+    (@items $menu:expr; column_break $(, $($rest:tt)*)?) => {
+        $crate::tray_menu!(@items $menu.column_break(); $($($rest)*)?)
+    };
+    (@items $menu:expr; item $name:expr => $id:expr $(, $($rest:tt)*)?) => {
+        $crate::tray_menu!(@items $menu.item($name, $id); $($($rest)*)?)
+    };
+    (@items $menu:expr; checkable $name:expr, $checked:expr => $id:expr $(, $($rest:tt)*)?) => {
+        $crate::tray_menu!(@items $menu.checkable($name, $checked, $id); $($($rest)*)?)
+    };
+    (@items $menu:expr; submenu $name:expr => [$($children:tt)*] $(, $($rest:tt)*)?) => {
+        $crate::tray_menu!(
+            @items $menu.submenu($name, $crate::tray_menu!($($children)*));
+            $($($rest)*)?
+        )
+    };
+    ($($items:tt)*) => {
+        $crate::tray_menu!(@items $crate::MenuBuilder::new(); $($items)*)
+    };
 }
 
 #[cfg(test)]
@@ -215,6 +1409,40 @@ pub(crate) mod tests {
         SubItem3,
     }
 
+    #[test]
+    fn test_standard_items() {
+        let info = AppInfo {
+            name: "App".to_string(),
+            version: "1.0".to_string(),
+            license: Some("MIT".to_string()),
+        };
+        #[cfg(all(any(target_os = "windows", target_os = "macos"), not(feature = "mock")))]
+        assert_eq!(info.text(), "App\nVersion 1.0\nLicense MIT");
+        let menu = MenuBuilder::new()
+            .item("Item 1", Events::Item1)
+            .standard_about(info, Events::Item2)
+            .standard_quit(Events::Item3);
+        let names = menu
+            .iter()
+            .map(|item| match item {
+                MenuItem::Item { name, .. } | MenuItem::Mapped { name, .. } => name.as_str(),
+                _ => "-",
+            })
+            .collect::<Vec<_>>();
+        let quit = if cfg!(target_os = "windows") {
+            "Exit"
+        } else {
+            "Quit"
+        };
+        assert_eq!(names, vec!["Item 1", "About App", "-", quit]);
+
+        // Separator isn't doubled, and the empty menu doesn't start with one
+        let menu = MenuBuilder::new().standard_quit(Events::Item3);
+        assert_eq!(menu.len(), 1);
+        let menu = MenuBuilder::new().separator().standard_quit(Events::Item3);
+        assert_eq!(menu.len(), 2);
+    }
+
     #[test]
     fn test_menu_mutation() {
         // This is a good way to create menu conditionally on application state, define a function "State -> Menu"
@@ -239,6 +1467,13 @@ pub(crate) mod tests {
                     disabled,
                     id: Events::DisabledItem1,
                     icon: None,
+                    item_id: None,
+                    command_id: None,
+                    is_default: false,
+                    accelerator: None,
+                    description: None,
+                    style: None,
+                    mnemonics: false,
                 })
         };
 
@@ -248,4 +1483,572 @@ pub(crate) mod tests {
         let _ = old.set_checkable(Events::CheckItem2, true);
         assert_eq!(old, menu_builder(true, true));
     }
+
+    #[test]
+    fn test_menu_item_id() {
+        const RECENT: MenuItemId = MenuItemId(1);
+        const SUB: MenuItemId = MenuItemId(2);
+        const MISSING: MenuItemId = MenuItemId(3);
+
+        let mut menu = MenuBuilder::new()
+            .item("Item 1", Events::Item1)
+            .submenu_with_id(
+                SUB,
+                "Sub Menu",
+                MenuBuilder::new().item_with_id(RECENT, "Sub item 1", Events::SubItem1),
+            );
+
+        assert!(matches!(
+            menu.get_by_id(RECENT),
+            Some(MenuItem::Item { name, .. }) if name == "Sub item 1"
+        ));
+        assert!(menu.get_by_id(MISSING).is_none());
+
+        if let Some(MenuItem::Item { name, .. }) = menu.get_by_id_mut(RECENT) {
+            *name = "Renamed".into();
+        }
+        assert!(menu.insert_after_id(RECENT, MenuItem::Separator).is_ok());
+        assert!(menu.insert_after_id(MISSING, MenuItem::Separator).is_err());
+        assert_eq!(
+            menu,
+            MenuBuilder::new()
+                .item("Item 1", Events::Item1)
+                .submenu_with_id(
+                    SUB,
+                    "Sub Menu",
+                    MenuBuilder::new()
+                        .item_with_id(RECENT, "Renamed", Events::SubItem1)
+                        .separator()
+                )
+        );
+
+        assert!(menu.remove_by_id(RECENT).is_some());
+        assert!(menu.remove_by_id(RECENT).is_none());
+        assert!(menu.remove_by_id(SUB).is_some());
+        assert_eq!(menu, MenuBuilder::new().item("Item 1", Events::Item1));
+    }
+
+    #[test]
+    #[cfg(all(
+        any(target_os = "windows", target_os = "linux", target_os = "macos"),
+        not(feature = "mock")
+    ))]
+    fn test_menu_label() {
+        assert_eq!(
+            menu_label("Files & Folders", false, Some('&')),
+            "Files && Folders"
+        );
+        assert_eq!(menu_label("snake_case", false, Some('_')), "snake__case");
+        assert_eq!(
+            menu_label("&Open && Save", true, Some('&')),
+            "&Open && Save"
+        );
+        assert_eq!(
+            menu_label("&Open && Save_", true, Some('_')),
+            "_Open & Save__"
+        );
+        assert_eq!(menu_label("&Open && Save", true, None), "Open & Save");
+
+        let menu = MenuBuilder::new().item_raw("&Open", Events::Item1);
+        assert!(matches!(
+            menu.iter().next(),
+            Some(MenuItem::Item {
+                mnemonics: true,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_menu_set_name() {
+        let mut menu = MenuBuilder::new().item("Item 1", Events::Item1).submenu(
+            "Sub Menu",
+            MenuBuilder::new().item("Sub item 1", Events::SubItem1),
+        );
+        assert!(menu.set_name(Events::SubItem1, "Renamed").is_ok());
+        assert!(menu.set_name(Events::Item2, "Not found").is_err());
+        assert_eq!(
+            menu,
+            MenuBuilder::new().item("Item 1", Events::Item1).submenu(
+                "Sub Menu",
+                MenuBuilder::new().item("Renamed", Events::SubItem1)
+            )
+        );
+    }
+
+    #[test]
+    fn test_menu_mutate_second_submenu() {
+        let submenus = |name: &str| {
+            MenuBuilder::new()
+                .submenu(
+                    "Sub Menu 1",
+                    MenuBuilder::new().item("Sub item 1", Events::SubItem1),
+                )
+                .submenu(
+                    "Sub Menu 2",
+                    MenuBuilder::new().item(name, Events::SubItem2).checkable(
+                        "Check item 1",
+                        false,
+                        Events::CheckItem1,
+                    ),
+                )
+        };
+        let mut menu = submenus("Sub item 2");
+        assert!(menu.set_name(Events::SubItem2, "Renamed").is_ok());
+        assert!(menu.set_checkable(Events::CheckItem1, true).is_ok());
+        assert!(menu.set_disabled(Events::SubItem3, true).is_err());
+        assert_eq!(menu.get_checkable(Events::CheckItem1), Some(true));
+        menu.set_checkable(Events::CheckItem1, false).unwrap();
+        assert_eq!(menu, submenus("Renamed"));
+    }
+
+    #[test]
+    fn test_menu_without_partialeq() {
+        // Events don't need to be comparable, when items are found by the id
+        #[derive(Clone, Debug)]
+        struct Callback(fn());
+
+        const ITEM: MenuItemId = MenuItemId(1);
+        let mut menu = MenuBuilder::new()
+            .item_with_id(ITEM, "Item 1", Callback(|| {}))
+            .separator();
+        match menu.get_by_id(ITEM) {
+            Some(MenuItem::Item { id, .. }) => (id.0)(),
+            _ => panic!("Item not found"),
+        }
+        assert!(menu.remove_by_id(ITEM).is_some());
+        assert_eq!(menu.menu_items.len(), 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_menu_from_json() {
+        #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+        enum Events {
+            Open,
+            Autostart,
+            Quit,
+        }
+
+        let menu = MenuBuilder::from_json(
+            r#"{"items": [
+                {"Item": {"id": "Open", "name": "Open"}},
+                {"Checkable": {"id": "Autostart", "name": "Autostart", "is_checked": true}},
+                "Separator",
+                {"Item": {"id": "Quit", "name": "Quit", "disabled": true}}
+            ]}"#,
+        )
+        .unwrap();
+        let expected = MenuBuilder::new()
+            .item("Open", Events::Open)
+            .checkable("Autostart", true, Events::Autostart)
+            .separator()
+            .item("Quit", Events::Quit)
+            .disabled();
+        assert_eq!(menu, expected);
+
+        let json = serde_json::to_string(&expected).unwrap();
+        assert_eq!(MenuBuilder::from_json(&json).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_tray_menu_macro() {
+        let menu = crate::tray_menu![
+            item "Item 1" => Events::Item1,
+            checkable "Checkable 1", true => Events::CheckItem1,
+            separator,
+            column_break,
+            submenu "Sub Menu" => [
+                item "Sub item 1" => Events::SubItem1,
+                submenu "Sub Sub Menu" => [item "Sub item 2" => Events::SubItem2],
+            ],
+        ];
+        let expected = MenuBuilder::new()
+            .item("Item 1", Events::Item1)
+            .checkable("Checkable 1", true, Events::CheckItem1)
+            .separator()
+            .column_break()
+            .submenu(
+                "Sub Menu",
+                MenuBuilder::new()
+                    .item("Sub item 1", Events::SubItem1)
+                    .submenu(
+                        "Sub Sub Menu",
+                        MenuBuilder::new().item("Sub item 2", Events::SubItem2),
+                    ),
+            );
+        assert_eq!(menu, expected);
+        assert_eq!(crate::tray_menu![], MenuBuilder::<Events>::new());
+    }
+
+    #[test]
+    fn test_menu_push() {
+        #[derive(Clone, Debug, PartialEq)]
+        enum Events {
+            Recent(usize),
+            Quit,
+        }
+
+        let mut menu = MenuBuilder::new();
+        for i in 0..3 {
+            menu.push_item(&format!("Recent {}", i), Events::Recent(i));
+        }
+        menu.push_separator()
+            .apply(|m| m.item("Quit", Events::Quit).disabled());
+        assert_eq!(
+            menu,
+            MenuBuilder::new()
+                .item("Recent 0", Events::Recent(0))
+                .item("Recent 1", Events::Recent(1))
+                .item("Recent 2", Events::Recent(2))
+                .separator()
+                .item("Quit", Events::Quit)
+                .disabled()
+        );
+    }
+
+    #[test]
+    fn test_menu_item_map() {
+        #[derive(Clone, Debug, PartialEq)]
+        enum Events {
+            Open(String),
+        }
+
+        let path = String::from("notes.txt");
+        let menu = MenuBuilder::new()
+            .item_map("Open file", move || Events::Open(path.clone()))
+            .disabled();
+        match menu.iter().next() {
+            Some(MenuItem::Mapped { map, disabled, .. }) => {
+                assert!(*disabled);
+                assert_eq!(map.call(), Events::Open("notes.txt".into()));
+            }
+            _ => panic!("Item not found"),
+        }
+        assert_eq!(menu.clone(), menu);
+        assert_ne!(
+            MenuBuilder::new().item_map("Open", || Events::Open("a".into())),
+            MenuBuilder::new().item_map("Open", || Events::Open("a".into()))
+        );
+    }
+
+    #[test]
+    fn test_menu_index_mutation() {
+        const CHECK: MenuItemId = MenuItemId(1);
+        let mut menu = MenuBuilder::new().item("Item 1", Events::Item1).submenu(
+            "Sub Menu",
+            MenuBuilder::new().checkable_with_id(CHECK, "Check 1", false, Events::CheckItem1),
+        );
+        menu.insert(
+            1,
+            MenuItem::Item {
+                id: Events::Item2,
+                name: "Item 2".into(),
+                disabled: false,
+                icon: None,
+                item_id: None,
+                command_id: None,
+                is_default: false,
+                accelerator: None,
+                description: None,
+                style: None,
+                mnemonics: false,
+            },
+        );
+        assert_eq!(menu.len(), 3);
+        assert!(menu.remove(3).is_none());
+        assert!(menu.set_checked_by_id(CHECK, true).is_ok());
+        assert!(menu.set_disabled_by_id(MenuItemId(2), true).is_err());
+        menu.iter_mut().for_each(|i| {
+            let _ = i.set_disabled(true);
+        });
+        assert!(menu.remove(0).is_some());
+        assert_eq!(
+            menu,
+            MenuBuilder::new()
+                .with(MenuItem::Item {
+                    id: Events::Item2,
+                    name: "Item 2".into(),
+                    disabled: true,
+                    icon: None,
+                    item_id: None,
+                    command_id: None,
+                    is_default: false,
+                    accelerator: None,
+                    description: None,
+                    style: None,
+                    mnemonics: false,
+                })
+                .with(MenuItem::Submenu {
+                    id: None,
+                    name: "Sub Menu".into(),
+                    children: MenuBuilder::new().checkable_with_id(
+                        CHECK,
+                        "Check 1",
+                        true,
+                        Events::CheckItem1
+                    ),
+                    disabled: true,
+                    icon: None,
+                    item_id: None,
+                    command_id: None,
+                    on_open: None,
+                    on_close: None,
+                })
+        );
+    }
+
+    #[test]
+    fn test_menu_disabled() {
+        let menu = MenuBuilder::new()
+            .item("Item 1", Events::Item1)
+            .disabled()
+            .checkable("Check 1", true, Events::CheckItem1)
+            .separator()
+            .disabled();
+        assert!(matches!(
+            menu.menu_items.as_slice(),
+            [
+                MenuItem::Item { disabled: true, .. },
+                MenuItem::Checkable {
+                    disabled: false,
+                    ..
+                },
+                MenuItem::Separator
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_menu_disabled_submenu() {
+        let menu = MenuBuilder::new()
+            .submenu(
+                "Sub",
+                MenuBuilder::new()
+                    .item("Item 1", Events::Item1)
+                    .submenu("Nested", MenuBuilder::new().item("Item 2", Events::Item2)),
+            )
+            .disabled()
+            .on_submenu_open(Events::CheckItem1)
+            .item("Item 3", Events::Item1)
+            .on_submenu_close(Events::Item2);
+        let built = menu.with_disabled_submenus();
+        let sub = match &built.menu_items[0] {
+            MenuItem::Submenu {
+                children,
+                on_open,
+                on_close,
+                ..
+            } => {
+                assert_eq!(on_open, &Some(Events::CheckItem1));
+                assert_eq!(on_close, &None);
+                children
+            }
+            _ => panic!("not a submenu"),
+        };
+        assert!(sub.menu_items.iter().all(|item| matches!(
+            item,
+            MenuItem::Item { disabled: true, .. } | MenuItem::Submenu { disabled: true, .. }
+        )));
+        assert!(matches!(
+            built.menu_items[1],
+            MenuItem::Item {
+                disabled: false,
+                ..
+            }
+        ));
+
+        // Builder itself is unchanged
+        assert_eq!(built.with_disabled_submenus(), built);
+        assert_ne!(built, menu);
+    }
+
+    #[test]
+    fn test_menu_style() {
+        let style = MenuItemStyle::new().bold(true);
+        let mut menu = MenuBuilder::new()
+            .item("Item 1", Events::Item1)
+            .style(style.clone())
+            .separator()
+            .style(style.clone());
+        assert!(matches!(
+            &menu.menu_items[0],
+            MenuItem::Item { style: Some(s), .. } if *s == style
+        ));
+        assert_eq!(
+            menu.menu_items[1].set_style(Some(style)),
+            Err(Error::MenuItemNotFound)
+        );
+    }
+
+    #[test]
+    fn test_menu_description() {
+        let mut menu = MenuBuilder::new()
+            .item("Sync", Events::Item1)
+            .description("Upload the changes now")
+            .submenu("Sub Menu", MenuBuilder::new())
+            .description("Ignored");
+        assert!(matches!(
+            menu.menu_items.as_slice(),
+            [
+                MenuItem::Item {
+                    description: Some(d),
+                    ..
+                },
+                MenuItem::Submenu { .. }
+            ] if d == "Upload the changes now"
+        ));
+        assert!(menu.menu_items[0].set_description(None).is_ok());
+        assert!(menu.menu_items[1].set_description(None).is_err());
+    }
+
+    #[test]
+    fn test_menu_paginated() {
+        let item = |id| MenuItem::Item {
+            id,
+            name: format!("{:?}", id),
+            disabled: false,
+            icon: None,
+            item_id: None,
+            command_id: None,
+            is_default: false,
+            accelerator: None,
+            description: None,
+            style: None,
+            mnemonics: false,
+        };
+        let items = vec![
+            item(Events::Item1),
+            item(Events::Item2),
+            item(Events::Item3),
+            item(Events::Item4),
+            item(Events::SubItem1),
+        ];
+
+        let menu = MenuBuilder::new().separator().paginated(items.clone(), 2);
+        let expected = MenuBuilder::new()
+            .separator()
+            .with(item(Events::Item1))
+            .with(item(Events::Item2))
+            .submenu(
+                "More…",
+                MenuBuilder::new()
+                    .with(item(Events::Item3))
+                    .with(item(Events::Item4))
+                    .submenu("More…", MenuBuilder::new().with(item(Events::SubItem1))),
+            );
+        assert_eq!(menu, expected);
+
+        // Full page doesn't need a submenu
+        let menu = MenuBuilder::new().paginated(items[..4].to_vec(), 4);
+        assert_eq!(menu.len(), 4);
+        assert!(MenuBuilder::<Events>::new().paginated(vec![], 3).is_empty());
+    }
+
+    #[test]
+    fn test_menu_duplicate_item_id() {
+        const RECENT: MenuItemId = MenuItemId(1);
+
+        let menu = MenuBuilder::new()
+            .item_with_id(RECENT, "Item 1", Events::Item1)
+            .submenu(
+                "Sub Menu",
+                MenuBuilder::new().item_with_id(RECENT, "Sub item 1", Events::SubItem1),
+            );
+        assert_eq!(menu.validate(), Err(Error::DuplicateMenuItemId(RECENT)));
+        assert_eq!(
+            MenuBuilder::new()
+                .item_with_id(RECENT, "Item 1", Events::Item1)
+                .validate(),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_menu_command_id() {
+        let menu = MenuBuilder::new()
+            .item("Item 1", Events::Item1)
+            .command_id(MenuCommandId(1))
+            .separator()
+            .command_id(MenuCommandId(1))
+            .submenu("Sub", MenuBuilder::new().item("Item 2", Events::Item2))
+            .command_id(MenuCommandId(2));
+        assert_eq!(menu.validate(), Ok(()));
+        assert_eq!(
+            menu.clone()
+                .item("Item 3", Events::Item1)
+                .command_id(MenuCommandId(2))
+                .validate(),
+            Err(Error::InvalidMenuCommandId(MenuCommandId(2)))
+        );
+        assert_eq!(
+            menu.item("Item 3", Events::Item1)
+                .command_id(MenuCommandId(0))
+                .validate(),
+            Err(Error::InvalidMenuCommandId(MenuCommandId(0)))
+        );
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn test_command_numbering() {
+        let menu = MenuBuilder::new()
+            .item("Item 1", Events::Item1)
+            .submenu(
+                "Sub",
+                MenuBuilder::new()
+                    .item("Item 2", Events::Item2)
+                    .command_id(MenuCommandId(2)),
+            )
+            .item("Item 3", Events::Item1)
+            .item("Item 4", Events::Item1)
+            .command_id(MenuCommandId(100));
+        let mut j = CommandNumbering::new(&menu);
+        let numbers = menu
+            .iter()
+            .flat_map(|item| match item {
+                MenuItem::Submenu { children, .. } => children.iter().collect(),
+                item => vec![item],
+            })
+            .map(|item| j.next(item))
+            .collect::<Vec<_>>();
+        assert_eq!(numbers, vec![1, 2, 3, 100]);
+    }
+
+    #[test]
+    fn test_menu_from_iter() {
+        let recent = ["a.txt", "b.txt"];
+        let item = |name: &str| MenuItem::Item {
+            id: Events::Item1,
+            name: name.to_string(),
+            disabled: false,
+            icon: None,
+            item_id: None,
+            command_id: None,
+            is_default: false,
+            accelerator: None,
+            description: None,
+            style: None,
+            mnemonics: false,
+        };
+
+        let mut menu: MenuBuilder<Events> = recent.iter().map(|name| item(name)).collect();
+        menu.extend(vec![MenuItem::Separator]);
+        assert_eq!(
+            menu,
+            MenuBuilder::new()
+                .item("a.txt", Events::Item1)
+                .item("b.txt", Events::Item1)
+                .separator()
+        );
+        assert_eq!(
+            MenuBuilder::new()
+                .item("Open", Events::Item2)
+                .items(recent.iter().map(|name| item(name))),
+            MenuBuilder::new()
+                .item("Open", Events::Item2)
+                .item("a.txt", Events::Item1)
+                .item("b.txt", Events::Item1)
+        );
+    }
 }
@@ -0,0 +1,168 @@
+use crate::Error;
+use std::path::{Path, PathBuf};
+
+/// Most recently used entries, e.g. the files opened, for a section of the menu
+///
+/// Entries are kept the latest first, without duplicates, and at most `max`
+/// of them. With `persisted` the list is saved to the file on each change,
+/// one entry per line, and loaded from it when created. Add the entries to
+/// the menu with `MenuBuilder::recent_items`.
+///
+/// ```ignore
+/// let mut recent = RecentItems::persisted(config_dir.join("recent.txt"), 10);
+/// recent.add("C:\\Notes\\todo.txt")?;
+/// let menu = MenuBuilder::new()
+///     .recent_items(&recent, |index, _path| Events::OpenRecent(index))
+///     .separator()
+///     .item("Quit", Events::Quit);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecentItems {
+    entries: Vec<String>,
+    max: usize,
+    path: Option<PathBuf>,
+}
+
+impl RecentItems {
+    /// Empty list, kept only in memory
+    pub fn new(max: usize) -> RecentItems {
+        RecentItems {
+            entries: vec![],
+            max,
+            path: None,
+        }
+    }
+
+    /// List loaded from the file, and saved to it on each change
+    ///
+    /// Missing or unreadable file gives an empty list, the file is created
+    /// with its directory when an entry is added.
+    pub fn persisted<P>(path: P, max: usize) -> RecentItems
+    where
+        P: Into<PathBuf>,
+    {
+        let path = path.into();
+        let mut entries = std::fs::read_to_string(&path)
+            .map(|text| {
+                text.lines()
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        entries.truncate(max);
+        RecentItems {
+            entries,
+            max,
+            path: Some(path),
+        }
+    }
+
+    /// Add the entry first, moving it if it's already in the list
+    ///
+    /// The oldest entry is dropped when the list is full. Entries can't
+    /// contain line breaks, those are ignored.
+    pub fn add(&mut self, entry: &str) -> Result<(), Error> {
+        if entry.is_empty() || entry.contains(&['\n', '\r'][..]) {
+            return Ok(());
+        }
+        self.entries.retain(|e| e != entry);
+        self.entries.insert(0, entry.to_string());
+        self.entries.truncate(self.max);
+        self.save()
+    }
+
+    /// Remove the entry, e.g. when the file doesn't exist anymore
+    pub fn remove(&mut self, entry: &str) -> Result<(), Error> {
+        self.entries.retain(|e| e != entry);
+        self.save()
+    }
+
+    pub fn clear(&mut self) -> Result<(), Error> {
+        self.entries.clear();
+        self.save()
+    }
+
+    /// Entry of the menu item with the index, as given to the event
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(String::as_str)
+    }
+
+    /// Entries, the latest first
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// File the list is saved to, if persisted
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| io_error("create_dir_all", err))?;
+        }
+        let text = self
+            .entries
+            .iter()
+            .map(|entry| format!("{}\n", entry))
+            .collect::<String>();
+        std::fs::write(path, text).map_err(|err| io_error("write", err))
+    }
+}
+
+fn io_error(operation: &'static str, err: std::io::Error) -> Error {
+    match err.raw_os_error() {
+        Some(code) => Error::os_error_code(operation, code),
+        None => Error::os_error(operation),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MenuBuilder, MenuItem};
+
+    #[test]
+    fn test_recent_items() {
+        let mut recent = RecentItems::new(3);
+        for entry in &["a", "b", "c", "a", "d", "", "e\nf"] {
+            recent.add(entry).unwrap();
+        }
+        assert_eq!(recent.entries(), &["d", "a", "c"]);
+        recent.remove("a").unwrap();
+        assert_eq!(recent.get(1), Some("c"));
+        assert_eq!(recent.get(2), None);
+
+        let menu = MenuBuilder::new().recent_items(&recent, |index, entry| (index, entry.len()));
+        let items = menu
+            .iter()
+            .map(|item| match item {
+                MenuItem::Item { id, name, .. } => (*id, name.as_str()),
+                _ => panic!("not an item"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(items, vec![((0, 1), "d"), ((1, 1), "c")]);
+    }
+
+    #[test]
+    fn test_recent_items_persisted() {
+        let dir = std::env::temp_dir().join(format!("trayicon-recent-{}", std::process::id()));
+        let path = dir.join("recent.txt");
+        let mut recent = RecentItems::persisted(&path, 2);
+        assert!(recent.entries().is_empty());
+        recent.add("first").unwrap();
+        recent.add("second").unwrap();
+        assert_eq!(RecentItems::persisted(&path, 2), recent);
+
+        // Smaller maximum drops the oldest
+        assert_eq!(RecentItems::persisted(&path, 1).entries(), &["second"]);
+        recent.clear().unwrap();
+        assert!(RecentItems::persisted(&path, 2).entries().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
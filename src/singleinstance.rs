@@ -0,0 +1,78 @@
+use crate::Error;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// Named lock for running only one instance of the app
+///
+/// The first instance holds the lock until it's dropped, and receives the
+/// arguments of the later instances with `TrayIconBuilder::on_second_instance`.
+///
+/// ```ignore
+/// let instance = SingleInstance::new("com.example.app")?;
+/// if !instance.is_primary() {
+///     let args = std::env::args().skip(1).collect::<Vec<_>>();
+///     return instance.forward(&args);
+/// }
+/// let tray_icon = TrayIconBuilder::new()
+///     .on_second_instance(&instance, Events::SecondInstance)
+///     ...
+/// ```
+#[derive(Debug)]
+pub struct SingleInstance {
+    name: String,
+    sys: crate::SingleInstanceSys,
+}
+
+impl SingleInstance {
+    /// Take the lock, unless another instance holds it
+    ///
+    /// The name is shared by the instances of the current session, e.g. a
+    /// reverse domain name of the app. It must not contain backslashes.
+    pub fn new(name: &str) -> Result<SingleInstance, Error> {
+        Ok(SingleInstance {
+            name: name.to_string(),
+            sys: crate::SingleInstanceSys::new(name)?,
+        })
+    }
+
+    /// This is the first instance holding the lock
+    pub fn is_primary(&self) -> bool {
+        self.sys.is_primary()
+    }
+
+    /// Send the arguments to the first instance, e.g. before exiting
+    ///
+    /// Waits a while for the tray icon of the first instance, if it's still
+    /// starting.
+    pub fn forward(&self, args: &[String]) -> Result<(), Error> {
+        crate::SingleInstanceSys::forward(&self.name, args)
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Function building the event from the arguments of the second instance
+#[derive(Clone)]
+pub(crate) struct SecondInstanceFn<T>(pub(crate) Arc<dyn Fn(Vec<String>) -> T + Send + Sync>)
+where
+    T: Clone + 'static;
+
+impl<T> SecondInstanceFn<T>
+where
+    T: Clone + 'static,
+{
+    pub(crate) fn call(&self, args: Vec<String>) -> T {
+        (self.0)(args)
+    }
+}
+
+impl<T> Debug for SecondInstanceFn<T>
+where
+    T: Clone + 'static,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SecondInstanceFn")
+    }
+}
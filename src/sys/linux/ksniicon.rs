@@ -0,0 +1,117 @@
+use crate::{Error, FontOptions, IconBase};
+use std::io::Cursor;
+
+/// Purpose of this struct is to keep the decoded ARGB32 pixmaps, these are
+/// sent over D-Bus as is
+#[derive(Debug, Clone)]
+pub struct KsniIcon {
+    pub pixmaps: Vec<ksni::Icon>,
+}
+
+impl IconBase for KsniIcon {
+    fn from_buffer(
+        buffer: &[u8],
+        width: Option<u32>,
+        height: Option<u32>,
+    ) -> Result<KsniIcon, Error> {
+        let icon_dir =
+            ico::IconDir::read(Cursor::new(buffer)).map_err(|_| Error::IconLoadingFailed)?;
+
+        // Without the size all of the frames are given, the host picks the
+        // one that fits the panel best
+        let pixmaps = icon_dir
+            .entries()
+            .iter()
+            .filter(|e| width.is_none_or(|w| e.width() == w))
+            .filter(|e| height.is_none_or(|h| e.height() == h))
+            .filter_map(|e| e.decode().ok())
+            .map(|image| ksni::Icon {
+                width: image.width() as i32,
+                height: image.height() as i32,
+                data: rgba_to_argb(image.rgba_data()),
+            })
+            .collect::<Vec<_>>();
+
+        if pixmaps.is_empty() {
+            return Err(Error::IconLoadingFailed);
+        }
+        Ok(KsniIcon { pixmaps })
+    }
+
+    fn from_rgba(rgba: &[u8], width: u32, height: u32) -> Result<KsniIcon, Error> {
+        Ok(KsniIcon {
+            pixmaps: vec![ksni::Icon {
+                width: width as i32,
+                height: height as i32,
+                data: rgba_to_argb(rgba),
+            }],
+        })
+    }
+
+    /// There are no fonts without a text rendering library
+    fn from_text(_text: &str, _options: &FontOptions) -> Result<KsniIcon, Error> {
+        Err(Error::Unsupported)
+    }
+
+    /// Pixmap closest to the width, scaled to the size by the nearest pixel
+    fn to_rgba(&self, width: u32, height: u32) -> Option<Vec<u8>> {
+        let pixmap = self
+            .pixmaps
+            .iter()
+            .filter(|p| p.width > 0 && p.height > 0)
+            .min_by_key(|p| (p.width - width as i32).abs())?;
+        let rgba = argb_to_rgba(&pixmap.data);
+        let (src_width, src_height) = (pixmap.width as u32, pixmap.height as u32);
+        let mut scaled = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let i =
+                    (((y * src_height / height) * src_width + x * src_width / width) * 4) as usize;
+                scaled.extend_from_slice(rgba.get(i..i + 4)?);
+            }
+        }
+        Some(scaled)
+    }
+}
+
+impl KsniIcon {
+    /// Smallest pixmap encoded as PNG, e.g. for the menu items
+    pub fn to_png(&self) -> Option<Vec<u8>> {
+        let pixmap = self.pixmaps.iter().min_by_key(|p| p.width * p.height)?;
+        let image = ico::IconImage::from_rgba_data(
+            pixmap.width as u32,
+            pixmap.height as u32,
+            argb_to_rgba(&pixmap.data),
+        );
+        let mut png = Vec::new();
+        image.write_png(&mut png).ok()?;
+        Some(png)
+    }
+}
+
+/// StatusNotifierItem pixmaps are ARGB32 in network byte order
+fn rgba_to_argb(rgba: &[u8]) -> Vec<u8> {
+    rgba.chunks_exact(4)
+        .flat_map(|p| [p[3], p[0], p[1], p[2]])
+        .collect()
+}
+
+fn argb_to_rgba(argb: &[u8]) -> Vec<u8> {
+    argb.chunks_exact(4)
+        .flat_map(|p| [p[1], p[2], p[3], p[0]])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rgba_to_argb() {
+        assert_eq!(
+            rgba_to_argb(&[1, 2, 3, 4, 5, 6, 7, 8]),
+            vec![4, 1, 2, 3, 8, 5, 6, 7]
+        );
+        assert_eq!(argb_to_rgba(&rgba_to_argb(&[1, 2, 3, 4])), vec![1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,470 @@
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{IconSys, MenuSys};
+use crate::{
+    badge::{self, Decoration},
+    menubuilder::{MenuFn, MenuUpdater, Toggled},
+    trayicon::TooltipFn,
+    trayiconsender::TrayIconSender,
+    Error, Icon, MenuBuilder, Modifiers, MouseButton, PhysicalPosition, TrayIconBase,
+    TrayIconEvent,
+};
+
+/// Update of the tray made in a thread, returns true when the thread is stopped
+type Update<T> = Box<dyn FnOnce(&mut KsniTray<T>) -> bool + Send>;
+
+/// Makes the next update of the tray, called in the thread before each update
+type Updates<T> = Box<dyn FnMut() -> Update<T> + Send>;
+
+/// Tray Icon over StatusNotifierItem D-Bus service
+///
+/// The service runs in its own thread, all changes are sent to it through the
+/// handle.
+pub struct KsniTrayIcon<T>
+where
+    T: Clone + 'static,
+{
+    handle: ksni::Handle<KsniTray<T>>,
+
+    /// Runs the updates in a thread until stopped, made in `new` where the
+    /// handle is known to be `Send`
+    spawn_updates: Box<dyn Fn(Updates<T>, Duration) + Send + Sync>,
+
+    /// Pixmaps of the icon, shown when the animation is not running
+    pixmaps: Vec<ksni::Icon>,
+
+    /// Badge and progress drawn on each of the pixmaps
+    decoration: Decoration,
+
+    /// Stops the running animation thread
+    animation: Option<Arc<AtomicBool>>,
+
+    /// Stops the thread refreshing the tooltip
+    tooltip_refresh: Option<Arc<AtomicBool>>,
+}
+
+impl<T> KsniTrayIcon<T>
+where
+    T: Clone + 'static,
+{
+    pub(crate) fn new(tray: KsniTray<T>) -> KsniTrayIcon<T>
+    where
+        T: Send,
+    {
+        let pixmaps = tray.pixmaps.clone();
+        let service = ksni::TrayService::new(tray);
+        let handle = service.handle();
+        service.spawn();
+        let updates_handle = handle.clone();
+        let spawn_updates = move |mut next: Updates<T>, interval: Duration| {
+            let handle = updates_handle.clone();
+            std::thread::spawn(move || {
+                while !handle.update(next()) {
+                    std::thread::sleep(interval);
+                }
+            });
+        };
+        KsniTrayIcon {
+            handle,
+            spawn_updates: Box::new(spawn_updates),
+            pixmaps,
+            decoration: Decoration::default(),
+            animation: None,
+            tooltip_refresh: None,
+        }
+    }
+
+    /// Pixmaps of the icon with the decoration drawn on them
+    fn shown_pixmaps(&self) -> Vec<ksni::Icon> {
+        self.pixmaps
+            .iter()
+            .map(|pixmap| {
+                let icon = IconSys {
+                    pixmaps: vec![pixmap.clone()],
+                };
+                let (width, height) = (pixmap.width as u32, pixmap.height as u32);
+                badge::decorate(&icon, width, height, &self.decoration)
+                    .and_then(|decorated| decorated.pixmaps.into_iter().next())
+                    .unwrap_or_else(|| pixmap.clone())
+            })
+            .collect()
+    }
+
+    /// Signal the animation thread to stop, it doesn't update after this
+    fn stop_animation_thread(&mut self) -> bool {
+        match self.animation.take() {
+            Some(stop) => {
+                // Stored inside the update, so the thread sees it before its
+                // next update
+                self.handle
+                    .update(move |_| stop.store(true, Ordering::SeqCst));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Signal the tooltip thread to stop, same as the animation thread
+    fn stop_tooltip_thread(&mut self) {
+        if let Some(stop) = self.tooltip_refresh.take() {
+            self.handle
+                .update(move |_| stop.store(true, Ordering::SeqCst));
+        }
+    }
+
+    /// Mutate the retained menu builder
+    fn update_menu<F>(&self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut MenuBuilder<T>) -> Result<(), Error>,
+    {
+        self.handle.update(move |tray| match tray.menu.as_mut() {
+            Some(menu) => f(&mut menu.builder),
+            None => Err(Error::MenuItemNotFound),
+        })
+    }
+}
+
+impl<T> Debug for KsniTrayIcon<T>
+where
+    T: Clone + 'static,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TrayIcon")
+    }
+}
+
+impl<T> TrayIconBase<T> for KsniTrayIcon<T>
+where
+    T: Clone + 'static,
+{
+    /// Set the tooltip
+    fn set_tooltip(&mut self, tooltip: &str) -> Result<(), Error> {
+        let tooltip = tooltip.to_string();
+        self.handle.update(move |tray| tray.tooltip = tooltip);
+        Ok(())
+    }
+
+    /// Refresh the tooltip in a thread, updating the service through the
+    /// handle
+    fn set_tooltip_fn(
+        &mut self,
+        tooltip_fn: Option<TooltipFn>,
+        interval: Duration,
+    ) -> Result<(), Error> {
+        self.stop_tooltip_thread();
+        let tooltip_fn = match tooltip_fn {
+            Some(tooltip_fn) => tooltip_fn,
+            None => return Ok(()),
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        (self.spawn_updates)(
+            Box::new(move || -> Update<T> {
+                let tooltip = tooltip_fn.tooltip();
+                let stop = thread_stop.clone();
+                Box::new(move |tray: &mut KsniTray<T>| {
+                    let stopped = stop.load(Ordering::SeqCst);
+                    if !stopped {
+                        tray.tooltip = tooltip;
+                    }
+                    stopped
+                })
+            }),
+            interval,
+        );
+        self.tooltip_refresh = Some(stop);
+        Ok(())
+    }
+
+    /// Set checkable, host is notified of the layout change
+    fn set_menu_item_checkable(&mut self, id: &T, checked: bool) -> Result<(), Error>
+    where
+        T: PartialEq,
+    {
+        let id = id.clone();
+        self.update_menu(move |menu| menu.set_checkable(id, checked))
+    }
+
+    /// Set disabled, host is notified of the layout change
+    fn set_menu_item_disabled(&mut self, id: &T, disabled: bool) -> Result<(), Error>
+    where
+        T: PartialEq,
+    {
+        let id = id.clone();
+        self.update_menu(move |menu| menu.set_disabled(id, disabled))
+    }
+
+    /// Set name, host is notified of the layout change
+    fn set_menu_item_name(&mut self, id: &T, name: &str) -> Result<(), Error>
+    where
+        T: PartialEq,
+    {
+        let id = id.clone();
+        let name = name.to_string();
+        self.update_menu(move |menu| menu.set_name(id, &name))
+    }
+
+    /// Set icon
+    fn set_icon(&mut self, icon: &Icon) -> Result<(), Error> {
+        self.pixmaps = icon.sys.pixmaps.clone();
+        if self.animation.is_none() {
+            let pixmaps = self.shown_pixmaps();
+            self.handle.update(move |tray| tray.pixmaps = pixmaps);
+        }
+        Ok(())
+    }
+
+    /// Decoration is shown after the animation, the frames are not decorated
+    fn set_decoration(&mut self, decoration: Decoration) -> Result<(), Error> {
+        self.decoration = decoration;
+        if self.animation.is_none() {
+            let pixmaps = self.shown_pixmaps();
+            self.handle.update(move |tray| tray.pixmaps = pixmaps);
+        }
+        Ok(())
+    }
+
+    /// Only the left click is reported by the host
+    fn set_click_events(
+        &mut self,
+        on_click: Option<T>,
+        _on_double_click: Option<T>,
+        _on_right_click: Option<T>,
+    ) -> Result<(), Error> {
+        self.handle.update(move |tray| tray.on_click = on_click);
+        Ok(())
+    }
+
+    /// Passive status, StatusNotifierItem can't be removed without dropping
+    fn set_visible(&mut self, visible: bool) -> Result<(), Error> {
+        self.handle.update(move |tray| tray.visible = visible);
+        Ok(())
+    }
+
+    /// Cycle the frames in a thread, updating the service through the handle
+    fn set_animation(&mut self, frames: Vec<Icon>, interval: Duration) -> Result<(), Error> {
+        self.stop_animation_thread();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let frames = frames
+            .into_iter()
+            .map(|f| f.sys.pixmaps)
+            .collect::<Vec<_>>();
+        if !frames.is_empty() {
+            let mut frame = 0;
+            (self.spawn_updates)(
+                Box::new(move || -> Update<T> {
+                    let pixmaps = frames[frame % frames.len()].clone();
+                    frame += 1;
+                    let stop = thread_stop.clone();
+                    Box::new(move |tray: &mut KsniTray<T>| {
+                        let stopped = stop.load(Ordering::SeqCst);
+                        if !stopped {
+                            tray.pixmaps = pixmaps;
+                        }
+                        stopped
+                    })
+                }),
+                interval,
+            );
+        }
+        self.animation = Some(stop);
+        Ok(())
+    }
+
+    /// Stop the thread and restore the icon
+    fn stop_animation(&mut self) -> Result<(), Error> {
+        if self.stop_animation_thread() {
+            let pixmaps = self.shown_pixmaps();
+            self.handle.update(move |tray| tray.pixmaps = pixmaps);
+        }
+        Ok(())
+    }
+
+    /// Notifications are a separate freedesktop service, not a part of
+    /// StatusNotifierItem
+    fn show_notification(
+        &mut self,
+        _title: &str,
+        _body: &str,
+        _icon: Option<&Icon>,
+        _timeout: Option<Duration>,
+    ) -> Result<(), Error> {
+        Err(Error::Unsupported)
+    }
+
+    /// Host shows the menu, it can't be opened by the item
+    fn show_menu(&mut self, _position: Option<PhysicalPosition>) -> Result<(), Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn show_menu_sync(&mut self, _position: Option<PhysicalPosition>) -> Result<Option<T>, Error> {
+        Err(Error::Unsupported)
+    }
+
+    /// StatusNotifierItem has no geometry
+    fn rect(&self) -> Result<(i32, i32, u32, u32), Error> {
+        Err(Error::Unsupported)
+    }
+
+    /// Set menu
+    fn set_menu(&mut self, menu: &MenuBuilder<T>) -> Result<(), Error> {
+        let menu = if menu.menu_items.is_empty() {
+            None
+        } else {
+            Some(menu.build()?)
+        };
+        self.handle.update(move |tray| {
+            tray.menu = menu;
+            tray.lazy_menu.borrow_mut().take();
+        });
+        Ok(())
+    }
+}
+
+impl<T> Drop for KsniTrayIcon<T>
+where
+    T: Clone + 'static,
+{
+    fn drop(&mut self) {
+        self.stop_animation_thread();
+        self.stop_tooltip_thread();
+        self.handle.shutdown();
+    }
+}
+
+/// State of the StatusNotifierItem, owned by the service thread
+pub struct KsniTray<T>
+where
+    T: Clone + 'static,
+{
+    pub(crate) sender: TrayIconSender<T>,
+    pub(crate) menu: Option<MenuSys<T>>,
+    pub(crate) menu_with: Option<MenuFn<T>>,
+    pub(crate) menu_updater: Option<MenuUpdater<T>>,
+
+    /// Menu last built on request, with `menu_with` or from the updater
+    pub(crate) lazy_menu: RefCell<Option<MenuSys<T>>>,
+
+    /// Checkables are toggled when activated, and recorded here
+    pub(crate) toggled: Option<Toggled<T>>,
+    pub(crate) pixmaps: Vec<ksni::Icon>,
+    pub(crate) tooltip: String,
+    pub(crate) on_click: Option<T>,
+    pub(crate) on_middle_click: Option<T>,
+    pub(crate) visible: bool,
+}
+
+impl<T> KsniTray<T>
+where
+    T: Clone + 'static,
+{
+    /// Send the event of the menu item, toggle the checkable if enabled
+    ///
+    /// Host requests the menu again after the item is activated.
+    fn send_menu_event(&mut self, j: usize) {
+        let mut lazy_menu = self.lazy_menu.borrow_mut();
+        let menu = match lazy_menu.as_mut().or(self.menu.as_mut()) {
+            Some(menu) => menu,
+            None => return,
+        };
+        if let Some(map) = menu.mapped.get(&j) {
+            self.sender.send_menu_item(&map.call());
+            return;
+        }
+        let event = match menu.ids.get(&j) {
+            Some(event) => event.clone(),
+            None => return,
+        };
+        match self
+            .toggled
+            .as_ref()
+            .and_then(|t| Some((t, menu.toggle(j)?)))
+        {
+            Some((toggled, checked)) => {
+                if let Ok(mut toggled) = toggled.lock() {
+                    toggled.push((event.clone(), checked));
+                }
+                self.sender.send_menu_item_toggled(&event, checked);
+            }
+            None => self.sender.send_menu_item(&event),
+        }
+    }
+}
+
+impl<T> ksni::Tray for KsniTray<T>
+where
+    T: Clone + 'static,
+{
+    fn id(&self) -> String {
+        std::env::current_exe()
+            .ok()
+            .and_then(|p| p.file_stem().map(|s| s.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "trayicon".into())
+    }
+
+    fn title(&self) -> String {
+        self.tooltip.clone()
+    }
+
+    fn status(&self) -> ksni::Status {
+        if self.visible {
+            ksni::Status::Active
+        } else {
+            ksni::Status::Passive
+        }
+    }
+
+    fn icon_pixmap(&self) -> Vec<ksni::Icon> {
+        self.pixmaps.clone()
+    }
+
+    fn tool_tip(&self) -> ksni::ToolTip {
+        ksni::ToolTip {
+            title: self.tooltip.clone(),
+            ..Default::default()
+        }
+    }
+
+    // Left click tray icon
+    fn activate(&mut self, x: i32, y: i32) {
+        self.sender.send(
+            self.on_click.as_ref(),
+            TrayIconEvent::Click {
+                button: MouseButton::Left,
+                position: PhysicalPosition { x, y },
+                modifiers: Modifiers::default(),
+            },
+        );
+    }
+
+    // Middle click tray icon
+    fn secondary_activate(&mut self, x: i32, y: i32) {
+        self.sender.send(
+            self.on_middle_click.as_ref(),
+            TrayIconEvent::Click {
+                button: MouseButton::Middle,
+                position: PhysicalPosition { x, y },
+                modifiers: Modifiers::default(),
+            },
+        );
+    }
+
+    fn menu(&self) -> Vec<ksni::MenuItem<Self>> {
+        let builder = match &self.menu_with {
+            Some(menu_with) => Some(menu_with.build()),
+            None => self.menu_updater.as_ref().and_then(|u| u.take()),
+        };
+        if let Some(builder) = builder {
+            *self.lazy_menu.borrow_mut() = builder.build().ok();
+        }
+        match self.lazy_menu.borrow().as_ref().or(self.menu.as_ref()) {
+            Some(menu) => menu.items(KsniTray::send_menu_event),
+            None => vec![],
+        }
+    }
+}
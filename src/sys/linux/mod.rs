@@ -0,0 +1,439 @@
+mod ksniicon;
+mod ksnitrayicon;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Condvar, Mutex};
+
+use crate::{
+    accelerator::{Accelerator, Key},
+    menubuilder::menu_label,
+    trayiconhandle::{Attached, Handler, Waker},
+    AppInfo, Capabilities, Error, EventFn, Icon, MenuBuilder, MenuItem, SystemTheme,
+    TrayIconBuilder,
+};
+use ksnitrayicon::KsniTray;
+
+// Linux implementations of Icon, TrayIcon, and Menu
+pub use ksniicon::KsniIcon as IconSys;
+pub use ksnitrayicon::KsniTrayIcon as TrayIconSys;
+
+/// DBusMenu is requested by the host each time it's shown, so the builder is
+/// retained and converted to ksni menu items on request
+#[derive(Debug)]
+pub struct MenuSys<T>
+where
+    T: Clone + 'static,
+{
+    ids: HashMap<usize, T>,
+    builder: MenuBuilder<T>,
+
+    /// Events of the items built when clicked
+    mapped: HashMap<usize, EventFn<T>>,
+}
+
+impl<T> MenuSys<T>
+where
+    T: Clone + 'static,
+{
+    fn items(&self, activate: fn(&mut KsniTray<T>, usize)) -> Vec<ksni::MenuItem<KsniTray<T>>> {
+        let mut j = 0;
+        let mut ids = HashMap::new();
+        let mut mapped = HashMap::new();
+        build_menu_inner(&mut j, &mut ids, &mut mapped, &self.builder, activate)
+    }
+
+    /// Toggle the checkable with the number, returns the new checked state
+    fn toggle(&mut self, k: usize) -> Option<bool> {
+        let mut j = 0;
+        toggle_inner(&mut j, k, &mut self.builder)
+    }
+}
+
+/// Recursive toggle, numbered the same as in `build_menu_inner`
+fn toggle_inner<T>(j: &mut usize, k: usize, builder: &mut MenuBuilder<T>) -> Option<bool>
+where
+    T: Clone + 'static,
+{
+    for item in builder.menu_items.iter_mut() {
+        match item {
+            MenuItem::Submenu { id, children, .. } => {
+                if id.is_some() {
+                    *j += 1;
+                }
+                if let Some(checked) = toggle_inner(j, k, children) {
+                    return Some(checked);
+                }
+            }
+            MenuItem::Checkable { is_checked, .. } => {
+                *j += 1;
+                if *j == k {
+                    *is_checked = !*is_checked;
+                    return Some(*is_checked);
+                }
+            }
+            MenuItem::Item { .. } | MenuItem::Mapped { .. } => {
+                *j += 1;
+            }
+            MenuItem::Separator | MenuItem::ColumnBreak => {}
+        }
+    }
+    None
+}
+
+/// Build the tray icon
+///
+/// StatusNotifierItem hosts show the menu on right click, and don't report
+/// double clicks, so `on_right_click` and `on_double_click` are not sent.
+pub fn build_trayicon<T>(builder: &TrayIconBuilder<T>) -> Result<TrayIconSys<T>, Error>
+where
+    T: Clone + Send + 'static,
+{
+    let mut menu: Option<MenuSys<T>> = None;
+    let pixmaps = builder.themed_icon()?.sys.pixmaps.clone();
+    let sender = builder.sender.clone().ok_or(Error::SenderMissing)?;
+
+    if let Some(rmenu) = &builder.menu {
+        menu = Some(rmenu.build()?);
+    }
+
+    Ok(TrayIconSys::new(KsniTray {
+        sender,
+        menu,
+        menu_with: builder.menu_with.clone(),
+        menu_updater: builder.menu_updater.clone(),
+        toggled: builder.toggled_checkables(),
+        lazy_menu: RefCell::new(None),
+        pixmaps,
+        tooltip: builder.tooltip.clone().unwrap_or_default(),
+        on_click: builder.on_click.clone(),
+        on_middle_click: builder.on_middle_click.clone(),
+        visible: true,
+    }))
+}
+
+/// Services are shut down when dropped, callable from any thread
+pub fn shutdown_trayicon<T>(trayicon: TrayIconSys<T>) -> Result<(), Error>
+where
+    T: Clone + 'static,
+{
+    drop(trayicon);
+    Ok(())
+}
+
+/// StatusNotifierItem features, the host is not checked
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        tray: true,
+        notify_icon_version: None,
+        wine: false,
+        reactos: false,
+        notifications: false,
+        rich_popup: false,
+        rect: false,
+        double_click: false,
+        hover: false,
+        show_menu: false,
+    }
+}
+
+/// Theme of the StatusNotifierItem host is not known
+pub fn system_theme() -> Option<SystemTheme> {
+    None
+}
+
+/// Message boxes need a toolkit, which StatusNotifierItem doesn't have
+pub fn show_about(_app_info: &AppInfo) {}
+
+/// Quit was requested, cleared when the last running event loop quits
+struct EventLoops {
+    quit: bool,
+    running: usize,
+}
+
+static EVENT_LOOPS: Mutex<EventLoops> = Mutex::new(EventLoops {
+    quit: false,
+    running: 0,
+});
+static QUIT_CONDVAR: Condvar = Condvar::new();
+
+/// Wait until quit, the services run in their own threads
+pub fn run_event_loop() {
+    let mut loops = EVENT_LOOPS.lock().unwrap();
+    loops.running += 1;
+    while !loops.quit {
+        loops = QUIT_CONDVAR.wait(loops).unwrap();
+    }
+    loops.running -= 1;
+    if loops.running == 0 {
+        loops.quit = false;
+    }
+}
+
+/// Wake the threads running the event loops, a loop started after this quits
+/// right away
+pub fn quit_event_loop() {
+    EVENT_LOOPS.lock().unwrap().quit = true;
+    QUIT_CONDVAR.notify_all();
+}
+
+/// Build on a dedicated thread, which runs the handler when woken
+///
+/// Handler returns false to quit the thread.
+pub fn spawn_detached<F>(build: F) -> Result<Attached, Error>
+where
+    F: FnOnce() -> Result<Handler, Error> + Send + 'static,
+{
+    let (result, receiver) = channel();
+    let (wake, woken) = channel::<()>();
+    let thread = std::thread::spawn(move || match build() {
+        Ok(handler) => {
+            let _ = result.send(Ok(()));
+            run_handler(handler, woken);
+        }
+        Err(err) => {
+            let _ = result.send(Err(err));
+        }
+    });
+    receiver.recv().map_err(|_| Error::TrayIconStopped)??;
+    let waker: Waker = Box::new(move || {
+        let _ = wake.send(());
+    });
+    Ok((waker, thread.thread().id()))
+}
+
+/// Run the handler on a thread when woken
+///
+/// The services run in their own threads, so there is no event loop to run
+/// the handler in.
+pub fn attach_handler(handler: Handler) -> Result<Attached, Error> {
+    let (wake, woken) = channel::<()>();
+    let thread = std::thread::spawn(move || run_handler(handler, woken));
+    let waker: Waker = Box::new(move || {
+        let _ = wake.send(());
+    });
+    Ok((waker, thread.thread().id()))
+}
+
+fn run_handler(mut handler: Handler, woken: Receiver<()>) {
+    for () in woken {
+        if !handler() {
+            break;
+        }
+    }
+}
+
+/// Build the menu for DBusMenu
+pub fn build_menu<T>(builder: &MenuBuilder<T>) -> Result<MenuSys<T>, Error>
+where
+    T: Clone + 'static,
+{
+    let mut j = 0;
+    let mut ids = HashMap::new();
+    let mut mapped = HashMap::new();
+    build_menu_inner(&mut j, &mut ids, &mut mapped, builder, |_, _| {});
+    Ok(MenuSys {
+        ids,
+        builder: builder.clone(),
+        mapped,
+    })
+}
+
+/// Recursive menu builder
+///
+/// Numbering is the same as in the Windows implementation, the ksni items only
+/// capture the number and the event is looked up from the ids when activated.
+fn build_menu_inner<T>(
+    j: &mut usize,
+    ids: &mut HashMap<usize, T>,
+    mapped: &mut HashMap<usize, EventFn<T>>,
+    builder: &MenuBuilder<T>,
+    activate: fn(&mut KsniTray<T>, usize),
+) -> Vec<ksni::MenuItem<KsniTray<T>>>
+where
+    T: Clone + 'static,
+{
+    builder
+        .menu_items
+        .iter()
+        .map(|item| match item {
+            MenuItem::Submenu {
+                id,
+                name,
+                children,
+                disabled,
+                icon,
+                ..
+            } => {
+                if let Some(id) = id {
+                    *j += 1;
+                    ids.insert(*j, id.clone());
+                }
+                ksni::menu::SubMenu {
+                    label: menu_label(name, false, Some('_')),
+                    enabled: !*disabled,
+                    icon_data: icon_data(icon),
+                    submenu: build_menu_inner(j, ids, mapped, children, activate),
+                    ..Default::default()
+                }
+                .into()
+            }
+
+            MenuItem::Checkable {
+                name,
+                is_checked,
+                id,
+                disabled,
+                icon,
+                ..
+            } => {
+                *j += 1;
+                ids.insert(*j, id.clone());
+                let k = *j;
+                ksni::menu::CheckmarkItem {
+                    label: menu_label(name, false, Some('_')),
+                    enabled: !*disabled,
+                    icon_data: icon_data(icon),
+                    checked: *is_checked,
+                    activate: Box::new(move |tray| activate(tray, k)),
+                    ..Default::default()
+                }
+                .into()
+            }
+
+            MenuItem::Item {
+                name,
+                id,
+                disabled,
+                icon,
+                accelerator,
+                mnemonics,
+                ..
+            } => {
+                *j += 1;
+                ids.insert(*j, id.clone());
+                let k = *j;
+                ksni::menu::StandardItem {
+                    label: menu_label(name, *mnemonics, Some('_')),
+                    enabled: !*disabled,
+                    icon_data: icon_data(icon),
+                    shortcut: shortcut(accelerator),
+                    activate: Box::new(move |tray| activate(tray, k)),
+                    ..Default::default()
+                }
+                .into()
+            }
+
+            MenuItem::Mapped {
+                map,
+                name,
+                disabled,
+                icon,
+                ..
+            } => {
+                *j += 1;
+                mapped.insert(*j, map.clone());
+                let k = *j;
+                ksni::menu::StandardItem {
+                    label: menu_label(name, false, Some('_')),
+                    enabled: !*disabled,
+                    icon_data: icon_data(icon),
+                    activate: Box::new(move |tray| activate(tray, k)),
+                    ..Default::default()
+                }
+                .into()
+            }
+
+            // Menus can't have columns, the break is shown as a separator
+            MenuItem::Separator | MenuItem::ColumnBreak => ksni::MenuItem::Separator,
+        })
+        .collect()
+}
+
+/// DBusMenu shortcut is a list of key chords, modifiers first
+fn shortcut(accelerator: &Option<String>) -> Vec<Vec<String>> {
+    let accelerator = match accelerator.as_deref().and_then(Accelerator::parse) {
+        Some(accelerator) => accelerator,
+        None => return vec![],
+    };
+    let mut chord = vec![];
+    if accelerator.ctrl {
+        chord.push("Control".to_string());
+    }
+    if accelerator.alt {
+        chord.push("Alt".to_string());
+    }
+    if accelerator.shift {
+        chord.push("Shift".to_string());
+    }
+    if accelerator.meta {
+        chord.push("Super".to_string());
+    }
+    chord.push(match accelerator.key {
+        Key::Char(c) => c.to_string(),
+        Key::F(n) => format!("F{}", n),
+        key => format!("{:?}", key),
+    });
+    vec![chord]
+}
+
+/// DBusMenu icons are PNG encoded
+fn icon_data(icon: &Option<Icon>) -> Vec<u8> {
+    icon.as_ref()
+        .and_then(|icon| icon.sys.to_png())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum Events {
+        CheckableItem1,
+        Item1,
+        SubItem1,
+        SubItem2,
+    }
+
+    #[test]
+    fn test_menu_build() {
+        let builder = MenuBuilder::new()
+            .checkable("This is checkable", true, Events::CheckableItem1)
+            .submenu(
+                "Sub Menu",
+                MenuBuilder::new()
+                    .item("Sub item 1", Events::SubItem1)
+                    .item("Sub Item 2", Events::SubItem2),
+            )
+            .separator()
+            .item("Item 1", Events::Item1)
+            .item_map("Item 2", || Events::Item1);
+
+        let menusys = build_menu(&builder).unwrap();
+        assert_eq!(menusys.ids.len(), 4);
+        assert_eq!(menusys.ids.get(&4), Some(&Events::Item1));
+        assert_eq!(
+            menusys.mapped.get(&5).map(|m| m.call()),
+            Some(Events::Item1)
+        );
+        assert_eq!(menusys.items(|_, _| {}).len(), 5);
+    }
+
+    #[test]
+    fn test_menu_toggle() {
+        let builder = MenuBuilder::new().item("Item 1", Events::Item1).submenu(
+            "Sub Menu",
+            MenuBuilder::new().checkable("This is checkable", true, Events::CheckableItem1),
+        );
+
+        let mut menusys = build_menu(&builder).unwrap();
+        assert_eq!(menusys.toggle(1), None);
+        assert_eq!(menusys.toggle(2), Some(false));
+        assert_eq!(
+            menusys.builder.get_checkable(Events::CheckableItem1),
+            Some(false)
+        );
+    }
+}
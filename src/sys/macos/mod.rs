@@ -0,0 +1,289 @@
+mod nsdetached;
+mod nsimageicon;
+mod nsmenu;
+mod nsstatusitem;
+
+use cocoa::base::{id, nil, BOOL, YES};
+use cocoa::foundation::{NSPoint, NSString};
+use objc::{class, msg_send, sel, sel_impl};
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    accelerator::Accelerator, menubuilder::menu_label, AppInfo, Capabilities, Error, EventFn,
+    MenuBuilder, MenuItem, SystemTheme, TrayIconBuilder,
+};
+use nsmenu::NSMenuHandle;
+use nsstatusitem::MacTrayIconImpl;
+
+// macOS implementations of Icon, TrayIcon, and Menu
+pub use nsdetached::{attach_handler, spawn_detached};
+pub use nsimageicon::NSImageIcon as IconSys;
+pub use nsstatusitem::MacTrayIcon as TrayIconSys;
+
+#[derive(Debug)]
+pub struct MenuSys<T>
+where
+    T: Clone + 'static,
+{
+    ids: HashMap<usize, T>,
+    menu: NSMenuHandle,
+
+    /// Tags of the checkable items
+    checkables: HashSet<usize>,
+
+    /// Events of the items built when clicked
+    mapped: HashMap<usize, EventFn<T>>,
+}
+
+impl<T> MenuSys<T>
+where
+    T: PartialEq + Clone + 'static,
+{
+    /// Tags of the menu items with the event
+    fn command_ids<'a>(&'a self, id: &'a T) -> impl Iterator<Item = usize> + 'a {
+        self.ids
+            .iter()
+            .filter(move |(_, v)| *v == id)
+            .map(|(k, _)| *k)
+    }
+}
+
+/// Build the tray icon
+pub fn build_trayicon<T>(builder: &TrayIconBuilder<T>) -> Result<TrayIconSys<T>, Error>
+where
+    T: Clone + 'static,
+{
+    let mut menu: Option<MenuSys<T>> = None;
+    let icon = builder.themed_icon()?.clone();
+    let sender = builder.sender.clone().ok_or(Error::SenderMissing)?;
+
+    // Try to get a popup menu
+    if let Some(rmenu) = &builder.menu {
+        menu = Some(rmenu.build()?);
+    }
+
+    let tray_icon = MacTrayIconImpl::new(builder, sender, menu, icon)?;
+    if let Some(guid) = builder.guid {
+        tray_icon.set_autosave_name(&format!("{:032x}", guid));
+    }
+    Ok(tray_icon)
+}
+
+// NSEventType value
+const NS_APPLICATION_DEFINED: u64 = 15;
+
+/// Run the application
+pub fn run_event_loop() {
+    unsafe {
+        let app: id = msg_send![class!(NSApplication), sharedApplication];
+        let _: () = msg_send![app, run];
+    }
+}
+
+/// Stop the application
+///
+/// Stopping takes effect after the next event, so an event is posted.
+pub fn quit_event_loop() {
+    unsafe {
+        let app: id = msg_send![class!(NSApplication), sharedApplication];
+        let _: () = msg_send![app, stop: nil];
+        let event: id = msg_send![class!(NSEvent),
+            otherEventWithType: NS_APPLICATION_DEFINED
+            location: NSPoint::new(0.0, 0.0)
+            modifierFlags: 0u64
+            timestamp: 0.0f64
+            windowNumber: 0isize
+            context: nil
+            subtype: 0i16
+            data1: 0isize
+            data2: 0isize];
+        let _: () = msg_send![app, postEvent: event atStart: YES];
+    }
+}
+
+/// Status item is removed when dropped, on the main thread like the other
+/// AppKit calls
+pub fn shutdown_trayicon<T>(trayicon: TrayIconSys<T>) -> Result<(), Error>
+where
+    T: Clone + 'static,
+{
+    drop(trayicon);
+    Ok(())
+}
+
+/// Status bar exists while the app runs as a regular or an accessory app
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        tray: true,
+        notify_icon_version: None,
+        wine: false,
+        reactos: false,
+        notifications: true,
+        rich_popup: false,
+        rect: true,
+        double_click: true,
+        hover: true,
+        show_menu: true,
+    }
+}
+
+/// Menu bar follows the interface style of the user
+pub fn system_theme() -> Option<SystemTheme> {
+    unsafe {
+        let defaults: id = msg_send![class!(NSUserDefaults), standardUserDefaults];
+        let style: id = msg_send![defaults, stringForKey: nsstring("AppleInterfaceStyle")];
+        if style == nil {
+            return Some(SystemTheme::Light);
+        }
+        let dark: BOOL = msg_send![style, isEqualToString: nsstring("Dark")];
+        if dark == YES {
+            Some(SystemTheme::Dark)
+        } else {
+            Some(SystemTheme::Light)
+        }
+    }
+}
+
+/// Modal NSAlert, the app is activated so the alert is in front
+pub fn show_about(app_info: &AppInfo) {
+    unsafe {
+        let app: id = msg_send![class!(NSApplication), sharedApplication];
+        let _: () = msg_send![app, activateIgnoringOtherApps: YES];
+        let alert: id = msg_send![class!(NSAlert), new];
+        let _: () = msg_send![alert, setMessageText: nsstring(&format!("About {}", app_info.name))];
+        let _: () = msg_send![alert, setInformativeText: nsstring(&app_info.text())];
+        let _: isize = msg_send![alert, runModal];
+        let _: () = msg_send![alert, release];
+    }
+}
+
+/// Build the menu from NSMenu
+pub fn build_menu<T>(builder: &MenuBuilder<T>) -> Result<MenuSys<T>, Error>
+where
+    T: Clone + 'static,
+{
+    let mut j = 0;
+    let menu = build_menu_inner(&mut j, builder)?;
+    if let Some(theme) = builder.theme {
+        menu.menu.set_theme(theme);
+    }
+    Ok(menu)
+}
+
+/// Recursive menu builder
+///
+/// Having a j value as mutable reference it's capable of handling nested
+/// submenus
+fn build_menu_inner<T>(j: &mut usize, builder: &MenuBuilder<T>) -> Result<MenuSys<T>, Error>
+where
+    T: Clone + 'static,
+{
+    let menu = NSMenuHandle::new()?;
+    let mut map: HashMap<usize, T> = HashMap::new();
+    let mut checkables = HashSet::new();
+    let mut mapped = HashMap::new();
+    builder.menu_items.iter().for_each(|item| match item {
+        MenuItem::Submenu {
+            id,
+            name,
+            children,
+            disabled,
+            icon,
+            ..
+        } => {
+            if let Some(id) = id {
+                *j += 1;
+                map.insert(*j, id.clone());
+            }
+            if let Ok(menusys) = build_menu_inner(j, children) {
+                map.extend(menusys.ids);
+                checkables.extend(menusys.checkables);
+                mapped.extend(menusys.mapped);
+                menu.add_child_menu(name, menusys.menu, *disabled);
+                if let Some(icon) = icon {
+                    menu.set_last_icon(&icon.sys);
+                }
+            }
+        }
+
+        MenuItem::Checkable {
+            name,
+            is_checked,
+            id,
+            disabled,
+            icon,
+            description,
+            ..
+        } => {
+            *j += 1;
+            map.insert(*j, id.clone());
+            checkables.insert(*j);
+            menu.add_checkable_item(name, *is_checked, *j, *disabled);
+            if let Some(icon) = icon {
+                menu.set_last_icon(&icon.sys);
+            }
+            if let Some(description) = description {
+                menu.set_last_tooltip(description);
+            }
+        }
+
+        MenuItem::Item {
+            name,
+            id,
+            disabled,
+            icon,
+            accelerator,
+            description,
+            mnemonics,
+            ..
+        } => {
+            *j += 1;
+            map.insert(*j, id.clone());
+            menu.add_menu_item(&menu_label(name, *mnemonics, None), *j, *disabled);
+            if let Some(icon) = icon {
+                menu.set_last_icon(&icon.sys);
+            }
+            if let Some(accelerator) = accelerator.as_deref().and_then(Accelerator::parse) {
+                menu.set_last_accelerator(&accelerator);
+            }
+            if let Some(description) = description {
+                menu.set_last_tooltip(description);
+            }
+        }
+
+        MenuItem::Mapped {
+            map,
+            name,
+            disabled,
+            icon,
+            ..
+        } => {
+            *j += 1;
+            mapped.insert(*j, map.clone());
+            menu.add_menu_item(name, *j, *disabled);
+            if let Some(icon) = icon {
+                menu.set_last_icon(&icon.sys);
+            }
+        }
+
+        // Menus can't have columns, the break is shown as a separator
+        MenuItem::Separator | MenuItem::ColumnBreak => {
+            menu.add_separator();
+        }
+    });
+
+    Ok(MenuSys {
+        ids: map,
+        menu,
+        checkables,
+        mapped,
+    })
+}
+
+/// Autoreleased NSString
+fn nsstring(string: &str) -> id {
+    unsafe {
+        let string = NSString::alloc(nil).init_str(string);
+        msg_send![string, autorelease]
+    }
+}
@@ -0,0 +1,92 @@
+use cocoa::base::{id, nil, NO};
+use objc::declare::ClassDecl;
+use objc::runtime::{Class, Object, Sel};
+use objc::{class, msg_send, sel, sel_impl};
+use std::ffi::c_void;
+use std::sync::Once;
+
+use crate::{
+    trayiconhandle::{Attached, Handler, Waker},
+    Error,
+};
+
+/// Status items must be created in the main thread
+pub fn spawn_detached<F>(_build: F) -> Result<Attached, Error>
+where
+    F: FnOnce() -> Result<Handler, Error> + Send + 'static,
+{
+    Err(Error::Unsupported)
+}
+
+/// Run the handler in the main thread when woken
+///
+/// Must be called in the main thread, the handler is run by the application
+/// event loop.
+pub fn attach_handler(handler: Handler) -> Result<Attached, Error> {
+    unsafe {
+        let object: id = msg_send![wake_class(), new];
+        if object == nil {
+            return Err(Error::os_error("TrayIconWake new"));
+        }
+        let handler = Box::into_raw(Box::new(handler));
+        (*object).set_ivar::<*mut c_void>("handler", handler as *mut c_void);
+        let object = WakeObject(object);
+        let waker: Waker = Box::new(move || {
+            let _: () = msg_send![object.0,
+                performSelectorOnMainThread: sel!(wake:)
+                withObject: nil
+                waitUntilDone: NO];
+        });
+        Ok((waker, std::thread::current().id()))
+    }
+}
+
+/// Wake object, released with the waker
+///
+/// Pending wake retains the object until it's performed.
+struct WakeObject(id);
+
+unsafe impl Send for WakeObject {}
+unsafe impl Sync for WakeObject {}
+
+impl Drop for WakeObject {
+    fn drop(&mut self) {
+        unsafe {
+            let _: () = msg_send![self.0, release];
+        }
+    }
+}
+
+/// Objective-C class for the wake object, registered once
+fn wake_class() -> &'static Class {
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| {
+        let mut decl = ClassDecl::new("TrayIconWake", class!(NSObject)).unwrap();
+        decl.add_ivar::<*mut c_void>("handler");
+        unsafe {
+            decl.add_method(sel!(wake:), wake as extern "C" fn(&mut Object, Sel, id));
+        }
+        decl.register();
+    });
+    class!(TrayIconWake)
+}
+
+/// Run the handler, and drop it when it's done
+///
+/// Handler is taken while it runs, a menu shown by it runs the event loop,
+/// which can perform the wake again.
+extern "C" fn wake(this: &mut Object, _cmd: Sel, _arg: id) {
+    unsafe {
+        let ptr: *mut c_void = *this.get_ivar("handler");
+        if ptr.is_null() {
+            return;
+        }
+        this.set_ivar::<*mut c_void>("handler", std::ptr::null_mut());
+        let handler = ptr as *mut Handler;
+        if (*handler)() {
+            this.set_ivar::<*mut c_void>("handler", ptr);
+        } else {
+            drop(Box::from_raw(handler));
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use crate::{Error, FontOptions, IconBase};
+use cocoa::base::{id, nil, NO, YES};
+use cocoa::foundation::{NSPoint, NSRect, NSSize};
+use objc::{class, msg_send, sel, sel_impl};
+
+/// Status bar icons are drawn in points, this fits the menu bar height
+const STATUS_BAR_ICON_SIZE: f64 = 18.0;
+
+/// NSBitmapFormatAlphaNonpremultiplied
+const NS_ALPHA_NONPREMULTIPLIED_BITMAP_FORMAT: u64 = 1 << 1;
+
+/// Purpose of this struct is to keep NSImage retained, and release it when the
+/// struct is dropped
+pub struct NSImageIcon {
+    pub image: id,
+}
+
+impl IconBase for NSImageIcon {
+    fn from_buffer(
+        buffer: &[u8],
+        width: Option<u32>,
+        height: Option<u32>,
+    ) -> Result<NSImageIcon, Error> {
+        let image: id = unsafe {
+            let data: id = msg_send![class!(NSData),
+                dataWithBytes: buffer.as_ptr() as *const std::ffi::c_void
+                length: buffer.len() as u64];
+            let image: id = msg_send![class!(NSImage), alloc];
+            msg_send![image, initWithData: data]
+        };
+        if image == nil {
+            return Err(Error::IconLoadingFailed);
+        }
+        let size = NSSize::new(
+            width.map_or(STATUS_BAR_ICON_SIZE, |w| w as f64),
+            height.map_or(STATUS_BAR_ICON_SIZE, |h| h as f64),
+        );
+        unsafe {
+            let _: () = msg_send![image, setSize: size];
+        }
+        Ok(NSImageIcon { image })
+    }
+
+    fn from_rgba(rgba: &[u8], width: u32, height: u32) -> Result<NSImageIcon, Error> {
+        unsafe {
+            let rep: id = msg_send![class!(NSBitmapImageRep), alloc];
+            let rep: id = msg_send![rep,
+                initWithBitmapDataPlanes: std::ptr::null_mut::<*mut u8>()
+                pixelsWide: width as isize
+                pixelsHigh: height as isize
+                bitsPerSample: 8isize
+                samplesPerPixel: 4isize
+                hasAlpha: YES
+                isPlanar: NO
+                colorSpaceName: super::nsstring("NSDeviceRGBColorSpace")
+                bitmapFormat: NS_ALPHA_NONPREMULTIPLIED_BITMAP_FORMAT
+                bytesPerRow: width as isize * 4
+                bitsPerPixel: 32isize];
+            if rep == nil {
+                return Err(Error::IconLoadingFailed);
+            }
+            let data: *mut u8 = msg_send![rep, bitmapData];
+            std::ptr::copy_nonoverlapping(rgba.as_ptr(), data, rgba.len());
+
+            let size = NSSize::new(STATUS_BAR_ICON_SIZE, STATUS_BAR_ICON_SIZE);
+            let image: id = msg_send![class!(NSImage), alloc];
+            let image: id = msg_send![image, initWithSize: size];
+            let _: () = msg_send![image, addRepresentation: rep];
+            let _: () = msg_send![rep, release];
+            Ok(NSImageIcon { image })
+        }
+    }
+
+    /// Text is drawn to the image with the attributes, the size is in points
+    fn from_text(text: &str, options: &FontOptions) -> Result<NSImageIcon, Error> {
+        let size = options.icon_size as f64;
+        unsafe {
+            let string = super::nsstring(text);
+            let (mut attributes, mut text_size) = text_attributes(string, options, size)?;
+
+            // Font is made smaller to fit the width
+            if text_size.width > size {
+                let font_size = size * size / text_size.width;
+                (attributes, text_size) = text_attributes(string, options, font_size)?;
+            }
+
+            let image: id = msg_send![class!(NSImage), alloc];
+            let image: id = msg_send![image, initWithSize: NSSize::new(size, size)];
+            let _: () = msg_send![image, lockFocus];
+            if let Some(background) = options.background {
+                let _: () = msg_send![ns_color(background), setFill];
+                let rect = NSRect::new(NSPoint::new(0.0, 0.0), NSSize::new(size, size));
+                let _: () = msg_send![class!(NSBezierPath), fillRect: rect];
+            }
+            let point = NSPoint::new(
+                (size - text_size.width) / 2.0,
+                (size - text_size.height) / 2.0,
+            );
+            let _: () = msg_send![string, drawAtPoint: point withAttributes: attributes];
+            let _: () = msg_send![image, unlockFocus];
+            Ok(NSImageIcon { image })
+        }
+    }
+
+    /// Images are drawn by the status bar, they are not rendered to pixels
+    fn to_rgba(&self, _width: u32, _height: u32) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Font and color attributes, and the size of the string drawn with them
+unsafe fn text_attributes(
+    string: id,
+    options: &FontOptions,
+    font_size: f64,
+) -> Result<(id, NSSize), Error> {
+    let font: id = match &options.family {
+        Some(family) => msg_send![class!(NSFont),
+            fontWithName: super::nsstring(family)
+            size: font_size],
+        None if options.bold => msg_send![class!(NSFont), boldSystemFontOfSize: font_size],
+        None => msg_send![class!(NSFont), systemFontOfSize: font_size],
+    };
+    if font == nil {
+        return Err(Error::IconLoadingFailed);
+    }
+
+    // Values of NSFontAttributeName and NSForegroundColorAttributeName
+    let keys = [super::nsstring("NSFont"), super::nsstring("NSColor")];
+    let objects = [font, ns_color(options.color)];
+    let attributes: id = msg_send![class!(NSDictionary),
+        dictionaryWithObjects: objects.as_ptr()
+        forKeys: keys.as_ptr()
+        count: 2u64];
+    let text_size: NSSize = msg_send![string, sizeWithAttributes: attributes];
+    Ok((attributes, text_size))
+}
+
+unsafe fn ns_color(rgba: [u8; 4]) -> id {
+    let [r, g, b, a] = rgba.map(|c| c as f64 / 255.0);
+    msg_send![class!(NSColor), colorWithSRGBRed: r green: g blue: b alpha: a]
+}
+
+impl Clone for NSImageIcon {
+    fn clone(&self) -> Self {
+        NSImageIcon {
+            image: unsafe { msg_send![self.image, retain] },
+        }
+    }
+}
+
+unsafe impl Send for NSImageIcon {}
+unsafe impl Sync for NSImageIcon {}
+
+impl Drop for NSImageIcon {
+    fn drop(&mut self) {
+        unsafe {
+            let _: () = msg_send![self.image, release];
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use super::{nsimageicon::NSImageIcon, nsstring};
+use crate::accelerator::{Accelerator, Key};
+use crate::{Error, MenuTheme};
+use cocoa::base::{id, nil, BOOL, NO, YES};
+use cocoa::foundation::NSSize;
+use objc::{class, msg_send, sel, sel_impl};
+use std::fmt::Debug;
+
+/// Menu item icons are drawn in points
+const MENU_ICON_SIZE: f64 = 16.0;
+
+// NSEventModifierFlags
+const MODIFIER_SHIFT: usize = 1 << 17;
+const MODIFIER_OPTION: usize = 1 << 19;
+const MODIFIER_COMMAND: usize = 1 << 20;
+
+// NSEvent function key characters
+const KEY_UP: u32 = 0xF700;
+const KEY_DOWN: u32 = 0xF701;
+const KEY_LEFT: u32 = 0xF702;
+const KEY_RIGHT: u32 = 0xF703;
+const KEY_F1: u32 = 0xF704;
+const KEY_INSERT: u32 = 0xF727;
+const KEY_DELETE: u32 = 0xF728;
+const KEY_HOME: u32 = 0xF729;
+const KEY_END: u32 = 0xF72B;
+const KEY_PAGE_UP: u32 = 0xF72C;
+const KEY_PAGE_DOWN: u32 = 0xF72D;
+
+/// Purpose of this struct is to keep NSMenu retained, and release it when the
+/// struct is dropped
+pub struct NSMenuHandle {
+    pub(crate) menu: id,
+}
+
+impl NSMenuHandle {
+    pub(crate) fn new() -> Result<NSMenuHandle, Error> {
+        let menu: id = unsafe {
+            let menu: id = msg_send![class!(NSMenu), alloc];
+            msg_send![menu, init]
+        };
+        if menu == nil {
+            return Err(Error::os_error("NSMenu init"));
+        }
+        unsafe {
+            // Otherwise AppKit enables all items that have a target
+            let _: () = msg_send![menu, setAutoenablesItems: NO];
+        }
+        Ok(NSMenuHandle { menu })
+    }
+
+    pub fn add_menu_item(&self, name: &str, id: usize, disabled: bool) {
+        self.append(name, id, disabled);
+    }
+
+    pub fn add_checkable_item(&self, name: &str, is_checked: bool, id: usize, disabled: bool) {
+        let item = self.append(name, id, disabled);
+        unsafe {
+            let _: () = msg_send![item, setState: is_checked as isize];
+        }
+    }
+
+    pub fn add_child_menu(&self, name: &str, menu: NSMenuHandle, disabled: bool) {
+        let item = self.append(name, 0, disabled);
+        unsafe {
+            let _: () = msg_send![item, setAction: nil];
+            let _: () = msg_send![item, setSubmenu: menu.menu];
+        }
+    }
+
+    pub fn add_separator(&self) {
+        unsafe {
+            let item: id = msg_send![class!(NSMenuItem), separatorItem];
+            let _: () = msg_send![self.menu, addItem: item];
+        }
+    }
+
+    /// Set the icon of the last added item, scaled to the menu font size
+    pub fn set_last_icon(&self, icon: &NSImageIcon) -> bool {
+        unsafe {
+            let count: isize = msg_send![self.menu, numberOfItems];
+            if count == 0 {
+                return false;
+            }
+            let item: id = msg_send![self.menu, itemAtIndex: count - 1];
+            let image: id = msg_send![icon.image, copy];
+            let _: () = msg_send![image, setSize: NSSize::new(MENU_ICON_SIZE, MENU_ICON_SIZE)];
+            let _: () = msg_send![item, setImage: image];
+            let _: () = msg_send![image, release];
+        }
+        true
+    }
+
+    /// Set the tooltip of the last added item
+    pub fn set_last_tooltip(&self, tooltip: &str) -> bool {
+        unsafe {
+            let count: isize = msg_send![self.menu, numberOfItems];
+            if count == 0 {
+                return false;
+            }
+            let item: id = msg_send![self.menu, itemAtIndex: count - 1];
+            let _: () = msg_send![item, setToolTip: nsstring(tooltip)];
+        }
+        true
+    }
+
+    /// Set the key equivalent of the last added item
+    ///
+    /// Ctrl is Command, as the shortcuts in macOS use it instead.
+    pub fn set_last_accelerator(&self, accelerator: &Accelerator) -> bool {
+        let key = match key_equivalent(accelerator.key) {
+            Some(key) => key,
+            None => return false,
+        };
+        let mut mask = 0;
+        if accelerator.ctrl || accelerator.meta {
+            mask |= MODIFIER_COMMAND;
+        }
+        if accelerator.alt {
+            mask |= MODIFIER_OPTION;
+        }
+        if accelerator.shift {
+            mask |= MODIFIER_SHIFT;
+        }
+        unsafe {
+            let count: isize = msg_send![self.menu, numberOfItems];
+            if count == 0 {
+                return false;
+            }
+            let item: id = msg_send![self.menu, itemAtIndex: count - 1];
+            let _: () = msg_send![item, setKeyEquivalent: nsstring(&key.to_string())];
+            let _: () = msg_send![item, setKeyEquivalentModifierMask: mask];
+        }
+        true
+    }
+
+    /// Checked state, searches the submenus too
+    pub fn is_checked(&self, id: usize) -> bool {
+        let item = unsafe { item_with_tag(self.menu, id as isize) };
+        if item == nil {
+            return false;
+        }
+        let state: isize = unsafe { msg_send![item, state] };
+        state != 0
+    }
+
+    pub fn set_checked(&self, id: usize, checked: bool) -> bool {
+        let item = unsafe { item_with_tag(self.menu, id as isize) };
+        if item != nil {
+            unsafe {
+                let _: () = msg_send![item, setState: checked as isize];
+            }
+        }
+        item != nil
+    }
+
+    pub fn set_disabled(&self, id: usize, disabled: bool) -> bool {
+        let item = unsafe { item_with_tag(self.menu, id as isize) };
+        if item != nil {
+            let enabled: BOOL = if disabled { NO } else { YES };
+            unsafe {
+                let _: () = msg_send![item, setEnabled: enabled];
+            }
+        }
+        item != nil
+    }
+
+    pub fn set_name(&self, id: usize, name: &str) -> bool {
+        let item = unsafe { item_with_tag(self.menu, id as isize) };
+        if item != nil {
+            unsafe {
+                let _: () = msg_send![item, setTitle: nsstring(name)];
+            }
+        }
+        item != nil
+    }
+
+    /// Appearance of the menu, submenus inherit it
+    pub fn set_theme(&self, theme: MenuTheme) {
+        unsafe {
+            let appearance: id = match theme {
+                MenuTheme::System => nil,
+                MenuTheme::Light => msg_send![class!(NSAppearance),
+                    appearanceNamed: nsstring("NSAppearanceNameAqua")],
+                MenuTheme::Dark => msg_send![class!(NSAppearance),
+                    appearanceNamed: nsstring("NSAppearanceNameDarkAqua")],
+            };
+            let _: () = msg_send![self.menu, setAppearance: appearance];
+        }
+    }
+
+    /// Set the target recursively, the menu is built before the tray icon
+    /// exists
+    pub fn set_target(&self, target: id) {
+        unsafe { set_target_recursive(self.menu, target) }
+    }
+
+    fn append(&self, name: &str, id: usize, disabled: bool) -> id {
+        unsafe {
+            let item: id = msg_send![class!(NSMenuItem), alloc];
+            let item: id = msg_send![item,
+                initWithTitle: nsstring(name)
+                action: sel!(onMenuItemClick:)
+                keyEquivalent: nsstring("")];
+            let _: () = msg_send![item, setTag: id as isize];
+            let enabled: BOOL = if disabled { NO } else { YES };
+            let _: () = msg_send![item, setEnabled: enabled];
+            let _: () = msg_send![self.menu, addItem: item];
+            let _: () = msg_send![item, release];
+            item
+        }
+    }
+}
+
+/// Character of the key equivalent, uppercase would imply Shift
+fn key_equivalent(key: Key) -> Option<char> {
+    let c = match key {
+        Key::Char(c) => return Some(c.to_ascii_lowercase()),
+        Key::F(n) => KEY_F1 + n as u32 - 1,
+        Key::Space => ' ' as u32,
+        Key::Enter => '\r' as u32,
+        Key::Escape => 0x1b,
+        Key::Tab => '\t' as u32,
+        Key::Backspace => 0x08,
+        Key::Delete => KEY_DELETE,
+        Key::Insert => KEY_INSERT,
+        Key::Home => KEY_HOME,
+        Key::End => KEY_END,
+        Key::PageUp => KEY_PAGE_UP,
+        Key::PageDown => KEY_PAGE_DOWN,
+        Key::Up => KEY_UP,
+        Key::Down => KEY_DOWN,
+        Key::Left => KEY_LEFT,
+        Key::Right => KEY_RIGHT,
+    };
+    std::char::from_u32(c)
+}
+
+/// Find item recursively, submenu items don't have a tag
+unsafe fn item_with_tag(menu: id, tag: isize) -> id {
+    let count: isize = msg_send![menu, numberOfItems];
+    for i in 0..count {
+        let item: id = msg_send![menu, itemAtIndex: i];
+        let submenu: id = msg_send![item, submenu];
+        let item_tag: isize = msg_send![item, tag];
+        let found = if submenu != nil {
+            item_with_tag(submenu, tag)
+        } else if item_tag == tag {
+            item
+        } else {
+            nil
+        };
+        if found != nil {
+            return found;
+        }
+    }
+    nil
+}
+
+unsafe fn set_target_recursive(menu: id, target: id) {
+    let count: isize = msg_send![menu, numberOfItems];
+    for i in 0..count {
+        let item: id = msg_send![menu, itemAtIndex: i];
+        let submenu: id = msg_send![item, submenu];
+        if submenu != nil {
+            set_target_recursive(submenu, target);
+        } else {
+            let _: () = msg_send![item, setTarget: target];
+        }
+    }
+}
+
+unsafe impl Send for NSMenuHandle {}
+unsafe impl Sync for NSMenuHandle {}
+
+impl Debug for NSMenuHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "NSMenu")
+    }
+}
+
+impl Drop for NSMenuHandle {
+    fn drop(&mut self) {
+        unsafe {
+            let _: () = msg_send![self.menu, release];
+        }
+    }
+}
@@ -0,0 +1,915 @@
+use cocoa::base::{id, nil, NO, YES};
+use cocoa::foundation::{NSPoint, NSRect, NSSize};
+use objc::declare::ClassDecl;
+use objc::runtime::{Class, Object, Sel};
+use objc::{class, msg_send, sel, sel_impl};
+use std::ffi::c_void;
+use std::fmt::Debug;
+use std::sync::Once;
+use std::time::Duration;
+
+use super::{nsmenu::NSMenuHandle, nsstring, MenuSys};
+use crate::{
+    badge::Decoration,
+    menubuilder::{MenuFn, MenuUpdater, Toggled},
+    trayicon::TooltipFn,
+    trayiconbuilder::TrayIconBuilder,
+    trayiconsender::TrayIconSender,
+    ClickBehavior, Error, Icon, MenuBuilder, Modifiers, MouseButton, PhysicalPosition, SystemTheme,
+    TrayIconBase, TrayIconEvent,
+};
+
+pub type MacTrayIcon<T> = Box<MacTrayIconImpl<T>>;
+
+// NSEventType values
+const NS_LEFT_MOUSE_UP: u64 = 2;
+const NS_RIGHT_MOUSE_UP: u64 = 4;
+const NS_OTHER_MOUSE_UP: u64 = 26;
+
+// NSEventMask values
+const NS_LEFT_MOUSE_UP_MASK: u64 = 1 << NS_LEFT_MOUSE_UP;
+const NS_RIGHT_MOUSE_UP_MASK: u64 = 1 << NS_RIGHT_MOUSE_UP;
+const NS_OTHER_MOUSE_UP_MASK: u64 = 1 << NS_OTHER_MOUSE_UP;
+
+const NS_VARIABLE_STATUS_ITEM_LENGTH: f64 = -1.0;
+
+// NSTrackingAreaOptions values
+const NS_TRACKING_MOUSE_ENTERED_AND_EXITED: u64 = 0x01;
+const NS_TRACKING_ACTIVE_ALWAYS: u64 = 0x80;
+const NS_TRACKING_IN_VISIBLE_RECT: u64 = 0x200;
+
+/// Receiver of the actions from the target object
+///
+/// Target class is shared by all tray icons, so it can't be generic over the
+/// event type.
+trait TargetHandler {
+    fn on_status_item_click(&mut self);
+    fn on_menu_item_click(&mut self, tag: usize);
+    fn on_mouse_entered(&mut self);
+    fn on_mouse_exited(&mut self);
+    fn on_notification_click(&mut self);
+    fn on_animation_timer(&mut self);
+    fn on_tooltip_timer(&mut self);
+    fn on_single_click(&mut self);
+    fn on_theme_changed(&mut self);
+}
+
+/// Tray Icon NSStatusItem
+///
+/// Clicks are received by a target object, which forwards them to this struct.
+/// This must be created and used in the main thread, with the application
+/// event loop running.
+pub struct MacTrayIconImpl<T>
+where
+    T: Clone + 'static,
+{
+    status_item: id,
+    target: id,
+    handler: *mut *mut dyn TargetHandler,
+    sender: TrayIconSender<T>,
+    menu: Option<MenuSys<T>>,
+    icon: Icon,
+    animation: Vec<Icon>,
+    animation_frame: usize,
+    timer: id,
+
+    /// Tooltip is set from this on the tooltip timer
+    tooltip_fn: Option<TooltipFn>,
+    tooltip_timer: id,
+    on_click: Option<T>,
+    on_double_click: Option<T>,
+    on_right_click: Option<T>,
+    on_middle_click: Option<T>,
+    on_mouse_enter: Option<T>,
+    on_mouse_leave: Option<T>,
+    on_menu_open: Option<T>,
+    on_notification_clicked: Option<T>,
+    on_theme_changed: Option<T>,
+
+    /// Icons switched with the theme, until the icon is set
+    icon_light: Option<Icon>,
+    icon_dark: Option<Icon>,
+    menu_with: Option<MenuFn<T>>,
+    menu_updater: Option<MenuUpdater<T>>,
+
+    /// Checkables are toggled when clicked, and recorded here
+    toggled: Option<Toggled<T>>,
+    menu_on_left_click: bool,
+
+    /// Left click is sent after the delay, unless it becomes a double click,
+    /// the double click interval of the system if none
+    click_behavior: ClickBehavior,
+    single_click_delay: Option<Duration>,
+    pending_click: Option<(PhysicalPosition, Modifiers)>,
+
+    /// Menu bar theme, for sending only the changes
+    theme: Option<SystemTheme>,
+}
+
+impl<T> MacTrayIconImpl<T>
+where
+    T: Clone + 'static,
+{
+    /// Status item of the tray icon, with the events of the builder
+    ///
+    /// Menu and the icon are built by the caller.
+    #[allow(clippy::new_ret_no_self)]
+    pub(crate) fn new(
+        builder: &TrayIconBuilder<T>,
+        sender: TrayIconSender<T>,
+        menu: Option<MenuSys<T>>,
+        icon: Icon,
+    ) -> Result<MacTrayIcon<T>, Error> {
+        unsafe {
+            let status_bar: id = msg_send![class!(NSStatusBar), systemStatusBar];
+            let status_item: id =
+                msg_send![status_bar, statusItemWithLength: NS_VARIABLE_STATUS_ITEM_LENGTH];
+            if status_item == nil {
+                return Err(Error::os_error("statusItemWithLength"));
+            }
+            let _: () = msg_send![status_item, retain];
+            let target: id = msg_send![target_class(), new];
+
+            // Box the tray icon to a memory location that doesn't change
+            let mut tray_icon = Box::new(MacTrayIconImpl {
+                status_item,
+                target,
+                handler: std::ptr::null_mut(),
+                sender,
+                menu,
+                icon,
+                animation: vec![],
+                animation_frame: 0,
+                timer: nil,
+                tooltip_fn: None,
+                tooltip_timer: nil,
+                on_click: builder.on_click.clone(),
+                on_double_click: builder.on_double_click.clone(),
+                on_right_click: builder.on_right_click.clone(),
+                on_middle_click: builder.on_middle_click.clone(),
+                on_mouse_enter: builder.on_mouse_enter.clone(),
+                on_mouse_leave: builder.on_mouse_leave.clone(),
+                on_menu_open: builder.on_menu_open.clone(),
+                on_notification_clicked: builder.on_notification_clicked.clone(),
+                on_theme_changed: builder.on_theme_changed.clone(),
+                icon_light: builder.icon_light.clone(),
+                icon_dark: builder.icon_dark.clone(),
+                menu_with: builder.menu_with.clone(),
+                menu_updater: builder.menu_updater.clone(),
+                toggled: builder.toggled_checkables(),
+                menu_on_left_click: builder.menu_on_left_click,
+                click_behavior: builder.click_behavior,
+                single_click_delay: builder.single_click_delay,
+                pending_click: None,
+                theme: super::system_theme(),
+            });
+            let handler: *mut dyn TargetHandler = &mut *tray_icon;
+            tray_icon.handler = Box::into_raw(Box::new(handler));
+            (*target).set_ivar::<*mut c_void>("handler", tray_icon.handler as *mut c_void);
+
+            let button: id = msg_send![status_item, button];
+            let _: () = msg_send![button, setImage: tray_icon.icon.sys.image];
+            if let Some(tooltip) = &builder.tooltip {
+                let _: () = msg_send![button, setToolTip: nsstring(tooltip)];
+            }
+            let _: () = msg_send![button, setTarget: target];
+            let _: () = msg_send![button, setAction: sel!(onStatusItemClick:)];
+            let _: () = msg_send![
+                button,
+                sendActionOn: NS_LEFT_MOUSE_UP_MASK | NS_RIGHT_MOUSE_UP_MASK | NS_OTHER_MOUSE_UP_MASK
+            ];
+
+            // Mouse enter and exit are sent to the target
+            let tracking_area: id = msg_send![class!(NSTrackingArea), alloc];
+            let tracking_area: id = msg_send![tracking_area,
+                initWithRect: NSRect::new(NSPoint::new(0.0, 0.0), NSSize::new(0.0, 0.0))
+                options: NS_TRACKING_MOUSE_ENTERED_AND_EXITED
+                    | NS_TRACKING_ACTIVE_ALWAYS
+                    | NS_TRACKING_IN_VISIBLE_RECT
+                owner: target
+                userInfo: nil];
+            let _: () = msg_send![button, addTrackingArea: tracking_area];
+            let _: () = msg_send![tracking_area, release];
+
+            if let Some(menu) = &tray_icon.menu {
+                menu.menu.set_target(target);
+            }
+
+            // Interface style changes are distributed to all apps
+            let center: id = msg_send![class!(NSDistributedNotificationCenter), defaultCenter];
+            let _: () = msg_send![center,
+                addObserver: target
+                selector: sel!(onThemeChanged:)
+                name: nsstring("AppleInterfaceThemeChangedNotification")
+                object: nil];
+
+            Ok(tray_icon)
+        }
+    }
+}
+
+impl<T> MacTrayIconImpl<T>
+where
+    T: Clone + 'static,
+{
+    /// Position in the menu bar is saved with this name
+    pub(crate) fn set_autosave_name(&self, name: &str) {
+        unsafe {
+            let _: () = msg_send![self.status_item, setAutosaveName: nsstring(name)];
+        }
+    }
+
+    /// Show the icon, or the current animation frame
+    fn update_icon(&self) {
+        let icon = self
+            .animation
+            .get(self.animation_frame)
+            .unwrap_or(&self.icon);
+        unsafe {
+            let button: id = msg_send![self.status_item, button];
+            let _: () = msg_send![button, setImage: icon.sys.image];
+        }
+    }
+
+    /// Invalidate the animation timer, it retains the target until then
+    fn invalidate_timer(&mut self) {
+        invalidate(&mut self.timer);
+    }
+
+    /// Invalidate the tooltip timer, same as the animation timer
+    fn invalidate_tooltip_timer(&mut self) {
+        invalidate(&mut self.tooltip_timer);
+        self.tooltip_fn = None;
+    }
+
+    /// Show the icon of the theme, if there is one
+    fn switch_icon(&mut self, theme: SystemTheme) {
+        let icon = match theme {
+            SystemTheme::Light => &self.icon_light,
+            SystemTheme::Dark => &self.icon_dark,
+        };
+        if let Some(icon) = icon.clone() {
+            self.icon = icon;
+            self.update_icon();
+        }
+    }
+
+    /// Send the left click, and show the menu if enabled
+    fn left_click(&mut self, position: PhysicalPosition, modifiers: Modifiers) {
+        self.sender.send(
+            self.on_click.as_ref(),
+            TrayIconEvent::Click {
+                button: MouseButton::Left,
+                position,
+                modifiers,
+            },
+        );
+        if self.menu_on_left_click {
+            let _ = self.show_menu(None);
+        }
+    }
+
+    /// Cancel the delayed left click, the request retains the target
+    fn cancel_single_click(&mut self) {
+        self.pending_click = None;
+        unsafe {
+            let _: () = msg_send![class!(NSObject),
+                cancelPreviousPerformRequestsWithTarget: self.target
+                selector: sel!(onSingleClick:)
+                object: nil];
+        }
+    }
+
+    /// Update menu items with the event in place
+    fn update_menu_items<F>(&self, id: &T, f: F) -> Result<(), Error>
+    where
+        T: PartialEq,
+        F: Fn(&NSMenuHandle, usize) -> bool,
+    {
+        let menu = self.menu.as_ref().ok_or(Error::MenuItemNotFound)?;
+        let mut found = false;
+        for j in menu.command_ids(id) {
+            found = f(&menu.menu, j) || found;
+        }
+        if found {
+            Ok(())
+        } else {
+            Err(Error::MenuItemNotFound)
+        }
+    }
+}
+
+impl<T> TargetHandler for MacTrayIconImpl<T>
+where
+    T: Clone + 'static,
+{
+    fn on_status_item_click(&mut self) {
+        let (event_type, click_count) = unsafe {
+            let app: id = msg_send![class!(NSApplication), sharedApplication];
+            let event: id = msg_send![app, currentEvent];
+            let event_type: u64 = msg_send![event, type];
+            let click_count: isize = msg_send![event, clickCount];
+            (event_type, click_count)
+        };
+        match event_type {
+            // Right click tray icon
+            NS_RIGHT_MOUSE_UP => {
+                // Send right click event
+                self.sender.send(
+                    self.on_right_click.as_ref(),
+                    TrayIconEvent::Click {
+                        button: MouseButton::Right,
+                        position: cursor_position(),
+                        modifiers: key_modifiers(),
+                    },
+                );
+
+                // Show menu, if it's there
+                let _ = self.show_menu(None);
+            }
+
+            // Middle click tray icon
+            NS_OTHER_MOUSE_UP => {
+                self.sender.send(
+                    self.on_middle_click.as_ref(),
+                    TrayIconEvent::Click {
+                        button: MouseButton::Middle,
+                        position: cursor_position(),
+                        modifiers: key_modifiers(),
+                    },
+                );
+            }
+
+            // Second click is a single click too
+            NS_LEFT_MOUSE_UP
+                if click_count == 2 && self.click_behavior == ClickBehavior::SingleOnly =>
+            {
+                self.left_click(cursor_position(), key_modifiers())
+            }
+
+            // Double click tray icon
+            NS_LEFT_MOUSE_UP if click_count == 2 => {
+                self.cancel_single_click();
+                self.sender.send(
+                    self.on_double_click.as_ref(),
+                    TrayIconEvent::DoubleClick {
+                        position: cursor_position(),
+                        modifiers: key_modifiers(),
+                    },
+                );
+            }
+
+            // Left click tray icon, delayed until a double click can't follow
+            NS_LEFT_MOUSE_UP => match self.click_behavior {
+                ClickBehavior::SingleWithDelay => {
+                    let delay = match self.single_click_delay {
+                        Some(delay) => delay.as_secs_f64(),
+                        None => unsafe { msg_send![class!(NSEvent), doubleClickInterval] },
+                    };
+                    self.cancel_single_click();
+                    self.pending_click = Some((cursor_position(), key_modifiers()));
+                    unsafe {
+                        let _: () = msg_send![self.target,
+                            performSelector: sel!(onSingleClick:)
+                            withObject: nil
+                            afterDelay: delay];
+                    }
+                }
+                ClickBehavior::DoubleOnly => {}
+                ClickBehavior::Both | ClickBehavior::SingleOnly => {
+                    self.left_click(cursor_position(), key_modifiers())
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn on_mouse_entered(&mut self) {
+        self.sender
+            .send(self.on_mouse_enter.as_ref(), TrayIconEvent::MouseEnter);
+    }
+
+    fn on_mouse_exited(&mut self) {
+        self.sender
+            .send(self.on_mouse_leave.as_ref(), TrayIconEvent::MouseLeave);
+    }
+
+    fn on_notification_click(&mut self) {
+        self.sender.send(
+            self.on_notification_clicked.as_ref(),
+            TrayIconEvent::BalloonClicked,
+        );
+    }
+
+    fn on_theme_changed(&mut self) {
+        let theme = super::system_theme();
+        if theme != self.theme {
+            self.theme = theme;
+            if let Some(theme) = theme {
+                self.switch_icon(theme);
+                self.sender.send(
+                    self.on_theme_changed.as_ref(),
+                    TrayIconEvent::ThemeChanged(theme),
+                );
+            }
+        }
+    }
+
+    fn on_single_click(&mut self) {
+        if let Some((position, modifiers)) = self.pending_click.take() {
+            self.left_click(position, modifiers);
+        }
+    }
+
+    fn on_animation_timer(&mut self) {
+        if !self.animation.is_empty() {
+            self.animation_frame = (self.animation_frame + 1) % self.animation.len();
+            self.update_icon();
+        }
+    }
+
+    fn on_tooltip_timer(&mut self) {
+        if let Some(tooltip_fn) = self.tooltip_fn.clone() {
+            let _ = self.set_tooltip(&tooltip_fn.tooltip());
+        }
+    }
+
+    /// Send the event of the menu item, toggle the checkable if enabled
+    fn on_menu_item_click(&mut self, tag: usize) {
+        let menu = match self.menu.as_ref() {
+            Some(menu) => menu,
+            None => return,
+        };
+        if let Some(map) = menu.mapped.get(&tag) {
+            self.sender.send_menu_item(&map.call());
+            return;
+        }
+        let event = match menu.ids.get(&tag) {
+            Some(event) => event,
+            None => return,
+        };
+        match &self.toggled {
+            Some(toggled) if menu.checkables.contains(&tag) => {
+                let checked = !menu.menu.is_checked(tag);
+                menu.menu.set_checked(tag, checked);
+                if let Ok(mut toggled) = toggled.lock() {
+                    toggled.push((event.clone(), checked));
+                }
+                self.sender.send_menu_item_toggled(event, checked);
+            }
+            _ => self.sender.send_menu_item(event),
+        }
+    }
+}
+
+impl<T> TrayIconBase<T> for MacTrayIconImpl<T>
+where
+    T: Clone + 'static,
+{
+    /// Set the tooltip
+    fn set_tooltip(&mut self, tooltip: &str) -> Result<(), Error> {
+        unsafe {
+            let button: id = msg_send![self.status_item, button];
+            let _: () = msg_send![button, setToolTip: nsstring(tooltip)];
+        }
+        Ok(())
+    }
+
+    /// Refresh the tooltip with NSTimer sending to the target
+    fn set_tooltip_fn(
+        &mut self,
+        tooltip_fn: Option<TooltipFn>,
+        interval: Duration,
+    ) -> Result<(), Error> {
+        self.invalidate_tooltip_timer();
+        let tooltip_fn = match tooltip_fn {
+            Some(tooltip_fn) => tooltip_fn,
+            None => return Ok(()),
+        };
+        unsafe {
+            let timer: id = msg_send![class!(NSTimer),
+                scheduledTimerWithTimeInterval: interval.as_secs_f64()
+                target: self.target
+                selector: sel!(onTooltipTimer:)
+                userInfo: nil
+                repeats: YES];
+            if timer == nil {
+                return Err(Error::os_error("scheduledTimerWithTimeInterval"));
+            }
+            let _: () = msg_send![timer, retain];
+            self.tooltip_timer = timer;
+        }
+        self.tooltip_fn = Some(tooltip_fn.clone());
+        self.set_tooltip(&tooltip_fn.tooltip())
+    }
+
+    /// Set checkable in place
+    fn set_menu_item_checkable(&mut self, id: &T, checked: bool) -> Result<(), Error>
+    where
+        T: PartialEq,
+    {
+        self.update_menu_items(id, |menu, j| menu.set_checked(j, checked))
+    }
+
+    /// Set disabled in place
+    fn set_menu_item_disabled(&mut self, id: &T, disabled: bool) -> Result<(), Error>
+    where
+        T: PartialEq,
+    {
+        self.update_menu_items(id, |menu, j| menu.set_disabled(j, disabled))
+    }
+
+    /// Set name in place
+    ///
+    /// Name with `&` is set by rebuilding the menu, which knows whether it
+    /// marks the mnemonic.
+    fn set_menu_item_name(&mut self, id: &T, name: &str) -> Result<(), Error>
+    where
+        T: PartialEq,
+    {
+        if name.contains('&') {
+            return Err(Error::Unsupported);
+        }
+        self.update_menu_items(id, |menu, j| menu.set_name(j, name))
+    }
+
+    /// Set icon
+    fn set_icon(&mut self, icon: &Icon) -> Result<(), Error> {
+        self.icon = icon.clone();
+        self.icon_light = None;
+        self.icon_dark = None;
+        self.update_icon();
+        Ok(())
+    }
+
+    /// Pop up the menu at the position, or below the status item
+    ///
+    /// Lazily built menu is built before.
+    fn show_menu(&mut self, position: Option<PhysicalPosition>) -> Result<(), Error> {
+        self.sender
+            .send(self.on_menu_open.as_ref(), TrayIconEvent::MenuOpen);
+        if let Some(menu) = self.menu_updater.as_ref().and_then(|u| u.take()) {
+            self.set_menu(&menu)?;
+        }
+        if let Some(menu_with) = self.menu_with.clone() {
+            self.set_menu(&menu_with.build())?;
+        }
+        let menu = match &self.menu {
+            Some(menu) => menu.menu.menu,
+            None => return Ok(()),
+        };
+        unsafe {
+            match position {
+                Some(position) => {
+                    let location =
+                        from_physical(position.x, position.y).ok_or(Error::os_error("NSScreen"))?;
+                    let _: bool = msg_send![menu,
+                        popUpMenuPositioningItem: nil
+                        atLocation: location
+                        inView: nil];
+                }
+                None => {
+                    let _: () = msg_send![self.status_item, popUpStatusItemMenu: menu];
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Menu items send their actions to the target, the chosen item is not
+    /// returned
+    fn show_menu_sync(&mut self, _position: Option<PhysicalPosition>) -> Result<Option<T>, Error> {
+        Err(Error::Unsupported)
+    }
+
+    /// Template images are tinted by the status bar, a colored badge would
+    /// be tinted too
+    fn set_decoration(&mut self, _decoration: Decoration) -> Result<(), Error> {
+        Err(Error::Unsupported)
+    }
+
+    /// Events are looked up when the actions arrive
+    fn set_click_events(
+        &mut self,
+        on_click: Option<T>,
+        on_double_click: Option<T>,
+        on_right_click: Option<T>,
+    ) -> Result<(), Error> {
+        self.on_click = on_click;
+        self.on_double_click = on_double_click;
+        self.on_right_click = on_right_click;
+        Ok(())
+    }
+
+    /// Set the status item visibility, the position is kept
+    fn set_visible(&mut self, visible: bool) -> Result<(), Error> {
+        unsafe {
+            let _: () = msg_send![self.status_item, setVisible: if visible { YES } else { NO }];
+        }
+        Ok(())
+    }
+
+    /// Cycle the frames with NSTimer sending to the target
+    fn set_animation(&mut self, frames: Vec<Icon>, interval: Duration) -> Result<(), Error> {
+        self.invalidate_timer();
+        unsafe {
+            let timer: id = msg_send![class!(NSTimer),
+                scheduledTimerWithTimeInterval: interval.as_secs_f64()
+                target: self.target
+                selector: sel!(onAnimationTimer:)
+                userInfo: nil
+                repeats: YES];
+            if timer == nil {
+                return Err(Error::os_error("scheduledTimerWithTimeInterval"));
+            }
+            let _: () = msg_send![timer, retain];
+            self.timer = timer;
+        }
+        self.animation = frames;
+        self.animation_frame = 0;
+        self.update_icon();
+        Ok(())
+    }
+
+    /// Stop the timer and restore the icon
+    fn stop_animation(&mut self) -> Result<(), Error> {
+        self.invalidate_timer();
+        self.animation.clear();
+        self.animation_frame = 0;
+        self.update_icon();
+        Ok(())
+    }
+
+    /// Show notification with NSUserNotificationCenter
+    ///
+    /// The notification center decides the timeout.
+    fn show_notification(
+        &mut self,
+        title: &str,
+        body: &str,
+        icon: Option<&Icon>,
+        _timeout: Option<Duration>,
+    ) -> Result<(), Error> {
+        unsafe {
+            let notification: id = msg_send![class!(NSUserNotification), new];
+            let _: () = msg_send![notification, setTitle: nsstring(title)];
+            let _: () = msg_send![notification, setInformativeText: nsstring(body)];
+            if let Some(icon) = icon {
+                let _: () = msg_send![notification, setContentImage: icon.sys.image];
+            }
+            let center: id = msg_send![
+                class!(NSUserNotificationCenter),
+                defaultUserNotificationCenter
+            ];
+            if center == nil {
+                let _: () = msg_send![notification, release];
+                return Err(Error::os_error("defaultUserNotificationCenter"));
+            }
+            let _: () = msg_send![center, setDelegate: self.target];
+            let _: () = msg_send![center, deliverNotification: notification];
+            let _: () = msg_send![notification, release];
+        }
+        Ok(())
+    }
+
+    /// Screen rectangle of the status item button window
+    fn rect(&self) -> Result<(i32, i32, u32, u32), Error> {
+        unsafe {
+            let button: id = msg_send![self.status_item, button];
+            let window: id = msg_send![button, window];
+            if window == nil {
+                return Err(Error::os_error("NSStatusBarButton window"));
+            }
+            let frame: NSRect = msg_send![window, frame];
+            let (x, y) = to_physical(frame.origin.x, frame.origin.y + frame.size.height)
+                .ok_or(Error::os_error("NSScreen"))?;
+            let (right, bottom) = to_physical(frame.origin.x + frame.size.width, frame.origin.y)
+                .ok_or(Error::os_error("NSScreen"))?;
+            Ok((x, y, (right - x) as u32, (bottom - y) as u32))
+        }
+    }
+
+    /// Set menu
+    fn set_menu(&mut self, menu: &MenuBuilder<T>) -> Result<(), Error> {
+        if menu.menu_items.is_empty() {
+            self.menu = None
+        } else {
+            let menu = menu.build()?;
+            menu.menu.set_target(self.target);
+            self.menu = Some(menu);
+        }
+        Ok(())
+    }
+}
+
+impl<T> Debug for MacTrayIconImpl<T>
+where
+    T: Clone + 'static,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TrayIcon")
+    }
+}
+
+impl<T> Drop for MacTrayIconImpl<T>
+where
+    T: Clone + 'static,
+{
+    fn drop(&mut self) {
+        self.invalidate_timer();
+        self.invalidate_tooltip_timer();
+        self.cancel_single_click();
+        unsafe {
+            let center: id = msg_send![class!(NSDistributedNotificationCenter), defaultCenter];
+            let _: () = msg_send![center, removeObserver: self.target];
+            let status_bar: id = msg_send![class!(NSStatusBar), systemStatusBar];
+            let _: () = msg_send![status_bar, removeStatusItem: self.status_item];
+            let _: () = msg_send![self.status_item, release];
+            (*self.target).set_ivar::<*mut c_void>("handler", std::ptr::null_mut());
+            let _: () = msg_send![self.target, release];
+            drop(Box::from_raw(self.handler));
+        }
+    }
+}
+
+/// Objective-C class for the target object, registered once
+fn target_class() -> &'static Class {
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| {
+        let mut decl = ClassDecl::new("TrayIconTarget", class!(NSObject)).unwrap();
+        decl.add_ivar::<*mut c_void>("handler");
+        unsafe {
+            decl.add_method(
+                sel!(onStatusItemClick:),
+                on_status_item_click as extern "C" fn(&Object, Sel, id),
+            );
+            decl.add_method(
+                sel!(onMenuItemClick:),
+                on_menu_item_click as extern "C" fn(&Object, Sel, id),
+            );
+            decl.add_method(
+                sel!(mouseEntered:),
+                on_mouse_entered as extern "C" fn(&Object, Sel, id),
+            );
+            decl.add_method(
+                sel!(mouseExited:),
+                on_mouse_exited as extern "C" fn(&Object, Sel, id),
+            );
+            decl.add_method(
+                sel!(userNotificationCenter:didActivateNotification:),
+                on_notification_click as extern "C" fn(&Object, Sel, id, id),
+            );
+            decl.add_method(
+                sel!(onAnimationTimer:),
+                on_animation_timer as extern "C" fn(&Object, Sel, id),
+            );
+            decl.add_method(
+                sel!(onTooltipTimer:),
+                on_tooltip_timer as extern "C" fn(&Object, Sel, id),
+            );
+            decl.add_method(
+                sel!(onSingleClick:),
+                on_single_click as extern "C" fn(&Object, Sel, id),
+            );
+            decl.add_method(
+                sel!(onThemeChanged:),
+                on_theme_changed as extern "C" fn(&Object, Sel, id),
+            );
+        }
+        decl.register();
+    });
+    class!(TrayIconTarget)
+}
+
+extern "C" fn on_status_item_click(this: &Object, _cmd: Sel, _sender: id) {
+    if let Some(handler) = handler(this) {
+        handler.on_status_item_click();
+    }
+}
+
+extern "C" fn on_menu_item_click(this: &Object, _cmd: Sel, sender: id) {
+    let tag: isize = unsafe { msg_send![sender, tag] };
+    if let Some(handler) = handler(this) {
+        handler.on_menu_item_click(tag as usize);
+    }
+}
+
+extern "C" fn on_mouse_entered(this: &Object, _cmd: Sel, _event: id) {
+    if let Some(handler) = handler(this) {
+        handler.on_mouse_entered();
+    }
+}
+
+extern "C" fn on_mouse_exited(this: &Object, _cmd: Sel, _event: id) {
+    if let Some(handler) = handler(this) {
+        handler.on_mouse_exited();
+    }
+}
+
+extern "C" fn on_notification_click(this: &Object, _cmd: Sel, _center: id, _notification: id) {
+    if let Some(handler) = handler(this) {
+        handler.on_notification_click();
+    }
+}
+
+extern "C" fn on_theme_changed(this: &Object, _cmd: Sel, _notification: id) {
+    if let Some(handler) = handler(this) {
+        handler.on_theme_changed();
+    }
+}
+
+extern "C" fn on_single_click(this: &Object, _cmd: Sel, _object: id) {
+    if let Some(handler) = handler(this) {
+        handler.on_single_click();
+    }
+}
+
+extern "C" fn on_animation_timer(this: &Object, _cmd: Sel, _timer: id) {
+    if let Some(handler) = handler(this) {
+        handler.on_animation_timer();
+    }
+}
+
+extern "C" fn on_tooltip_timer(this: &Object, _cmd: Sel, _timer: id) {
+    if let Some(handler) = handler(this) {
+        handler.on_tooltip_timer();
+    }
+}
+
+/// Invalidate and release the timer, if there is one
+fn invalidate(timer: &mut id) {
+    if *timer != nil {
+        unsafe {
+            let _: () = msg_send![*timer, invalidate];
+            let _: () = msg_send![*timer, release];
+        }
+        *timer = nil;
+    }
+}
+
+fn handler(this: &Object) -> Option<&mut dyn TargetHandler> {
+    unsafe {
+        let ptr: *mut c_void = *this.get_ivar("handler");
+        if ptr.is_null() {
+            None
+        } else {
+            Some(&mut **(ptr as *mut *mut dyn TargetHandler))
+        }
+    }
+}
+
+/// Cursor position in physical pixels from the top left of the main screen
+fn cursor_position() -> PhysicalPosition {
+    let location: NSPoint = unsafe { msg_send![class!(NSEvent), mouseLocation] };
+    to_physical(location.x, location.y)
+        .map(|(x, y)| PhysicalPosition { x, y })
+        .unwrap_or_default()
+}
+
+/// Modifier keys held now, from `NSEventModifierFlags`
+fn key_modifiers() -> Modifiers {
+    let flags: u64 = unsafe { msg_send![class!(NSEvent), modifierFlags] };
+    Modifiers {
+        shift: flags & (1 << 17) != 0,
+        ctrl: flags & (1 << 18) != 0,
+        alt: flags & (1 << 19) != 0,
+        meta: flags & (1 << 20) != 0,
+    }
+}
+
+/// Convert physical pixels from the top left of the main screen to a screen
+/// point, the inverse of `to_physical`
+fn from_physical(x: i32, y: i32) -> Option<NSPoint> {
+    unsafe {
+        let screens: id = msg_send![class!(NSScreen), screens];
+        let screen: id = msg_send![screens, firstObject];
+        if screen == nil {
+            return None;
+        }
+        let frame: NSRect = msg_send![screen, frame];
+        let scale: f64 = msg_send![screen, backingScaleFactor];
+        Some(NSPoint::new(
+            x as f64 / scale,
+            frame.size.height - y as f64 / scale,
+        ))
+    }
+}
+
+/// Convert screen point to physical pixels from the top left of the main screen
+///
+/// Cocoa uses points from the bottom left corner.
+fn to_physical(x: f64, y: f64) -> Option<(i32, i32)> {
+    unsafe {
+        let screens: id = msg_send![class!(NSScreen), screens];
+        let screen: id = msg_send![screens, firstObject];
+        if screen == nil {
+            return None;
+        }
+        let frame: NSRect = msg_send![screen, frame];
+        let scale: f64 = msg_send![screen, backingScaleFactor];
+        Some((
+            (x * scale).round() as i32,
+            ((frame.size.height - y) * scale).round() as i32,
+        ))
+    }
+}
@@ -0,0 +1,84 @@
+use crate::{Error, FontOptions, IconBase};
+
+/// Icon is kept as RGBA pixels, buffers are not decoded
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockIcon {
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+impl MockIcon {
+    fn transparent(width: u32, height: u32) -> MockIcon {
+        MockIcon {
+            width,
+            height,
+            rgba: vec![0; (width * height * 4) as usize],
+        }
+    }
+
+    /// Resources are not loaded, the icon is transparent
+    #[cfg(target_os = "windows")]
+    pub fn from_resource(
+        _resource_id: u16,
+        width: Option<u32>,
+        height: Option<u32>,
+    ) -> Result<MockIcon, Error> {
+        Ok(MockIcon::transparent(
+            width.unwrap_or(16),
+            height.unwrap_or(16),
+        ))
+    }
+
+    /// Stock icons are not loaded, the icon is transparent
+    #[cfg(target_os = "windows")]
+    pub fn from_stock(_stock_icon: crate::StockIcon) -> Result<MockIcon, Error> {
+        Ok(MockIcon::transparent(16, 16))
+    }
+}
+
+impl IconBase for MockIcon {
+    /// Buffer is not decoded, the icon is transparent at the size, or 16x16
+    fn from_buffer(
+        buffer: &[u8],
+        width: Option<u32>,
+        height: Option<u32>,
+    ) -> Result<MockIcon, Error> {
+        if buffer.is_empty() {
+            return Err(Error::IconLoadingFailed);
+        }
+        Ok(MockIcon::transparent(
+            width.unwrap_or(16),
+            height.unwrap_or(16),
+        ))
+    }
+
+    fn from_rgba(rgba: &[u8], width: u32, height: u32) -> Result<MockIcon, Error> {
+        Ok(MockIcon {
+            width,
+            height,
+            rgba: rgba.to_vec(),
+        })
+    }
+
+    /// Text is not rendered, the icon is transparent
+    fn from_text(_text: &str, options: &FontOptions) -> Result<MockIcon, Error> {
+        Ok(MockIcon::transparent(options.icon_size, options.icon_size))
+    }
+
+    /// Scaled to the size by the nearest pixel
+    fn to_rgba(&self, width: u32, height: u32) -> Option<Vec<u8>> {
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+        let mut scaled = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let i = (((y * self.height / height) * self.width + x * self.width / width) * 4)
+                    as usize;
+                scaled.extend_from_slice(self.rgba.get(i..i + 4)?);
+            }
+        }
+        Some(scaled)
+    }
+}
@@ -0,0 +1,423 @@
+#[cfg(target_os = "windows")]
+use std::collections::HashMap;
+use std::time::Duration;
+
+use super::MenuSys;
+use crate::{
+    badge::Decoration,
+    menubuilder::{MenuFn, MenuUpdater, Toggled},
+    trayicon::TooltipFn,
+    trayiconsender::TrayIconSender,
+    ClickBehavior, Error, Icon, MenuBuilder, MenuItem, Modifiers, MouseButton, PhysicalPosition,
+    TrayIconBase, TrayIconEvent,
+};
+#[cfg(target_os = "windows")]
+use crate::{
+    trayiconbuilder::{ErrorFn, RawMessageFn},
+    MenuCommandId,
+};
+
+/// Call of the tray icon recorded by the mock backend
+///
+/// Only the calls reaching the backend are recorded, e.g. `set_icon` with the
+/// same icon is not.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockCall<T>
+where
+    T: Clone + 'static,
+{
+    SetIcon(Icon),
+    SetMenu(MenuBuilder<T>),
+    SetTooltip(String),
+
+    /// Refresh interval, none stops the refreshing
+    SetTooltipFn(Option<Duration>),
+    SetMenuItemCheckable(T, bool),
+    SetMenuItemDisabled(T, bool),
+    SetMenuItemName(T, String),
+    ShowNotification {
+        title: String,
+        body: String,
+    },
+
+    /// Number of the frames
+    SetAnimation(usize),
+    StopAnimation,
+    SetVisible(bool),
+    ShowMenu(Option<PhysicalPosition>),
+
+    /// Badge or progress changed
+    SetDecoration,
+    SetClickEvents(Option<T>, Option<T>, Option<T>),
+}
+
+/// Tray icon recording the calls, without showing anything
+///
+/// Clicks and the menu items are sent to the sender the same as in Windows,
+/// when they are injected with the `mock_` methods of `TrayIcon`.
+#[derive(Debug)]
+pub struct MockTrayIcon<T>
+where
+    T: Clone + 'static,
+{
+    pub(crate) sender: TrayIconSender<T>,
+    pub(crate) menu: Option<MenuSys<T>>,
+    pub(crate) menu_with: Option<MenuFn<T>>,
+    pub(crate) menu_updater: Option<MenuUpdater<T>>,
+    pub(crate) toggled: Option<Toggled<T>>,
+    pub(crate) on_click: Option<T>,
+    pub(crate) on_double_click: Option<T>,
+    pub(crate) on_right_click: Option<T>,
+    pub(crate) on_middle_click: Option<T>,
+    pub(crate) on_menu_open: Option<T>,
+    pub(crate) on_menu_dismissed: Option<T>,
+    pub(crate) menu_on_left_click: bool,
+    pub(crate) click_behavior: ClickBehavior,
+    #[cfg(target_os = "windows")]
+    pub(crate) on_raw_message: Option<RawMessageFn<T>>,
+    #[cfg(target_os = "windows")]
+    pub(crate) on_error: Option<ErrorFn>,
+    pub(crate) calls: Vec<MockCall<T>>,
+}
+
+#[cfg_attr(not(feature = "mock"), allow(dead_code))]
+impl<T> MockTrayIcon<T>
+where
+    T: Clone + 'static,
+{
+    pub(crate) fn calls(&self) -> &[MockCall<T>] {
+        &self.calls
+    }
+
+    /// Click with the button, right click shows the menu
+    ///
+    /// Left click is ignored with `ClickBehavior::DoubleOnly`, and not
+    /// delayed with `SingleWithDelay`.
+    pub(crate) fn click(&mut self, button: MouseButton, modifiers: Modifiers) {
+        if button == MouseButton::Left && self.click_behavior == ClickBehavior::DoubleOnly {
+            return;
+        }
+        let user_event = match button {
+            MouseButton::Left => self.on_click.as_ref(),
+            MouseButton::Right => self.on_right_click.as_ref(),
+            MouseButton::Middle => self.on_middle_click.as_ref(),
+        };
+        self.sender.send(
+            user_event,
+            TrayIconEvent::Click {
+                button,
+                position: PhysicalPosition::default(),
+                modifiers,
+            },
+        );
+        let shows_menu = match button {
+            MouseButton::Left => self.menu_on_left_click,
+            MouseButton::Right => true,
+            MouseButton::Middle => false,
+        };
+        if shows_menu {
+            let _ = self.show_menu(None);
+        }
+    }
+
+    /// Double click, a single click with `ClickBehavior::SingleOnly`
+    pub(crate) fn double_click(&mut self) {
+        if self.click_behavior == ClickBehavior::SingleOnly {
+            return self.click(MouseButton::Left, Modifiers::default());
+        }
+        self.sender.send(
+            self.on_double_click.as_ref(),
+            TrayIconEvent::DoubleClick {
+                position: PhysicalPosition::default(),
+                modifiers: Modifiers::default(),
+            },
+        );
+    }
+
+    /// Choose the menu item, the checkable is toggled if enabled
+    pub(crate) fn select(&mut self, id: &T) -> Result<(), Error>
+    where
+        T: PartialEq,
+    {
+        let menu = match self.menu.as_mut() {
+            Some(menu) => &mut menu.builder,
+            None => return Err(Error::MenuItemNotFound),
+        };
+        if !contains(menu, id) {
+            return Err(Error::MenuItemNotFound);
+        }
+        match (&self.toggled, menu.get_checkable(id.clone())) {
+            (Some(toggled), Some(checked)) => {
+                menu.set_checkable(id.clone(), !checked)?;
+                if let Ok(mut toggled) = toggled.lock() {
+                    toggled.push((id.clone(), !checked));
+                }
+                self.sender.send_menu_item_toggled(id, !checked);
+            }
+            _ => self.sender.send_menu_item(id),
+        }
+        Ok(())
+    }
+
+    /// Message of the hidden window, handled by `on_raw_message`
+    ///
+    /// Returns true if the handler returned an event. A panic of the handler
+    /// is caught and reported to `on_error`, the same as in Windows.
+    #[cfg(target_os = "windows")]
+    pub(crate) fn raw_message(&mut self, msg: u32, wparam: usize, lparam: isize) -> bool {
+        let on_raw_message = match &self.on_raw_message {
+            Some(on_raw_message) => on_raw_message,
+            None => return false,
+        };
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            on_raw_message.call(msg, wparam, lparam)
+        }));
+        match result {
+            Ok(Some(event)) => {
+                self.sender
+                    .send(Some(&event), TrayIconEvent::RawMessage(event.clone()));
+                true
+            }
+            Ok(None) => false,
+            Err(_) => {
+                if let Some(on_error) = &self.on_error {
+                    on_error.call(Error::HandlerPanicked);
+                }
+                false
+            }
+        }
+    }
+
+    /// Always Ok, there is no window
+    #[cfg(target_os = "windows")]
+    pub(crate) fn position_flyout(&self, _hwnd: isize) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Icon is always on the taskbar
+    #[cfg(target_os = "windows")]
+    pub(crate) fn is_visible_in_tray(&self) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    /// Items numbered the same as in Windows
+    #[cfg(target_os = "windows")]
+    pub(crate) fn menu_commands(&self) -> HashMap<MenuCommandId, T> {
+        use crate::menubuilder::CommandNumbering;
+
+        fn collect<T: Clone>(
+            j: &mut CommandNumbering,
+            menu: &MenuBuilder<T>,
+            commands: &mut HashMap<MenuCommandId, T>,
+        ) {
+            for item in &menu.menu_items {
+                let command = match item {
+                    MenuItem::Separator | MenuItem::ColumnBreak => continue,
+                    MenuItem::Submenu { id: None, .. } => None,
+                    item => Some(MenuCommandId(j.next(item) as u16)),
+                };
+                match (command, item) {
+                    (Some(command), MenuItem::Submenu { id: Some(id), .. })
+                    | (Some(command), MenuItem::Item { id, .. })
+                    | (Some(command), MenuItem::Checkable { id, .. }) => {
+                        commands.insert(command, id.clone());
+                    }
+                    _ => {}
+                }
+                if let MenuItem::Submenu { children, .. } = item {
+                    collect(j, children, commands);
+                }
+            }
+        }
+        let mut commands = HashMap::new();
+        if let Some(menu) = &self.menu {
+            collect(
+                &mut CommandNumbering::new(&menu.builder),
+                &menu.builder,
+                &mut commands,
+            );
+        }
+        commands
+    }
+
+    /// There is no window
+    #[cfg(target_os = "windows")]
+    pub(crate) fn hwnd(&self) -> isize {
+        0
+    }
+}
+
+/// Menu has an enabled item with the event, searches the submenus too
+fn contains<T>(menu: &MenuBuilder<T>, id: &T) -> bool
+where
+    T: PartialEq + Clone + 'static,
+{
+    menu.menu_items.iter().any(|item| match item {
+        MenuItem::Item {
+            id: item_id,
+            disabled,
+            ..
+        }
+        | MenuItem::Checkable {
+            id: item_id,
+            disabled,
+            ..
+        } => item_id == id && !*disabled,
+        MenuItem::Submenu {
+            children, disabled, ..
+        } => !*disabled && contains(children, id),
+        MenuItem::Mapped { .. } | MenuItem::Separator | MenuItem::ColumnBreak => false,
+    })
+}
+
+impl<T> TrayIconBase<T> for MockTrayIcon<T>
+where
+    T: Clone + 'static,
+{
+    fn set_icon(&mut self, icon: &Icon) -> Result<(), Error> {
+        self.calls.push(MockCall::SetIcon(icon.clone()));
+        Ok(())
+    }
+
+    fn set_menu(&mut self, menu: &MenuBuilder<T>) -> Result<(), Error> {
+        self.menu = Some(menu.build()?);
+        self.calls.push(MockCall::SetMenu(menu.clone()));
+        Ok(())
+    }
+
+    fn set_tooltip(&mut self, tooltip: &str) -> Result<(), Error> {
+        self.calls.push(MockCall::SetTooltip(tooltip.to_string()));
+        Ok(())
+    }
+
+    /// Function is called once for the tooltip, it's not refreshed
+    fn set_tooltip_fn(
+        &mut self,
+        tooltip_fn: Option<TooltipFn>,
+        interval: Duration,
+    ) -> Result<(), Error> {
+        self.calls.push(MockCall::SetTooltipFn(
+            tooltip_fn.as_ref().map(|_| interval),
+        ));
+        match tooltip_fn {
+            Some(tooltip_fn) => self.set_tooltip(&tooltip_fn.tooltip()),
+            None => Ok(()),
+        }
+    }
+
+    fn set_menu_item_checkable(&mut self, id: &T, checked: bool) -> Result<(), Error>
+    where
+        T: PartialEq,
+    {
+        self.calls
+            .push(MockCall::SetMenuItemCheckable(id.clone(), checked));
+        match self.menu.as_mut() {
+            Some(menu) => menu.builder.set_checkable(id.clone(), checked),
+            None => Err(Error::MenuItemNotFound),
+        }
+    }
+
+    fn set_menu_item_disabled(&mut self, id: &T, disabled: bool) -> Result<(), Error>
+    where
+        T: PartialEq,
+    {
+        self.calls
+            .push(MockCall::SetMenuItemDisabled(id.clone(), disabled));
+        match self.menu.as_mut() {
+            Some(menu) => menu.builder.set_disabled(id.clone(), disabled),
+            None => Err(Error::MenuItemNotFound),
+        }
+    }
+
+    fn set_menu_item_name(&mut self, id: &T, name: &str) -> Result<(), Error>
+    where
+        T: PartialEq,
+    {
+        self.calls
+            .push(MockCall::SetMenuItemName(id.clone(), name.to_string()));
+        match self.menu.as_mut() {
+            Some(menu) => menu.builder.set_name(id.clone(), name),
+            None => Err(Error::MenuItemNotFound),
+        }
+    }
+
+    fn show_notification(
+        &mut self,
+        title: &str,
+        body: &str,
+        _icon: Option<&Icon>,
+        _timeout: Option<Duration>,
+    ) -> Result<(), Error> {
+        self.calls.push(MockCall::ShowNotification {
+            title: title.to_string(),
+            body: body.to_string(),
+        });
+        Ok(())
+    }
+
+    /// Fixed 16x16 rectangle at the origin
+    fn rect(&self) -> Result<(i32, i32, u32, u32), Error> {
+        Ok((0, 0, 16, 16))
+    }
+
+    fn set_animation(&mut self, frames: Vec<Icon>, _interval: Duration) -> Result<(), Error> {
+        self.calls.push(MockCall::SetAnimation(frames.len()));
+        Ok(())
+    }
+
+    fn stop_animation(&mut self) -> Result<(), Error> {
+        self.calls.push(MockCall::StopAnimation);
+        Ok(())
+    }
+
+    fn set_visible(&mut self, visible: bool) -> Result<(), Error> {
+        self.calls.push(MockCall::SetVisible(visible));
+        Ok(())
+    }
+
+    /// Lazily built menu is built, the menu is not shown
+    fn show_menu(&mut self, position: Option<PhysicalPosition>) -> Result<(), Error> {
+        self.sender
+            .send(self.on_menu_open.as_ref(), TrayIconEvent::MenuOpen);
+        if let Some(menu) = self.menu_updater.as_ref().and_then(|u| u.take()) {
+            self.set_menu(&menu)?;
+        }
+        if let Some(menu_with) = self.menu_with.clone() {
+            self.set_menu(&menu_with.build())?;
+        }
+        self.calls.push(MockCall::ShowMenu(position));
+        Ok(())
+    }
+
+    /// Menu is closed without choosing an item
+    fn show_menu_sync(&mut self, position: Option<PhysicalPosition>) -> Result<Option<T>, Error> {
+        self.show_menu(position)?;
+        self.sender.send(
+            self.on_menu_dismissed.as_ref(),
+            TrayIconEvent::MenuDismissed,
+        );
+        Ok(None)
+    }
+
+    fn set_decoration(&mut self, _decoration: Decoration) -> Result<(), Error> {
+        self.calls.push(MockCall::SetDecoration);
+        Ok(())
+    }
+
+    fn set_click_events(
+        &mut self,
+        on_click: Option<T>,
+        on_double_click: Option<T>,
+        on_right_click: Option<T>,
+    ) -> Result<(), Error> {
+        self.calls.push(MockCall::SetClickEvents(
+            on_click.clone(),
+            on_double_click.clone(),
+            on_right_click.clone(),
+        ));
+        self.on_click = on_click;
+        self.on_double_click = on_double_click;
+        self.on_right_click = on_right_click;
+        Ok(())
+    }
+}
@@ -0,0 +1,369 @@
+mod mockicon;
+mod mocktrayicon;
+
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Condvar, Mutex};
+
+use crate::{
+    trayiconhandle::{Attached, Handler, Waker},
+    AppInfo, Capabilities, Error, MenuBuilder, SystemTheme, TrayIconBuilder,
+};
+
+// Mock implementations of Icon, TrayIcon, and Menu, for testing the apps
+pub use mockicon::MockIcon as IconSys;
+pub use mocktrayicon::MockCall;
+pub use mocktrayicon::MockTrayIcon as TrayIconSys;
+
+/// Menu is kept as the builder, for choosing the items by the event
+#[derive(Debug)]
+pub struct MenuSys<T>
+where
+    T: Clone + 'static,
+{
+    builder: MenuBuilder<T>,
+}
+
+/// Build the tray icon, nothing is shown
+pub fn build_trayicon<T>(builder: &TrayIconBuilder<T>) -> Result<TrayIconSys<T>, Error>
+where
+    T: Clone + 'static,
+{
+    builder.themed_icon()?;
+    let sender = builder.sender.clone().ok_or(Error::SenderMissing)?;
+    let menu = match &builder.menu {
+        Some(menu) => Some(menu.build()?),
+        None => None,
+    };
+    Ok(TrayIconSys {
+        sender,
+        menu,
+        menu_with: builder.menu_with.clone(),
+        menu_updater: builder.menu_updater.clone(),
+        toggled: builder.toggled_checkables(),
+        on_click: builder.on_click.clone(),
+        on_double_click: builder.on_double_click.clone(),
+        on_right_click: builder.on_right_click.clone(),
+        on_middle_click: builder.on_middle_click.clone(),
+        on_menu_open: builder.on_menu_open.clone(),
+        on_menu_dismissed: builder.on_menu_dismissed.clone(),
+        menu_on_left_click: builder.menu_on_left_click,
+        click_behavior: builder.click_behavior,
+        #[cfg(target_os = "windows")]
+        on_raw_message: builder.on_raw_message.clone(),
+        #[cfg(target_os = "windows")]
+        on_error: builder.on_error.clone(),
+        calls: vec![],
+    })
+}
+
+/// Build the menu, the items are not validated further
+pub fn build_menu<T>(builder: &MenuBuilder<T>) -> Result<MenuSys<T>, Error>
+where
+    T: Clone + 'static,
+{
+    Ok(MenuSys {
+        builder: builder.clone(),
+    })
+}
+
+/// Nothing to remove
+pub fn shutdown_trayicon<T>(trayicon: TrayIconSys<T>) -> Result<(), Error>
+where
+    T: Clone + 'static,
+{
+    drop(trayicon);
+    Ok(())
+}
+
+/// Everything is supported, the calls are only recorded
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        tray: true,
+        notify_icon_version: None,
+        wine: false,
+        reactos: false,
+        notifications: true,
+        rich_popup: false,
+        rect: true,
+        double_click: true,
+        hover: false,
+        show_menu: true,
+    }
+}
+
+pub fn system_theme() -> Option<SystemTheme> {
+    None
+}
+
+/// Nothing is shown
+pub fn show_about(_app_info: &AppInfo) {}
+
+/// Nothing to clean up
+#[cfg(target_os = "windows")]
+pub fn cleanup_stale_icons(_guid: Option<u128>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Icons are not created
+#[cfg(target_os = "windows")]
+pub fn gdi_handle_count() -> usize {
+    0
+}
+
+/// Names of the apps started at the sign in, the registry is not changed
+#[cfg(target_os = "windows")]
+static AUTOSTART: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+#[cfg(target_os = "windows")]
+pub fn set_autostart(enabled: bool, app_name: &str, _args: &[String]) -> Result<(), Error> {
+    let mut autostart = AUTOSTART.lock().unwrap();
+    autostart.retain(|name| name != app_name);
+    if enabled {
+        autostart.push(app_name.to_string());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+pub fn is_autostart_enabled(app_name: &str) -> bool {
+    AUTOSTART
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|name| name == app_name)
+}
+
+/// Quit was requested, cleared when the last running event loop quits
+struct EventLoops {
+    quit: bool,
+    running: usize,
+}
+
+static EVENT_LOOPS: Mutex<EventLoops> = Mutex::new(EventLoops {
+    quit: false,
+    running: 0,
+});
+static QUIT_CONDVAR: Condvar = Condvar::new();
+
+/// Wait until quit, there are no messages to process
+pub fn run_event_loop() {
+    let mut loops = EVENT_LOOPS.lock().unwrap();
+    loops.running += 1;
+    while !loops.quit {
+        loops = QUIT_CONDVAR.wait(loops).unwrap();
+    }
+    loops.running -= 1;
+    if loops.running == 0 {
+        loops.quit = false;
+    }
+}
+
+/// Wake the threads running the event loops, a loop started after this quits
+/// right away
+pub fn quit_event_loop() {
+    EVENT_LOOPS.lock().unwrap().quit = true;
+    QUIT_CONDVAR.notify_all();
+}
+
+/// Build on a dedicated thread, which runs the handler when woken
+///
+/// Handler returns false to quit the thread.
+pub fn spawn_detached<F>(build: F) -> Result<Attached, Error>
+where
+    F: FnOnce() -> Result<Handler, Error> + Send + 'static,
+{
+    let (result, receiver) = channel();
+    let (wake, woken) = channel::<()>();
+    let thread = std::thread::spawn(move || match build() {
+        Ok(handler) => {
+            let _ = result.send(Ok(()));
+            run_handler(handler, woken);
+        }
+        Err(err) => {
+            let _ = result.send(Err(err));
+        }
+    });
+    receiver.recv().map_err(|_| Error::TrayIconStopped)??;
+    let waker: Waker = Box::new(move || {
+        let _ = wake.send(());
+    });
+    Ok((waker, thread.thread().id()))
+}
+
+/// Run the handler on a thread when woken
+pub fn attach_handler(handler: Handler) -> Result<Attached, Error> {
+    let (wake, woken) = channel::<()>();
+    let thread = std::thread::spawn(move || run_handler(handler, woken));
+    let waker: Waker = Box::new(move || {
+        let _ = wake.send(());
+    });
+    Ok((waker, thread.thread().id()))
+}
+
+fn run_handler(mut handler: Handler, woken: Receiver<()>) {
+    for () in woken {
+        if !handler() {
+            break;
+        }
+    }
+}
+
+/// Single instance in the mock is always the primary one
+#[cfg(target_os = "windows")]
+#[derive(Debug)]
+pub struct SingleInstanceSys;
+
+#[cfg(target_os = "windows")]
+impl SingleInstanceSys {
+    pub fn new(_name: &str) -> Result<SingleInstanceSys, Error> {
+        Ok(SingleInstanceSys)
+    }
+
+    pub fn is_primary(&self) -> bool {
+        true
+    }
+
+    /// There is no other instance to forward to
+    pub fn forward(_name: &str, _args: &[String]) -> Result<(), Error> {
+        Err(Error::Unsupported)
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::{ClickBehavior, Modifiers, MouseButton, TrayIconEvent};
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum Events {
+        RightClick,
+        CheckableItem1,
+        Item1,
+    }
+
+    #[test]
+    fn test_mock_event_loop() {
+        quit_event_loop();
+        run_event_loop();
+
+        let thread = std::thread::spawn(run_event_loop);
+        quit_event_loop();
+        thread.join().unwrap();
+        assert!(!EVENT_LOOPS.lock().unwrap().quit);
+    }
+
+    #[test]
+    fn test_mock_click_behavior() {
+        let (s, r) = std::sync::mpsc::channel::<Events>();
+        let mut trayicon = TrayIconBuilder::new()
+            .sender(s)
+            .icon_from_buffer(&[0])
+            .on_click(Events::Item1)
+            .on_double_click(Events::RightClick)
+            .click_behavior(ClickBehavior::DoubleOnly)
+            .build()
+            .unwrap();
+        trayicon.mock_click(MouseButton::Left);
+        trayicon.mock_double_click();
+        assert_eq!(r.try_iter().collect::<Vec<_>>(), vec![Events::RightClick]);
+    }
+
+    #[test]
+    fn test_mock_click_modifiers() {
+        let (s, r) = std::sync::mpsc::channel();
+        let mut trayicon = TrayIconBuilder::<Events>::new()
+            .sender_events(s)
+            .icon_from_buffer(&[0])
+            .build()
+            .unwrap();
+        let modifiers = Modifiers {
+            ctrl: true,
+            ..Modifiers::default()
+        };
+        trayicon.mock_click_with_modifiers(MouseButton::Middle, modifiers);
+        assert!(matches!(
+            r.try_recv(),
+            Ok(TrayIconEvent::Click { modifiers: m, .. }) if m == modifiers
+        ));
+    }
+
+    #[test]
+    fn test_mock_menu_dismissed() {
+        let (s, r) = std::sync::mpsc::channel::<Events>();
+        let mut trayicon = TrayIconBuilder::new()
+            .sender(s)
+            .icon_from_buffer(&[0])
+            .on_menu_dismissed(Events::RightClick)
+            .menu(MenuBuilder::new().item("Item 1", Events::Item1))
+            .build()
+            .unwrap();
+        assert_eq!(trayicon.show_menu_sync(), Ok(None));
+        assert_eq!(r.try_recv(), Ok(Events::RightClick));
+    }
+
+    #[test]
+    fn test_mock_calls_and_events() {
+        let (s, r) = std::sync::mpsc::channel::<Events>();
+        let mut trayicon = TrayIconBuilder::new()
+            .sender(s)
+            .icon_from_buffer(&[0])
+            .on_right_click(Events::RightClick)
+            .auto_toggle_checkables(true)
+            .menu(
+                MenuBuilder::new()
+                    .checkable("Checkable", false, Events::CheckableItem1)
+                    .item("Item 1", Events::Item1),
+            )
+            .build()
+            .unwrap();
+
+        trayicon.set_tooltip("Tooltip").unwrap();
+        trayicon.mock_click(MouseButton::Right);
+        trayicon.mock_select(&Events::CheckableItem1).unwrap();
+        assert_eq!(
+            trayicon.mock_calls(),
+            &[
+                MockCall::SetTooltip("Tooltip".to_string()),
+                MockCall::ShowMenu(None),
+            ]
+        );
+        assert_eq!(r.try_recv(), Ok(Events::RightClick));
+        assert_eq!(r.try_recv(), Ok(Events::CheckableItem1));
+        assert_eq!(
+            trayicon.get_menu_item_checkable(Events::CheckableItem1),
+            Some(true)
+        );
+
+        trayicon
+            .set_menu_item_disabled(Events::Item1, true)
+            .unwrap();
+        assert_eq!(
+            trayicon.mock_select(&Events::Item1),
+            Err(Error::MenuItemNotFound)
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "windows")]
+    fn test_mock_raw_message() {
+        let (s, r) = std::sync::mpsc::channel::<Events>();
+        let reported = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let errors = reported.clone();
+        let mut trayicon = TrayIconBuilder::new()
+            .sender(s)
+            .icon_from_buffer(&[0])
+            .on_raw_message(|msg, _, _| match msg {
+                1 => Some(Events::Item1),
+                2 => panic!("handler panicked"),
+                _ => None,
+            })
+            .on_error(move |err| errors.lock().unwrap().push(err))
+            .build()
+            .unwrap();
+        assert!(trayicon.mock_raw_message(1, 0, 0));
+        assert!(!trayicon.mock_raw_message(3, 0, 0));
+        assert!(!trayicon.mock_raw_message(2, 0, 0));
+        assert_eq!(r.try_iter().collect::<Vec<_>>(), vec![Events::Item1]);
+        assert_eq!(*reported.lock().unwrap(), vec![Error::HandlerPanicked]);
+    }
+}
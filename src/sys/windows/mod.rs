@@ -1,129 +1,822 @@
 mod wchar;
+mod winautostart;
+mod windetached;
 mod winhicon;
 mod winhmenu;
 mod winnotifyicon;
+mod winsession;
+mod winsingleinstance;
 mod wintrayicon;
+mod winuxtheme;
+mod winwindow;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use winapi::shared::minwindef::{DWORD, UINT};
+use winapi::shared::windef::{HMENU, HWND};
+use winapi::shared::winerror::ERROR_SUCCESS;
+use winapi::um::{libloaderapi, winnls, winreg, winuser};
 use wintrayicon::WinTrayIconImpl;
 
-use crate::{Error, MenuBuilder, MenuItem, TrayIconBuilder};
+use crate::{
+    menubuilder::{menu_label, CommandNumbering},
+    AppInfo, Capabilities, Error, EventFn, HorizontalAlign, Icon, MenuBuilder, MenuCommandId,
+    MenuItem, MenuItemStyle, MenuOptions, MenuTheme, NotifyIconVersion, SystemTheme,
+    TrayIconBuilder, VerticalAlign,
+};
+use wchar::wchar;
 use winhmenu::WinHMenu;
 use winnotifyicon::WinNotifyIcon;
 
 // Windows implementations of Icon, TrayIcon, and Menu
-pub use winhicon::WinHIcon as IconSys;
+pub use winautostart::{is_autostart_enabled, set_autostart};
+pub use windetached::{attach_handler, spawn_detached};
+pub use winhicon::{gdi_handle_count, WinHIcon as IconSys};
+pub use winsingleinstance::WinSingleInstance as SingleInstanceSys;
 pub use wintrayicon::WinTrayIcon as TrayIconSys;
 
 #[derive(Debug)]
 pub struct MenuSys<T>
 where
-    T: PartialEq + Clone + 'static,
+    T: Clone + 'static,
 {
     ids: HashMap<usize, T>,
     menu: WinHMenu,
+
+    /// Command identifier of the default item
+    default: Option<usize>,
+
+    /// Theme of the top level menu, set when it's shown
+    theme: Option<MenuTheme>,
+
+    /// Flags of `TrackPopupMenuEx`, from the options of the top level menu
+    track_flags: UINT,
+
+    /// Command identifiers of the checkable items
+    checkables: HashSet<usize>,
+
+    /// Events of the items built when clicked
+    mapped: HashMap<usize, EventFn<T>>,
+
+    /// Shortcut texts of the items, shown after the name
+    accelerators: HashMap<usize, String>,
+
+    /// Command identifiers of the items with the mnemonics in the name
+    mnemonics: HashSet<usize>,
+
+    /// Descriptions of the items, sent when highlighted
+    descriptions: HashMap<usize, String>,
+
+    /// Items of the menu, for updating only the changed items
+    builder: MenuBuilder<T>,
+}
+
+impl<T> MenuSys<T>
+where
+    T: Clone + 'static,
+{
+    /// Update the menu in place to the builder
+    ///
+    /// Items are matched with the previous builder, and only the changed items
+    /// are modified, inserted or removed, so the menu doesn't flicker and the
+    /// icons of the unchanged items are retained. Command identifiers are
+    /// numbered in the order of the items, the same as when built, around the
+    /// fixed identifiers.
+    fn update(&mut self, builder: &MenuBuilder<T>) -> Result<(), Error> {
+        trace_span!(DEBUG, "update_menu", items = builder.menu_items.len());
+        let builder = &builder.with_disabled_submenus();
+        let mut j = CommandNumbering::new(builder);
+        update_menu_inner(&mut j, &mut self.menu, &self.builder, builder)?;
+        self.ids.clear();
+        self.default = None;
+        self.checkables.clear();
+        self.mapped.clear();
+        self.accelerators.clear();
+        self.mnemonics.clear();
+        self.descriptions.clear();
+        let mut j = CommandNumbering::new(builder);
+        self.index(&mut j, builder);
+        self.builder = builder.clone();
+        self.theme = builder.theme;
+        self.track_flags = track_flags(&builder.options.unwrap_or_default());
+        Ok(())
+    }
+
+    /// Submenu item with the handle, searches the nested submenus too
+    fn submenu(&self, hmenu: HMENU) -> Option<&MenuItem<T>> {
+        find_submenu(&self.menu, &self.builder, hmenu)
+    }
+
+    /// Events, checkables and the texts of the items by the command identifier
+    fn index(&mut self, j: &mut CommandNumbering, builder: &MenuBuilder<T>) {
+        for item in &builder.menu_items {
+            match item {
+                MenuItem::Submenu { id, children, .. } => {
+                    if let Some(id) = id {
+                        self.ids.insert(j.next(item), id.clone());
+                    }
+                    self.index(j, children);
+                }
+                MenuItem::Checkable {
+                    id, description, ..
+                } => {
+                    let command = j.next(item);
+                    self.ids.insert(command, id.clone());
+                    self.checkables.insert(command);
+                    if let Some(description) = description {
+                        self.descriptions.insert(command, description.clone());
+                    }
+                }
+                MenuItem::Item {
+                    id,
+                    is_default,
+                    accelerator,
+                    description,
+                    mnemonics,
+                    ..
+                } => {
+                    let command = j.next(item);
+                    self.ids.insert(command, id.clone());
+                    if let Some(accelerator) = accelerator {
+                        self.accelerators.insert(command, accelerator.clone());
+                    }
+                    if *mnemonics {
+                        self.mnemonics.insert(command);
+                    }
+                    if let Some(description) = description {
+                        self.descriptions.insert(command, description.clone());
+                    }
+                    if *is_default {
+                        self.default = Some(command);
+                    }
+                }
+                MenuItem::Mapped { map, .. } => {
+                    self.mapped.insert(j.next(item), map.clone());
+                }
+                MenuItem::Separator | MenuItem::ColumnBreak => {}
+            }
+        }
+    }
+
+    /// Events of the items by the command identifier, without the mapped items
+    pub(crate) fn command_events(&self) -> HashMap<MenuCommandId, T> {
+        self.ids
+            .iter()
+            .map(|(j, id)| (MenuCommandId(*j as u16), id.clone()))
+            .collect()
+    }
+}
+
+impl<T> MenuSys<T>
+where
+    T: PartialEq + Clone + 'static,
+{
+    /// Command identifiers of the menu items with the event
+    fn command_ids<'a>(&'a self, id: &'a T) -> impl Iterator<Item = usize> + 'a {
+        self.ids
+            .iter()
+            .filter(move |(_, v)| *v == id)
+            .map(|(k, _)| *k)
+    }
 }
 
 /// Build the tray icon
 pub fn build_trayicon<T>(builder: &TrayIconBuilder<T>) -> Result<TrayIconSys<T>, Error>
 where
-    T: PartialEq + Clone + 'static,
+    T: Clone + 'static,
 {
     let mut menu: Option<MenuSys<T>> = None;
-    let tooltip = &builder.tooltip;
-    let hicon = &builder.icon.as_ref()?.sys;
-    let on_click = builder.on_click.clone();
-    let on_right_click = builder.on_right_click.clone();
+    let rtl = builder.rtl.unwrap_or_else(locale_rtl);
+    let tooltip = &builder.tooltip.as_ref().map(|t| reading_order(t, rtl));
+    let icon = builder.themed_icon()?.clone();
     let sender = builder.sender.clone().ok_or(Error::SenderMissing)?;
-    let on_double_click = builder.on_double_click.clone();
-    let notify_icon = WinNotifyIcon::new(hicon, tooltip);
+
+    // Wine and ReactOS accept NOTIFYICON_VERSION_4 without sending all of its
+    // messages, so the rich popup would never open and the tooltip would be
+    // hidden for nothing
+    let limited_shell = is_wine() || is_reactos();
+    let rich_popup = builder.rich_popup && !limited_shell;
+    let version = if rich_popup {
+        NotifyIconVersion::V4
+    } else if limited_shell {
+        builder.notify_icon_version.min(NotifyIconVersion::V3)
+    } else {
+        builder.notify_icon_version
+    };
+    if limited_shell && version != builder.notify_icon_version {
+        trace_event!(
+            INFO,
+            "limited shell, using the notify icon version {:?}",
+            version
+        );
+    }
+    let notify_icon = WinNotifyIcon::new(
+        &icon.sys,
+        tooltip,
+        builder.guid,
+        builder.uid,
+        version,
+        rich_popup,
+    );
 
     // Try to get a popup menu
     if let Some(rhmenu) = &builder.menu {
         menu = Some(rhmenu.build()?);
     }
+    let alt_menu = match &builder.alt_menu {
+        Some(alt_menu) => Some((alt_menu.build()?, builder.alt_menu_modifiers)),
+        None => None,
+    };
 
-    Ok(WinTrayIconImpl::new(
-        sender,
-        menu,
-        notify_icon,
-        on_click,
-        on_double_click,
-        on_right_click,
-    )?)
+    WinTrayIconImpl::new(builder, sender, menu, alt_menu, icon, notify_icon, rtl)
+}
+
+/// Message box with the information icon, in the foreground
+pub fn show_about(app_info: &AppInfo) {
+    unsafe {
+        winuser::MessageBoxW(
+            std::ptr::null_mut(),
+            wchar(&app_info.text()).as_ptr(),
+            wchar(&format!("About {}", app_info.name)).as_ptr(),
+            winuser::MB_OK | winuser::MB_ICONINFORMATION | winuser::MB_SETFOREGROUND,
+        )
+    };
+}
+
+/// Remove the ghost icons left by the crashed instances
+pub fn cleanup_stale_icons(guid: Option<u128>) -> Result<(), Error> {
+    if !winnotifyicon::remove_stale_icons(guid) {
+        return Err(Error::os_error("Shell_TrayWnd"));
+    }
+    Ok(())
+}
+
+/// Destroy the window of the tray icon, callable from any thread
+pub fn shutdown_trayicon<T>(mut trayicon: TrayIconSys<T>) -> Result<(), Error>
+where
+    T: Clone + 'static,
+{
+    trayicon.close()
+}
+
+/// Reading layout of the user's locale is right to left
+fn locale_rtl() -> bool {
+    // Missing from winapi, the layout is 1 for right to left
+    const LOCALE_IREADINGLAYOUT: DWORD = 0x70;
+    const LOCALE_RETURN_NUMBER: DWORD = 0x2000_0000;
+    let mut layout: DWORD = 0;
+    let res = unsafe {
+        winnls::GetLocaleInfoEx(
+            std::ptr::null(),
+            LOCALE_IREADINGLAYOUT | LOCALE_RETURN_NUMBER,
+            &mut layout as *mut DWORD as _,
+            (std::mem::size_of::<DWORD>() / 2) as _,
+        )
+    };
+    res != 0 && layout == 1
+}
+
+/// Tooltip has no layout flag, the leading right-to-left mark sets the reading
+/// order of the text
+fn reading_order(text: &str, rtl: bool) -> String {
+    if rtl && !text.is_empty() && !text.starts_with('\u{200f}') {
+        format!("\u{200f}{}", text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Query the shell
+///
+/// Shell_NotifyIconGetRect is available since Windows 7, which supports
+/// NOTIFYICON_VERSION_4 too. Wine is detected by the export of its ntdll.
+pub fn capabilities() -> Capabilities {
+    let tray = unsafe {
+        !winuser::FindWindowW(wchar("Shell_TrayWnd").as_ptr(), std::ptr::null()).is_null()
+    };
+    let wine = is_wine();
+    let reactos = is_reactos();
+    let rect = has_export("shell32.dll", "Shell_NotifyIconGetRect");
+    let version = if rect {
+        NotifyIconVersion::V4
+    } else {
+        NotifyIconVersion::V3
+    };
+    Capabilities {
+        tray,
+        notify_icon_version: Some(version),
+        wine,
+        reactos,
+        notifications: tray,
+        rich_popup: tray && version == NotifyIconVersion::V4 && !wine && !reactos,
+        rect,
+        double_click: true,
+        hover: true,
+        show_menu: true,
+    }
+}
+
+/// Taskbar theme from the personalization settings
+///
+/// Before Windows 10 1903 the setting doesn't exist, then the theme is not
+/// known.
+pub fn system_theme() -> Option<SystemTheme> {
+    let mut value: DWORD = 0;
+    let mut size = std::mem::size_of::<DWORD>() as DWORD;
+    let res = unsafe {
+        winreg::RegGetValueW(
+            winreg::HKEY_CURRENT_USER,
+            wchar("Software\\Microsoft\\Windows\\CurrentVersion\\Themes\\Personalize").as_ptr(),
+            wchar("SystemUsesLightTheme").as_ptr(),
+            winreg::RRF_RT_REG_DWORD,
+            std::ptr::null_mut(),
+            &mut value as *mut DWORD as _,
+            &mut size,
+        )
+    };
+    if res != ERROR_SUCCESS as i32 {
+        None
+    } else if value != 0 {
+        Some(SystemTheme::Light)
+    } else {
+        Some(SystemTheme::Dark)
+    }
+}
+
+/// Wine exports its version from ntdll
+fn is_wine() -> bool {
+    has_export("ntdll.dll", "wine_get_version")
+}
+
+/// ReactOS reports a Windows version, but its own product name
+fn is_reactos() -> bool {
+    let mut name: [u16; 64] = [0; 64];
+    let mut size = (name.len() * 2) as DWORD;
+    let res = unsafe {
+        winreg::RegGetValueW(
+            winreg::HKEY_LOCAL_MACHINE,
+            wchar("Software\\Microsoft\\Windows NT\\CurrentVersion").as_ptr(),
+            wchar("ProductName").as_ptr(),
+            winreg::RRF_RT_REG_SZ,
+            std::ptr::null_mut(),
+            name.as_mut_ptr() as _,
+            &mut size,
+        )
+    };
+    res == ERROR_SUCCESS as i32 && String::from_utf16_lossy(&name).starts_with("ReactOS")
+}
+
+/// Module is loaded and exports the function
+fn has_export(module: &str, function: &str) -> bool {
+    unsafe {
+        let module = libloaderapi::GetModuleHandleW(wchar(module).as_ptr());
+        if module.is_null() {
+            return false;
+        }
+        let function = format!("{}\0", function);
+        !libloaderapi::GetProcAddress(module, function.as_ptr() as _).is_null()
+    }
+}
+
+/// Quit was requested, cleared when the last running event loop quits
+static QUIT: AtomicBool = AtomicBool::new(false);
+
+/// Wake windows of the running event loops
+static EVENT_LOOPS: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+/// Pump the messages of the thread until quit
+///
+/// Quit is posted to the wake window of the loop, the messages posted to
+/// windows are dispatched also while a menu or a message box is shown. The
+/// woken window posts the quit message, and destroys itself.
+pub fn run_event_loop() {
+    let wake =
+        unsafe { windetached::create_wake_window(Box::new(|| !QUIT.load(Ordering::SeqCst)), true) };
+    let hwnd = match wake {
+        Ok(hwnd) => {
+            EVENT_LOOPS.lock().unwrap().push(hwnd as usize);
+            hwnd as usize
+        }
+        Err(err) => {
+            trace_event!(WARN, "event loop can't be quit: {}", err);
+            0
+        }
+    };
+
+    // Quit of a nested loop is seen by the loop running it when it returns
+    unsafe {
+        let mut msg: winuser::MSG = std::mem::zeroed();
+        while !QUIT.load(Ordering::SeqCst)
+            && winuser::GetMessageW(&mut msg, std::ptr::null_mut(), 0, 0) > 0
+        {
+            winuser::TranslateMessage(&msg);
+            winuser::DispatchMessageW(&msg);
+        }
+        if hwnd != 0 && winuser::IsWindow(hwnd as HWND) != 0 {
+            winuser::DestroyWindow(hwnd as HWND);
+        }
+    }
+
+    let mut loops = EVENT_LOOPS.lock().unwrap();
+    if let Some(j) = loops.iter().position(|h| *h == hwnd) {
+        loops.remove(j);
+    }
+    if loops.is_empty() {
+        QUIT.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Wake the running event loops, a loop started after this quits right away
+pub fn quit_event_loop() {
+    QUIT.store(true, Ordering::SeqCst);
+    for hwnd in EVENT_LOOPS.lock().unwrap().iter() {
+        unsafe { winuser::PostMessageW(*hwnd as HWND, msgs::WM_USER_WAKE, 0, 0) };
+    }
 }
 
 /// Build the menu from Windows HMENU
+///
+/// Items are inserted to an empty menu the same way as they're updated.
 pub fn build_menu<T>(builder: &MenuBuilder<T>) -> Result<MenuSys<T>, Error>
 where
-    T: PartialEq + Clone + 'static,
+    T: Clone + 'static,
 {
-    let mut j = 0;
-    build_menu_inner(&mut j, builder)
+    trace_span!(DEBUG, "build_menu", items = builder.menu_items.len());
+    let mut menu = MenuSys {
+        ids: HashMap::new(),
+        menu: WinHMenu::new()?,
+        default: None,
+        theme: None,
+        track_flags: 0,
+        checkables: HashSet::new(),
+        mapped: HashMap::new(),
+        accelerators: HashMap::new(),
+        mnemonics: HashSet::new(),
+        descriptions: HashMap::new(),
+        builder: MenuBuilder::new(),
+    };
+    menu.update(builder)?;
+    Ok(menu)
 }
 
-/// Recursive menu builder
-///
-/// Having a j value as mutable reference it's capable of handling nested
-/// submenus
-fn build_menu_inner<T>(j: &mut usize, builder: &MenuBuilder<T>) -> Result<MenuSys<T>, Error>
+/// Recursive search of the submenu, positioned the same as in `update_menu_inner`
+fn find_submenu<'a, T>(
+    menu: &WinHMenu,
+    builder: &'a MenuBuilder<T>,
+    hmenu: HMENU,
+) -> Option<&'a MenuItem<T>>
 where
-    T: PartialEq + Clone + 'static,
+    T: Clone + 'static,
 {
-    let mut hmenu = WinHMenu::new()?;
-    let mut map: HashMap<usize, T> = HashMap::new();
-    builder.menu_items.iter().for_each(|item| match item {
-        MenuItem::Submenu {
-            id,
-            name,
-            children,
-            disabled,
-            ..
-        } => {
-            if let Some(id) = id {
-                *j += 1;
-                map.insert(*j, id.clone());
-            }
-            if let Ok(menusys) = build_menu_inner(j, children) {
-                map.extend(menusys.ids.into_iter());
-                hmenu.add_child_menu(&name, menusys.menu, *disabled);
+    menu_entries(builder)
+        .into_iter()
+        .enumerate()
+        .find_map(|(pos, (item, _))| match item {
+            MenuItem::Submenu { children, .. } => {
+                let child = menu.child_menu(pos as UINT)?;
+                if child.is(hmenu) {
+                    Some(item)
+                } else {
+                    find_submenu(child, children, hmenu)
+                }
             }
+            _ => None,
+        })
+}
+
+/// Change of a menu item, when updating the menu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MenuEdit {
+    /// Previous item is modified to the new item
+    Modify,
+    Remove,
+    Insert,
+}
+
+/// Items of a menu, with the column break before each
+///
+/// Column break is a flag of the next item in the HMENU, so the positions of
+/// the items are without the breaks.
+fn menu_entries<T>(builder: &MenuBuilder<T>) -> Vec<(&MenuItem<T>, bool)>
+where
+    T: Clone + 'static,
+{
+    let mut column_break = false;
+    let mut entries = vec![];
+    for item in &builder.menu_items {
+        match item {
+            MenuItem::ColumnBreak => column_break = true,
+            item => entries.push((item, std::mem::take(&mut column_break))),
         }
+    }
+    entries
+}
 
-        MenuItem::Checkable {
-            name,
-            is_checked,
-            id,
-            disabled,
-            ..
-        } => {
-            *j += 1;
-            map.insert(*j, id.clone());
-            hmenu.add_checkable_item(&name, *is_checked, *j, *disabled);
+/// How well the previous item can be modified to the new one
+///
+/// Zero if the kind differs and the item must be replaced, and higher if the
+/// text is the same too.
+fn similarity<T>(previous: &MenuItem<T>, item: &MenuItem<T>) -> usize
+where
+    T: Clone + 'static,
+{
+    let (a, b) = match (previous, item) {
+        (MenuItem::Separator, MenuItem::Separator) => return 2,
+        (
+            MenuItem::Item { name: a, .. } | MenuItem::Mapped { name: a, .. },
+            MenuItem::Item { name: b, .. } | MenuItem::Mapped { name: b, .. },
+        )
+        | (MenuItem::Checkable { name: a, .. }, MenuItem::Checkable { name: b, .. })
+        | (MenuItem::Submenu { name: a, .. }, MenuItem::Submenu { name: b, .. }) => (a, b),
+        _ => return 0,
+    };
+    if a == b {
+        2
+    } else {
+        1
+    }
+}
+
+/// Edits from the previous items to the new items, modifying as many similar
+/// items as possible
+fn menu_edits<T>(previous: &[(&MenuItem<T>, bool)], items: &[(&MenuItem<T>, bool)]) -> Vec<MenuEdit>
+where
+    T: Clone + 'static,
+{
+    // Best similarity of the rest of the items, from each pair of positions
+    let (n, m) = (previous.len(), items.len());
+    let mut best = vec![vec![0; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for k in (0..m).rev() {
+            let modify = match similarity(previous[i].0, items[k].0) {
+                0 => 0,
+                s => s + best[i + 1][k + 1],
+            };
+            best[i][k] = modify.max(best[i + 1][k]).max(best[i][k + 1]);
+        }
+    }
+
+    let mut edits = vec![];
+    let (mut i, mut k) = (0, 0);
+    while i < n || k < m {
+        let s = if i < n && k < m {
+            similarity(previous[i].0, items[k].0)
+        } else {
+            0
+        };
+        if s > 0 && best[i][k] == s + best[i + 1][k + 1] {
+            edits.push(MenuEdit::Modify);
+            i += 1;
+            k += 1;
+        } else if i < n && (k == m || best[i][k] == best[i + 1][k]) {
+            edits.push(MenuEdit::Remove);
+            i += 1;
+        } else {
+            edits.push(MenuEdit::Insert);
+            k += 1;
         }
+    }
+    edits
+}
 
-        MenuItem::Item {
-            name, id, disabled, ..
-        } => {
-            *j += 1;
-            map.insert(*j, id.clone());
-            hmenu.add_menu_item(&name, *j, *disabled);
+/// Recursive menu updater
+///
+/// Having a j value as mutable reference it's capable of numbering the items
+/// of the nested submenus.
+fn update_menu_inner<T>(
+    j: &mut CommandNumbering,
+    hmenu: &mut WinHMenu,
+    previous: &MenuBuilder<T>,
+    builder: &MenuBuilder<T>,
+) -> Result<(), Error>
+where
+    T: Clone + 'static,
+{
+    let previous = menu_entries(previous);
+    let items = menu_entries(builder);
+    let (mut i, mut k) = (0, 0);
+    let mut pos = 0;
+    for edit in menu_edits(&previous, &items) {
+        if edit == MenuEdit::Remove {
+            hmenu.remove_item(pos)?;
+            i += 1;
+            continue;
         }
 
-        MenuItem::Separator => {
-            hmenu.add_separator();
+        let (item, column_break) = items[k];
+        let previous_item = match edit {
+            MenuEdit::Modify => Some(previous[i].0),
+            _ => None,
+        };
+        let mut ftype = if column_break {
+            winuser::MFT_MENUBARBREAK
+        } else {
+            0
+        };
+        match item {
+            MenuItem::Submenu {
+                id,
+                name,
+                children,
+                disabled,
+                ..
+            } => {
+                if id.is_some() {
+                    j.next(item);
+                }
+                match previous_item {
+                    Some(MenuItem::Submenu {
+                        children: previous_children,
+                        ..
+                    }) => {
+                        let child = hmenu
+                            .child_menu_mut(pos)
+                            .ok_or(Error::os_error("GetSubMenu"))?;
+                        update_menu_inner(j, child, previous_children, children)?;
+                    }
+                    _ => {
+                        let mut child = WinHMenu::new()?;
+                        update_menu_inner(j, &mut child, &MenuBuilder::new(), children)?;
+                        hmenu.insert_child_menu(pos, child)?;
+                    }
+                }
+                let name = menu_label(name, false, Some('&'));
+                hmenu.set_item(pos, None, &name, ftype, state(*disabled, false, false))?;
+            }
+
+            MenuItem::Separator => {
+                if previous_item.is_none() {
+                    hmenu.insert_item(pos, true)?;
+                }
+                ftype |= winuser::MFT_SEPARATOR;
+                hmenu.set_item(pos, None, "", ftype, 0)?;
+            }
+
+            item => {
+                let command = j.next(item);
+                let (name, disabled, checked, default) = match item {
+                    MenuItem::Checkable {
+                        name,
+                        is_checked,
+                        disabled,
+                        style,
+                        ..
+                    } => (
+                        menu_label(name, false, Some('&')),
+                        *disabled,
+                        *is_checked,
+                        style.as_ref().and_then(|s| s.bold).unwrap_or(false),
+                    ),
+                    MenuItem::Item {
+                        name,
+                        disabled,
+                        is_default,
+                        accelerator: Some(accelerator),
+                        mnemonics,
+                        ..
+                    } => (
+                        format!(
+                            "{}\t{}",
+                            menu_label(name, *mnemonics, Some('&')),
+                            accelerator
+                        ),
+                        *disabled,
+                        false,
+                        *is_default,
+                    ),
+                    MenuItem::Item {
+                        name,
+                        disabled,
+                        is_default,
+                        mnemonics,
+                        ..
+                    } => (
+                        menu_label(name, *mnemonics, Some('&')),
+                        *disabled,
+                        false,
+                        *is_default,
+                    ),
+                    MenuItem::Mapped { name, disabled, .. } => {
+                        (menu_label(name, false, Some('&')), *disabled, false, false)
+                    }
+                    _ => unreachable!(),
+                };
+                let default = match item {
+                    MenuItem::Item {
+                        style:
+                            Some(MenuItemStyle {
+                                bold: Some(bold), ..
+                            }),
+                        ..
+                    } => *bold,
+                    _ => default,
+                };
+                if previous_item.is_none() {
+                    hmenu.insert_item(pos, false)?;
+                }
+                hmenu.set_item(
+                    pos,
+                    Some(command),
+                    &name,
+                    ftype,
+                    state(disabled, checked, default),
+                )?;
+            }
+        }
+        // Item is shown without the icon if it can't be rendered
+        let icon = item_icon(item);
+        if icon != previous_item.and_then(item_icon) {
+            let _ = hmenu.set_icon(pos, icon);
+        }
+        let check_icons = item_check_icons(item);
+        if check_icons != previous_item.map_or((None, None), item_check_icons) {
+            let _ = hmenu.set_check_icons(pos, check_icons.0, check_icons.1);
         }
-    });
 
-    Ok(MenuSys {
-        ids: map,
-        menu: hmenu,
-    })
+        if edit == MenuEdit::Modify {
+            i += 1;
+        }
+        k += 1;
+        pos += 1;
+    }
+    Ok(())
+}
+
+/// Icon of the item, if it has one
+fn item_icon<T>(item: &MenuItem<T>) -> Option<&Icon>
+where
+    T: Clone + 'static,
+{
+    match item {
+        MenuItem::Item { icon, .. }
+        | MenuItem::Checkable { icon, .. }
+        | MenuItem::Submenu { icon, .. }
+        | MenuItem::Mapped { icon, .. } => icon.as_ref(),
+        MenuItem::Separator | MenuItem::ColumnBreak => None,
+    }
+}
+
+/// Icons of the checkable shown instead of the checkmark, checked one first
+fn item_check_icons<T>(item: &MenuItem<T>) -> (Option<&Icon>, Option<&Icon>)
+where
+    T: Clone + 'static,
+{
+    match item {
+        MenuItem::Checkable {
+            style: Some(style), ..
+        } => (style.checked_icon.as_ref(), style.unchecked_icon.as_ref()),
+        _ => (None, None),
+    }
+}
+
+/// State flags of the menu item
+fn state(disabled: bool, checked: bool, default: bool) -> UINT {
+    let mut state = 0;
+    if disabled {
+        state |= winuser::MFS_GRAYED;
+    }
+    if checked {
+        state |= winuser::MFS_CHECKED;
+    }
+    if default {
+        state |= winuser::MFS_DEFAULT;
+    }
+    state
+}
+
+fn track_flags(options: &MenuOptions) -> UINT {
+    let mut flags = match options.align {
+        HorizontalAlign::Left => winuser::TPM_LEFTALIGN,
+        HorizontalAlign::Center => winuser::TPM_CENTERALIGN,
+        HorizontalAlign::Right => winuser::TPM_RIGHTALIGN,
+    };
+    flags |= match options.vertical {
+        VerticalAlign::Top => winuser::TPM_TOPALIGN,
+        VerticalAlign::Bottom => winuser::TPM_BOTTOMALIGN,
+    };
+    if !options.animation {
+        flags |= winuser::TPM_NOANIMATION;
+    }
+    if options.allow_right_button {
+        flags |= winuser::TPM_RIGHTBUTTON;
+    }
+    flags
 }
 
 // For pattern matching, these are in own mod
 mod msgs {
     pub const WM_USER_TRAYICON: u32 = 0x400 + 1001;
+    pub const WM_USER_WAKE: u32 = 0x400 + 1002;
+
+    // Timer identifiers
+    pub const TIMER_MOUSE_LEAVE: usize = 1;
+    pub const TIMER_ANIMATION: usize = 2;
+    pub const TIMER_SINGLE_CLICK: usize = 3;
+    pub const TIMER_TOOLTIP: usize = 4;
+    pub const TIMER_TASKBAR: usize = 5;
+    pub const TIMER_ADD_RETRY: usize = 6;
+    pub const TIMER_TRAY_VISIBILITY: usize = 7;
 }
 
 #[cfg(test)]
@@ -178,4 +871,109 @@ pub(crate) mod tests {
             panic!()
         }
     }
+
+    #[test]
+    fn test_menu_edits() {
+        let previous = MenuBuilder::new()
+            .item("Item 1", Events::Item1)
+            .separator()
+            .column_break()
+            .checkable("This is checkable", true, Events::CheckableItem1)
+            .item("Sub item 1", Events::SubItem1);
+        let builder = MenuBuilder::new()
+            .item("Item 1", Events::Item1)
+            .item("Sub item 2", Events::SubItem2)
+            .separator()
+            .item("This is checkable", Events::CheckableItem1)
+            .item("Sub item 1", Events::SubItem1);
+        assert_eq!(
+            menu_edits(&menu_entries(&previous), &menu_entries(&builder)),
+            vec![
+                MenuEdit::Modify,
+                MenuEdit::Insert,
+                MenuEdit::Modify,
+                MenuEdit::Remove,
+                MenuEdit::Insert,
+                MenuEdit::Modify,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_menu_update() {
+        let mut menusys = build_menu(
+            &MenuBuilder::new()
+                .checkable("This is checkable", true, Events::CheckableItem1)
+                .item("Item 1", Events::Item1),
+        )
+        .unwrap();
+        menusys
+            .update(
+                &MenuBuilder::new()
+                    .checkable("This is checkable", false, Events::CheckableItem1)
+                    .submenu(
+                        "Sub Menu",
+                        MenuBuilder::new().item("Sub item 1", Events::SubItem1),
+                    )
+                    .item("Item 1 changed", Events::Item1),
+            )
+            .unwrap();
+        assert_eq!(menusys.ids.get(&2), Some(&Events::SubItem1));
+        assert_eq!(menusys.ids.get(&3), Some(&Events::Item1));
+        assert!(!menusys.menu.is_checked(1));
+    }
+
+    #[test]
+    fn test_menu_style() {
+        let dot = Icon::from_rgba(16, 16, vec![255; 16 * 16 * 4]).unwrap();
+        let style = MenuItemStyle::new().check_icons(Some(dot.clone()), None);
+        let builder = MenuBuilder::new()
+            .checkable("Online", true, Events::CheckableItem1)
+            .style(style.clone())
+            .item("Item 1", Events::Item1)
+            .style(style.bold(true));
+        assert_eq!(item_check_icons(&builder.menu_items[0]), (Some(&dot), None));
+        assert_eq!(item_check_icons(&builder.menu_items[1]), (None, None));
+
+        // Icons are replaced and removed with the items
+        let mut menusys = build_menu(&builder).unwrap();
+        menusys
+            .update(
+                &MenuBuilder::new()
+                    .checkable("Online", false, Events::CheckableItem1)
+                    .style(MenuItemStyle::new().check_icons(None, Some(dot)))
+                    .item("Item 1", Events::Item1),
+            )
+            .unwrap();
+        menusys
+            .update(&MenuBuilder::new().item("Item 1", Events::Item1))
+            .unwrap();
+        assert_eq!(menusys.ids.get(&1), Some(&Events::Item1));
+    }
+
+    #[test]
+    fn test_track_flags() {
+        assert_eq!(track_flags(&MenuOptions::default()), 0);
+        let options = MenuOptions {
+            align: HorizontalAlign::Right,
+            vertical: VerticalAlign::Bottom,
+            animation: false,
+            allow_right_button: true,
+        };
+        assert_eq!(
+            track_flags(&options),
+            winuser::TPM_RIGHTALIGN
+                | winuser::TPM_BOTTOMALIGN
+                | winuser::TPM_NOANIMATION
+                | winuser::TPM_RIGHTBUTTON
+        );
+    }
+
+    #[test]
+    fn test_reading_order() {
+        assert_eq!(reading_order("Hello", false), "Hello");
+        assert_eq!(reading_order("שלום", true), "\u{200f}שלום");
+        assert_eq!(reading_order("\u{200f}שלום", true), "\u{200f}שלום");
+        assert_eq!(reading_order("", true), "");
+    }
 }
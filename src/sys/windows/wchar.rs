@@ -21,6 +21,11 @@ pub fn wchar_array(string: &str, dst: &mut [u16]) {
     // Truncate utf16 array to fit in the buffer with null terminator
     s.truncate(dst.len() - 1);
 
+    // Don't leave half of a surrogate pair at the end
+    if let Some(0xD800..=0xDBFF) = s.last() {
+        s.pop();
+    }
+
     dst[..s.len()].copy_from_slice(s.as_slice());
 
     // Null terminator
@@ -47,6 +52,14 @@ mod tests {
         assert_eq!(dst, [72, 73, 33, 0, 99]);
     }
 
+    /// Ensure that surrogate pair is not split when truncating
+    #[test]
+    fn test_wchar_too_long_surrogate_pair() {
+        let mut dst: [u16; 5] = [99, 99, 99, 99, 99];
+        wchar_array("HEL👀", dst.as_mut());
+        assert_eq!(dst, [72, 69, 76, 0, 99]);
+    }
+
     /// Ensure that empty string is null terminated
     #[test]
     fn test_wchar_empty() {
@@ -0,0 +1,141 @@
+use super::wchar::wchar;
+use crate::Error;
+use winapi::shared::minwindef::{BYTE, DWORD};
+use winapi::shared::winerror::{ERROR_FILE_NOT_FOUND, ERROR_SUCCESS};
+use winapi::um::{winnt, winreg};
+
+/// Programs started at the sign in of the user
+const RUN_KEY: &str = "Software\\Microsoft\\Windows\\CurrentVersion\\Run";
+
+/// Task Manager disables the programs of the Run key here, without removing
+/// them
+const STARTUP_APPROVED_KEY: &str =
+    "Software\\Microsoft\\Windows\\CurrentVersion\\Explorer\\StartupApproved\\Run";
+
+/// Add the current executable with the arguments to the Run key, or remove it
+///
+/// Enabling removes the disabling of Task Manager too.
+pub fn set_autostart(enabled: bool, app_name: &str, args: &[String]) -> Result<(), Error> {
+    if !enabled {
+        delete_value(RUN_KEY, app_name)?;
+        return delete_value(STARTUP_APPROVED_KEY, app_name);
+    }
+    let exe = std::env::current_exe().map_err(|_| Error::os_error("GetModuleFileNameW"))?;
+    let command = std::iter::once(exe.to_string_lossy().into_owned())
+        .chain(args.iter().cloned())
+        .map(|arg| quote_arg(&arg))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let command = wchar(&command);
+    let res = unsafe {
+        winreg::RegSetKeyValueW(
+            winreg::HKEY_CURRENT_USER,
+            wchar(RUN_KEY).as_ptr(),
+            wchar(app_name).as_ptr(),
+            winnt::REG_SZ,
+            command.as_ptr() as _,
+            (command.len() * 2) as DWORD,
+        )
+    };
+    if res != ERROR_SUCCESS as i32 {
+        return Err(Error::os_error_code("RegSetKeyValueW", res));
+    }
+    delete_value(STARTUP_APPROVED_KEY, app_name)
+}
+
+/// Run key has the value, and Task Manager hasn't disabled it
+pub fn is_autostart_enabled(app_name: &str) -> bool {
+    let res = unsafe {
+        winreg::RegGetValueW(
+            winreg::HKEY_CURRENT_USER,
+            wchar(RUN_KEY).as_ptr(),
+            wchar(app_name).as_ptr(),
+            winreg::RRF_RT_REG_SZ,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if res != ERROR_SUCCESS as i32 {
+        return false;
+    }
+
+    // First byte is odd when disabled, the rest is the time of disabling
+    let mut approved: [BYTE; 12] = [0; 12];
+    let mut size = approved.len() as DWORD;
+    let res = unsafe {
+        winreg::RegGetValueW(
+            winreg::HKEY_CURRENT_USER,
+            wchar(STARTUP_APPROVED_KEY).as_ptr(),
+            wchar(app_name).as_ptr(),
+            winreg::RRF_RT_REG_BINARY,
+            std::ptr::null_mut(),
+            approved.as_mut_ptr() as _,
+            &mut size,
+        )
+    };
+    res != ERROR_SUCCESS as i32 || size == 0 || approved[0] & 1 == 0
+}
+
+/// Remove the value, it's fine if it doesn't exist
+fn delete_value(key: &str, name: &str) -> Result<(), Error> {
+    let res = unsafe {
+        winreg::RegDeleteKeyValueW(
+            winreg::HKEY_CURRENT_USER,
+            wchar(key).as_ptr(),
+            wchar(name).as_ptr(),
+        )
+    };
+    if res != ERROR_SUCCESS as i32 && res != ERROR_FILE_NOT_FOUND as i32 {
+        return Err(Error::os_error_code("RegDeleteKeyValueW", res));
+    }
+    Ok(())
+}
+
+/// Quote the argument for `CommandLineToArgvW`, if it has spaces or quotes
+///
+/// Backslashes are doubled only before the quotes.
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(&[' ', '\t', '"'][..]) {
+        return arg.to_string();
+    }
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                backslashes = 0;
+            }
+            _ => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                backslashes = 0;
+            }
+        }
+        if c != '\\' {
+            quoted.push(c);
+        }
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_arg() {
+        assert_eq!(quote_arg("--minimized"), "--minimized");
+        assert_eq!(quote_arg(""), "\"\"");
+        assert_eq!(
+            quote_arg("C:\\Program Files\\App\\app.exe"),
+            "\"C:\\Program Files\\App\\app.exe\""
+        );
+        assert_eq!(quote_arg("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(quote_arg("C:\\My Dir\\"), "\"C:\\My Dir\\\\\"");
+        assert_eq!(quote_arg("a\\\\\"b"), "\"a\\\\\\\\\\\"b\"");
+    }
+}
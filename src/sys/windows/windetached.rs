@@ -0,0 +1,181 @@
+use super::msgs;
+use super::wchar::wchar;
+use super::wintrayicon::register_class;
+use crate::{
+    trayiconhandle::{Attached, Handler, Waker},
+    Error,
+};
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc::channel;
+use winapi::shared::minwindef::{LPARAM, LRESULT, UINT, WPARAM};
+use winapi::shared::windef::HWND;
+use winapi::um::libloaderapi::GetModuleHandleW;
+use winapi::um::winuser;
+
+/// State of the wake window
+struct Wake {
+    handler: Handler,
+
+    /// Handler is running, it can show a menu which dispatches messages
+    running: bool,
+
+    /// Woken while running, the handler is run again when it returns
+    woken: bool,
+
+    /// Detached thread quits with the handler
+    quit_thread: bool,
+}
+
+/// Build on a dedicated thread, and pump its messages
+///
+/// Handler returns false to quit the thread.
+pub fn spawn_detached<F>(build: F) -> Result<Attached, Error>
+where
+    F: FnOnce() -> Result<Handler, Error> + Send + 'static,
+{
+    let (result, receiver) = channel::<Result<usize, Error>>();
+    let thread = std::thread::spawn(move || unsafe {
+        let hwnd = match build().and_then(|handler| create_wake_window(handler, true)) {
+            Ok(hwnd) => hwnd,
+            Err(err) => {
+                let _ = result.send(Err(err));
+                return;
+            }
+        };
+        let _ = result.send(Ok(hwnd as usize));
+
+        let mut msg: winuser::MSG = std::mem::zeroed();
+        while winuser::GetMessageW(&mut msg, std::ptr::null_mut(), 0, 0) > 0 {
+            winuser::TranslateMessage(&msg);
+            winuser::DispatchMessageW(&msg);
+        }
+
+        // Tray icon is dropped in this thread, with its window
+        if winuser::IsWindow(hwnd) != 0 {
+            winuser::DestroyWindow(hwnd);
+        }
+    });
+
+    let hwnd = receiver.recv().map_err(|_| Error::TrayIconStopped)??;
+    Ok((waker(hwnd as HWND), thread.thread().id()))
+}
+
+/// Run the handler by the message loop of the current thread
+///
+/// Handler returns false when it's done, and it's dropped in this thread.
+pub fn attach_handler(handler: Handler) -> Result<Attached, Error> {
+    let hwnd = unsafe { create_wake_window(handler, false)? };
+    Ok((waker(hwnd), std::thread::current().id()))
+}
+
+/// Posts to the wake window, the messages posted to windows are dispatched
+/// even while a menu is shown, unlike the thread messages
+fn waker(hwnd: HWND) -> Waker {
+    let hwnd = hwnd as usize;
+    Box::new(move || unsafe {
+        winuser::PostMessageW(hwnd as HWND, msgs::WM_USER_WAKE, 0, 0);
+    })
+}
+
+/// Message-only window running the handler when woken
+pub(super) unsafe fn create_wake_window(
+    handler: Handler,
+    quit_thread: bool,
+) -> Result<HWND, Error> {
+    let hinstance = GetModuleHandleW(0 as _);
+    let wnd_class_name = wchar("TrayIconWake");
+    let wnd_class = winuser::WNDCLASSW {
+        style: 0,
+        lpfnWndProc: Some(wake_proc),
+        hInstance: hinstance,
+        lpszClassName: wnd_class_name.as_ptr() as _,
+        cbClsExtra: 0,
+        cbWndExtra: 0,
+        hIcon: 0 as _,
+        hCursor: 0 as _,
+        hbrBackground: 0 as _,
+        lpszMenuName: 0 as _,
+    };
+    register_class(&wnd_class)?;
+    let hwnd = winuser::CreateWindowExW(
+        0,
+        wnd_class_name.as_ptr() as _,
+        wnd_class_name.as_ptr() as _,
+        0,
+        0,
+        0,
+        0,
+        0,
+        winuser::HWND_MESSAGE,
+        0 as _,
+        hinstance,
+        0 as _,
+    );
+    if hwnd.is_null() {
+        return Err(Error::last_os_error("CreateWindowExW"));
+    }
+    let wake = Box::new(Wake {
+        handler,
+        running: false,
+        woken: false,
+        quit_thread,
+    });
+    winuser::SetWindowLongPtrW(hwnd, winuser::GWLP_USERDATA, Box::into_raw(wake) as _);
+    Ok(hwnd)
+}
+
+unsafe extern "system" fn wake_proc(
+    hwnd: HWND,
+    msg: UINT,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        msgs::WM_USER_WAKE => {
+            let wake = winuser::GetWindowLongPtrW(hwnd, winuser::GWLP_USERDATA) as *mut Wake;
+            if wake.is_null() {
+                return 0;
+            }
+            if (*wake).running {
+                (*wake).woken = true;
+                return 0;
+            }
+            (*wake).running = true;
+            let mut panicked = false;
+            let done = loop {
+                (*wake).woken = false;
+
+                // Unwinding into the system is undefined behavior, same as in
+                // the window procedure of the tray icon
+                match std::panic::catch_unwind(AssertUnwindSafe(|| ((*wake).handler)())) {
+                    Ok(true) if (*wake).woken => {}
+                    Ok(keep) => break !keep,
+
+                    // Run again to clean up after the panic, e.g. to drop the
+                    // tray icon behind the poisoned lock
+                    Err(_) if !panicked => {
+                        trace_event!(ERROR, "handler of the tray icon handle panicked");
+                        panicked = true;
+                    }
+                    Err(_) => break true,
+                }
+            };
+            (*wake).running = false;
+            if done {
+                if (*wake).quit_thread {
+                    winuser::PostQuitMessage(0);
+                }
+                winuser::DestroyWindow(hwnd);
+            }
+            0
+        }
+        winuser::WM_NCDESTROY => {
+            let wake = winuser::SetWindowLongPtrW(hwnd, winuser::GWLP_USERDATA, 0) as *mut Wake;
+            if !wake.is_null() {
+                drop(Box::from_raw(wake));
+            }
+            winuser::DefWindowProcW(hwnd, msg, wparam, lparam)
+        }
+        _ => winuser::DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+}
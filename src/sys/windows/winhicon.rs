@@ -1,20 +1,63 @@
-use crate::{Error, IconBase};
-use winapi::shared::minwindef::PBYTE;
-use winapi::shared::windef::HICON;
-use winapi::um::winuser;
+use super::wchar::wchar;
+use crate::{badge, Error, FontOptions, IconBase, StockIcon};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, Weak};
+use winapi::ctypes::c_void;
+use winapi::shared::minwindef::{DWORD, PBYTE, TRUE, UINT};
+use winapi::shared::windef::{HBITMAP, HFONT, HICON, RECT, SIZE};
+use winapi::shared::winerror::SUCCEEDED;
+use winapi::um::libloaderapi::{GetModuleHandleW, GetProcAddress};
+use winapi::um::winnt::HANDLE;
+use winapi::um::{processthreadsapi, shellapi, wingdi, winuser};
 
-/// Purpose of this struct is to keep hicon handle, and drop it when the struct
-/// is dropped
+/// DrawIconEx flags, missing from winapi
+const DI_NORMAL: UINT = 0x0003;
+
+/// HICON shared by the clones of the icon, destroyed when the last one is
+/// dropped
+struct SharedHIcon(HICON);
+
+unsafe impl Send for SharedHIcon {}
+unsafe impl Sync for SharedHIcon {}
+
+impl Drop for SharedHIcon {
+    fn drop(&mut self) {
+        unsafe { winuser::DestroyIcon(self.0) };
+        if let Ok(mut decoded) = DECODED.lock() {
+            decoded.retain(|(_, hicon)| hicon.strong_count() > 0);
+        }
+    }
+}
+
+/// Hash and the length of the buffer, and the requested size
+type DecodedKey = (u64, usize, Option<u32>, Option<u32>);
+
+/// Icons decoded from the buffers, while they're in use
+///
+/// Apps switching between a few icons decode the same buffers again, so the
+/// HICON is reused instead of creating a new one each time.
+static DECODED: Mutex<Vec<(DecodedKey, Weak<SharedHIcon>)>> = Mutex::new(Vec::new());
+
+/// Purpose of this struct is to keep hicon handle, and drop it when the last
+/// clone is dropped
 pub struct WinHIcon {
-    pub hicon: HICON,
+    shared: Arc<SharedHIcon>,
 }
 
-impl IconBase for WinHIcon {
-    fn from_buffer(
-        buffer: &'static [u8],
-        width: Option<u32>,
-        height: Option<u32>,
-    ) -> Result<WinHIcon, Error> {
+impl WinHIcon {
+    fn new(hicon: HICON) -> WinHIcon {
+        WinHIcon {
+            shared: Arc::new(SharedHIcon(hicon)),
+        }
+    }
+
+    pub fn hicon(&self) -> HICON {
+        self.shared.0
+    }
+
+    /// Create the HICON of the size from the icon file
+    fn decode(buffer: &[u8], width: Option<u32>, height: Option<u32>) -> Result<WinHIcon, Error> {
         let offset = unsafe {
             winuser::LookupIconIdFromDirectoryEx(
                 buffer.as_ptr() as PBYTE,
@@ -42,23 +85,361 @@ impl IconBase for WinHIcon {
         if hicon.is_null() {
             return Err(Error::IconLoadingFailed);
         }
-        Ok(WinHIcon { hicon })
+        Ok(WinHIcon::new(hicon))
     }
 }
 
+impl IconBase for WinHIcon {
+    /// Decoded icon is reused while any icon decoded from the same buffer at
+    /// the same size exists
+    fn from_buffer(
+        buffer: &[u8],
+        width: Option<u32>,
+        height: Option<u32>,
+    ) -> Result<WinHIcon, Error> {
+        let mut hasher = DefaultHasher::new();
+        buffer.hash(&mut hasher);
+        let key = (hasher.finish(), buffer.len(), width, height);
+        let mut decoded = DECODED.lock().map_err(|_| Error::IconLoadingFailed)?;
+        let reused = decoded
+            .iter()
+            .find(|(k, _)| *k == key)
+            .and_then(|(_, hicon)| hicon.upgrade());
+        if let Some(shared) = reused {
+            return Ok(WinHIcon { shared });
+        }
+        let icon = WinHIcon::decode(buffer, width, height)?;
+        decoded.push((key, Arc::downgrade(&icon.shared)));
+        Ok(icon)
+    }
+
+    fn from_rgba(rgba: &[u8], width: u32, height: u32) -> Result<WinHIcon, Error> {
+        unsafe {
+            let (hbm_color, bits) =
+                dib_section(width as i32, height as i32).ok_or(Error::IconLoadingFailed)?;
+
+            // DIB is BGRA
+            let dst = std::slice::from_raw_parts_mut(bits as *mut u8, rgba.len());
+            for (d, s) in dst.chunks_exact_mut(4).zip(rgba.chunks_exact(4)) {
+                d.copy_from_slice(&[s[2], s[1], s[0], s[3]]);
+            }
+
+            // Alpha channel is used instead of the mask, but the mask is still
+            // required, rows are aligned to 16 bits
+            let mask = vec![0u8; (width as usize).div_ceil(16) * 2 * height as usize];
+            let hbm_mask =
+                wingdi::CreateBitmap(width as i32, height as i32, 1, 1, mask.as_ptr() as _);
+
+            let mut icon_info = winuser::ICONINFO {
+                fIcon: TRUE,
+                xHotspot: 0,
+                yHotspot: 0,
+                hbmMask: hbm_mask,
+                hbmColor: hbm_color,
+            };
+            let hicon = winuser::CreateIconIndirect(&mut icon_info);
+            wingdi::DeleteObject(hbm_color as _);
+            wingdi::DeleteObject(hbm_mask as _);
+            if hicon.is_null() {
+                return Err(Error::IconLoadingFailed);
+            }
+            Ok(WinHIcon::new(hicon))
+        }
+    }
+
+    /// Text is drawn white on black with grayscale antialiasing, and the
+    /// brightness is used as the coverage of the color
+    fn from_text(text: &str, options: &FontOptions) -> Result<WinHIcon, Error> {
+        let size = options.icon_size;
+        let text = wchar(text);
+        let len = text.len() as i32 - 1;
+        let coverage = unsafe {
+            let (hbitmap, bits) =
+                dib_section(size as i32, size as i32).ok_or(Error::IconLoadingFailed)?;
+            let hdc = wingdi::CreateCompatibleDC(std::ptr::null_mut());
+            let old_bitmap = wingdi::SelectObject(hdc, hbitmap as _);
+            wingdi::SetTextColor(hdc, wingdi::RGB(0xff, 0xff, 0xff));
+            wingdi::SetBkMode(hdc, wingdi::TRANSPARENT as i32);
+
+            // Font is made smaller to fit the width
+            let mut font = create_font(options, size as i32);
+            let old_font = wingdi::SelectObject(hdc, font as _);
+            let mut extent: SIZE = std::mem::zeroed();
+            wingdi::GetTextExtentPoint32W(hdc, text.as_ptr(), len, &mut extent);
+            if extent.cx > size as i32 {
+                wingdi::SelectObject(hdc, old_font);
+                wingdi::DeleteObject(font as _);
+                font = create_font(options, (size as i32 * size as i32 / extent.cx).max(1));
+                wingdi::SelectObject(hdc, font as _);
+            }
+
+            let mut rect = RECT {
+                left: 0,
+                top: 0,
+                right: size as i32,
+                bottom: size as i32,
+            };
+            winuser::DrawTextW(
+                hdc,
+                text.as_ptr(),
+                len,
+                &mut rect,
+                winuser::DT_CENTER
+                    | winuser::DT_VCENTER
+                    | winuser::DT_SINGLELINE
+                    | winuser::DT_NOPREFIX,
+            );
+            wingdi::GdiFlush();
+            let bgra = std::slice::from_raw_parts(bits as *const u8, (size * size * 4) as usize);
+            let coverage = bgra
+                .chunks_exact(4)
+                .map(|p| p[0].max(p[1]).max(p[2]))
+                .collect::<Vec<_>>();
+
+            wingdi::SelectObject(hdc, old_font);
+            wingdi::DeleteObject(font as _);
+            wingdi::SelectObject(hdc, old_bitmap);
+            wingdi::DeleteDC(hdc);
+            wingdi::DeleteObject(hbitmap as _);
+            coverage
+        };
+
+        let mut rgba = match options.background {
+            Some(background) => background.repeat((size * size) as usize),
+            None => vec![0; (size * size * 4) as usize],
+        };
+        let [r, g, b, a] = options.color;
+        for (i, c) in coverage.iter().enumerate() {
+            let alpha = *c as f32 / 255.0 * a as f32 / 255.0;
+            badge::blend(
+                &mut rgba,
+                size,
+                i as u32 % size,
+                i as u32 / size,
+                [r, g, b],
+                alpha,
+            );
+        }
+        WinHIcon::from_rgba(&rgba, size, size)
+    }
+
+    /// Draw the icon at the size, the pixels are converted to straight alpha
+    ///
+    /// Icons without the alpha channel are drawn transparent.
+    fn to_rgba(&self, width: u32, height: u32) -> Option<Vec<u8>> {
+        unsafe {
+            let (hbitmap, bits) = self.draw(width as i32, height as i32)?;
+            wingdi::GdiFlush();
+            let bgra = std::slice::from_raw_parts(bits as *const u8, (width * height * 4) as usize);
+            let rgba = bgra
+                .chunks_exact(4)
+                .flat_map(|p| {
+                    let alpha = p[3] as u32;
+                    let straight = |c: u8| match alpha {
+                        0 => 0,
+                        _ => ((c as u32 * 255 + alpha / 2) / alpha).min(255) as u8,
+                    };
+                    [straight(p[2]), straight(p[1]), straight(p[0]), p[3]]
+                })
+                .collect();
+            wingdi::DeleteObject(hbitmap as _);
+            Some(rgba)
+        }
+    }
+}
+
+impl WinHIcon {
+    /// Load the icon from the resources of the executable
+    ///
+    /// Without the size the small icon size is used.
+    pub fn from_resource(
+        resource_id: u16,
+        width: Option<u32>,
+        height: Option<u32>,
+    ) -> Result<WinHIcon, Error> {
+        let hicon = unsafe {
+            let width = width.map_or_else(
+                || winuser::GetSystemMetrics(winuser::SM_CXSMICON),
+                |w| w as i32,
+            );
+            let height = height.map_or_else(
+                || winuser::GetSystemMetrics(winuser::SM_CYSMICON),
+                |h| h as i32,
+            );
+            winuser::LoadImageW(
+                GetModuleHandleW(std::ptr::null()),
+                winuser::MAKEINTRESOURCEW(resource_id),
+                winuser::IMAGE_ICON,
+                width,
+                height,
+                winuser::LR_DEFAULTCOLOR,
+            )
+        };
+        if hicon.is_null() {
+            return Err(Error::IconLoadingFailed);
+        }
+        Ok(WinHIcon::new(hicon as HICON))
+    }
+
+    /// Shell stock icon in the small icon size
+    pub fn from_stock(stock_icon: StockIcon) -> Result<WinHIcon, Error> {
+        let siid = match stock_icon {
+            StockIcon::Application => shellapi::SIID_APPLICATION,
+            StockIcon::Delete => shellapi::SIID_DELETE,
+            StockIcon::Error => shellapi::SIID_ERROR,
+            StockIcon::Find => shellapi::SIID_FIND,
+            StockIcon::Folder => shellapi::SIID_FOLDER,
+            StockIcon::Help => shellapi::SIID_HELP,
+            StockIcon::Info => shellapi::SIID_INFO,
+            StockIcon::Lock => shellapi::SIID_LOCK,
+            StockIcon::Settings => shellapi::SIID_SETTINGS,
+            StockIcon::Shield => shellapi::SIID_SHIELD,
+            StockIcon::Warning => shellapi::SIID_WARNING,
+        };
+        let mut info: shellapi::SHSTOCKICONINFO = unsafe { std::mem::zeroed() };
+        info.cbSize = std::mem::size_of::<shellapi::SHSTOCKICONINFO>() as u32;
+        let res = unsafe {
+            shellapi::SHGetStockIconInfo(
+                siid,
+                shellapi::SHGSI_ICON | shellapi::SHGSI_SMALLICON,
+                &mut info,
+            )
+        };
+        if !SUCCEEDED(res) || info.hIcon.is_null() {
+            return Err(Error::IconLoadingFailed);
+        }
+        Ok(WinHIcon::new(info.hIcon))
+    }
+
+    /// Draw the icon to a 32-bit bitmap with alpha, e.g. for the menu items
+    ///
+    /// Caller owns the bitmap and must delete it.
+    pub fn to_hbitmap(&self, width: i32, height: i32) -> Option<HBITMAP> {
+        unsafe { self.draw(width, height).map(|(hbitmap, _)| hbitmap) }
+    }
+
+    /// Draw to a new DIB section, the pixels are premultiplied BGRA
+    unsafe fn draw(&self, width: i32, height: i32) -> Option<(HBITMAP, *mut c_void)> {
+        let (hbitmap, bits) = dib_section(width, height)?;
+        let hdc = wingdi::CreateCompatibleDC(std::ptr::null_mut());
+        let old = wingdi::SelectObject(hdc, hbitmap as _);
+        winuser::DrawIconEx(
+            hdc,
+            0,
+            0,
+            self.hicon(),
+            width,
+            height,
+            0,
+            std::ptr::null_mut(),
+            DI_NORMAL,
+        );
+        wingdi::SelectObject(hdc, old);
+        wingdi::DeleteDC(hdc);
+        Some((hbitmap, bits))
+    }
+}
+
+/// Font with the character height in pixels, antialiased without ClearType
+unsafe fn create_font(options: &FontOptions, height: i32) -> HFONT {
+    let family = wchar(options.family.as_deref().unwrap_or("Segoe UI"));
+    wingdi::CreateFontW(
+        -height,
+        0,
+        0,
+        0,
+        if options.bold {
+            wingdi::FW_BOLD
+        } else {
+            wingdi::FW_NORMAL
+        },
+        0,
+        0,
+        0,
+        wingdi::DEFAULT_CHARSET,
+        wingdi::OUT_DEFAULT_PRECIS,
+        wingdi::CLIP_DEFAULT_PRECIS,
+        wingdi::ANTIALIASED_QUALITY,
+        wingdi::DEFAULT_PITCH,
+        family.as_ptr(),
+    )
+}
+
+/// Top-down 32-bit DIB section, and the pointer to its pixels
+unsafe fn dib_section(width: i32, height: i32) -> Option<(HBITMAP, *mut c_void)> {
+    let mut bitmap_info: wingdi::BITMAPINFO = std::mem::zeroed();
+    bitmap_info.bmiHeader.biSize = std::mem::size_of::<wingdi::BITMAPINFOHEADER>() as u32;
+    bitmap_info.bmiHeader.biWidth = width;
+    // Negative height is a top-down bitmap, same as the RGBA rows
+    bitmap_info.bmiHeader.biHeight = -height;
+    bitmap_info.bmiHeader.biPlanes = 1;
+    bitmap_info.bmiHeader.biBitCount = 32;
+    bitmap_info.bmiHeader.biCompression = wingdi::BI_RGB;
+
+    let mut bits = std::ptr::null_mut();
+    let hdc = winuser::GetDC(std::ptr::null_mut());
+    let hbitmap = wingdi::CreateDIBSection(
+        hdc,
+        &bitmap_info,
+        wingdi::DIB_RGB_COLORS,
+        &mut bits,
+        std::ptr::null_mut(),
+        0,
+    );
+    winuser::ReleaseDC(std::ptr::null_mut(), hdc);
+    if hbitmap.is_null() {
+        None
+    } else {
+        Some((hbitmap, bits))
+    }
+}
+
+/// Clones share the HICON
 impl Clone for WinHIcon {
     fn clone(&self) -> Self {
         WinHIcon {
-            hicon: unsafe { winuser::CopyIcon(self.hicon) },
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+/// Number of the GDI objects of the process, e.g. for finding leaked icons
+///
+/// GetGuiResources is missing from winapi, so it's loaded from user32.
+pub fn gdi_handle_count() -> usize {
+    const GR_GDIOBJECTS: DWORD = 0;
+    unsafe {
+        let user32 = GetModuleHandleW(wchar("user32.dll").as_ptr());
+        if user32.is_null() {
+            return 0;
+        }
+        let proc = GetProcAddress(user32, "GetGuiResources\0".as_ptr() as _);
+        if proc.is_null() {
+            return 0;
         }
+        let get_gui_resources: extern "system" fn(HANDLE, DWORD) -> DWORD =
+            std::mem::transmute(proc);
+        get_gui_resources(processthreadsapi::GetCurrentProcess(), GR_GDIOBJECTS) as usize
     }
 }
 
-unsafe impl Send for WinHIcon {}
-unsafe impl Sync for WinHIcon {}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl Drop for WinHIcon {
-    fn drop(&mut self) {
-        unsafe { winuser::DestroyIcon(self.hicon) };
+    #[test]
+    fn test_decoded_icon_shared() {
+        let buffer = include_bytes!("../../testresource/icon1.ico");
+        let icon = WinHIcon::from_buffer(buffer, None, None).unwrap();
+        let same = WinHIcon::from_buffer(buffer, None, None).unwrap();
+        assert_eq!(icon.hicon(), same.hicon());
+        assert_eq!(Arc::strong_count(&icon.shared), 2);
+
+        let other = WinHIcon::from_buffer(buffer, Some(32), Some(32)).unwrap();
+        assert_ne!(icon.hicon(), other.hicon());
+        let other =
+            WinHIcon::from_buffer(include_bytes!("../../testresource/icon2.ico"), None, None)
+                .unwrap();
+        assert_ne!(icon.hicon(), other.hicon());
     }
 }
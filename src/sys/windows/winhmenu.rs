@@ -1,8 +1,9 @@
 use super::wchar::wchar;
-use crate::Error;
+use crate::{Error, Icon};
 use std::fmt::Debug;
-use winapi::shared::windef::{HMENU, HWND};
-use winapi::um::winuser;
+use winapi::shared::minwindef::{FALSE, TRUE, UINT};
+use winapi::shared::windef::{HBITMAP, HMENU, HWND};
+use winapi::um::{wingdi, winuser};
 
 /// Purpose of this struct is to keep hmenu handle, and drop it when the struct
 /// is dropped
@@ -10,6 +11,7 @@ use winapi::um::winuser;
 pub struct WinHMenu {
     hmenu: HMENU,
     child_menus: Vec<WinHMenu>,
+    bitmaps: Vec<HBITMAP>,
 }
 
 impl WinHMenu {
@@ -18,75 +20,323 @@ impl WinHMenu {
             hmenu: unsafe {
                 let res = winuser::CreatePopupMenu();
                 if res.is_null() {
-                    return Err(Error::OsError);
+                    return Err(Error::last_os_error("CreatePopupMenu"));
                 }
                 res
             },
             child_menus: vec![],
+            bitmaps: vec![],
         })
     }
 
-    pub fn add_menu_item(&self, name: &str, id: usize, disabled: bool) -> bool {
+    /// Insert an empty item at the position, it's set with `set_item`
+    pub fn insert_item(&mut self, pos: UINT, separator: bool) -> Result<(), Error> {
+        let flags = if separator {
+            winuser::MF_SEPARATOR
+        } else {
+            winuser::MF_STRING
+        };
         let res = unsafe {
-            winuser::AppendMenuW(
+            winuser::InsertMenuW(
                 self.hmenu,
-                {
-                    if disabled {
-                        winuser::MF_GRAYED
-                    } else {
-                        winuser::MF_STRING
-                    }
-                },
-                id,
-                wchar(name).as_ptr() as _,
+                pos,
+                winuser::MF_BYPOSITION | flags,
+                0,
+                wchar("").as_ptr() as _,
             )
         };
-        res >= 0
+        if res == 0 {
+            return Err(Error::last_os_error("InsertMenuW"));
+        }
+        Ok(())
     }
 
-    pub fn add_checkable_item(
+    /// Insert the submenu at the position, it's set with `set_item`
+    pub fn insert_child_menu(&mut self, pos: UINT, menu: WinHMenu) -> Result<(), Error> {
+        let res = unsafe {
+            winuser::InsertMenuW(
+                self.hmenu,
+                pos,
+                winuser::MF_BYPOSITION | winuser::MF_POPUP,
+                menu.hmenu as _,
+                wchar("").as_ptr() as _,
+            )
+        };
+        if res == 0 {
+            return Err(Error::last_os_error("InsertMenuW"));
+        }
+        self.child_menus.push(menu);
+        Ok(())
+    }
+
+    /// Set the type, the state and the text of the item at the position
+    ///
+    /// Command identifier is not set for the submenus.
+    pub fn set_item(
         &self,
+        pos: UINT,
+        id: Option<usize>,
         name: &str,
-        is_checked: bool,
-        id: usize,
-        disabled: bool,
-    ) -> bool {
-        let mut flags = if is_checked {
-            winuser::MF_CHECKED
-        } else {
-            winuser::MF_UNCHECKED
-        };
+        ftype: UINT,
+        state: UINT,
+    ) -> Result<(), Error> {
+        let mut name = wchar(name);
+        let mut info: winuser::MENUITEMINFOW = unsafe { std::mem::zeroed() };
+        info.cbSize = std::mem::size_of::<winuser::MENUITEMINFOW>() as _;
+        info.fMask = winuser::MIIM_FTYPE | winuser::MIIM_STATE;
+        info.fType = ftype;
+        info.fState = state;
+        if ftype & winuser::MFT_SEPARATOR == 0 {
+            info.fMask |= winuser::MIIM_STRING;
+            info.dwTypeData = name.as_mut_ptr();
+        }
+        if let Some(id) = id {
+            info.fMask |= winuser::MIIM_ID;
+            info.wID = id as _;
+        }
+        let res = unsafe { winuser::SetMenuItemInfoW(self.hmenu, pos, TRUE, &info) };
+        if res == 0 {
+            return Err(Error::last_os_error("SetMenuItemInfoW"));
+        }
+        Ok(())
+    }
 
-        if disabled {
-            flags |= winuser::MF_GRAYED
+    /// Remove the item at the position, with its submenu and icons
+    pub fn remove_item(&mut self, pos: UINT) -> Result<(), Error> {
+        let hbitmap = self.item_bitmap(pos);
+        let (checked, unchecked) = self.check_bitmaps(pos);
+        let child = unsafe { winuser::GetSubMenu(self.hmenu, pos as _) };
+        let res = unsafe { winuser::RemoveMenu(self.hmenu, pos, winuser::MF_BYPOSITION) };
+        if res == 0 {
+            return Err(Error::last_os_error("RemoveMenu"));
+        }
+        if !child.is_null() {
+            self.child_menus.retain(|menu| menu.hmenu != child);
         }
-        let res = unsafe { winuser::AppendMenuW(self.hmenu, flags, id, wchar(name).as_ptr() as _) };
-        res >= 0
+        self.delete_bitmap(hbitmap);
+        self.delete_bitmap(checked);
+        self.delete_bitmap(unchecked);
+        Ok(())
+    }
+
+    /// Submenu of the item at the position
+    pub fn child_menu(&self, pos: UINT) -> Option<&WinHMenu> {
+        let child = unsafe { winuser::GetSubMenu(self.hmenu, pos as _) };
+        self.child_menus.iter().find(|menu| menu.hmenu == child)
+    }
+
+    /// Submenu of the item at the position
+    pub fn child_menu_mut(&mut self, pos: UINT) -> Option<&mut WinHMenu> {
+        let child = unsafe { winuser::GetSubMenu(self.hmenu, pos as _) };
+        self.child_menus.iter_mut().find(|menu| menu.hmenu == child)
     }
-    pub fn add_child_menu(&mut self, name: &str, menu: WinHMenu, disabled: bool) -> bool {
-        let mut flags = winuser::MF_POPUP;
-        if disabled {
-            flags |= winuser::MF_GRAYED
+
+    /// Set the icon of the item at the position, the previous icon is deleted
+    ///
+    /// Icon is drawn in the small icon size.
+    pub fn set_icon(&mut self, pos: UINT, icon: Option<&Icon>) -> Result<(), Error> {
+        let previous = self.item_bitmap(pos);
+        let hbitmap = match icon {
+            Some(icon) => {
+                let width = unsafe { winuser::GetSystemMetrics(winuser::SM_CXSMICON) };
+                let height = unsafe { winuser::GetSystemMetrics(winuser::SM_CYSMICON) };
+                icon.sys_for_size(width as u32, height as u32)
+                    .to_hbitmap(width, height)
+                    .ok_or(Error::os_error("CreateDIBSection"))?
+            }
+            None => std::ptr::null_mut(),
+        };
+        let mut info: winuser::MENUITEMINFOW = unsafe { std::mem::zeroed() };
+        info.cbSize = std::mem::size_of::<winuser::MENUITEMINFOW>() as _;
+        info.fMask = winuser::MIIM_BITMAP;
+        info.hbmpItem = hbitmap;
+        let res = unsafe { winuser::SetMenuItemInfoW(self.hmenu, pos, TRUE, &info) };
+        if res == 0 {
+            let err = Error::last_os_error("SetMenuItemInfoW");
+            if !hbitmap.is_null() {
+                unsafe { wingdi::DeleteObject(hbitmap as _) };
+            }
+            return Err(err);
+        }
+
+        // Bitmap must be retained while the menu exists
+        if !hbitmap.is_null() {
+            self.bitmaps.push(hbitmap);
         }
+        self.delete_bitmap(previous);
+        Ok(())
+    }
+
+    /// Set the icons shown instead of the checkmark, the previous ones are
+    /// deleted
+    ///
+    /// Icons are drawn in the size of the checkmark, without them the
+    /// checkmark is shown.
+    pub fn set_check_icons(
+        &mut self,
+        pos: UINT,
+        checked: Option<&Icon>,
+        unchecked: Option<&Icon>,
+    ) -> Result<(), Error> {
+        let previous = self.check_bitmaps(pos);
+        let width = unsafe { winuser::GetSystemMetrics(winuser::SM_CXMENUCHECK) };
+        let height = unsafe { winuser::GetSystemMetrics(winuser::SM_CYMENUCHECK) };
+        let to_hbitmap = |icon: Option<&Icon>| match icon {
+            Some(icon) => icon
+                .sys_for_size(width as u32, height as u32)
+                .to_hbitmap(width, height)
+                .ok_or(Error::os_error("CreateDIBSection")),
+            None => Ok(std::ptr::null_mut()),
+        };
+        let hchecked = to_hbitmap(checked)?;
+        let hunchecked = match to_hbitmap(unchecked) {
+            Ok(hbitmap) => hbitmap,
+            Err(err) => {
+                self.delete_unretained(hchecked);
+                return Err(err);
+            }
+        };
         let res = unsafe {
-            winuser::AppendMenuW(
+            winuser::SetMenuItemBitmaps(
                 self.hmenu,
-                flags,
-                menu.hmenu as _,
-                wchar(name).as_ptr() as _,
+                pos,
+                winuser::MF_BYPOSITION,
+                hunchecked,
+                hchecked,
             )
         };
-        self.child_menus.push(menu);
-        res >= 0
+        if res == 0 {
+            let err = Error::last_os_error("SetMenuItemBitmaps");
+            self.delete_unretained(hchecked);
+            self.delete_unretained(hunchecked);
+            return Err(err);
+        }
+
+        // Bitmaps must be retained while the menu exists
+        for hbitmap in [hchecked, hunchecked] {
+            if !hbitmap.is_null() {
+                self.bitmaps.push(hbitmap);
+            }
+        }
+        self.delete_bitmap(previous.0);
+        self.delete_bitmap(previous.1);
+        Ok(())
     }
 
-    pub fn add_separator(&self) -> bool {
-        let res = unsafe { winuser::AppendMenuW(self.hmenu, winuser::MF_SEPARATOR, 0, 0 as _) };
-        res >= 0
+    /// Check bitmaps of the item at the position, the checked one first
+    fn check_bitmaps(&self, pos: UINT) -> (HBITMAP, HBITMAP) {
+        let mut info: winuser::MENUITEMINFOW = unsafe { std::mem::zeroed() };
+        info.cbSize = std::mem::size_of::<winuser::MENUITEMINFOW>() as _;
+        info.fMask = winuser::MIIM_CHECKMARKS;
+        if unsafe { winuser::GetMenuItemInfoW(self.hmenu, pos, TRUE, &mut info) } == 0 {
+            return (std::ptr::null_mut(), std::ptr::null_mut());
+        }
+        (info.hbmpChecked, info.hbmpUnchecked)
+    }
+
+    /// Delete the bitmap not yet given to the menu
+    fn delete_unretained(&self, hbitmap: HBITMAP) {
+        if !hbitmap.is_null() {
+            unsafe { wingdi::DeleteObject(hbitmap as _) };
+        }
+    }
+
+    /// Bitmap of the item at the position, null if none
+    fn item_bitmap(&self, pos: UINT) -> HBITMAP {
+        let mut info: winuser::MENUITEMINFOW = unsafe { std::mem::zeroed() };
+        info.cbSize = std::mem::size_of::<winuser::MENUITEMINFOW>() as _;
+        info.fMask = winuser::MIIM_BITMAP;
+        if unsafe { winuser::GetMenuItemInfoW(self.hmenu, pos, TRUE, &mut info) } == 0 {
+            return std::ptr::null_mut();
+        }
+        info.hbmpItem
+    }
+
+    /// Delete the bitmap, if it's retained by this menu
+    fn delete_bitmap(&mut self, hbitmap: HBITMAP) {
+        if let Some(i) = self.bitmaps.iter().position(|b| *b == hbitmap) {
+            self.bitmaps.swap_remove(i);
+            unsafe { wingdi::DeleteObject(hbitmap as _) };
+        }
     }
 
-    pub fn track(&self, hwnd: HWND, x: i32, y: i32) {
-        unsafe { winuser::TrackPopupMenu(self.hmenu, 0, x, y, 0, hwnd, std::ptr::null_mut()) };
+    /// Checked state, searches the submenus too
+    pub fn is_checked(&self, id: usize) -> bool {
+        let state = unsafe { winuser::GetMenuState(self.hmenu, id as _, winuser::MF_BYCOMMAND) };
+        state != u32::MAX && state & winuser::MF_CHECKED != 0
+    }
+
+    /// Disabled state, searches the submenus too
+    pub fn is_disabled(&self, id: usize) -> bool {
+        let state = unsafe { winuser::GetMenuState(self.hmenu, id as _, winuser::MF_BYCOMMAND) };
+        state != u32::MAX && state & (winuser::MF_GRAYED | winuser::MF_DISABLED) != 0
+    }
+
+    /// Menu is this one, e.g. the one in `WM_INITMENUPOPUP`
+    pub fn is(&self, hmenu: HMENU) -> bool {
+        self.hmenu == hmenu
+    }
+
+    pub fn set_checked(&self, id: usize, checked: bool) -> bool {
+        let flags = if checked {
+            winuser::MF_CHECKED
+        } else {
+            winuser::MF_UNCHECKED
+        };
+        let res =
+            unsafe { winuser::CheckMenuItem(self.hmenu, id as _, winuser::MF_BYCOMMAND | flags) };
+        res != u32::MAX
+    }
+
+    pub fn set_disabled(&self, id: usize, disabled: bool) -> bool {
+        let flags = if disabled {
+            winuser::MF_GRAYED
+        } else {
+            winuser::MF_ENABLED
+        };
+        let res =
+            unsafe { winuser::EnableMenuItem(self.hmenu, id as _, winuser::MF_BYCOMMAND | flags) };
+        res != -1
+    }
+
+    pub fn set_name(&self, id: usize, name: &str) -> bool {
+        let mut name = wchar(name);
+        let mut info: winuser::MENUITEMINFOW = unsafe { std::mem::zeroed() };
+        info.cbSize = std::mem::size_of::<winuser::MENUITEMINFOW>() as _;
+        info.fMask = winuser::MIIM_STRING;
+        info.dwTypeData = name.as_mut_ptr();
+        let res = unsafe { winuser::SetMenuItemInfoW(self.hmenu, id as _, FALSE, &info) };
+        res != 0
+    }
+
+    /// Right-to-left reading order of the items, in the submenus too
+    pub fn set_right_order(&self) {
+        let count = unsafe { winuser::GetMenuItemCount(self.hmenu) };
+        for i in 0..count.max(0) {
+            let mut info: winuser::MENUITEMINFOW = unsafe { std::mem::zeroed() };
+            info.cbSize = std::mem::size_of::<winuser::MENUITEMINFOW>() as _;
+            info.fMask = winuser::MIIM_FTYPE;
+            if unsafe { winuser::GetMenuItemInfoW(self.hmenu, i as _, TRUE, &mut info) } == 0 {
+                continue;
+            }
+            info.fType |= winuser::MFT_RIGHTORDER;
+            unsafe { winuser::SetMenuItemInfoW(self.hmenu, i as _, TRUE, &info) };
+        }
+        for child in &self.child_menus {
+            child.set_right_order();
+        }
+    }
+
+    /// Command of the chosen item with `TPM_RETURNCMD`, zero if none
+    pub fn track(&self, hwnd: HWND, x: i32, y: i32, flags: UINT) -> usize {
+        let res = unsafe {
+            winuser::TrackPopupMenuEx(self.hmenu, flags, x, y, hwnd, std::ptr::null_mut())
+        };
+        if flags & winuser::TPM_RETURNCMD != 0 {
+            res.max(0) as usize
+        } else {
+            0
+        }
     }
 }
 
@@ -96,5 +346,8 @@ unsafe impl Sync for WinHMenu {}
 impl Drop for WinHMenu {
     fn drop(&mut self) {
         unsafe { winuser::DestroyMenu(self.hmenu) };
+        for hbitmap in &self.bitmaps {
+            unsafe { wingdi::DeleteObject(*hbitmap as _) };
+        }
     }
 }
@@ -1,74 +1,428 @@
-use super::{msgs, wchar::wchar_array, winhicon::WinHIcon};
+use super::{
+    msgs,
+    wchar::{wchar, wchar_array},
+    winhicon::WinHIcon,
+};
+use crate::{Error, NotifyIconVersion};
 use std::fmt::Debug;
-use winapi::shared::windef::HWND;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use winapi::shared::guiddef::GUID;
+use winapi::shared::minwindef::UINT;
+use winapi::shared::windef::{HWND, POINT, RECT};
+use winapi::um::winuser;
+
+/// Distance of the mouse moves sweeping the notification area, less than the
+/// width of an icon
+const SWEEP_STEP: usize = 8;
 
-#[cfg_attr(target_arch = "x86", repr(packed))]
 /// Purpose of this struct is to retain NotifyIconDataW and remove it on drop
 pub struct WinNotifyIcon {
     winhicon: WinHIcon,
+    balloon_icon: Option<WinHIcon>,
+
+    /// Timeout of the balloon while it's shown, for showing it again
+    balloon_timeout: Option<UINT>,
+    visible: bool,
+
+    /// Version set when added, lowered if the shell doesn't support it
+    version: NotifyIconVersion,
+    nid: winapi::um::shellapi::NOTIFYICONDATAW,
+
+    /// Set by the `NotifyIconRemover`, the icon is not added again
+    removed: Arc<AtomicBool>,
+}
+
+/// Removes the icon from any thread, without the data of the icon, which
+/// belongs to the thread of the window
+pub struct NotifyIconRemover {
+    /// Only the window with the id, or the guid
     nid: winapi::um::shellapi::NOTIFYICONDATAW,
+    removed: Arc<AtomicBool>,
 }
 
 impl WinNotifyIcon {
-    pub fn new(winhicon: &WinHIcon, tooltip: &Option<String>) -> WinNotifyIcon {
-        static mut ICON_ID: u32 = 1000;
-        unsafe {
-            ICON_ID += 1;
-        }
+    /// With the rich popup the tooltip is not shown, so that the popup can be
+    /// shown instead
+    pub fn new(
+        winhicon: &WinHIcon,
+        tooltip: &Option<String>,
+        guid: Option<u128>,
+        uid: Option<u32>,
+        version: NotifyIconVersion,
+        rich_popup: bool,
+    ) -> WinNotifyIcon {
+        static ICON_ID: AtomicU32 = AtomicU32::new(1000);
         let mut icon = WinNotifyIcon {
             winhicon: winhicon.clone(),
+            balloon_icon: None,
+            balloon_timeout: None,
+            visible: true,
+            version,
             nid: unsafe { std::mem::zeroed() },
+            removed: Arc::new(AtomicBool::new(false)),
         };
         if let Some(tooltip) = tooltip {
             let sz_tip = std::ptr::addr_of_mut!(icon.nid.szTip);
             wchar_array(tooltip, unsafe { &mut *sz_tip });
         }
         icon.nid.cbSize = std::mem::size_of::<winapi::um::shellapi::NOTIFYICONDATAW>() as u32;
-        icon.nid.uID = unsafe { ICON_ID };
+        icon.nid.uID = uid.unwrap_or_else(|| ICON_ID.fetch_add(1, Ordering::Relaxed) + 1);
         icon.nid.uCallbackMessage = msgs::WM_USER_TRAYICON;
-        icon.nid.hIcon = icon.winhicon.hicon;
+        icon.nid.hIcon = icon.winhicon.hicon();
         icon.nid.uFlags = winapi::um::shellapi::NIF_MESSAGE
             | winapi::um::shellapi::NIF_ICON
             | winapi::um::shellapi::NIF_TIP;
+        if version == NotifyIconVersion::V4 && !rich_popup {
+            icon.nid.uFlags |= winapi::um::shellapi::NIF_SHOWTIP;
+        }
+        if let Some(guid) = guid {
+            icon.nid.guidItem = to_guid(guid);
+            icon.nid.uFlags |= winapi::um::shellapi::NIF_GUID;
+        }
 
         icon
     }
 }
 
 impl WinNotifyIcon {
+    /// Add the icon, unless it's hidden
     pub fn add(&mut self, hwnd: HWND) -> bool {
         self.nid.hWnd = hwnd;
-        let res = unsafe {
+        if !self.visible || self.removed.load(Ordering::SeqCst) {
+            return true;
+        }
+        trace_span!(DEBUG, "notify_icon_add");
+        let mut res = unsafe {
             winapi::um::shellapi::Shell_NotifyIconW(winapi::um::shellapi::NIM_ADD, &mut self.nid)
         };
-        res == 1
+
+        // Icon with the guid is refused while the ghost icon of a crashed
+        // instance is still there, it's removed and added again
+        if res != 1 && self.nid.uFlags & winapi::um::shellapi::NIF_GUID != 0 {
+            trace_event!(DEBUG, "NIM_ADD failed, removing the icon with the guid");
+            delete_icon(self.nid.guidItem);
+            res = unsafe {
+                winapi::um::shellapi::Shell_NotifyIconW(
+                    winapi::um::shellapi::NIM_ADD,
+                    &mut self.nid,
+                )
+            };
+        }
+        if res != 1 {
+            trace_event!(WARN, "NIM_ADD failed, the shell refused the icon");
+            return false;
+        }
+
+        // Version 3 keeps the message format, but adds the keyboard selection
+        // and the context menu messages. Version 4 moves the message to the
+        // low word, and adds the rich popup messages. Older shells, e.g. Wine,
+        // refuse the newer versions, so fall back until one is accepted.
+        while self.version != NotifyIconVersion::Legacy {
+            let version = match self.version {
+                NotifyIconVersion::V4 => winapi::um::shellapi::NOTIFYICON_VERSION_4,
+                _ => winapi::um::shellapi::NOTIFYICON_VERSION,
+            };
+            let u = std::ptr::addr_of_mut!(self.nid.u);
+            unsafe { std::ptr::write_unaligned(u as *mut UINT, version) };
+            let res = unsafe {
+                winapi::um::shellapi::Shell_NotifyIconW(
+                    winapi::um::shellapi::NIM_SETVERSION,
+                    &mut self.nid,
+                )
+            };
+            if res == 1 {
+                break;
+            }
+            let refused = self.version;
+            trace_event!(DEBUG, "NIM_SETVERSION {:?} refused", refused);
+            self.version = match self.version {
+                NotifyIconVersion::V4 => NotifyIconVersion::V3,
+                _ => NotifyIconVersion::Legacy,
+            };
+        }
+        let version = self.version;
+        trace_event!(DEBUG, "icon added with the version {:?}", version);
+        true
+    }
+
+    /// Version accepted by the shell
+    pub fn version(&self) -> NotifyIconVersion {
+        self.version
+    }
+
+    /// Add the icon again after the taskbar is restarted
+    ///
+    /// Tooltip and visibility are retained in the icon data, the balloon is
+    /// shown again if it was not closed yet.
+    pub fn restore(&mut self, hwnd: HWND) -> bool {
+        if !self.add(hwnd) {
+            return false;
+        }
+        match self.balloon_timeout {
+            Some(timeout_ms) if self.visible => self.modify_balloon(timeout_ms),
+            _ => true,
+        }
+    }
+
+    /// Balloon was closed, clicked or timed out
+    pub fn balloon_closed(&mut self) {
+        self.balloon_timeout = None;
+        self.balloon_icon = None;
+        self.nid.hBalloonIcon = std::ptr::null_mut();
+    }
+
+    /// Remover of the icon added to the window
+    pub fn remover(&self, hwnd: HWND) -> NotifyIconRemover {
+        let mut nid: winapi::um::shellapi::NOTIFYICONDATAW = unsafe { std::mem::zeroed() };
+        nid.cbSize = self.nid.cbSize;
+        nid.hWnd = hwnd;
+        nid.uID = self.nid.uID;
+        nid.uFlags = self.nid.uFlags & winapi::um::shellapi::NIF_GUID;
+        nid.guidItem = self.nid.guidItem;
+        NotifyIconRemover {
+            nid,
+            removed: self.removed.clone(),
+        }
     }
 
     pub fn remove(&mut self) -> bool {
         let res = unsafe {
             winapi::um::shellapi::Shell_NotifyIconW(winapi::um::shellapi::NIM_DELETE, &mut self.nid)
         };
+        trace_event!(DEBUG, "NIM_DELETE returned {}", res);
         res == 1
     }
 
-    pub fn set_icon(&mut self, winhicon: &WinHIcon) -> bool {
-        self.winhicon = winhicon.clone();
-        self.nid.hIcon = self.winhicon.hicon;
+    /// Hidden icon is removed, and added again when shown
+    pub fn set_visible(&mut self, visible: bool) -> bool {
+        if self.visible == visible {
+            return true;
+        }
+        self.visible = visible;
+        if visible {
+            self.add(self.nid.hWnd)
+        } else {
+            self.remove()
+        }
+    }
+
+    /// Modify the added icon, hidden icon is only modified for adding
+    fn modify(&mut self) -> bool {
+        if !self.visible {
+            return true;
+        }
         let res = unsafe {
             winapi::um::shellapi::Shell_NotifyIconW(winapi::um::shellapi::NIM_MODIFY, &mut self.nid)
         };
+        if res != 1 {
+            trace_event!(WARN, "NIM_MODIFY failed");
+        }
         res == 1
     }
 
+    /// Screen rectangle of the icon, if it's shown
+    pub fn rect(&self) -> Option<RECT> {
+        let mut identifier: winapi::um::shellapi::NOTIFYICONIDENTIFIER =
+            unsafe { std::mem::zeroed() };
+        identifier.cbSize =
+            std::mem::size_of::<winapi::um::shellapi::NOTIFYICONIDENTIFIER>() as u32;
+        identifier.hWnd = self.nid.hWnd;
+        identifier.uID = self.nid.uID;
+        identifier.guidItem = self.nid.guidItem;
+        let mut rect: RECT = unsafe { std::mem::zeroed() };
+        let res = unsafe { winapi::um::shellapi::Shell_NotifyIconGetRect(&identifier, &mut rect) };
+        if res == 0 {
+            Some(rect)
+        } else {
+            None
+        }
+    }
+
+    /// Icon is shown on the taskbar, not in the overflow area or hidden
+    pub fn is_in_tray(&self) -> Result<bool, Error> {
+        if !self.visible {
+            return Ok(false);
+        }
+        let rect = self
+            .rect()
+            .ok_or(Error::os_error("Shell_NotifyIconGetRect"))?;
+        let tray = find_window(None, "Shell_TrayWnd").ok_or(Error::os_error("FindWindowW"))?;
+
+        // Windows 11 draws the notification area without the child window
+        let area = find_window(Some(tray), "TrayNotifyWnd").unwrap_or(tray);
+        let mut area_rect: RECT = unsafe { std::mem::zeroed() };
+        if unsafe { winuser::GetWindowRect(area, &mut area_rect) } == 0 {
+            return Err(Error::last_os_error("GetWindowRect"));
+        }
+        let center = POINT {
+            x: (rect.left + rect.right) / 2,
+            y: (rect.top + rect.bottom) / 2,
+        };
+        Ok(unsafe { winuser::PtInRect(&area_rect, center) } != 0)
+    }
+
+    pub fn set_icon(&mut self, winhicon: &WinHIcon) -> bool {
+        self.winhicon = winhicon.clone();
+        self.nid.hIcon = self.winhicon.hicon();
+        self.modify()
+    }
+
     pub fn set_tooltip(&mut self, tooltip: &str) -> bool {
         let sz_tip = std::ptr::addr_of_mut!(self.nid.szTip);
         wchar_array(tooltip, unsafe { &mut *sz_tip });
+        self.modify()
+    }
+
+    pub fn show_notification(
+        &mut self,
+        title: &str,
+        body: &str,
+        icon: Option<&WinHIcon>,
+        timeout: Option<Duration>,
+    ) -> bool {
+        let sz_info_title = std::ptr::addr_of_mut!(self.nid.szInfoTitle);
+        wchar_array(title, unsafe { &mut *sz_info_title });
+        let sz_info = std::ptr::addr_of_mut!(self.nid.szInfo);
+        wchar_array(body, unsafe { &mut *sz_info });
+
+        // Balloon icon must be retained while the balloon is shown
+        self.balloon_icon = icon.cloned();
+        if let Some(balloon_icon) = &self.balloon_icon {
+            self.nid.hBalloonIcon = balloon_icon.hicon();
+            self.nid.dwInfoFlags =
+                winapi::um::shellapi::NIIF_USER | winapi::um::shellapi::NIIF_LARGE_ICON;
+        } else {
+            self.nid.hBalloonIcon = std::ptr::null_mut();
+            self.nid.dwInfoFlags = winapi::um::shellapi::NIIF_NONE;
+        }
+
+        let timeout_ms = timeout.map_or(0, |t| t.as_millis().min(UINT::MAX as u128) as UINT);
+        let res = self.modify_balloon(timeout_ms);
+        self.balloon_timeout = if res { Some(timeout_ms) } else { None };
+        res
+    }
+
+    /// Show the balloon set in the icon data
+    fn modify_balloon(&mut self, timeout_ms: UINT) -> bool {
+        let u = std::ptr::addr_of_mut!(self.nid.u);
+        unsafe { std::ptr::write_unaligned(u as *mut UINT, timeout_ms) };
+
+        self.nid.uFlags |= winapi::um::shellapi::NIF_INFO;
         let res = unsafe {
             winapi::um::shellapi::Shell_NotifyIconW(winapi::um::shellapi::NIM_MODIFY, &mut self.nid)
         };
+
+        // Otherwise the balloon is shown again with each modification
+        self.nid.uFlags &= !winapi::um::shellapi::NIF_INFO;
+        if res != 1 {
+            trace_event!(WARN, "NIM_MODIFY of the balloon failed");
+        }
         res == 1
     }
 }
+
+fn to_guid(guid: u128) -> GUID {
+    GUID {
+        Data1: (guid >> 96) as u32,
+        Data2: (guid >> 80) as u16,
+        Data3: (guid >> 64) as u16,
+        Data4: (guid as u64).to_be_bytes(),
+    }
+}
+
+/// Remove the icon with the guid, e.g. the ghost icon of a crashed instance
+///
+/// Guid is tied to the path of the executable, so icons of the other apps are
+/// not removed.
+pub fn delete_icon(guid: GUID) -> bool {
+    let mut nid: winapi::um::shellapi::NOTIFYICONDATAW = unsafe { std::mem::zeroed() };
+    nid.cbSize = std::mem::size_of::<winapi::um::shellapi::NOTIFYICONDATAW>() as u32;
+    nid.uFlags = winapi::um::shellapi::NIF_GUID;
+    nid.guidItem = guid;
+    let res = unsafe {
+        winapi::um::shellapi::Shell_NotifyIconW(winapi::um::shellapi::NIM_DELETE, &mut nid)
+    };
+    trace_event!(DEBUG, "NIM_DELETE of the guid returned {}", res);
+    res == 1
+}
+
+/// Remove the ghost icons, the one with the guid and the ones without a window
+///
+/// Shell removes the icons of the destroyed windows only when the mouse is
+/// moved over them, so the toolbars of the notification area and the overflow
+/// area are swept with mouse moves. Returns false if there is no taskbar.
+pub fn remove_stale_icons(guid: Option<u128>) -> bool {
+    if let Some(guid) = guid {
+        delete_icon(to_guid(guid));
+    }
+    let tray = match find_window(None, "Shell_TrayWnd") {
+        Some(tray) => tray,
+        None => {
+            trace_event!(WARN, "there is no taskbar");
+            return false;
+        }
+    };
+    let notify = find_window(Some(tray), "TrayNotifyWnd");
+    let pager = notify.and_then(|notify| find_window(Some(notify), "SysPager"));
+    let overflow = find_window(None, "NotifyIconOverflowWindow");
+
+    // Windows XP has the toolbar directly in the notification area
+    for parent in [pager, notify, overflow].iter().flatten() {
+        if let Some(toolbar) = find_window(Some(*parent), "ToolbarWindow32") {
+            sweep(toolbar);
+        }
+    }
+    true
+}
+
+/// Top level window with the class, or the first child window of the parent
+fn find_window(parent: Option<HWND>, class: &str) -> Option<HWND> {
+    let class = wchar(class);
+    let hwnd = unsafe {
+        match parent {
+            Some(parent) => winuser::FindWindowExW(
+                parent,
+                std::ptr::null_mut(),
+                class.as_ptr(),
+                std::ptr::null(),
+            ),
+            None => winuser::FindWindowW(class.as_ptr(), std::ptr::null()),
+        }
+    };
+    if hwnd.is_null() {
+        None
+    } else {
+        Some(hwnd)
+    }
+}
+
+/// Move the mouse over the whole toolbar
+fn sweep(toolbar: HWND) {
+    let mut rect: RECT = unsafe { std::mem::zeroed() };
+    if unsafe { winuser::GetClientRect(toolbar, &mut rect) } == 0 {
+        return;
+    }
+    for y in (0..rect.bottom.max(0)).step_by(SWEEP_STEP) {
+        for x in (0..rect.right.max(0)).step_by(SWEEP_STEP) {
+            let lparam = ((y as u32) << 16 | (x as u32 & 0xffff)) as isize;
+            let mut result = 0;
+            unsafe {
+                winuser::SendMessageTimeoutW(
+                    toolbar,
+                    winuser::WM_MOUSEMOVE,
+                    0,
+                    lparam,
+                    winuser::SMTO_ABORTIFHUNG,
+                    100,
+                    &mut result,
+                )
+            };
+        }
+    }
+}
+
 unsafe impl Send for WinNotifyIcon {}
 unsafe impl Sync for WinNotifyIcon {}
 
@@ -78,6 +432,25 @@ impl Debug for WinNotifyIcon {
     }
 }
 
+impl NotifyIconRemover {
+    /// Remove the icon, it's not added again afterwards, e.g. when the
+    /// taskbar is restarted
+    pub fn remove(&mut self) -> bool {
+        self.removed.store(true, Ordering::SeqCst);
+        let res = unsafe {
+            winapi::um::shellapi::Shell_NotifyIconW(winapi::um::shellapi::NIM_DELETE, &mut self.nid)
+        };
+        trace_event!(DEBUG, "NIM_DELETE from another thread returned {}", res);
+        res == 1
+    }
+}
+
+impl Debug for NotifyIconRemover {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "NotifyIconRemover({})", self.nid.uID)
+    }
+}
+
 impl Drop for WinNotifyIcon {
     fn drop(&mut self) {
         unsafe {
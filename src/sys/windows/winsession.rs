@@ -0,0 +1,47 @@
+use super::wchar::wchar;
+use winapi::shared::minwindef::{BOOL, DWORD};
+use winapi::shared::windef::HWND;
+use winapi::um::libloaderapi::{GetProcAddress, LoadLibraryW};
+
+/// Only the session of the process, not all the sessions
+const NOTIFY_FOR_THIS_SESSION: DWORD = 0;
+
+/// Send the session lock and unlock messages to the window
+///
+/// wtsapi32.dll is loaded when needed, it's not linked by winapi.
+pub(crate) fn register_session_notification(hwnd: HWND) -> bool {
+    match proc_address("WTSRegisterSessionNotification") {
+        Some(proc) => unsafe {
+            let register: extern "system" fn(HWND, DWORD) -> BOOL = std::mem::transmute(proc);
+            register(hwnd, NOTIFY_FOR_THIS_SESSION) != 0
+        },
+        None => false,
+    }
+}
+
+/// Unregister before the window is destroyed
+pub(crate) fn unregister_session_notification(hwnd: HWND) {
+    if let Some(proc) = proc_address("WTSUnRegisterSessionNotification") {
+        unsafe {
+            let unregister: extern "system" fn(HWND) -> BOOL = std::mem::transmute(proc);
+            unregister(hwnd);
+        }
+    }
+}
+
+fn proc_address(function: &str) -> Option<usize> {
+    unsafe {
+        // Library is left loaded, it's loaded only once
+        let wtsapi32 = LoadLibraryW(wchar("wtsapi32.dll").as_ptr());
+        if wtsapi32.is_null() {
+            return None;
+        }
+        let function = format!("{}\0", function);
+        let proc = GetProcAddress(wtsapi32, function.as_ptr() as _);
+        if proc.is_null() {
+            None
+        } else {
+            Some(proc as usize)
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use super::wchar::wchar;
+use crate::Error;
+use winapi::shared::minwindef::{DWORD, FALSE, LPARAM};
+use winapi::shared::windef::HWND;
+use winapi::shared::winerror::ERROR_ALREADY_EXISTS;
+use winapi::um::winnt::HANDLE;
+use winapi::um::{errhandlingapi, handleapi, synchapi, winuser};
+
+/// Attempts to find the window of the first instance, it may be still starting
+const FIND_WINDOW_ATTEMPTS: u32 = 50;
+const FIND_WINDOW_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Named mutex held by the first instance until dropped
+pub struct WinSingleInstance {
+    mutex: HANDLE,
+    primary: bool,
+}
+
+impl WinSingleInstance {
+    pub fn new(name: &str) -> Result<WinSingleInstance, Error> {
+        let mutex =
+            unsafe { synchapi::CreateMutexW(std::ptr::null_mut(), FALSE, wchar(name).as_ptr()) };
+        if mutex.is_null() {
+            return Err(Error::last_os_error("CreateMutexW"));
+        }
+        let primary = unsafe { errhandlingapi::GetLastError() } != ERROR_ALREADY_EXISTS;
+        Ok(WinSingleInstance { mutex, primary })
+    }
+
+    pub fn is_primary(&self) -> bool {
+        self.primary
+    }
+
+    /// Send the arguments to the hidden window of the first instance
+    ///
+    /// The first instance is allowed to bring its window to the foreground.
+    pub fn forward(name: &str, args: &[String]) -> Result<(), Error> {
+        let hwnd = find_window(name).ok_or_else(|| Error::os_error("FindWindowW"))?;
+        let mut data = encode_args(args);
+        let copy_data = winuser::COPYDATASTRUCT {
+            dwData: copy_data_id(),
+            cbData: (data.len() * std::mem::size_of::<u16>()) as DWORD,
+            lpData: data.as_mut_ptr() as _,
+        };
+        unsafe {
+            let mut process_id: DWORD = 0;
+            winuser::GetWindowThreadProcessId(hwnd, &mut process_id);
+            winuser::AllowSetForegroundWindow(process_id);
+            let res = winuser::SendMessageW(
+                hwnd,
+                winuser::WM_COPYDATA,
+                0,
+                &copy_data as *const _ as LPARAM,
+            );
+            if res == 0 {
+                return Err(Error::os_error("SendMessageW"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for WinSingleInstance {
+    fn drop(&mut self) {
+        unsafe { handleapi::CloseHandle(self.mutex) };
+    }
+}
+
+unsafe impl Send for WinSingleInstance {}
+unsafe impl Sync for WinSingleInstance {}
+
+impl std::fmt::Debug for WinSingleInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SingleInstance")
+    }
+}
+
+/// Title of the hidden window of the first instance, for finding it
+pub(crate) fn window_name(name: &str) -> String {
+    format!("TrayIconSingleInstance {}", name)
+}
+
+/// `dwData` of the `WM_COPYDATA` with the arguments
+pub(crate) fn copy_data_id() -> usize {
+    unsafe { winuser::RegisterWindowMessageW(wchar("TrayIconSecondInstance").as_ptr()) as usize }
+}
+
+/// Arguments of the `WM_COPYDATA` sent by the second instance
+///
+/// The caller checks the `dwData` before.
+pub(crate) unsafe fn copy_data_args(copy_data: &winuser::COPYDATASTRUCT) -> Vec<String> {
+    if copy_data.lpData.is_null() {
+        return vec![];
+    }
+    let data = std::slice::from_raw_parts(
+        copy_data.lpData as *const u16,
+        copy_data.cbData as usize / std::mem::size_of::<u16>(),
+    );
+    decode_args(data)
+}
+
+fn find_window(name: &str) -> Option<HWND> {
+    let window_name = wchar(&window_name(name));
+    for _ in 0..FIND_WINDOW_ATTEMPTS {
+        // Message-only windows are found only by their parent
+        let hwnd = unsafe {
+            match winuser::FindWindowW(std::ptr::null(), window_name.as_ptr()) {
+                hwnd if hwnd.is_null() => winuser::FindWindowExW(
+                    winuser::HWND_MESSAGE,
+                    std::ptr::null_mut(),
+                    std::ptr::null(),
+                    window_name.as_ptr(),
+                ),
+                hwnd => hwnd,
+            }
+        };
+        if !hwnd.is_null() {
+            return Some(hwnd);
+        }
+        std::thread::sleep(FIND_WINDOW_INTERVAL);
+    }
+    None
+}
+
+/// Each argument is terminated with a null
+fn encode_args(args: &[String]) -> Vec<u16> {
+    args.iter()
+        .flat_map(|arg| arg.encode_utf16().chain(std::iter::once(0)))
+        .collect()
+}
+
+fn decode_args(data: &[u16]) -> Vec<String> {
+    match data.strip_suffix(&[0]) {
+        Some(data) => data
+            .split(|c| *c == 0)
+            .map(String::from_utf16_lossy)
+            .collect(),
+        None => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_args_round_trip() {
+        let args = vec!["--open".to_string(), "".to_string(), "ä.txt".to_string()];
+        assert_eq!(decode_args(&encode_args(&args)), args);
+        assert_eq!(decode_args(&encode_args(&[])), Vec::<String>::new());
+    }
+}
@@ -1,55 +1,132 @@
 use std::{
+    collections::HashMap,
     fmt::Debug,
     ops::{Deref, DerefMut},
+    time::{Duration, Instant},
 };
-use winapi::shared::minwindef::{HIWORD, LOWORD, LPARAM, LPVOID, LRESULT, UINT, WPARAM};
-use winapi::shared::windef::{HBRUSH, HICON, HMENU, HWND, POINT};
-use winapi::um::libloaderapi::GetModuleHandleW;
-use winapi::um::winuser;
+use winapi::shared::minwindef::{HIWORD, LOWORD, LPARAM, LPVOID, LRESULT, TRUE, UINT, WPARAM};
+use winapi::shared::windef::{HBRUSH, HICON, HMENU, HWND, POINT, RECT, SIZE};
+use winapi::shared::winerror::ERROR_CLASS_ALREADY_EXISTS;
+use winapi::um::libloaderapi::{GetModuleHandleW, GetProcAddress};
+use winapi::um::processthreadsapi::GetCurrentThreadId;
+use winapi::um::shellapi::{
+    NIN_BALLOONHIDE, NIN_BALLOONTIMEOUT, NIN_BALLOONUSERCLICK, NIN_KEYSELECT, NIN_POPUPCLOSE,
+    NIN_POPUPOPEN, NIN_SELECT,
+};
+use winapi::um::{dbt, wingdi, winuser};
 
 use super::wchar::wchar;
-use super::{msgs, winnotifyicon::WinNotifyIcon, MenuSys};
-use crate::{trayiconsender::TrayIconSender, Error, Icon, MenuBuilder, TrayIconBase};
+use super::{
+    msgs,
+    winhmenu::WinHMenu,
+    winnotifyicon::{NotifyIconRemover, WinNotifyIcon},
+    winsession, winsingleinstance, winuxtheme, winwindow, MenuSys,
+};
+use crate::{
+    accelerator::{Accelerator, Key},
+    badge::{self, Decoration},
+    menubuilder::{menu_label, MenuFn, MenuUpdater, Toggled},
+    singleinstance::SecondInstanceFn,
+    trayicon::TooltipFn,
+    trayiconbuilder::{ErrorFn, RawMessageFn, TrayIconBuilder},
+    trayiconsender::TrayIconSender,
+    ClickBehavior, Error, Icon, MenuBuilder, MenuCommandId, MenuItem, Modifiers, MouseButton,
+    NotifyIconVersion, PhysicalPosition, SystemTheme, TrayIconBase, TrayIconEvent,
+};
 
 pub type WinTrayIcon<T> = WindowBox<T>;
 
+/// Adding the icon is retried after this, doubling the delay each time
+const ADD_RETRY_INTERVAL: UINT = 500;
+const ADD_RETRY_MAX_INTERVAL: UINT = 8000;
+const ADD_RETRIES: u32 = 8;
+
+/// How often the icon is checked to be on the taskbar
+const TRAY_VISIBILITY_POLL_INTERVAL: UINT = 2000;
+
+/// How often the message-only window checks the taskbar and the theme
+const TASKBAR_POLL_INTERVAL: UINT = 1000;
+
 /// WindowBox retains the memory for the Window object until WM_NCDESTROY
 #[derive(Debug)]
-pub struct WindowBox<T>(*mut WinTrayIconImpl<T>)
+pub struct WindowBox<T>
 where
-    T: PartialEq + Clone + 'static;
+    T: Clone + 'static,
+{
+    ptr: *mut WinTrayIconImpl<T>,
 
-impl<T> Drop for WindowBox<T>
+    /// Kept here, the window object is freed when the window is destroyed
+    hwnd: HWND,
+    closed: bool,
+
+    /// Removes the icon from other threads, which can't touch the window
+    /// object
+    remover: NotifyIconRemover,
+}
+
+impl<T> WindowBox<T>
 where
-    T: PartialEq + Clone + 'static,
+    T: Clone + 'static,
 {
-    fn drop(&mut self) {
+    /// Remove the icon and destroy the window, from any thread
+    ///
+    /// On the thread of the window it's destroyed right away. From other
+    /// threads the icon is removed right away, and it's not added again, then
+    /// the window is closed by its own thread when it processes the messages.
+    pub(crate) fn close(&mut self) -> Result<(), Error> {
+        if std::mem::replace(&mut self.closed, true) {
+            return Ok(());
+        }
         unsafe {
-            // PostMessage doesn't seem to work here, because winit exits before it manages to be processed
+            // Window was destroyed by the system, e.g. at the end of the session
+            if winuser::IsWindow(self.hwnd) == 0 {
+                return Ok(());
+            }
+            let thread = winuser::GetWindowThreadProcessId(self.hwnd, std::ptr::null_mut());
+            if thread == GetCurrentThreadId() {
+                // PostMessage doesn't seem to work here, because winit exits before it manages to be processed
 
-            // https://devblogs.microsoft.com/oldnewthing/20110926-00/?p=9553
-            winuser::SendMessageW(self.hwnd, winuser::WM_CLOSE, 0, 0);
+                // https://devblogs.microsoft.com/oldnewthing/20110926-00/?p=9553
+                winuser::SendMessageW(self.hwnd, winuser::WM_CLOSE, 0, 0);
+                return Ok(());
+            }
+            self.remover.remove();
+            if winuser::PostMessageW(self.hwnd, winuser::WM_CLOSE, 0, 0) == 0
+                && winuser::IsWindow(self.hwnd) != 0
+            {
+                return Err(Error::last_os_error("PostMessageW"));
+            }
         }
+        Ok(())
+    }
+}
+
+impl<T> Drop for WindowBox<T>
+where
+    T: Clone + 'static,
+{
+    fn drop(&mut self) {
+        let _ = self.close();
     }
 }
 
 impl<T> Deref for WindowBox<T>
 where
-    T: PartialEq + Clone + 'static,
+    T: Clone + 'static,
 {
     type Target = WinTrayIconImpl<T>;
 
     fn deref(&self) -> &WinTrayIconImpl<T> {
-        unsafe { &mut *(self.0) }
+        unsafe { &mut *(self.ptr) }
     }
 }
 
 impl<T> DerefMut for WindowBox<T>
 where
-    T: PartialEq + Clone + 'static,
+    T: Clone + 'static,
 {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { &mut *(self.0) }
+        unsafe { &mut *(self.ptr) }
     }
 }
 
@@ -59,41 +136,152 @@ where
 #[derive(Debug)]
 pub struct WinTrayIconImpl<T>
 where
-    T: PartialEq + Clone + 'static,
+    T: Clone + 'static,
 {
     hwnd: HWND,
     sender: TrayIconSender<T>,
     menu: Option<MenuSys<T>>,
+    icon: Icon,
     notify_icon: WinNotifyIcon,
     on_click: Option<T>,
     on_double_click: Option<T>,
     on_right_click: Option<T>,
+    on_middle_click: Option<T>,
+    on_mouse_enter: Option<T>,
+    on_mouse_leave: Option<T>,
+    on_key_select: Option<T>,
+    on_taskbar_restarted: Option<T>,
+    on_menu_open: Option<T>,
+    on_menu_dismissed: Option<T>,
+    on_menu_hover: Option<T>,
+    on_notification_clicked: Option<T>,
+    on_notification_closed: Option<T>,
+    on_notification_hidden: Option<T>,
+    on_popup_open: Option<T>,
+    on_popup_close: Option<T>,
+    on_theme_changed: Option<T>,
+    on_clipboard_changed: Option<T>,
+    on_session_lock: Option<T>,
+    on_session_unlock: Option<T>,
+    on_suspend: Option<T>,
+    on_resume: Option<T>,
+    on_end_session: Option<T>,
+    on_display_changed: Option<T>,
+    on_raw_message: Option<RawMessageFn<T>>,
+    on_second_instance: Option<(String, SecondInstanceFn<T>)>,
+    on_error: Option<ErrorFn>,
+
+    /// Window shown and hidden by the left click
+    toggle_window: Option<HWND>,
+
+    /// Window hidden instead of minimized or closed, restored by the click
+    minimize_to_tray: Option<HWND>,
+    on_restore_requested: Option<T>,
+    on_icon_added: Option<T>,
+    on_icon_add_failed: Option<T>,
+
+    /// Icon was on the taskbar when polled last, not in the overflow area
+    on_tray_visibility_changed: Option<T>,
+    in_tray: Option<bool>,
+
+    /// Adding the icon failed, and it's retried on the timer
+    add_retries: u32,
+
+    /// `dwData` of the arguments of the second instance
+    copy_data_id: usize,
+
+    /// Icons switched with the theme, until the icon is set
+    icon_light: Option<Icon>,
+    icon_dark: Option<Icon>,
+    menu_with: Option<MenuFn<T>>,
+    menu_updater: Option<MenuUpdater<T>>,
+
+    /// Checkables are toggled when clicked, and recorded here
+    toggled: Option<Toggled<T>>,
+    menu_on_left_click: bool,
+
+    /// Menu shown on the right click with the modifiers, instead of the menu
+    alt_menu: Option<(MenuSys<T>, Modifiers)>,
+
+    /// Right button went up with the modifiers of the alternate menu, it's
+    /// shown by the context menu message following it, and kept set while
+    /// it's shown
+    alt_menu_active: bool,
+
+    /// Left click is sent after the delay, unless it becomes a double click,
+    /// the double click time of the system if none
+    click_behavior: ClickBehavior,
+    single_click_delay: Option<Duration>,
+    pending_click: Option<(PhysicalPosition, Modifiers)>,
+
+    /// Second button up of the double click is not a click
+    double_clicked: bool,
+
+    /// Shell sends the selection again after the left click, and twice for
+    /// Enter, those within the double click time are not sent
+    last_left_up: Option<Instant>,
+    last_select: Option<Instant>,
+
+    /// Accelerators of the menu items are registered as global hotkeys
+    menu_hotkeys: bool,
+
+    /// Menu and the tooltip are laid out right to left
+    rtl: bool,
+    mouse_over: bool,
+    animation: Vec<Icon>,
+    animation_frame: usize,
+
+    /// Tooltip is set from this on the timer
+    tooltip_fn: Option<TooltipFn>,
+
+    /// Badge and progress drawn on the icon, and on each animation frame
+    decoration: Decoration,
     msg_taskbarcreated: Option<UINT>,
-}
 
-unsafe impl<T> Send for WinTrayIconImpl<T> where T: PartialEq + Clone {}
-unsafe impl<T> Sync for WinTrayIconImpl<T> where T: PartialEq + Clone {}
+    /// Message-only window doesn't receive TaskbarCreated, the taskbar window
+    /// is polled instead
+    message_only: bool,
+    taskbar: HWND,
+
+    /// Taskbar theme, for sending only the changes
+    theme: Option<SystemTheme>,
+
+    /// Number of monitors, for sending only the changes
+    monitors: i32,
+}
 
 impl<T> WinTrayIconImpl<T>
 where
-    T: PartialEq + Clone + 'static,
+    T: Clone + 'static,
 {
+    /// Hidden window of the tray icon, with the events of the builder
+    ///
+    /// Menus, the icon and the notify icon are built by the caller.
     #[allow(clippy::new_ret_no_self)]
-    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
+        builder: &TrayIconBuilder<T>,
         sender: TrayIconSender<T>,
         menu: Option<MenuSys<T>>,
+        alt_menu: Option<(MenuSys<T>, Modifiers)>,
+        icon: Icon,
         notify_icon: WinNotifyIcon,
-        on_click: Option<T>,
-        on_double_click: Option<T>,
-        on_right_click: Option<T>,
+        rtl: bool,
     ) -> Result<WinTrayIcon<T>, Error>
     where
-        T: PartialEq + Clone + 'static,
+        T: Clone + 'static,
     {
         unsafe {
             let hinstance = GetModuleHandleW(0 as _);
-            let wnd_class_name = wchar("TrayIconCls");
+
+            // Window procedure is different for each event type, so is the
+            // class, registering it again for the same type fails harmlessly
+            let class_name = &builder.class_name;
+            let wnd_class_name = wchar(&class_name.clone().unwrap_or_else(|| {
+                format!(
+                    "TrayIconCls{:p}",
+                    WinTrayIconImpl::<T>::winproc as *const ()
+                )
+            }));
             let wnd_class = winuser::WNDCLASSW {
                 style: 0,
                 lpfnWndProc: Some(WinTrayIconImpl::<T>::winproc),
@@ -106,48 +294,170 @@ where
                 hbrBackground: 0 as HBRUSH,
                 lpszMenuName: 0 as _,
             };
-            winuser::RegisterClassW(&wnd_class);
+            trace_span!(DEBUG, "create_window");
+            register_class(&wnd_class)?;
+
+            // Class of the given name may have the procedure of another type
+            if class_name.is_some() {
+                let mut registered: winuser::WNDCLASSW = std::mem::zeroed();
+                winuser::GetClassInfoW(hinstance, wnd_class_name.as_ptr(), &mut registered);
+                if registered.lpfnWndProc.map(|f| f as usize)
+                    != wnd_class.lpfnWndProc.map(|f| f as usize)
+                {
+                    return Err(Error::os_error_code(
+                        "RegisterClassW",
+                        ERROR_CLASS_ALREADY_EXISTS as i32,
+                    ));
+                }
+            }
+
+            // Second instance finds the window of the first one by the title
+            let window_name = match &builder.on_second_instance {
+                Some((name, _)) => winsingleinstance::window_name(name),
+                None => "TrayIcon".to_string(),
+            };
 
             // Create window in a memory location that doesn't change
             let window = Box::new(WinTrayIconImpl {
                 hwnd: 0 as HWND,
+                icon,
                 notify_icon,
                 menu,
-                on_click,
-                on_right_click,
-                on_double_click,
+                on_click: builder.on_click.clone(),
+                on_right_click: builder.on_right_click.clone(),
+                on_double_click: builder.on_double_click.clone(),
+                on_middle_click: builder.on_middle_click.clone(),
+                on_mouse_enter: builder.on_mouse_enter.clone(),
+                on_mouse_leave: builder.on_mouse_leave.clone(),
+                on_key_select: builder
+                    .on_key_select
+                    .clone()
+                    .or_else(|| builder.on_click.clone()),
+                on_taskbar_restarted: builder.on_taskbar_restarted.clone(),
+                on_menu_open: builder.on_menu_open.clone(),
+                on_menu_dismissed: builder.on_menu_dismissed.clone(),
+                on_menu_hover: builder.on_menu_hover.clone(),
+                on_notification_clicked: builder.on_notification_clicked.clone(),
+                on_notification_closed: builder.on_notification_closed.clone(),
+                on_notification_hidden: builder.on_notification_hidden.clone(),
+                on_popup_open: builder.on_popup_open.clone(),
+                on_popup_close: builder.on_popup_close.clone(),
+                on_theme_changed: builder.on_theme_changed.clone(),
+                on_clipboard_changed: builder.on_clipboard_changed.clone(),
+                on_session_lock: builder.on_session_lock.clone(),
+                on_session_unlock: builder.on_session_unlock.clone(),
+                on_suspend: builder.on_suspend.clone(),
+                on_resume: builder.on_resume.clone(),
+                on_end_session: builder.on_end_session.clone(),
+                on_display_changed: builder.on_display_changed.clone(),
+                on_raw_message: builder.on_raw_message.clone(),
+                on_second_instance: builder.on_second_instance.clone(),
+                on_error: builder.on_error.clone(),
+                toggle_window: builder.toggle_window.map(|hwnd| hwnd as HWND),
+                minimize_to_tray: builder.minimize_to_tray.map(|hwnd| hwnd as HWND),
+                on_restore_requested: builder.on_restore_requested.clone(),
+                on_icon_added: builder.on_icon_added.clone(),
+                on_icon_add_failed: builder.on_icon_add_failed.clone(),
+                on_tray_visibility_changed: builder.on_tray_visibility_changed.clone(),
+                in_tray: None,
+                add_retries: 0,
+                copy_data_id: winsingleinstance::copy_data_id(),
+                icon_light: builder.icon_light.clone(),
+                icon_dark: builder.icon_dark.clone(),
+                menu_with: builder.menu_with.clone(),
+                menu_updater: builder.menu_updater.clone(),
+                toggled: builder.toggled_checkables(),
+                menu_on_left_click: builder.menu_on_left_click,
+                alt_menu,
+                alt_menu_active: false,
+                click_behavior: builder.click_behavior,
+                single_click_delay: builder.single_click_delay,
+                pending_click: None,
+                double_clicked: false,
+                last_left_up: None,
+                last_select: None,
+                menu_hotkeys: builder.menu_hotkeys,
+                rtl,
+                mouse_over: false,
+                animation: vec![],
+                animation_frame: 0,
+                tooltip_fn: None,
+                decoration: Decoration::default(),
                 sender,
                 msg_taskbarcreated: None,
+                message_only: builder.message_only_window,
+                taskbar: find_taskbar(),
+                theme: super::system_theme(),
+                monitors: monitor_count(),
             });
             let ptr = Box::into_raw(window);
             let hwnd = winuser::CreateWindowExW(
                 0,
                 wnd_class_name.as_ptr() as _,
-                wchar("TrayIcon").as_ptr() as _,
+                wchar(&window_name).as_ptr() as _,
                 0, //winuser::WS_OVERLAPPEDWINDOW | winuser::WS_VISIBLE,
                 winuser::CW_USEDEFAULT,
                 winuser::CW_USEDEFAULT,
                 winuser::CW_USEDEFAULT,
                 winuser::CW_USEDEFAULT,
-                0 as _,
+                if builder.message_only_window {
+                    winuser::HWND_MESSAGE
+                } else {
+                    0 as _
+                },
                 0 as HMENU,
                 hinstance,
                 ptr as *mut _ as LPVOID,
             ) as u32;
 
             if hwnd == 0 {
-                return Err(Error::OsError);
+                return Err(Error::last_os_error("CreateWindowExW"));
+            }
+            if let Some(window) = (*ptr).minimize_to_tray {
+                if !winwindow::subclass_minimize_to_tray(window) {
+                    let err = Error::last_os_error("SetWindowSubclass");
+                    winuser::DestroyWindow(hwnd as HWND);
+                    return Err(err);
+                }
             }
+            trace_event!(DEBUG, "created the window {:#x}", hwnd);
 
-            Ok(WindowBox(ptr))
+            Ok(WindowBox {
+                ptr,
+                hwnd: (*ptr).hwnd,
+                closed: false,
+                remover: (*ptr).notify_icon.remover((*ptr).hwnd),
+            })
         }
     }
 
     pub fn wndproc(&mut self, msg: UINT, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+        // Window must be created and destroyed by the tray icon
+        let own = matches!(msg, winuser::WM_CREATE | winuser::WM_NCDESTROY);
+        if let Some(on_raw_message) = self.on_raw_message.as_ref().filter(|_| !own) {
+            if let Some(event) = on_raw_message.call(msg, wparam, lparam) {
+                self.sender
+                    .send(Some(&event), TrayIconEvent::RawMessage(event.clone()));
+                return 0;
+            }
+        }
+
         match msg {
             winuser::WM_CREATE => {
                 // Create notification area icon
-                self.notify_icon.add(self.hwnd);
+                self.update_icon();
+                if !self.notify_icon.add(self.hwnd) {
+                    self.retry_add();
+                }
+                self.register_hotkeys();
+
+                // Listener is removed when the window is destroyed
+                if self.on_clipboard_changed.is_some() {
+                    unsafe { winuser::AddClipboardFormatListener(self.hwnd) };
+                }
+                if self.listens_session() {
+                    winsession::register_session_notification(self.hwnd);
+                }
 
                 // Register to listen taskbar creation
                 self.msg_taskbarcreated = unsafe {
@@ -155,66 +465,405 @@ where
                         "TaskbarCreated\0".as_ptr() as _
                     ))
                 };
+                if self.on_tray_visibility_changed.is_some() {
+                    self.in_tray = self.notify_icon.is_in_tray().ok();
+                    unsafe {
+                        winuser::SetTimer(
+                            self.hwnd,
+                            msgs::TIMER_TRAY_VISIBILITY,
+                            TRAY_VISIBILITY_POLL_INTERVAL,
+                            None,
+                        )
+                    };
+                }
+                if self.message_only {
+                    unsafe {
+                        winuser::SetTimer(
+                            self.hwnd,
+                            msgs::TIMER_TASKBAR,
+                            TASKBAR_POLL_INTERVAL,
+                            None,
+                        )
+                    };
+                }
             }
 
             // Mouse events on the tray icon
+            //
+            // With NOTIFYICON_VERSION_4 the message is in the low word, and
+            // the icon identifier in the high word
             msgs::WM_USER_TRAYICON => {
-                match lparam as u32 {
+                let event = LOWORD(lparam as u32) as u32;
+                if event == winuser::WM_LBUTTONUP {
+                    self.last_left_up = Some(Instant::now());
+                }
+                match event {
                     // Left click tray icon
-                    winuser::WM_LBUTTONUP => {
-                        if let Some(e) = self.on_click.as_ref() {
-                            self.sender.send(e);
-                        }
+                    winuser::WM_LBUTTONUP if self.double_clicked => {
+                        self.double_clicked = false;
                     }
+                    winuser::WM_LBUTTONUP => match self.click_behavior {
+                        ClickBehavior::SingleWithDelay => {
+                            let delay = self.single_click_delay.map_or_else(
+                                || unsafe { winuser::GetDoubleClickTime() },
+                                |delay| delay.as_millis().min(u32::MAX as u128) as u32,
+                            );
+                            self.pending_click = Some((cursor_position(), key_modifiers()));
+                            unsafe {
+                                winuser::SetTimer(self.hwnd, msgs::TIMER_SINGLE_CLICK, delay, None)
+                            };
+                        }
+                        ClickBehavior::DoubleOnly => {}
+                        ClickBehavior::Both | ClickBehavior::SingleOnly => {
+                            self.left_click(cursor_position(), key_modifiers())
+                        }
+                    },
 
                     // Right click tray icon
                     winuser::WM_RBUTTONUP => {
+                        let modifiers = key_modifiers();
+                        self.alt_menu_active = self
+                            .alt_menu
+                            .as_ref()
+                            .is_some_and(|(_, required)| modifiers.contains(*required));
+
                         // Send right click event
-                        if let Some(e) = self.on_right_click.as_ref() {
-                            self.sender.send(e);
+                        self.sender.send(
+                            self.on_right_click.as_ref(),
+                            TrayIconEvent::Click {
+                                button: MouseButton::Right,
+                                position: cursor_position(),
+                                modifiers,
+                            },
+                        );
+
+                        // Legacy shell sends no context menu message
+                        if self.notify_icon.version() == NotifyIconVersion::Legacy {
+                            self.show_context_menu();
                         }
+                    }
 
-                        // Show menu, if it's there
-                        if let Some(menu) = &self.menu {
-                            let mut pos = POINT { x: 0, y: 0 };
-                            unsafe {
-                                winuser::GetCursorPos(&mut pos as _);
-                                winuser::SetForegroundWindow(self.hwnd);
-                            }
-                            menu.menu.track(self.hwnd, pos.x, pos.y);
+                    // Context menu with right click or with keyboard
+                    winuser::WM_CONTEXTMENU => self.show_context_menu(),
+
+                    // Keyboard selection of the tray icon, NIN_SELECT is also
+                    // sent after the left click
+                    select @ (NIN_SELECT | NIN_KEYSELECT) => {
+                        let now = Instant::now();
+                        let double_click_time =
+                            Duration::from_millis(unsafe { winuser::GetDoubleClickTime() } as u64);
+                        let recent = |last: Option<Instant>| {
+                            last.is_some_and(|t| now - t < double_click_time)
+                        };
+                        let repeated = recent(self.last_select)
+                            || (select == NIN_SELECT && recent(self.last_left_up));
+                        self.last_select = Some(now);
+                        if !repeated {
+                            self.sender
+                                .send(self.on_key_select.as_ref(), TrayIconEvent::KeySelect);
                         }
                     }
 
-                    // Double click tray icon
+                    // Mouse moves over the tray icon
+                    winuser::WM_MOUSEMOVE => self.mouse_enter(),
+
+                    // Rich popup should open, with NOTIFYICON_VERSION_4 the
+                    // anchor of the popup is in the wparam
+                    NIN_POPUPOPEN => {
+                        self.mouse_enter();
+                        self.sender.send(
+                            self.on_popup_open.as_ref(),
+                            TrayIconEvent::PopupOpen {
+                                position: PhysicalPosition {
+                                    x: LOWORD(wparam as u32) as i16 as i32,
+                                    y: HIWORD(wparam as u32) as i16 as i32,
+                                },
+                            },
+                        );
+                    }
+
+                    NIN_POPUPCLOSE => {
+                        self.mouse_leave();
+                        self.sender
+                            .send(self.on_popup_close.as_ref(), TrayIconEvent::PopupClose);
+                    }
+
+                    // Notification balloon clicked
+                    NIN_BALLOONUSERCLICK => {
+                        self.notify_icon.balloon_closed();
+                        self.sender.send(
+                            self.on_notification_clicked.as_ref(),
+                            TrayIconEvent::BalloonClicked,
+                        );
+                    }
+
+                    // Notification balloon closed by the user or timed out
+                    NIN_BALLOONTIMEOUT => {
+                        self.notify_icon.balloon_closed();
+                        self.sender.send(
+                            self.on_notification_closed.as_ref(),
+                            TrayIconEvent::BalloonClosed,
+                        );
+                    }
+
+                    // Notification balloon hidden, e.g. the icon was hidden
+                    NIN_BALLOONHIDE => {
+                        self.notify_icon.balloon_closed();
+                        self.sender.send(
+                            self.on_notification_hidden.as_ref(),
+                            TrayIconEvent::BalloonHidden,
+                        );
+                    }
+
+                    // Middle click tray icon
+                    winuser::WM_MBUTTONUP => {
+                        self.sender.send(
+                            self.on_middle_click.as_ref(),
+                            TrayIconEvent::Click {
+                                button: MouseButton::Middle,
+                                position: cursor_position(),
+                                modifiers: key_modifiers(),
+                            },
+                        );
+                    }
+
+                    // Double click tray icon, the button up after it is not
+                    // another click
                     winuser::WM_LBUTTONDBLCLK => {
-                        if let Some(e) = self.on_double_click.as_ref() {
-                            self.sender.send(e);
+                        self.double_clicked = true;
+                        if self.pending_click.take().is_some() {
+                            unsafe { winuser::KillTimer(self.hwnd, msgs::TIMER_SINGLE_CLICK) };
+                        }
+                        if self.click_behavior == ClickBehavior::SingleOnly {
+                            self.left_click(cursor_position(), key_modifiers());
+                        } else {
+                            self.double_click(cursor_position(), key_modifiers());
                         }
                     }
                     _ => {}
                 }
             }
 
+            // Poll if mouse has left the tray icon
+            winuser::WM_TIMER if wparam == msgs::TIMER_MOUSE_LEAVE => {
+                let mut pos = POINT { x: 0, y: 0 };
+                unsafe { winuser::GetCursorPos(&mut pos as _) };
+                let inside = self
+                    .notify_icon
+                    .rect()
+                    .is_some_and(|rect| unsafe { winuser::PtInRect(&rect, pos) } != 0);
+                if !inside {
+                    self.mouse_leave();
+                }
+            }
+
+            // Global hotkey of a menu item, identifier is the command
+            // Hotkeys of the disabled items are ignored
+            winuser::WM_HOTKEY => {
+                let disabled = self.menu.as_ref().map(|menu| menu.menu.is_disabled(wparam));
+                if disabled == Some(false) {
+                    self.menu_command(wparam);
+                }
+            }
+
+            // Submenu opened or closed, the menu itself has no events
+            winuser::WM_INITMENUPOPUP | winuser::WM_UNINITMENUPOPUP => {
+                self.submenu_event(wparam as HMENU, msg == winuser::WM_INITMENUPOPUP);
+            }
+
+            // Double click time passed after the left click
+            winuser::WM_TIMER if wparam == msgs::TIMER_SINGLE_CLICK => {
+                unsafe { winuser::KillTimer(self.hwnd, msgs::TIMER_SINGLE_CLICK) };
+                if let Some((position, modifiers)) = self.pending_click.take() {
+                    self.left_click(position, modifiers);
+                }
+            }
+
             // Any of the menu commands
             //
             // https://docs.microsoft.com/en-us/windows/win32/menurc/wm-command#parameters
+            // Highlighted item, submenus are skipped as their identifier is
+            // the position
+            winuser::WM_MENUSELECT => {
+                let flags = HIWORD(wparam as u32) as UINT;
+                if flags != 0xFFFF && flags & winuser::MF_POPUP == 0 {
+                    self.menu_select(LOWORD(wparam as u32) as usize);
+                }
+            }
+
             winuser::WM_COMMAND => {
                 let identifier = LOWORD(wparam as u32);
                 let cmd = HIWORD(wparam as u32);
 
                 // Menu command
                 if cmd == 0 {
-                    if let Some(v) = self.menu.as_ref() {
-                        if let Some(event) = v.ids.get(&(identifier as usize)) {
-                            self.sender.send(event);
-                        }
-                    }
+                    self.menu_command(identifier as usize);
                 }
             }
 
-            // TaskbarCreated
+            // TaskbarCreated, e.g. explorer.exe restarted after a crash
             x if Some(x) == self.msg_taskbarcreated => {
-                self.notify_icon.add(self.hwnd);
+                self.taskbar_restarted();
+            }
+
+            // Adding the icon failed before
+            winuser::WM_TIMER if wparam == msgs::TIMER_ADD_RETRY => {
+                unsafe { winuser::KillTimer(self.hwnd, msgs::TIMER_ADD_RETRY) };
+                if self.notify_icon.restore(self.hwnd) {
+                    trace_event!(INFO, "icon added after {} retries", self.add_retries);
+                    self.add_retries = 0;
+                    self.sender
+                        .send(self.on_icon_added.as_ref(), TrayIconEvent::IconAdded);
+                } else {
+                    self.retry_add();
+                }
+            }
+
+            // Icon may have been dragged to or from the overflow area
+            winuser::WM_TIMER if wparam == msgs::TIMER_TRAY_VISIBILITY => {
+                if let Ok(in_tray) = self.notify_icon.is_in_tray() {
+                    if self.in_tray.is_some_and(|previous| previous != in_tray) {
+                        self.sender.send(
+                            self.on_tray_visibility_changed.as_ref(),
+                            TrayIconEvent::TrayVisibilityChanged(in_tray),
+                        );
+                    }
+                    self.in_tray = Some(in_tray);
+                }
+            }
+
+            // Message-only window polls for the broadcasts it doesn't receive
+            winuser::WM_TIMER if wparam == msgs::TIMER_TASKBAR => {
+                let taskbar = find_taskbar();
+                if taskbar != self.taskbar {
+                    self.taskbar = taskbar;
+                    if !taskbar.is_null() {
+                        self.taskbar_restarted();
+                    }
+                }
+                self.theme_changed();
+            }
+
+            // Theme or the accent color changed
+            winuser::WM_SETTINGCHANGE
+                if setting_name(lparam).as_deref() == Some("ImmersiveColorSet") =>
+            {
+                self.theme_changed();
+                return unsafe { winuser::DefWindowProcW(self.hwnd, msg, wparam, lparam) };
+            }
+
+            winuser::WM_DESTROY => {
+                if self.listens_session() {
+                    winsession::unregister_session_notification(self.hwnd);
+                }
+                if let Some(window) = self.minimize_to_tray {
+                    winwindow::remove_minimize_to_tray(window);
+                }
+                return unsafe { winuser::DefWindowProcW(self.hwnd, msg, wparam, lparam) };
+            }
+
+            // Workstation was locked or unlocked
+            winuser::WM_WTSSESSION_CHANGE => match wparam {
+                winuser::WTS_SESSION_LOCK => {
+                    self.sender
+                        .send(self.on_session_lock.as_ref(), TrayIconEvent::SessionLock);
+                }
+                winuser::WTS_SESSION_UNLOCK => {
+                    self.sender.send(
+                        self.on_session_unlock.as_ref(),
+                        TrayIconEvent::SessionUnlock,
+                    );
+                }
+                _ => {}
+            },
+
+            // Resume is sent also without user input, e.g. for a wake timer
+            winuser::WM_POWERBROADCAST => {
+                match wparam {
+                    winuser::PBT_APMSUSPEND => {
+                        self.sender
+                            .send(self.on_suspend.as_ref(), TrayIconEvent::Suspend);
+                    }
+                    winuser::PBT_APMRESUMEAUTOMATIC => {
+                        self.sender
+                            .send(self.on_resume.as_ref(), TrayIconEvent::Resume);
+                    }
+                    _ => {}
+                }
+                return TRUE as LRESULT;
+            }
+
+            // Session is ending, the shutdown is not blocked
+            winuser::WM_QUERYENDSESSION => {
+                self.sender
+                    .send(self.on_end_session.as_ref(), TrayIconEvent::EndSession);
+                return TRUE as LRESULT;
+            }
+
+            // Arguments of the second instance
+            winuser::WM_COPYDATA => {
+                let copy_data = unsafe { &*(lparam as *const winuser::COPYDATASTRUCT) };
+                match &self.on_second_instance {
+                    Some((_, f)) if copy_data.dwData == self.copy_data_id => {
+                        let args = unsafe { winsingleinstance::copy_data_args(copy_data) };
+                        let event = f.call(args.clone());
+                        self.sender
+                            .send(Some(&event), TrayIconEvent::SecondInstance(args));
+                        return TRUE as LRESULT;
+                    }
+                    _ => return 0,
+                }
+            }
+
+            // Contents of the clipboard changed
+            winuser::WM_CLIPBOARDUPDATE => {
+                self.sender.send(
+                    self.on_clipboard_changed.as_ref(),
+                    TrayIconEvent::ClipboardChanged,
+                );
+            }
+
+            // Scaling changed, or the taskbar moved to another monitor
+            winuser::WM_DPICHANGED => {
+                self.update_icon();
+            }
+
+            // Resolution changed, or a monitor was added or removed
+            winuser::WM_DISPLAYCHANGE => {
+                self.update_icon();
+                self.monitors = monitor_count();
+                self.sender.send(
+                    self.on_display_changed.as_ref(),
+                    TrayIconEvent::DisplayChanged,
+                );
+            }
+
+            // Devices changed, sent only if the number of monitors changed
+            winuser::WM_DEVICECHANGE if wparam == dbt::DBT_DEVNODES_CHANGED => {
+                let monitors = monitor_count();
+                if monitors != self.monitors {
+                    self.monitors = monitors;
+                    self.sender.send(
+                        self.on_display_changed.as_ref(),
+                        TrayIconEvent::DisplayChanged,
+                    );
+                }
+                return TRUE as LRESULT;
+            }
+
+            // Next animation frame
+            winuser::WM_TIMER if wparam == msgs::TIMER_ANIMATION => {
+                if !self.animation.is_empty() {
+                    self.animation_frame = (self.animation_frame + 1) % self.animation.len();
+                    self.update_icon();
+                }
+            }
+
+            // Refresh the tooltip
+            winuser::WM_TIMER if wparam == msgs::TIMER_TOOLTIP => {
+                if let Some(tooltip_fn) = self.tooltip_fn.clone() {
+                    let _ = self.set_tooltip(&tooltip_fn.tooltip());
+                }
             }
 
             // Default
@@ -225,6 +874,478 @@ where
         0
     }
 
+    /// Set the icon, or the current animation frame, fitting the current DPI
+    ///
+    /// Before the icon is added this only sets the icon used for adding.
+    fn update_icon(&mut self) -> bool {
+        let size = icon_size(self.hwnd);
+        let icon = self
+            .animation
+            .get(self.animation_frame)
+            .unwrap_or(&self.icon);
+        let sys = icon.sys_for_size(size, size);
+        match badge::decorate(&sys, size, size, &self.decoration) {
+            Some(decorated) => self.notify_icon.set_icon(&decorated),
+            None => self.notify_icon.set_icon(&sys),
+        }
+    }
+
+    /// Icon is shown on the taskbar, not in the overflow area
+    pub(crate) fn is_visible_in_tray(&self) -> Result<bool, Error> {
+        self.notify_icon.is_in_tray()
+    }
+
+    /// Events of the menu items by the command identifier
+    pub(crate) fn menu_commands(&self) -> HashMap<MenuCommandId, T> {
+        self.menu
+            .as_ref()
+            .map(|menu| menu.command_events())
+            .unwrap_or_default()
+    }
+
+    /// Hidden window receiving the messages of the notify icon
+    pub(crate) fn hwnd(&self) -> HWND {
+        self.hwnd
+    }
+
+    /// Move the window next to the tray icon, like the flyouts of the taskbar
+    ///
+    /// Window is kept in the work area, and placed above or below the icon
+    /// when the taskbar is at the top or bottom, otherwise beside it.
+    pub(crate) fn position_flyout(&self, hwnd: HWND) -> Result<(), Error> {
+        let mut icon_rect = self
+            .notify_icon
+            .rect()
+            .ok_or(Error::os_error("Shell_NotifyIconGetRect"))?;
+        unsafe {
+            let mut window: RECT = std::mem::zeroed();
+            if winuser::GetWindowRect(hwnd, &mut window) == 0 {
+                return Err(Error::last_os_error("GetWindowRect"));
+            }
+            let size = SIZE {
+                cx: window.right - window.left,
+                cy: window.bottom - window.top,
+            };
+            let anchor = POINT {
+                x: (icon_rect.left + icon_rect.right) / 2,
+                y: (icon_rect.top + icon_rect.bottom) / 2,
+            };
+
+            // Taskbar takes the full width of the monitor at the top or bottom
+            let mut info: winuser::MONITORINFO = std::mem::zeroed();
+            info.cbSize = std::mem::size_of::<winuser::MONITORINFO>() as u32;
+            let monitor = winuser::MonitorFromRect(&icon_rect, winuser::MONITOR_DEFAULTTONEAREST);
+            let horizontal_taskbar = winuser::GetMonitorInfoW(monitor, &mut info) != 0
+                && info.rcWork.right - info.rcWork.left
+                    == info.rcMonitor.right - info.rcMonitor.left;
+            let flags = winuser::TPM_CENTERALIGN
+                | winuser::TPM_VCENTERALIGN
+                | winuser::TPM_WORKAREA
+                | if horizontal_taskbar {
+                    winuser::TPM_VERTICAL
+                } else {
+                    winuser::TPM_HORIZONTAL
+                };
+
+            let mut position: RECT = std::mem::zeroed();
+            if winuser::CalculatePopupWindowPosition(
+                &anchor,
+                &size,
+                flags,
+                &mut icon_rect,
+                &mut position,
+            ) == 0
+            {
+                return Err(Error::last_os_error("CalculatePopupWindowPosition"));
+            }
+            if winuser::SetWindowPos(
+                hwnd,
+                std::ptr::null_mut(),
+                position.left,
+                position.top,
+                0,
+                0,
+                winuser::SWP_NOSIZE | winuser::SWP_NOZORDER | winuser::SWP_NOACTIVATE,
+            ) == 0
+            {
+                return Err(Error::last_os_error("SetWindowPos"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Session notifications are registered only for the lock events
+    fn listens_session(&self) -> bool {
+        self.on_session_lock.is_some() || self.on_session_unlock.is_some()
+    }
+
+    /// Register the accelerators of the menu items as global hotkeys
+    ///
+    /// Accelerators taken by other apps, or not parsed, are skipped.
+    fn register_hotkeys(&self) {
+        let menu = match &self.menu {
+            Some(menu) if self.menu_hotkeys => menu,
+            _ => return,
+        };
+        for (j, text) in &menu.accelerators {
+            if let Some((modifiers, vk)) = Accelerator::parse(text).and_then(|a| hotkey(&a)) {
+                unsafe { winuser::RegisterHotKey(self.hwnd, *j as i32, modifiers, vk) };
+            }
+        }
+    }
+
+    fn unregister_hotkeys(&self) {
+        let menu = match &self.menu {
+            Some(menu) if self.menu_hotkeys => menu,
+            _ => return,
+        };
+        for j in menu.accelerators.keys() {
+            unsafe { winuser::UnregisterHotKey(self.hwnd, *j as i32) };
+        }
+    }
+
+    /// Add the icon again to the new taskbar
+    fn taskbar_restarted(&mut self) {
+        trace_event!(INFO, "taskbar restarted, adding the icon again");
+        self.mouse_leave();
+        self.update_icon();
+        if self.add_retries > 0 {
+            unsafe { winuser::KillTimer(self.hwnd, msgs::TIMER_ADD_RETRY) };
+            self.add_retries = 0;
+        }
+        if !self.notify_icon.restore(self.hwnd) {
+            self.retry_add();
+        }
+        self.sender.send(
+            self.on_taskbar_restarted.as_ref(),
+            TrayIconEvent::TaskbarRestarted,
+        );
+    }
+
+    /// Add the icon later, or give up after the retries
+    fn retry_add(&mut self) {
+        if self.add_retries >= ADD_RETRIES {
+            trace_event!(WARN, "adding the icon failed, giving up");
+            self.add_retries = 0;
+            self.sender.send(
+                self.on_icon_add_failed.as_ref(),
+                TrayIconEvent::IconAddFailed,
+            );
+            return;
+        }
+        let delay = (ADD_RETRY_INTERVAL << self.add_retries).min(ADD_RETRY_MAX_INTERVAL);
+        self.add_retries += 1;
+        unsafe { winuser::SetTimer(self.hwnd, msgs::TIMER_ADD_RETRY, delay, None) };
+    }
+
+    /// Switch the icon and send the event, if the theme changed
+    fn theme_changed(&mut self) {
+        let theme = super::system_theme();
+        if theme != self.theme {
+            self.theme = theme;
+            if let Some(theme) = theme {
+                self.switch_icon(theme);
+                self.sender.send(
+                    self.on_theme_changed.as_ref(),
+                    TrayIconEvent::ThemeChanged(theme),
+                );
+            }
+        }
+    }
+
+    /// Show the icon of the theme, if there is one
+    fn switch_icon(&mut self, theme: SystemTheme) {
+        let icon = match theme {
+            SystemTheme::Light => &self.icon_light,
+            SystemTheme::Dark => &self.icon_dark,
+        };
+        if let Some(icon) = icon.clone() {
+            self.icon = icon;
+            self.update_icon();
+        }
+    }
+
+    /// Restore or toggle the window if enabled, send the left click, and show
+    /// the menu if enabled
+    fn left_click(&mut self, position: PhysicalPosition, modifiers: Modifiers) {
+        if !self.restore_window() {
+            if let Some(hwnd) = self.toggle_window {
+                winwindow::toggle_window(hwnd);
+            }
+        }
+        self.sender.send(
+            self.on_click.as_ref(),
+            TrayIconEvent::Click {
+                button: MouseButton::Left,
+                position,
+                modifiers,
+            },
+        );
+        if self.menu_on_left_click {
+            self.show_context_menu();
+        }
+    }
+
+    /// Send the double click, or activate the default item if there is no
+    /// double click event
+    fn double_click(&self, position: PhysicalPosition, modifiers: Modifiers) {
+        let default_item = self
+            .menu
+            .as_ref()
+            .and_then(|m| m.default.and_then(|j| m.ids.get(&j)));
+        match default_item {
+            Some(e) if self.on_double_click.is_none() => self.sender.send_menu_item(e),
+            _ => self.sender.send(
+                self.on_double_click.as_ref(),
+                TrayIconEvent::DoubleClick {
+                    position,
+                    modifiers,
+                },
+            ),
+        }
+    }
+
+    /// Show the window hidden to the tray, and send the event
+    fn restore_window(&mut self) -> bool {
+        let hwnd = match self.minimize_to_tray {
+            Some(hwnd) if !winwindow::is_shown(hwnd) => hwnd,
+            _ => return false,
+        };
+        winwindow::show_window(hwnd);
+        self.sender.send(
+            self.on_restore_requested.as_ref(),
+            TrayIconEvent::RestoreRequested,
+        );
+        true
+    }
+
+    /// Show context menu, if it's there
+    ///
+    /// Menu is shown at the cursor, or at the tray icon if the cursor is
+    /// elsewhere because the menu was opened with the keyboard.
+    fn show_context_menu(&mut self) {
+        let pos = cursor_position();
+        let mut pos = POINT { x: pos.x, y: pos.y };
+        if let Some(rect) = self.notify_icon.rect() {
+            if unsafe { winuser::PtInRect(&rect, pos) } == 0 {
+                pos = POINT {
+                    x: rect.left,
+                    y: rect.top,
+                };
+            }
+        }
+        if self.alt_menu_active {
+            self.track_alt_menu(pos);
+            self.alt_menu_active = false;
+        } else {
+            self.track_menu(pos, 0);
+        }
+    }
+
+    /// Track the alternate menu, the chosen item is sent right away as the
+    /// command identifiers overlap with the menu
+    fn track_alt_menu(&mut self, pos: POINT) {
+        let menu = match &self.alt_menu {
+            Some((menu, _)) => menu,
+            None => return,
+        };
+        if let Some(theme) = menu.theme {
+            winuxtheme::set_menu_theme(theme);
+        }
+        let mut flags = menu.track_flags | winuser::TPM_RETURNCMD;
+        if self.rtl {
+            menu.menu.set_right_order();
+            flags |= winuser::TPM_LAYOUTRTL;
+        }
+        unsafe { winuser::SetForegroundWindow(self.hwnd) };
+        let j = menu.menu.track(self.hwnd, pos.x, pos.y, flags);
+        if let Some(map) = menu.mapped.get(&j) {
+            self.sender.send_menu_item(&map.call());
+        } else if let Some(event) = menu.ids.get(&j) {
+            self.sender.send_menu_item(event);
+        } else {
+            self.sender.send(
+                self.on_menu_dismissed.as_ref(),
+                TrayIconEvent::MenuDismissed,
+            );
+        }
+    }
+
+    /// Menu being shown, the alternate one or the menu
+    fn active_menu(&self) -> Option<&MenuSys<T>> {
+        match &self.alt_menu {
+            Some((menu, _)) if self.alt_menu_active => Some(menu),
+            _ => self.menu.as_ref(),
+        }
+    }
+
+    /// Track menu at the position, if it's there
+    ///
+    /// Lazily built menu is built before, the menu is retained for the
+    /// commands posted after the menu is closed. With `TPM_RETURNCMD` the
+    /// chosen command is returned, otherwise it's posted. The menu is always
+    /// tracked with it, to know when it's closed without a command.
+    fn track_menu(&mut self, pos: POINT, flags: UINT) -> usize {
+        self.sender
+            .send(self.on_menu_open.as_ref(), TrayIconEvent::MenuOpen);
+        if let Some(menu) = self.menu_updater.as_ref().and_then(|u| u.take()) {
+            let _ = self.set_menu(&menu);
+        }
+        if let Some(menu_with) = self.menu_with.clone() {
+            let _ = self.set_menu(&menu_with.build());
+        }
+        if let Some(menu) = &self.menu {
+            if let Some(theme) = menu.theme {
+                winuxtheme::set_menu_theme(theme);
+            }
+            let mut flags = menu.track_flags | flags;
+            if self.rtl {
+                menu.menu.set_right_order();
+                flags |= winuser::TPM_LAYOUTRTL;
+            }
+            unsafe { winuser::SetForegroundWindow(self.hwnd) };
+            let j = menu
+                .menu
+                .track(self.hwnd, pos.x, pos.y, flags | winuser::TPM_RETURNCMD);
+            if j == 0 {
+                self.sender.send(
+                    self.on_menu_dismissed.as_ref(),
+                    TrayIconEvent::MenuDismissed,
+                );
+            } else if flags & winuser::TPM_RETURNCMD == 0 {
+                unsafe { winuser::PostMessageW(self.hwnd, winuser::WM_COMMAND, j, 0) };
+                return 0;
+            }
+            return j;
+        }
+        0
+    }
+
+    /// Menu position, or the tray icon if none
+    fn menu_position(&self, position: Option<PhysicalPosition>) -> Result<POINT, Error> {
+        match position {
+            Some(position) => Ok(POINT {
+                x: position.x,
+                y: position.y,
+            }),
+            None => {
+                let rect = self
+                    .notify_icon
+                    .rect()
+                    .ok_or(Error::os_error("Shell_NotifyIconGetRect"))?;
+                Ok(POINT {
+                    x: rect.left,
+                    y: rect.top,
+                })
+            }
+        }
+    }
+
+    /// Send the event of the menu item, toggle the checkable if enabled
+    fn menu_command(&mut self, j: usize) {
+        match self.menu_item_event(j) {
+            Some((event, Some(checked))) => self.sender.send_menu_item_toggled(&event, checked),
+            Some((event, None)) => self.sender.send_menu_item(&event),
+            None => {}
+        }
+    }
+
+    /// Event of the menu item, the checkable is toggled if enabled
+    ///
+    /// New checked state is returned with the event of the toggled checkable.
+    fn menu_item_event(&self, j: usize) -> Option<(T, Option<bool>)> {
+        let menu = self.menu.as_ref()?;
+        if let Some(map) = menu.mapped.get(&j) {
+            return Some((map.call(), None));
+        }
+        let event = menu.ids.get(&j)?;
+        match &self.toggled {
+            Some(toggled) if menu.checkables.contains(&j) => {
+                let checked = !menu.menu.is_checked(j);
+                menu.menu.set_checked(j, checked);
+                if let Ok(mut toggled) = toggled.lock() {
+                    toggled.push((event.clone(), checked));
+                }
+                Some((event.clone(), Some(checked)))
+            }
+            _ => Some((event.clone(), None)),
+        }
+    }
+
+    /// Send the event of the opened or closed submenu, if it has one
+    fn submenu_event(&self, hmenu: HMENU, opened: bool) {
+        let (on_open, on_close) = match self.active_menu().and_then(|menu| menu.submenu(hmenu)) {
+            Some(MenuItem::Submenu {
+                on_open, on_close, ..
+            }) => (on_open, on_close),
+            _ => return,
+        };
+        match (opened, on_open, on_close) {
+            (true, Some(id), _) => self
+                .sender
+                .send(Some(id), TrayIconEvent::SubmenuOpen(id.clone())),
+            (false, _, Some(id)) => self
+                .sender
+                .send(Some(id), TrayIconEvent::SubmenuClose(id.clone())),
+            _ => {}
+        }
+    }
+
+    fn menu_select(&mut self, j: usize) {
+        let menu = match self.active_menu() {
+            Some(menu) => menu,
+            None => return,
+        };
+        let event = match (menu.mapped.get(&j), menu.ids.get(&j)) {
+            (Some(map), _) => map.call(),
+            (None, Some(event)) => event.clone(),
+            (None, None) => return,
+        };
+        let description = menu.descriptions.get(&j).cloned();
+        self.sender.send(
+            self.on_menu_hover.as_ref(),
+            TrayIconEvent::MenuHover(event, description),
+        );
+    }
+
+    fn mouse_enter(&mut self) {
+        if !self.mouse_over {
+            self.mouse_over = true;
+            self.sender
+                .send(self.on_mouse_enter.as_ref(), TrayIconEvent::MouseEnter);
+
+            // There is no message when the mouse leaves
+            unsafe { winuser::SetTimer(self.hwnd, msgs::TIMER_MOUSE_LEAVE, 100, None) };
+        }
+    }
+
+    fn mouse_leave(&mut self) {
+        if self.mouse_over {
+            self.mouse_over = false;
+            unsafe { winuser::KillTimer(self.hwnd, msgs::TIMER_MOUSE_LEAVE) };
+            self.sender
+                .send(self.on_mouse_leave.as_ref(), TrayIconEvent::MouseLeave);
+        }
+    }
+
+    /// Update menu items with the event in place
+    fn update_menu_items<F>(&self, id: &T, f: F) -> Result<(), Error>
+    where
+        T: PartialEq,
+        F: Fn(&WinHMenu, usize) -> bool,
+    {
+        let menu = self.menu.as_ref().ok_or(Error::MenuItemNotFound)?;
+        let mut found = false;
+        for j in menu.command_ids(id) {
+            found = f(&menu.menu, j) || found;
+        }
+        if found {
+            Ok(())
+        } else {
+            Err(Error::MenuItemNotFound)
+        }
+    }
+
     // This serves as a conduit for actual winproc in the subproc
     pub unsafe extern "system" fn winproc(
         hwnd: HWND,
@@ -232,6 +1353,35 @@ where
         wparam: WPARAM,
         lparam: LPARAM,
     ) -> LRESULT {
+        // Unwinding into the system is undefined behavior, the panic is
+        // reported and the message is handled by the default handler
+        match std::panic::catch_unwind(|| Self::dispatch(hwnd, msg, wparam, lparam)) {
+            Ok(res) => res,
+            Err(_) => {
+                trace_event!(ERROR, "event handler panicked on the message {:#x}", msg);
+                Self::report_panic(hwnd);
+                winuser::DefWindowProcW(hwnd, msg, wparam, lparam)
+            }
+        }
+    }
+
+    /// Error handler is called if the window still exists
+    unsafe fn report_panic(hwnd: HWND) {
+        let window_ptr = winuser::GetWindowLongPtrW(hwnd, winuser::GWL_USERDATA);
+        if window_ptr == 0 {
+            return;
+        }
+        let window: &WinTrayIconImpl<T> = &*(window_ptr as *const _);
+        if let Some(on_error) = window.on_error.as_ref() {
+            // Panic of the error handler is not reported again
+            let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                on_error.call(Error::HandlerPanicked)
+            }));
+        }
+    }
+
+    unsafe fn dispatch(hwnd: HWND, msg: UINT, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+        trace_span!(TRACE, "wndproc", msg);
         match msg {
             winuser::WM_CREATE => {
                 let create_struct: &mut winuser::CREATESTRUCTW = &mut *(lparam as *mut _);
@@ -267,40 +1417,807 @@ where
 
 impl<T> TrayIconBase<T> for WinTrayIconImpl<T>
 where
-    T: PartialEq + Clone + 'static,
+    T: Clone + 'static,
 {
     /// Set the tooltip
     fn set_tooltip(&mut self, tooltip: &str) -> Result<(), Error> {
-        if !self.notify_icon.set_tooltip(tooltip) {
-            return Err(Error::OsError);
+        let tooltip = super::reading_order(tooltip, self.rtl);
+        if !self.notify_icon.set_tooltip(&tooltip) {
+            return Err(Error::os_error("Shell_NotifyIconW"));
         }
         Ok(())
     }
 
+    /// Refresh the tooltip with a timer of the hidden window
+    fn set_tooltip_fn(
+        &mut self,
+        tooltip_fn: Option<TooltipFn>,
+        interval: Duration,
+    ) -> Result<(), Error> {
+        let tooltip_fn = match tooltip_fn {
+            Some(tooltip_fn) => tooltip_fn,
+            None => {
+                if self.tooltip_fn.take().is_some() {
+                    unsafe { winuser::KillTimer(self.hwnd, msgs::TIMER_TOOLTIP) };
+                }
+                return Ok(());
+            }
+        };
+        let elapse = interval.as_millis().min(u32::MAX as u128) as u32;
+        let res = unsafe { winuser::SetTimer(self.hwnd, msgs::TIMER_TOOLTIP, elapse, None) };
+        if res == 0 {
+            return Err(Error::last_os_error("SetTimer"));
+        }
+        self.tooltip_fn = Some(tooltip_fn.clone());
+        self.set_tooltip(&tooltip_fn.tooltip())
+    }
+
+    /// Set checkable in place
+    fn set_menu_item_checkable(&mut self, id: &T, checked: bool) -> Result<(), Error>
+    where
+        T: PartialEq,
+    {
+        self.update_menu_items(id, |menu, j| menu.set_checked(j, checked))
+    }
+
+    /// Set disabled in place
+    fn set_menu_item_disabled(&mut self, id: &T, disabled: bool) -> Result<(), Error>
+    where
+        T: PartialEq,
+    {
+        self.update_menu_items(id, |menu, j| menu.set_disabled(j, disabled))
+    }
+
+    /// Set name in place
+    ///
+    /// Name with `&` is set by updating the menu, which knows whether it marks
+    /// the mnemonic.
+    fn set_menu_item_name(&mut self, id: &T, name: &str) -> Result<(), Error>
+    where
+        T: PartialEq,
+    {
+        let menu = self.menu.as_ref().ok_or(Error::MenuItemNotFound)?;
+        self.update_menu_items(id, |hmenu, j| {
+            let label = menu_label(name, menu.mnemonics.contains(&j), Some('&'));
+            match menu.accelerators.get(&j) {
+                Some(accelerator) => hmenu.set_name(j, &format!("{}\t{}", label, accelerator)),
+                None => hmenu.set_name(j, &label),
+            }
+        })
+    }
+
     /// Set icon
     fn set_icon(&mut self, icon: &Icon) -> Result<(), Error> {
-        if !self.notify_icon.set_icon(&icon.sys) {
+        self.icon = icon.clone();
+        self.icon_light = None;
+        self.icon_dark = None;
+        if !self.update_icon() {
+            return Err(Error::IconLoadingFailed);
+        }
+        Ok(())
+    }
+
+    /// Show menu at the position, or at the tray icon
+    fn show_menu(&mut self, position: Option<PhysicalPosition>) -> Result<(), Error> {
+        let pos = self.menu_position(position)?;
+        self.track_menu(pos, 0);
+        Ok(())
+    }
+
+    /// Track with `TPM_RETURNCMD`, so the command is not posted
+    fn show_menu_sync(&mut self, position: Option<PhysicalPosition>) -> Result<Option<T>, Error> {
+        let pos = self.menu_position(position)?;
+        let j = self.track_menu(pos, winuser::TPM_RETURNCMD);
+        Ok(self.menu_item_event(j).map(|(event, _)| event))
+    }
+
+    /// Events are looked up when the messages arrive
+    fn set_click_events(
+        &mut self,
+        on_click: Option<T>,
+        on_double_click: Option<T>,
+        on_right_click: Option<T>,
+    ) -> Result<(), Error> {
+        self.on_click = on_click;
+        self.on_double_click = on_double_click;
+        self.on_right_click = on_right_click;
+        Ok(())
+    }
+
+    /// Remove or add the notify icon
+    fn set_visible(&mut self, visible: bool) -> Result<(), Error> {
+        if !self.notify_icon.set_visible(visible) {
+            return Err(Error::os_error("Shell_NotifyIconW"));
+        }
+        Ok(())
+    }
+
+    /// Cycle the frames with a timer of the hidden window
+    fn set_animation(&mut self, frames: Vec<Icon>, interval: Duration) -> Result<(), Error> {
+        let elapse = interval.as_millis().min(u32::MAX as u128) as u32;
+        let res = unsafe { winuser::SetTimer(self.hwnd, msgs::TIMER_ANIMATION, elapse, None) };
+        if res == 0 {
+            return Err(Error::last_os_error("SetTimer"));
+        }
+        self.animation = frames;
+        self.animation_frame = 0;
+        if !self.update_icon() {
+            return Err(Error::IconLoadingFailed);
+        }
+        Ok(())
+    }
+
+    /// Decoration is drawn when the icon is set, so it fits the current DPI
+    fn set_decoration(&mut self, decoration: Decoration) -> Result<(), Error> {
+        self.decoration = decoration;
+        if !self.update_icon() {
+            return Err(Error::IconLoadingFailed);
+        }
+        Ok(())
+    }
+
+    /// Stop the timer and restore the icon
+    fn stop_animation(&mut self) -> Result<(), Error> {
+        if self.animation.is_empty() {
+            return Ok(());
+        }
+        unsafe { winuser::KillTimer(self.hwnd, msgs::TIMER_ANIMATION) };
+        self.animation.clear();
+        self.animation_frame = 0;
+        if !self.update_icon() {
             return Err(Error::IconLoadingFailed);
         }
         Ok(())
     }
 
+    /// Show balloon notification
+    fn show_notification(
+        &mut self,
+        title: &str,
+        body: &str,
+        icon: Option<&Icon>,
+        timeout: Option<Duration>,
+    ) -> Result<(), Error> {
+        // Balloon shows the large icon
+        let icon = icon.map(|icon| unsafe {
+            icon.sys_for_size(
+                winuser::GetSystemMetrics(winuser::SM_CXICON) as u32,
+                winuser::GetSystemMetrics(winuser::SM_CYICON) as u32,
+            )
+        });
+        if !self
+            .notify_icon
+            .show_notification(title, body, icon.as_ref(), timeout)
+        {
+            return Err(Error::os_error("Shell_NotifyIconW"));
+        }
+        Ok(())
+    }
+
+    /// Screen rectangle with Shell_NotifyIconGetRect
+    fn rect(&self) -> Result<(i32, i32, u32, u32), Error> {
+        let rect = self
+            .notify_icon
+            .rect()
+            .ok_or(Error::os_error("Shell_NotifyIconGetRect"))?;
+        Ok((
+            rect.left,
+            rect.top,
+            (rect.right - rect.left) as u32,
+            (rect.bottom - rect.top) as u32,
+        ))
+    }
+
     /// Set menu
+    ///
+    /// Only the changed items are updated in place, the menu is rebuilt if
+    /// that fails.
     fn set_menu(&mut self, menu: &MenuBuilder<T>) -> Result<(), Error> {
-        if menu.menu_items.is_empty() {
-            self.menu = None
+        self.unregister_hotkeys();
+        let res = if menu.menu_items.is_empty() {
+            self.menu = None;
+            Ok(())
+        } else if self
+            .menu
+            .as_mut()
+            .is_some_and(|current| current.update(menu).is_ok())
+        {
+            Ok(())
         } else {
-            self.menu = Some(menu.build()?);
-        }
-        Ok(())
+            self.menu = None;
+            menu.build().map(|menu| self.menu = Some(menu))
+        };
+        self.register_hotkeys();
+        res
     }
 }
 
 impl<T> Drop for WinTrayIconImpl<T>
 where
-    T: PartialEq + Clone + 'static,
+    T: Clone + 'static,
 {
     fn drop(&mut self) {
         self.notify_icon.remove();
     }
 }
+
+/// Register the window class, registering it again fails harmlessly
+pub(crate) fn register_class(wnd_class: &winuser::WNDCLASSW) -> Result<(), Error> {
+    let atom = unsafe { winuser::RegisterClassW(wnd_class) };
+    if atom == 0 {
+        let code = std::io::Error::last_os_error().raw_os_error();
+        if code != Some(ERROR_CLASS_ALREADY_EXISTS as i32) {
+            return Err(Error::last_os_error("RegisterClassW"));
+        }
+    }
+    Ok(())
+}
+
+/// Number of the monitors on the desktop
+fn monitor_count() -> i32 {
+    unsafe { winuser::GetSystemMetrics(winuser::SM_CMONITORS) }
+}
+
+/// Modifiers and the virtual key code for RegisterHotKey
+fn hotkey(accelerator: &Accelerator) -> Option<(UINT, UINT)> {
+    let mut modifiers = winuser::MOD_NOREPEAT as UINT;
+    if accelerator.ctrl {
+        modifiers |= winuser::MOD_CONTROL as UINT;
+    }
+    if accelerator.alt {
+        modifiers |= winuser::MOD_ALT as UINT;
+    }
+    if accelerator.shift {
+        modifiers |= winuser::MOD_SHIFT as UINT;
+    }
+    if accelerator.meta {
+        modifiers |= winuser::MOD_WIN as UINT;
+    }
+    let vk = match accelerator.key {
+        Key::Char(c) => {
+            // Low byte is the key, high byte the shift state of the layout
+            let scan = unsafe { winuser::VkKeyScanW(c as u16) };
+            if scan == -1 {
+                return None;
+            }
+            (scan & 0xff) as i32
+        }
+        Key::F(n) => winuser::VK_F1 + n as i32 - 1,
+        Key::Space => winuser::VK_SPACE,
+        Key::Enter => winuser::VK_RETURN,
+        Key::Escape => winuser::VK_ESCAPE,
+        Key::Tab => winuser::VK_TAB,
+        Key::Backspace => winuser::VK_BACK,
+        Key::Delete => winuser::VK_DELETE,
+        Key::Insert => winuser::VK_INSERT,
+        Key::Home => winuser::VK_HOME,
+        Key::End => winuser::VK_END,
+        Key::PageUp => winuser::VK_PRIOR,
+        Key::PageDown => winuser::VK_NEXT,
+        Key::Up => winuser::VK_UP,
+        Key::Down => winuser::VK_DOWN,
+        Key::Left => winuser::VK_LEFT,
+        Key::Right => winuser::VK_RIGHT,
+    };
+    Some((modifiers, vk as UINT))
+}
+
+/// Window of the taskbar, null while explorer.exe is restarting
+fn find_taskbar() -> HWND {
+    unsafe { winuser::FindWindowW(wchar("Shell_TrayWnd").as_ptr(), std::ptr::null()) }
+}
+
+/// Name of the changed setting in WM_SETTINGCHANGE, if given
+fn setting_name(lparam: LPARAM) -> Option<String> {
+    let ptr = lparam as *const u16;
+    if ptr.is_null() {
+        return None;
+    }
+    unsafe {
+        let len = (0..).take_while(|&i| *ptr.add(i) != 0).count();
+        Some(String::from_utf16_lossy(std::slice::from_raw_parts(
+            ptr, len,
+        )))
+    }
+}
+
+/// Small icon size for the DPI of the window
+///
+/// Small icon metric is scaled to the system DPI, with per-monitor DPI
+/// awareness the window can be on a monitor with different scaling.
+/// GetDpiForWindow is loaded dynamically, it's available since Windows 10.
+fn icon_size(hwnd: HWND) -> u32 {
+    let size = unsafe { winuser::GetSystemMetrics(winuser::SM_CXSMICON) } as u32;
+    unsafe {
+        let user32 = GetModuleHandleW(wchar("user32.dll").as_ptr());
+        if user32.is_null() {
+            return size;
+        }
+        let proc = GetProcAddress(user32, "GetDpiForWindow\0".as_ptr() as _);
+        if proc.is_null() {
+            return size;
+        }
+        let get_dpi_for_window: extern "system" fn(HWND) -> UINT = std::mem::transmute(proc);
+        let dpi = get_dpi_for_window(hwnd);
+        let hdc = winuser::GetDC(std::ptr::null_mut());
+        let system_dpi = wingdi::GetDeviceCaps(hdc, wingdi::LOGPIXELSX);
+        winuser::ReleaseDC(std::ptr::null_mut(), hdc);
+        if dpi == 0 || system_dpi <= 0 {
+            return size;
+        }
+        size * dpi / system_dpi as u32
+    }
+}
+
+/// Cursor position when the current message was posted
+///
+/// Coordinates are signed, they are negative on the monitors left or above
+/// the primary monitor.
+fn cursor_position() -> PhysicalPosition {
+    let pos = unsafe { winuser::GetMessagePos() };
+    PhysicalPosition {
+        x: LOWORD(pos) as i16 as i32,
+        y: HIWORD(pos) as i16 as i32,
+    }
+}
+
+/// Modifier keys held now
+///
+/// Keyboard input goes to the taskbar, not to this thread, so `GetKeyState`
+/// would be stale. The asynchronous state is read instead.
+fn key_modifiers() -> Modifiers {
+    let down = |vk| unsafe { winuser::GetAsyncKeyState(vk) } as u16 & 0x8000 != 0;
+    Modifiers {
+        shift: down(winuser::VK_SHIFT),
+        ctrl: down(winuser::VK_CONTROL),
+        alt: down(winuser::VK_MENU),
+        meta: down(winuser::VK_LWIN) || down(winuser::VK_RWIN),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TrayIcon, TrayIconBuilder};
+    use std::sync::mpsc::{channel, Receiver};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum Events {
+        Click,
+        DoubleClick,
+        RightClick,
+        MiddleClick,
+        NotificationClicked,
+        Raw,
+        KeySelect,
+        Restore,
+        CheckableItem1,
+        Item1,
+    }
+
+    /// Tray icon with its hidden window, the messages are sent synchronously
+    /// so the events are in the channel when `send` returns
+    ///
+    /// Without the taskbar the icon is not added, the messages are handled
+    /// the same.
+    struct TestTray<E>
+    where
+        E: Clone + 'static,
+    {
+        trayicon: TrayIcon<Events>,
+        receiver: Receiver<E>,
+    }
+
+    impl<E> TestTray<E>
+    where
+        E: Clone + 'static,
+    {
+        fn send(&self, msg: UINT, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+            unsafe { winuser::SendMessageW(self.trayicon.hwnd() as HWND, msg, wparam, lparam) }
+        }
+
+        /// Mouse or keyboard message of the tray icon, as sent by the shell
+        fn tray_message(&self, message: UINT) {
+            self.send(msgs::WM_USER_TRAYICON, 0, message as LPARAM);
+        }
+
+        /// Menu item with the number chosen
+        fn command(&self, j: usize) {
+            self.send(winuser::WM_COMMAND, j, 0);
+        }
+
+        fn events(&self) -> Vec<E> {
+            self.receiver.try_iter().collect()
+        }
+    }
+
+    fn icon() -> Icon {
+        Icon::from_rgba(16, 16, vec![255; 16 * 16 * 4]).unwrap()
+    }
+
+    fn menu() -> MenuBuilder<Events> {
+        MenuBuilder::new()
+            .checkable("This is checkable", false, Events::CheckableItem1)
+            .item("Item 1", Events::Item1)
+    }
+
+    fn build(builder: TrayIconBuilder<Events>) -> TestTray<Events> {
+        let (s, receiver) = channel();
+        let trayicon = builder.sender(s).icon(icon()).build().unwrap();
+        TestTray { trayicon, receiver }
+    }
+
+    fn build_events(builder: TrayIconBuilder<Events>) -> TestTray<TrayIconEvent<Events>> {
+        let (s, receiver) = channel();
+        let trayicon = builder.sender_events(s).icon(icon()).build().unwrap();
+        TestTray { trayicon, receiver }
+    }
+
+    #[test]
+    fn test_clicks() {
+        let tray = build(
+            TrayIconBuilder::new()
+                .on_click(Events::Click)
+                .on_double_click(Events::DoubleClick)
+                .on_right_click(Events::RightClick)
+                .on_middle_click(Events::MiddleClick),
+        );
+        tray.tray_message(winuser::WM_LBUTTONUP);
+        tray.tray_message(winuser::WM_RBUTTONUP);
+        tray.tray_message(winuser::WM_MBUTTONUP);
+        tray.tray_message(winuser::WM_LBUTTONDBLCLK);
+        tray.tray_message(NIN_KEYSELECT);
+        assert_eq!(
+            tray.events(),
+            vec![
+                Events::Click,
+                Events::RightClick,
+                Events::MiddleClick,
+                Events::DoubleClick,
+                Events::Click
+            ]
+        );
+    }
+
+    #[test]
+    fn test_key_select() {
+        let tray = build(
+            TrayIconBuilder::new()
+                .on_click(Events::Click)
+                .on_key_select(Events::KeySelect),
+        );
+        tray.tray_message(NIN_KEYSELECT);
+        tray.tray_message(NIN_KEYSELECT);
+        tray.tray_message(winuser::WM_LBUTTONUP);
+        tray.tray_message(NIN_SELECT);
+        assert_eq!(tray.events(), vec![Events::KeySelect, Events::Click]);
+
+        let tray = build(TrayIconBuilder::new().on_click(Events::Click));
+        tray.tray_message(NIN_SELECT);
+        assert_eq!(tray.events(), vec![Events::Click]);
+    }
+
+    #[test]
+    fn test_click_behavior() {
+        let double_click = |behavior| {
+            let tray = build(
+                TrayIconBuilder::new()
+                    .on_click(Events::Click)
+                    .on_double_click(Events::DoubleClick)
+                    .click_behavior(behavior),
+            );
+            for msg in &[
+                winuser::WM_LBUTTONUP,
+                winuser::WM_LBUTTONDBLCLK,
+                winuser::WM_LBUTTONUP,
+            ] {
+                tray.tray_message(*msg);
+            }
+            tray.events()
+        };
+        assert_eq!(
+            double_click(ClickBehavior::Both),
+            vec![Events::Click, Events::DoubleClick]
+        );
+        assert_eq!(
+            double_click(ClickBehavior::SingleOnly),
+            vec![Events::Click, Events::Click]
+        );
+        assert_eq!(
+            double_click(ClickBehavior::DoubleOnly),
+            vec![Events::DoubleClick]
+        );
+        assert_eq!(
+            double_click(ClickBehavior::SingleWithDelay),
+            vec![Events::DoubleClick]
+        );
+    }
+
+    #[test]
+    fn test_click_events() {
+        let tray = build_events(TrayIconBuilder::new());
+        tray.tray_message(winuser::WM_MBUTTONUP);
+        tray.tray_message(NIN_BALLOONUSERCLICK);
+        tray.tray_message(NIN_BALLOONTIMEOUT);
+        let events = tray.events();
+        assert!(matches!(
+            events[0],
+            TrayIconEvent::Click {
+                button: MouseButton::Middle,
+                ..
+            }
+        ));
+        assert_eq!(
+            events[1..],
+            [TrayIconEvent::BalloonClicked, TrayIconEvent::BalloonClosed]
+        );
+    }
+
+    #[test]
+    fn test_tooltip_fn() {
+        let mut tray = build(TrayIconBuilder::new());
+        let count = Arc::new(Mutex::new(0));
+        let counter = count.clone();
+        let tooltip_fn = move || {
+            let mut count = counter.lock().unwrap();
+            *count += 1;
+            format!("Refreshed {}", count)
+        };
+
+        // Without the taskbar setting the tooltip fails, the timer still runs
+        let _ = tray
+            .trayicon
+            .set_tooltip_fn(tooltip_fn, Duration::from_secs(60));
+        tray.send(winuser::WM_TIMER, msgs::TIMER_TOOLTIP, 0);
+        tray.send(winuser::WM_TIMER, msgs::TIMER_TOOLTIP, 0);
+        assert_eq!(*count.lock().unwrap(), 3);
+
+        // Setting the tooltip stops the refreshing
+        let _ = tray.trayicon.set_tooltip("Tooltip");
+        tray.send(winuser::WM_TIMER, msgs::TIMER_TOOLTIP, 0);
+        assert_eq!(*count.lock().unwrap(), 3);
+    }
+
+    /// Window of the app, hidden until shown
+    fn app_window() -> HWND {
+        let window = unsafe {
+            winuser::CreateWindowExW(
+                0,
+                wchar("STATIC").as_ptr(),
+                wchar("Window").as_ptr(),
+                winuser::WS_OVERLAPPEDWINDOW,
+                0,
+                0,
+                100,
+                100,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        };
+        assert!(!window.is_null());
+        window
+    }
+
+    #[test]
+    fn test_toggle_window() {
+        let window = app_window();
+        let tray = build(
+            TrayIconBuilder::new()
+                .on_click(Events::Click)
+                .toggle_window_on_click(window as isize),
+        );
+        let visible = || unsafe { winuser::IsWindowVisible(window) != 0 };
+        tray.tray_message(winuser::WM_LBUTTONUP);
+        assert!(visible());
+        tray.tray_message(winuser::WM_LBUTTONUP);
+        assert!(!visible());
+
+        // Minimized window is restored
+        unsafe { winuser::ShowWindow(window, winuser::SW_SHOWMINNOACTIVE) };
+        tray.tray_message(winuser::WM_LBUTTONUP);
+        assert!(visible());
+        assert_eq!(unsafe { winuser::IsIconic(window) }, 0);
+        assert_eq!(tray.events(), vec![Events::Click; 3]);
+        unsafe { winuser::DestroyWindow(window) };
+    }
+
+    #[test]
+    fn test_minimize_to_tray() {
+        let window = app_window();
+        unsafe { winuser::ShowWindow(window, winuser::SW_SHOWNOACTIVATE) };
+        let tray = build(
+            TrayIconBuilder::new()
+                .on_click(Events::Click)
+                .on_restore_requested(Events::Restore)
+                .minimize_to_tray(window as isize),
+        );
+        let visible = || unsafe { winuser::IsWindowVisible(window) != 0 };
+        let send = |msg, wparam| unsafe { winuser::SendMessageW(window, msg, wparam, 0) };
+
+        // Minimizing and closing hide the window
+        send(winuser::WM_SYSCOMMAND, winuser::SC_MINIMIZE);
+        assert!(!visible());
+        assert_eq!(unsafe { winuser::IsIconic(window) }, 0);
+        tray.tray_message(winuser::WM_LBUTTONUP);
+        assert!(visible());
+        send(winuser::WM_CLOSE, 0);
+        assert!(!visible());
+        assert_ne!(unsafe { winuser::IsWindow(window) }, 0);
+        tray.tray_message(winuser::WM_LBUTTONUP);
+
+        // Click on the shown window isn't a restore
+        tray.tray_message(winuser::WM_LBUTTONUP);
+        assert_eq!(
+            tray.events(),
+            vec![
+                Events::Restore,
+                Events::Click,
+                Events::Restore,
+                Events::Click,
+                Events::Click
+            ]
+        );
+
+        // Closing works again after the tray icon is dropped
+        drop(tray);
+        send(winuser::WM_CLOSE, 0);
+        assert_eq!(unsafe { winuser::IsWindow(window) }, 0);
+    }
+
+    #[test]
+    fn test_class_name() {
+        let class_name = |tray: &TestTray<_>| {
+            let mut name = [0u16; 64];
+            let len = unsafe {
+                winuser::GetClassNameW(
+                    tray.trayicon.hwnd() as HWND,
+                    name.as_mut_ptr() as _,
+                    name.len() as i32,
+                )
+            };
+            String::from_utf16_lossy(&name[..len as usize])
+        };
+        let tray = build(TrayIconBuilder::new().class_name("TrayIconTestCls").uid(7));
+        assert_eq!(class_name(&tray), "TrayIconTestCls");
+        let tray = build(TrayIconBuilder::new().class_name("TrayIconTestCls"));
+        assert_eq!(class_name(&tray), "TrayIconTestCls");
+
+        // Other event type has another window procedure
+        let (s, _r) = channel::<u32>();
+        let res = TrayIconBuilder::new()
+            .sender(s)
+            .icon(icon())
+            .class_name("TrayIconTestCls")
+            .build();
+        assert!(matches!(res, Err(Error::OsError(_))));
+    }
+
+    #[test]
+    fn test_add_retry() {
+        let mut tray = build_events(TrayIconBuilder::new());
+
+        // Hidden icon is added without the shell
+        tray.trayicon.set_visible(false).unwrap();
+        tray.send(winuser::WM_TIMER, msgs::TIMER_ADD_RETRY, 0);
+        assert_eq!(tray.events(), vec![TrayIconEvent::IconAdded]);
+    }
+
+    #[test]
+    fn test_hidden_not_in_tray() {
+        let mut tray = build(TrayIconBuilder::new().on_tray_visibility_changed(Events::Raw));
+        tray.trayicon.set_visible(false).unwrap();
+        assert!(!tray.trayicon.is_visible_in_tray().unwrap());
+
+        // Polling sends only the changes
+        tray.send(winuser::WM_TIMER, msgs::TIMER_TRAY_VISIBILITY, 0);
+        tray.events();
+        tray.send(winuser::WM_TIMER, msgs::TIMER_TRAY_VISIBILITY, 0);
+        assert_eq!(tray.events(), vec![]);
+    }
+
+    #[test]
+    fn test_message_only_window() {
+        let parent = |tray: &TestTray<Events>| unsafe {
+            winuser::GetAncestor(tray.trayicon.hwnd() as HWND, winuser::GA_PARENT)
+        };
+        let desktop = unsafe { winuser::GetDesktopWindow() };
+        let tray = build(TrayIconBuilder::new().on_click(Events::Click));
+        assert_eq!(parent(&tray), desktop);
+
+        let tray = build(
+            TrayIconBuilder::new()
+                .on_click(Events::Click)
+                .on_taskbar_restarted(Events::Restore)
+                .message_only_window(true),
+        );
+        assert_ne!(parent(&tray), desktop);
+
+        // Polling sees the same taskbar
+        tray.send(winuser::WM_TIMER, msgs::TIMER_TASKBAR, 0);
+        tray.tray_message(winuser::WM_LBUTTONUP);
+        assert_eq!(tray.events(), vec![Events::Click]);
+    }
+
+    #[test]
+    fn test_menu_commands() {
+        let tray = build(TrayIconBuilder::new().menu(menu()));
+        tray.command(2);
+        tray.command(1);
+
+        // Accelerators and the unknown items are ignored
+        tray.send(winuser::WM_COMMAND, 0x10002, 0);
+        tray.command(3);
+        assert_eq!(tray.events(), vec![Events::Item1, Events::CheckableItem1]);
+    }
+
+    #[test]
+    fn test_menu_commands_toggled() {
+        let mut tray = build_events(
+            TrayIconBuilder::new()
+                .menu(menu())
+                .auto_toggle_checkables(true),
+        );
+        tray.command(1);
+        tray.command(1);
+        assert_eq!(
+            tray.events(),
+            vec![
+                TrayIconEvent::MenuItemToggled(Events::CheckableItem1, true),
+                TrayIconEvent::MenuItemToggled(Events::CheckableItem1, false)
+            ]
+        );
+        assert_eq!(
+            tray.trayicon
+                .get_menu_item_checkable(Events::CheckableItem1),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_raw_message() {
+        let tray = build(
+            TrayIconBuilder::new()
+                .on_click(Events::Click)
+                .on_raw_message(|msg, _, lparam| match (msg, lparam as UINT) {
+                    (msgs::WM_USER_TRAYICON, winuser::WM_LBUTTONUP) => Some(Events::Raw),
+                    _ => None,
+                }),
+        );
+        tray.tray_message(winuser::WM_LBUTTONUP);
+        tray.tray_message(NIN_KEYSELECT);
+        assert_eq!(tray.events(), vec![Events::Raw, Events::Click]);
+    }
+
+    #[test]
+    fn test_handler_panic() {
+        let (s, receiver) = channel();
+        let errors = Arc::new(Mutex::new(vec![]));
+        let reported = errors.clone();
+        let trayicon = TrayIconBuilder::new()
+            .on_event(move |e: Events| {
+                s.send(e).unwrap();
+                if e == Events::NotificationClicked {
+                    panic!("handler panicked");
+                }
+            })
+            .on_notification_clicked(Events::NotificationClicked)
+            .on_click(Events::Click)
+            .on_error(move |err| reported.lock().unwrap().push(err))
+            .icon(icon())
+            .build()
+            .unwrap();
+        let tray = TestTray { trayicon, receiver };
+
+        // Tray icon keeps handling the messages after the panic
+        tray.tray_message(NIN_BALLOONUSERCLICK);
+        tray.tray_message(winuser::WM_LBUTTONUP);
+        assert_eq!(
+            tray.events(),
+            vec![Events::NotificationClicked, Events::Click]
+        );
+        assert_eq!(*errors.lock().unwrap(), vec![Error::HandlerPanicked]);
+    }
+}
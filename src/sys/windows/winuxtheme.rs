@@ -0,0 +1,70 @@
+use super::wchar::wchar;
+use crate::MenuTheme;
+use winapi::shared::minwindef::DWORD;
+use winapi::um::libloaderapi::{GetModuleHandleW, GetProcAddress, LoadLibraryW};
+use winapi::um::winnt::{LONG, OSVERSIONINFOW};
+
+/// First build with `SetPreferredAppMode`, Windows 10 1903
+const MIN_BUILD: DWORD = 18362;
+
+// Undocumented ordinals of uxtheme.dll
+const ORDINAL_SET_PREFERRED_APP_MODE: usize = 135;
+const ORDINAL_FLUSH_MENU_THEMES: usize = 136;
+
+// PreferredAppMode values
+const APP_MODE_ALLOW_DARK: i32 = 1;
+const APP_MODE_FORCE_DARK: i32 = 2;
+const APP_MODE_FORCE_LIGHT: i32 = 3;
+
+/// Set the theme of the popup menus of the process
+///
+/// Does nothing on the versions without the dark mode.
+pub(crate) fn set_menu_theme(theme: MenuTheme) {
+    if build_number() < MIN_BUILD {
+        return;
+    }
+    let mode = match theme {
+        MenuTheme::System => APP_MODE_ALLOW_DARK,
+        MenuTheme::Light => APP_MODE_FORCE_LIGHT,
+        MenuTheme::Dark => APP_MODE_FORCE_DARK,
+    };
+    unsafe {
+        // Library is left loaded, the mode is kept by it
+        let uxtheme = LoadLibraryW(wchar("uxtheme.dll").as_ptr());
+        if uxtheme.is_null() {
+            return;
+        }
+        let set_preferred_app_mode = GetProcAddress(uxtheme, ORDINAL_SET_PREFERRED_APP_MODE as _);
+        let flush_menu_themes = GetProcAddress(uxtheme, ORDINAL_FLUSH_MENU_THEMES as _);
+        if set_preferred_app_mode.is_null() || flush_menu_themes.is_null() {
+            return;
+        }
+        let set_preferred_app_mode: extern "system" fn(i32) -> i32 =
+            std::mem::transmute(set_preferred_app_mode);
+        let flush_menu_themes: extern "system" fn() = std::mem::transmute(flush_menu_themes);
+        set_preferred_app_mode(mode);
+        flush_menu_themes();
+    }
+}
+
+/// Build number from RtlGetVersion, GetVersionEx lies without a manifest
+fn build_number() -> DWORD {
+    unsafe {
+        let ntdll = GetModuleHandleW(wchar("ntdll.dll").as_ptr());
+        if ntdll.is_null() {
+            return 0;
+        }
+        let proc = GetProcAddress(ntdll, "RtlGetVersion\0".as_ptr() as _);
+        if proc.is_null() {
+            return 0;
+        }
+        let rtl_get_version: extern "system" fn(*mut OSVERSIONINFOW) -> LONG =
+            std::mem::transmute(proc);
+        let mut info: OSVERSIONINFOW = std::mem::zeroed();
+        info.dwOSVersionInfoSize = std::mem::size_of::<OSVERSIONINFOW>() as DWORD;
+        if rtl_get_version(&mut info) != 0 {
+            return 0;
+        }
+        info.dwBuildNumber
+    }
+}
@@ -0,0 +1,117 @@
+use winapi::shared::basetsd::{DWORD_PTR, UINT_PTR};
+use winapi::shared::minwindef::{DWORD, FALSE, LPARAM, LRESULT, TRUE, UINT, WPARAM};
+use winapi::shared::windef::HWND;
+use winapi::um::processthreadsapi::GetCurrentThreadId;
+use winapi::um::{commctrl, winuser};
+
+/// Identifier of the subclass hiding the window, one per window
+const MINIMIZE_TO_TRAY_SUBCLASS: UINT_PTR = 1;
+
+/// Window is visible and not minimized
+pub(crate) fn is_shown(hwnd: HWND) -> bool {
+    unsafe { winuser::IsWindowVisible(hwnd) != 0 && winuser::IsIconic(hwnd) == 0 }
+}
+
+/// Hide the window if it's shown, otherwise show it in the foreground
+///
+/// Minimized window is restored instead of hidden.
+pub(crate) fn toggle_window(hwnd: HWND) {
+    if unsafe { winuser::IsWindow(hwnd) } == 0 {
+        return;
+    }
+    if is_shown(hwnd) {
+        unsafe { winuser::ShowWindow(hwnd, winuser::SW_HIDE) };
+    } else {
+        show_window(hwnd);
+    }
+}
+
+/// Show the window, restore it if minimized, and bring it to the foreground
+pub(crate) fn show_window(hwnd: HWND) {
+    unsafe {
+        if winuser::IsIconic(hwnd) != 0 {
+            winuser::ShowWindow(hwnd, winuser::SW_RESTORE);
+        } else {
+            winuser::ShowWindow(hwnd, winuser::SW_SHOW);
+        }
+    }
+    set_foreground(hwnd);
+}
+
+/// Bring the window to the foreground
+///
+/// Clicking the tray icon lets this process set the foreground window, and the
+/// process of the window is allowed to do the same, in case it's another one.
+/// If the foreground is still refused, the input of the foreground thread is
+/// attached for the duration of the call.
+fn set_foreground(hwnd: HWND) {
+    unsafe {
+        let mut process: DWORD = 0;
+        winuser::GetWindowThreadProcessId(hwnd, &mut process);
+        winuser::AllowSetForegroundWindow(process);
+        if winuser::SetForegroundWindow(hwnd) != 0 {
+            return;
+        }
+        let foreground = winuser::GetForegroundWindow();
+        let foreground_thread = winuser::GetWindowThreadProcessId(foreground, std::ptr::null_mut());
+        let thread = GetCurrentThreadId();
+        if foreground_thread == 0 || foreground_thread == thread {
+            return;
+        }
+        if winuser::AttachThreadInput(thread, foreground_thread, TRUE) != 0 {
+            winuser::BringWindowToTop(hwnd);
+            winuser::SetForegroundWindow(hwnd);
+            winuser::AttachThreadInput(thread, foreground_thread, FALSE);
+        }
+    }
+}
+
+/// Subclass the window, so minimizing or closing it hides it instead
+///
+/// Must be called from the thread of the window, the subclass can't be set
+/// across the threads.
+pub(crate) fn subclass_minimize_to_tray(hwnd: HWND) -> bool {
+    unsafe {
+        commctrl::SetWindowSubclass(
+            hwnd,
+            Some(minimize_to_tray_proc),
+            MINIMIZE_TO_TRAY_SUBCLASS,
+            0,
+        ) != 0
+    }
+}
+
+/// Minimizing and closing work normally again
+pub(crate) fn remove_minimize_to_tray(hwnd: HWND) {
+    unsafe {
+        commctrl::RemoveWindowSubclass(hwnd, Some(minimize_to_tray_proc), MINIMIZE_TO_TRAY_SUBCLASS)
+    };
+}
+
+unsafe extern "system" fn minimize_to_tray_proc(
+    hwnd: HWND,
+    msg: UINT,
+    wparam: WPARAM,
+    lparam: LPARAM,
+    _id: UINT_PTR,
+    _data: DWORD_PTR,
+) -> LRESULT {
+    match msg {
+        // Low four bits are used by the system
+        winuser::WM_SYSCOMMAND if wparam & 0xfff0 == winuser::SC_MINIMIZE => {
+            winuser::ShowWindow(hwnd, winuser::SW_HIDE);
+            0
+        }
+        winuser::WM_CLOSE => {
+            winuser::ShowWindow(hwnd, winuser::SW_HIDE);
+            0
+        }
+
+        // Subclass must be removed before the window is gone
+        winuser::WM_NCDESTROY => {
+            remove_minimize_to_tray(hwnd);
+            commctrl::DefSubclassProc(hwnd, msg, wparam, lparam)
+        }
+        _ => commctrl::DefSubclassProc(hwnd, msg, wparam, lparam),
+    }
+}
@@ -0,0 +1,26 @@
+//! Instrumentation with the `tracing` feature, without it these expand to
+//! nothing
+
+/// Event with the level and the message, e.g. `trace_event!(WARN, "{}", err)`
+///
+/// Without the feature the arguments are only type checked.
+macro_rules! trace_event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::event!(tracing::Level::$level, $($arg)+);
+        #[cfg(not(feature = "tracing"))]
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    };
+}
+
+/// Span entered until the end of the current block, with the level, the name
+/// and the fields, e.g. `trace_span!(TRACE, "wndproc", msg)`
+#[allow(unused_macros)]
+macro_rules! trace_span {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::span!(tracing::Level::$level, $($arg)+).entered();
+    };
+}
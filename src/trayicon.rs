@@ -1,46 +1,187 @@
-use crate::{Error, Icon, MenuBuilder, TrayIconBase, TrayIconBuilder};
+#[cfg(target_os = "windows")]
+use crate::MenuCommandId;
+use crate::{
+    badge::{Badge, Decoration},
+    Error, Icon, MenuBuilder, PhysicalPosition, TrayIconBase, TrayIconBuilder, TrayIconHandle,
+};
+#[cfg(target_os = "windows")]
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
 
+/// Same as the minimum timer interval in Windows
+const MIN_TIMER_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Gives the tooltip, called again on each refresh
+#[derive(Clone)]
+pub(crate) struct TooltipFn(pub(crate) Arc<dyn Fn() -> String + Send + Sync>);
+
+impl TooltipFn {
+    pub(crate) fn tooltip(&self) -> String {
+        (self.0)()
+    }
+}
+
+impl Debug for TooltipFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TooltipFn")
+    }
+}
+
+/// Windows and status items belong to the thread which built them, for
+/// changing the tray icon from other threads use `into_handle`
 pub struct TrayIcon<T>
 where
-    T: PartialEq + Clone + 'static,
+    T: Clone + 'static,
 {
     sys: crate::TrayIconSys<T>,
     builder: TrayIconBuilder<T>,
+
+    /// Badge and progress, set separately but drawn together
+    decoration: Decoration,
+
+    /// Tooltip is refreshed from the function
+    tooltip_fn: bool,
 }
 
 impl<T> TrayIcon<T>
 where
-    T: PartialEq + Clone + 'static,
+    T: Clone + 'static,
 {
     pub(crate) fn new(sys: crate::TrayIconSys<T>, builder: TrayIconBuilder<T>) -> TrayIcon<T> {
-        TrayIcon { builder, sys }
+        TrayIcon {
+            builder,
+            sys,
+            decoration: Decoration::default(),
+            tooltip_fn: false,
+        }
     }
 
     /// Set the icon if changed
+    ///
+    /// Stops switching the `icon_light` and `icon_dark` with the theme.
     pub fn set_icon(&mut self, icon: &Icon) -> Result<(), Error> {
-        if self.builder.icon.as_ref() == Ok(icon) {
+        self.builder.state = None;
+        let themed = self.builder.icon_light.is_some() || self.builder.icon_dark.is_some();
+        if self.builder.icon.as_ref() == Ok(icon) && !themed {
             return Ok(());
         }
         self.builder.icon = Ok(icon.clone());
+        self.builder.icon_light = None;
+        self.builder.icon_dark = None;
         self.sys.set_icon(icon)
     }
 
-    /// Set the menu if changed
+    /// Switch to the state of the `icon_set`, setting the icon and the tooltip
     ///
-    /// This can be used reactively, each time the application state changes,
-    /// build a new menu and set it with this method. This way one can avoid
-    /// using more imperative `set_item_checkable`, `get_item_checkable` and
-    /// `set_item_disabled` methods.
-    pub fn set_menu(&mut self, menu: &MenuBuilder<T>) -> Result<(), Error> {
-        if self.builder.menu.as_ref() == Some(menu) {
+    /// Tooltip is kept if the state doesn't have one. Setting the icon with
+    /// `set_icon` leaves the state.
+    pub fn set_state(&mut self, name: &str) -> Result<(), Error> {
+        if self.builder.state.as_deref() == Some(name) {
             return Ok(());
         }
+        let state = self
+            .builder
+            .icon_set
+            .as_ref()
+            .and_then(|icon_set| icon_set.get(name))
+            .cloned()
+            .ok_or(Error::StateNotFound)?;
+        self.set_icon(&state.icon)?;
+        if let Some(tooltip) = &state.tooltip {
+            self.set_tooltip(tooltip)?;
+        }
+        self.builder.state = Some(name.to_string());
+        Ok(())
+    }
+
+    /// Current state of the `icon_set`, none after `set_icon`
+    pub fn state(&self) -> Option<&str> {
+        self.builder.state.as_deref()
+    }
+
+    /// Cycle the icon frames on a timer
+    ///
+    /// Useful for indicating activity, e.g. sync in progress or recording.
+    /// Animation runs until `stop_animation` is called, or a new animation is
+    /// set. Icon set with `set_icon` is shown after the animation stops.
+    pub fn set_animation(&mut self, frames: Vec<Icon>, interval: Duration) -> Result<(), Error> {
+        if frames.is_empty() {
+            return Err(Error::IconMissing);
+        }
+        self.sys
+            .set_animation(frames, interval.max(MIN_TIMER_INTERVAL))
+    }
+
+    /// Stop the animation and show the icon again
+    pub fn stop_animation(&mut self) -> Result<(), Error> {
+        self.sys.stop_animation()
+    }
+
+    /// Draw the icon at the bottom right corner of the tray icon
+    ///
+    /// Badge is drawn at half the size of the tray icon, `None` removes it.
+    /// Replaces the count set with `set_badge_count`. Not supported on macOS,
+    /// the status bar icons are template images.
+    pub fn set_overlay_badge(&mut self, badge: Option<Icon>) -> Result<(), Error> {
+        self.set_badge(badge.map(Badge::Icon))
+    }
+
+    /// Draw the count in a red circle at the bottom right corner of the tray
+    /// icon, e.g. for the unread messages
+    ///
+    /// Zero removes the badge, counts over 99 are shown as "9+". Replaces the
+    /// icon set with `set_overlay_badge`. Not supported on macOS.
+    pub fn set_badge_count(&mut self, count: u32) -> Result<(), Error> {
+        self.set_badge(Some(count).filter(|&c| c > 0).map(Badge::Count))
+    }
+
+    fn set_badge(&mut self, badge: Option<Badge>) -> Result<(), Error> {
+        let mut decoration = self.decoration.clone();
+        decoration.badge = badge;
+        self.set_decoration(decoration)
+    }
+
+    /// Draw a progress bar along the bottom edge of the tray icon
+    ///
+    /// Progress is from 0.0 to 1.0, values outside are clamped, `None`
+    /// removes the bar. Drawn under the badge. Not supported on macOS.
+    pub fn set_progress(&mut self, progress: Option<f32>) -> Result<(), Error> {
+        let mut decoration = self.decoration.clone();
+        decoration.progress = progress.filter(|p| !p.is_nan()).map(|p| p.clamp(0.0, 1.0));
+        self.set_decoration(decoration)
+    }
+
+    /// Set the decoration if changed, kept only if it was set
+    fn set_decoration(&mut self, decoration: Decoration) -> Result<(), Error> {
+        if self.decoration == decoration {
+            return Ok(());
+        }
+        self.sys.set_decoration(decoration.clone())?;
+        self.decoration = decoration;
+        Ok(())
+    }
+
+    /// Set the menu without comparing it to the current menu
+    ///
+    /// For the event types without `PartialEq`, otherwise prefer `set_menu`.
+    pub fn replace_menu(&mut self, menu: &MenuBuilder<T>) -> Result<(), Error> {
+        if let Ok(mut toggled) = self.builder.toggled.lock() {
+            toggled.clear();
+        }
         self.builder.menu = Some(menu.clone());
         self.sys.set_menu(menu)
     }
 
     /// Set the tooltip if changed
+    ///
+    /// In Windows the tooltip is truncated to 127 UTF-16 characters.
     pub fn set_tooltip(&mut self, tooltip: &str) -> Result<(), Error> {
+        if self.tooltip_fn {
+            self.sys.set_tooltip_fn(None, Duration::default())?;
+            self.tooltip_fn = false;
+        }
         if self.builder.tooltip.as_deref() == Some(tooltip) {
             return Ok(());
         }
@@ -48,15 +189,223 @@ where
         self.sys.set_tooltip(tooltip)
     }
 
+    /// Set the tooltip from the function, and refresh it on the interval
+    ///
+    /// For readouts like "CPU 37% · RAM 62%", without a timer thread in the
+    /// app. The function is called on the thread of the tray icon, or on a
+    /// thread of its own on Linux, so keep it fast. Setting the tooltip with
+    /// `set_tooltip` stops the refreshing.
+    pub fn set_tooltip_fn<F>(&mut self, tooltip_fn: F, interval: Duration) -> Result<(), Error>
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        self.builder.tooltip = None;
+        self.tooltip_fn = true;
+        self.sys.set_tooltip_fn(
+            Some(TooltipFn(Arc::new(tooltip_fn))),
+            interval.max(MIN_TIMER_INTERVAL),
+        )
+    }
+
+    /// Show a balloon notification
+    ///
+    /// Without the `icon` the notification is shown without one. The `timeout`
+    /// is only a hint, since Windows Vista the accessibility settings decide
+    /// how long the notification is shown.
+    pub fn show_notification(
+        &mut self,
+        title: &str,
+        body: &str,
+        icon: Option<&Icon>,
+        timeout: Option<Duration>,
+    ) -> Result<(), Error> {
+        self.sys.show_notification(title, body, icon, timeout)
+    }
+
+    /// Show the menu at the tray icon
+    ///
+    /// E.g. from a global hotkey, or on left click. Does nothing without a
+    /// menu. Not supported on Linux, there the host shows the menu.
+    pub fn show_menu(&mut self) -> Result<(), Error> {
+        self.sys.show_menu(None)
+    }
+
+    /// Show the menu and return the event of the chosen item
+    ///
+    /// Blocks until the menu is closed, and the event is not sent to the
+    /// sender, e.g. for simple apps that only act on the menu. `None` if the
+    /// menu was dismissed. Only on Windows.
+    pub fn show_menu_sync(&mut self) -> Result<Option<T>, Error> {
+        self.sys.show_menu_sync(None)
+    }
+
+    /// Show the menu at the position
+    ///
+    /// Position is in physical pixels, same as the click positions.
+    pub fn show_menu_at(&mut self, position: PhysicalPosition) -> Result<(), Error> {
+        self.sys.show_menu(Some(position))
+    }
+
+    /// Hide or show the tray icon
+    ///
+    /// Hidden tray icon keeps its menu and events, e.g. for a "hide tray icon"
+    /// preference. On Linux the item is set passive, hosts usually hide
+    /// passive items.
+    pub fn set_visible(&mut self, visible: bool) -> Result<(), Error> {
+        self.sys.set_visible(visible)
+    }
+
+    /// Change the left click event, `None` ignores the clicks
+    ///
+    /// E.g. for disabling the click while a modal dialog is open.
+    pub fn set_on_click(&mut self, id: Option<T>) -> Result<(), Error> {
+        self.builder.on_click = id;
+        self.update_click_events()
+    }
+
+    /// Change the double click event, not sent on Linux
+    pub fn set_on_double_click(&mut self, id: Option<T>) -> Result<(), Error> {
+        self.builder.on_double_click = id;
+        self.update_click_events()
+    }
+
+    /// Change the right click event, not sent on Linux
+    pub fn set_on_right_click(&mut self, id: Option<T>) -> Result<(), Error> {
+        self.builder.on_right_click = id;
+        self.update_click_events()
+    }
+
+    fn update_click_events(&mut self) -> Result<(), Error> {
+        self.sys.set_click_events(
+            self.builder.on_click.clone(),
+            self.builder.on_double_click.clone(),
+            self.builder.on_right_click.clone(),
+        )
+    }
+
+    /// Handle for changing the tray icon from other threads
+    ///
+    /// The tray icon is moved to be run by the event loop of the current
+    /// thread, which must be the thread that built it.
+    pub fn into_handle(self) -> Result<TrayIconHandle<T>, Error>
+    where
+        T: Send,
+    {
+        TrayIconHandle::attached(self)
+    }
+
+    /// Screen rectangle of the tray icon
+    ///
+    /// Returns x, y, width and height in physical pixels, with the same origin
+    /// as the click positions. Useful for positioning a popup window next to
+    /// the tray icon. Not supported on Linux, StatusNotifierItem doesn't tell
+    /// where the host shows the icon.
+    pub fn rect(&self) -> Result<(i32, i32, u32, u32), Error> {
+        self.sys.rect()
+    }
+
+    /// Move the window next to the tray icon, e.g. the popup shown on the
+    /// `on_popup_open` event
+    ///
+    /// The `hwnd` is the `HWND` of the window, it's moved but not resized or
+    /// shown.
+    #[cfg(target_os = "windows")]
+    pub fn position_flyout(&self, hwnd: isize) -> Result<(), Error> {
+        self.sys.position_flyout(hwnd as _)
+    }
+
+    /// Icon is shown on the taskbar, not hidden in the overflow area
+    ///
+    /// E.g. for asking the user to pin the icon to the taskbar only when it's
+    /// in the overflow area. Hidden icon is not on the taskbar. The position
+    /// of the icon is compared to the notification area, so an error is
+    /// returned if there is no taskbar. Only on Windows.
+    #[cfg(target_os = "windows")]
+    pub fn is_visible_in_tray(&self) -> Result<bool, Error> {
+        self.sys.is_visible_in_tray()
+    }
+
+    /// Events of the menu items by their command identifiers, only on Windows
+    ///
+    /// Copy of the table used for the `WM_COMMAND` messages of the menu, e.g.
+    /// for checking that the commands of the app's own menus or accelerators
+    /// don't collide. Mapped items are numbered but not included, their
+    /// events are built when clicked. Menu built with `menu_with` is numbered
+    /// when it's shown.
+    #[cfg(target_os = "windows")]
+    pub fn menu_commands(&self) -> HashMap<MenuCommandId, T> {
+        self.sys.menu_commands()
+    }
+
+    /// Remove the tray icon, same as dropping it but the error is returned
+    ///
+    /// In Windows this can be called from any thread. The icon is removed
+    /// right away, and the hidden window is destroyed by the thread which
+    /// built it. If that thread doesn't process messages within a second, the
+    /// window is destroyed when it does.
+    pub fn shutdown(self) -> Result<(), Error> {
+        crate::shutdown_trayicon(self.sys)
+    }
+
+    /// `HWND` of the hidden window receiving the messages of the tray icon
+    ///
+    /// E.g. for subclassing the window to handle more messages, or for
+    /// registering it with other Win32 APIs. The window is destroyed when the
+    /// tray icon is dropped. Messages from `WM_USER + 1001` are used by the
+    /// tray icon.
+    #[cfg(target_os = "windows")]
+    pub fn hwnd(&self) -> isize {
+        self.sys.hwnd() as isize
+    }
+}
+
+/// Methods finding the menu items by the event
+impl<T> TrayIcon<T>
+where
+    T: PartialEq + Clone + 'static,
+{
+    /// Set the menu if changed
+    ///
+    /// This can be used reactively, each time the application state changes,
+    /// build a new menu and set it with this method. This way one can avoid
+    /// using more imperative `set_item_checkable`, `get_item_checkable` and
+    /// `set_item_disabled` methods.
+    pub fn set_menu(&mut self, menu: &MenuBuilder<T>) -> Result<(), Error> {
+        self.apply_toggled();
+        if self.builder.menu.as_ref() == Some(menu) {
+            return Ok(());
+        }
+        self.replace_menu(menu)
+    }
+
+    /// Mutate the current menu and set it if changed
+    ///
+    /// Useful with the `MenuItemId` for updating, removing or inserting
+    /// specific items.
+    pub fn update_menu<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut MenuBuilder<T>),
+    {
+        self.apply_toggled();
+        let mut menu = self.builder.menu.clone().unwrap_or_else(MenuBuilder::new);
+        f(&mut menu);
+        self.set_menu(&menu)
+    }
+
     /// Set disabled
     ///
     /// Prefer building a new menu if application state changes instead of
     /// mutating a menu with this method. Suggestion is to use just `set_menu`
     /// method instead of this.
+    ///
+    /// Menu item is updated in place, the menu is rebuilt only if that fails.
     pub fn set_menu_item_disabled(&mut self, id: T, disabled: bool) -> Result<(), Error> {
+        self.apply_toggled();
         if let Some(menu) = self.builder.menu.as_mut() {
-            let _ = menu.set_disabled(id, disabled);
-            let _ = self.sys.set_menu(menu);
+            menu.set_disabled(id.clone(), disabled)?;
+            if self.sys.set_menu_item_disabled(&id, disabled).is_err() {
+                self.sys.set_menu(menu)?;
+            }
         }
         Ok(())
     }
@@ -66,20 +415,57 @@ where
     /// Prefer building a new menu when application state changes instead of
     /// mutating a menu with this method.  Suggestion is to use just `set_menu`
     /// method instead of this.
+    ///
+    /// Menu item is updated in place, the menu is rebuilt only if that fails.
     pub fn set_menu_item_checkable(&mut self, id: T, checked: bool) -> Result<(), Error> {
+        self.apply_toggled();
         if let Some(menu) = self.builder.menu.as_mut() {
-            let _ = menu.set_checkable(id, checked);
-            let _ = self.sys.set_menu(menu);
+            menu.set_checkable(id.clone(), checked)?;
+            if self.sys.set_menu_item_checkable(&id, checked).is_err() {
+                self.sys.set_menu(menu)?;
+            }
         }
         Ok(())
     }
 
+    /// Set name
+    ///
+    /// Prefer building a new menu when application state changes instead of
+    /// mutating a menu with this method.  Suggestion is to use just `set_menu`
+    /// method instead of this.
+    ///
+    /// Menu item is updated in place, the menu is rebuilt only if that fails.
+    pub fn set_menu_item_name(&mut self, id: T, name: &str) -> Result<(), Error> {
+        self.apply_toggled();
+        if let Some(menu) = self.builder.menu.as_mut() {
+            menu.set_name(id.clone(), name)?;
+            if self.sys.set_menu_item_name(&id, name).is_err() {
+                self.sys.set_menu(menu)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the checkables toggled by the tray icon to the menu
+    fn apply_toggled(&mut self) {
+        let toggled = match self.builder.toggled.lock() {
+            Ok(mut toggled) => std::mem::take(&mut *toggled),
+            Err(_) => return,
+        };
+        if let Some(menu) = self.builder.menu.as_mut() {
+            for (id, checked) in toggled {
+                let _ = menu.set_checkable(id, checked);
+            }
+        }
+    }
+
     /// Get checkable state
     ///
     /// Prefer maintaining proper application state instead of getting checkable
     /// state with this method. Suggestion is to use just `set_menu` method
     /// instead of this.
     pub fn get_menu_item_checkable(&mut self, id: T) -> Option<bool> {
+        self.apply_toggled();
         if let Some(menu) = self.builder.menu.as_mut() {
             menu.get_checkable(id)
         } else {
@@ -88,6 +474,52 @@ where
     }
 }
 
-unsafe impl<T> Sync for TrayIcon<T> where T: PartialEq + Clone + 'static {}
+/// Methods of the mock backend, for testing how the app handles the events
+///
+/// Events are sent to the sender of the builder, the same as in Windows.
+#[cfg(feature = "mock")]
+impl<T> TrayIcon<T>
+where
+    T: PartialEq + Clone + 'static,
+{
+    /// Calls which reached the backend, in order
+    pub fn mock_calls(&self) -> &[crate::MockCall<T>] {
+        self.sys.calls()
+    }
+
+    /// Click with the button, right click and `menu_on_left_click` show the
+    /// menu
+    pub fn mock_click(&mut self, button: crate::MouseButton) {
+        self.sys.click(button, crate::Modifiers::default())
+    }
 
-unsafe impl<T> Send for TrayIcon<T> where T: PartialEq + Clone + 'static {}
+    /// Click with the modifier keys held, e.g. for testing Ctrl+click
+    pub fn mock_click_with_modifiers(
+        &mut self,
+        button: crate::MouseButton,
+        modifiers: crate::Modifiers,
+    ) {
+        self.sys.click(button, modifiers)
+    }
+
+    pub fn mock_double_click(&mut self) {
+        self.sys.double_click()
+    }
+
+    /// Choose the enabled menu item with the event
+    ///
+    /// The checkable is toggled with `auto_toggle_checkables`. Fails if there
+    /// is no such item in the menu.
+    pub fn mock_select(&mut self, id: &T) -> Result<(), Error> {
+        self.sys.select(id)
+    }
+
+    /// Message of the hidden window, returns true if `on_raw_message` handled
+    /// it
+    ///
+    /// A panic of the handler is reported to `on_error`.
+    #[cfg(target_os = "windows")]
+    pub fn mock_raw_message(&mut self, msg: u32, wparam: usize, lparam: isize) -> bool {
+        self.sys.raw_message(msg, wparam, lparam)
+    }
+}
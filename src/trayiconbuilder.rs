@@ -1,70 +1,255 @@
-use std::fmt::{Display, Formatter};
-use crate::{trayiconsender::TrayIconSender, Icon, MenuBuilder, TrayIcon};
-
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum Error {
-    MenuItemNotFound,
-    IconLoadingFailed,
-    SenderMissing,
-    IconMissing,
-    OsError,
+#[cfg(target_os = "windows")]
+use crate::singleinstance::{SecondInstanceFn, SingleInstance};
+#[cfg(target_os = "windows")]
+use crate::Modifiers;
+use crate::{
+    menubuilder::{MenuFn, MenuUpdater, Toggled},
+    trayiconsender::{TrayIconChannel, TrayIconSender},
+    ClickBehavior, Error, Icon, IconSet, MenuBuilder, NotifyIconVersion, SystemTheme, TrayIcon,
+    TrayIconEvent, TrayIconHandle,
+};
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Largest icon size, the maximum of .ico files
+const MAX_ICON_SIZE: u32 = 256;
+
+/// Function handling the messages of the hidden window
+#[cfg(target_os = "windows")]
+#[derive(Clone)]
+pub(crate) struct RawMessageFn<T>(Arc<dyn Fn(u32, usize, isize) -> Option<T> + Send + Sync>)
+where
+    T: Clone + 'static;
+
+#[cfg(target_os = "windows")]
+impl<T> RawMessageFn<T>
+where
+    T: Clone + 'static,
+{
+    pub(crate) fn call(&self, msg: u32, wparam: usize, lparam: isize) -> Option<T> {
+        (self.0)(msg, wparam, lparam)
+    }
 }
 
-// Why do I need to do this, can't Rust do this automatically?
-impl From<&Error> for Error {
-    fn from(e: &Error) -> Self {
-        *e
+#[cfg(target_os = "windows")]
+impl<T> Debug for RawMessageFn<T>
+where
+    T: Clone + 'static,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RawMessageFn")
     }
 }
 
-impl Display for Error {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self)
+/// Function receiving the errors which can't be returned
+#[cfg(target_os = "windows")]
+#[derive(Clone)]
+pub(crate) struct ErrorFn(Arc<dyn Fn(Error) + Send + Sync>);
+
+#[cfg(target_os = "windows")]
+impl ErrorFn {
+    pub(crate) fn call(&self, error: Error) {
+        (self.0)(error)
     }
 }
 
-impl std::error::Error for Error {
+#[cfg(target_os = "windows")]
+impl Debug for ErrorFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ErrorFn")
+    }
 }
 
 /// Tray Icon builder
 ///
-/// Start by choosing an event sender implementation. There are three different
+/// Start by choosing an event sender implementation. There are four different
 /// senders depending on the optional features. By default the sender function
 /// uses `std::sync::mpsc::Sender<T>`, additionally if `winit` feature is
 /// enabled you can choose to use `winit::event_loop::EventLoopProxy<T>` or with
 /// `crossbeam-channel` feature the `crossbeam_channel::Sender<T>` is available.
+/// With `tokio` feature the `tokio::sync::mpsc::UnboundedSender<T>` is
+/// available. Without a channel the `on_event` function can handle the events
+/// directly.
+///
+/// Alternatively the `sender_events` functions send `TrayIconEvent<T>` for all
+/// interactions, with the metadata such as the cursor position. Then the
+/// `on_click` and other user events are not needed.
 ///
 /// This is defined as consuming builder, this includes conditional helper
-/// `when` for composing conditionally some settings.
+/// `when` for composing conditionally some settings, and `apply` for setting
+/// them through a mutable reference.
 ///
 /// [Open full example with winit here 🢅](https://github.com/Ciantic/trayicon-rs/blob/master/examples/winit/src/main.rs)
 #[derive(Debug, Clone)]
 pub struct TrayIconBuilder<T>
 where
-    T: PartialEq + Clone + 'static,
+    T: Clone + 'static,
 {
     pub(crate) icon: Result<Icon, Error>,
+
+    /// Icons for the light and the dark theme, switched automatically
+    pub(crate) icon_light: Option<Icon>,
+    pub(crate) icon_dark: Option<Icon>,
     pub(crate) menu: Option<MenuBuilder<T>>,
+    pub(crate) menu_with: Option<MenuFn<T>>,
+    pub(crate) menu_updater: Option<MenuUpdater<T>>,
     pub(crate) tooltip: Option<String>,
+
+    /// Named states, and the current state, see `TrayIcon::set_state`
+    pub(crate) icon_set: Option<IconSet>,
+    pub(crate) state: Option<String>,
     pub(crate) on_click: Option<T>,
     pub(crate) on_double_click: Option<T>,
     pub(crate) on_right_click: Option<T>,
+    pub(crate) on_middle_click: Option<T>,
+    pub(crate) on_mouse_enter: Option<T>,
+    pub(crate) on_mouse_leave: Option<T>,
+    pub(crate) on_key_select: Option<T>,
+    pub(crate) on_taskbar_restarted: Option<T>,
+    pub(crate) on_menu_open: Option<T>,
+    pub(crate) on_menu_dismissed: Option<T>,
+    pub(crate) on_menu_hover: Option<T>,
+    pub(crate) on_notification_clicked: Option<T>,
+    pub(crate) on_notification_closed: Option<T>,
+    pub(crate) on_notification_hidden: Option<T>,
+    pub(crate) on_popup_open: Option<T>,
+    pub(crate) on_popup_close: Option<T>,
+    pub(crate) on_theme_changed: Option<T>,
+    pub(crate) on_clipboard_changed: Option<T>,
+    pub(crate) on_session_lock: Option<T>,
+    pub(crate) on_session_unlock: Option<T>,
+    pub(crate) on_suspend: Option<T>,
+    pub(crate) on_resume: Option<T>,
+    pub(crate) on_end_session: Option<T>,
+    pub(crate) on_display_changed: Option<T>,
+    pub(crate) on_restore_requested: Option<T>,
+    pub(crate) on_icon_added: Option<T>,
+    pub(crate) on_icon_add_failed: Option<T>,
+    pub(crate) on_tray_visibility_changed: Option<T>,
+    #[cfg(target_os = "windows")]
+    pub(crate) on_raw_message: Option<RawMessageFn<T>>,
+    #[cfg(target_os = "windows")]
+    pub(crate) on_error: Option<ErrorFn>,
+
+    /// Name of the single instance, and the event built from the arguments
+    #[cfg(target_os = "windows")]
+    pub(crate) on_second_instance: Option<(String, SecondInstanceFn<T>)>,
+
+    /// Window shown and hidden by the left click
+    #[cfg(target_os = "windows")]
+    pub(crate) toggle_window: Option<isize>,
+
+    /// Window hidden instead of minimized or closed
+    #[cfg(target_os = "windows")]
+    pub(crate) minimize_to_tray: Option<isize>,
+
+    /// Menu shown on the right click with the modifiers held
+    #[cfg(target_os = "windows")]
+    pub(crate) alt_menu: Option<MenuBuilder<T>>,
+    #[cfg(target_os = "windows")]
+    pub(crate) alt_menu_modifiers: Modifiers,
+
+    /// Hidden window is created as a message-only window
+    #[cfg(target_os = "windows")]
+    pub(crate) message_only_window: bool,
+
+    /// Class of the hidden window, and the identifier of the notify icon
+    #[cfg(target_os = "windows")]
+    pub(crate) class_name: Option<String>,
+    #[cfg(target_os = "windows")]
+    pub(crate) uid: Option<u32>,
+    pub(crate) guid: Option<u128>,
+    pub(crate) menu_on_left_click: bool,
+    pub(crate) rich_popup: bool,
+    pub(crate) notify_icon_version: NotifyIconVersion,
+    pub(crate) single_click_delay: Option<Duration>,
+    pub(crate) click_behavior: ClickBehavior,
+    pub(crate) menu_hotkeys: bool,
+    pub(crate) auto_toggle_checkables: bool,
+
+    /// Right-to-left layout, the locale of the user is used if none
+    pub(crate) rtl: Option<bool>,
+
+    /// Checkables toggled by the built tray icon
+    pub(crate) toggled: Toggled<T>,
     pub(crate) sender: Option<TrayIconSender<T>>,
 }
 
 impl<T> TrayIconBuilder<T>
 where
-    T: PartialEq + Clone + 'static,
+    T: Clone + 'static,
 {
     #[allow(clippy::new_without_default)]
     pub fn new() -> TrayIconBuilder<T> {
         TrayIconBuilder {
             icon: Err(Error::IconMissing),
+            icon_light: None,
+            icon_dark: None,
             menu: None,
+            menu_with: None,
+            menu_updater: None,
             tooltip: None,
+            icon_set: None,
+            state: None,
             on_click: None,
             on_double_click: None,
             on_right_click: None,
+            on_middle_click: None,
+            on_mouse_enter: None,
+            on_mouse_leave: None,
+            on_key_select: None,
+            on_taskbar_restarted: None,
+            on_menu_open: None,
+            on_menu_dismissed: None,
+            on_menu_hover: None,
+            on_notification_clicked: None,
+            on_notification_closed: None,
+            on_notification_hidden: None,
+            on_popup_open: None,
+            on_popup_close: None,
+            on_theme_changed: None,
+            on_clipboard_changed: None,
+            on_session_lock: None,
+            on_session_unlock: None,
+            on_suspend: None,
+            on_resume: None,
+            on_end_session: None,
+            on_display_changed: None,
+            on_restore_requested: None,
+            on_icon_added: None,
+            on_icon_add_failed: None,
+            on_tray_visibility_changed: None,
+            #[cfg(target_os = "windows")]
+            on_raw_message: None,
+            #[cfg(target_os = "windows")]
+            on_error: None,
+            #[cfg(target_os = "windows")]
+            on_second_instance: None,
+            #[cfg(target_os = "windows")]
+            toggle_window: None,
+            #[cfg(target_os = "windows")]
+            minimize_to_tray: None,
+            #[cfg(target_os = "windows")]
+            alt_menu: None,
+            #[cfg(target_os = "windows")]
+            alt_menu_modifiers: Modifiers::shift(),
+            #[cfg(target_os = "windows")]
+            message_only_window: false,
+            #[cfg(target_os = "windows")]
+            class_name: None,
+            #[cfg(target_os = "windows")]
+            uid: None,
+            guid: None,
+            menu_on_left_click: false,
+            rich_popup: false,
+            notify_icon_version: NotifyIconVersion::default(),
+            single_click_delay: None,
+            click_behavior: ClickBehavior::default(),
+            menu_hotkeys: false,
+            auto_toggle_checkables: false,
+            rtl: None,
+            toggled: Toggled::default(),
             sender: None,
         }
     }
@@ -77,25 +262,160 @@ where
         f(self)
     }
 
+    /// Use the consuming functions through a mutable reference
+    ///
+    /// E.g. for settings read from a config file,
+    /// `builder.apply(|b| b.tooltip(&config.tooltip))`.
+    pub fn apply<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnOnce(Self) -> Self,
+    {
+        let builder = std::mem::replace(self, TrayIconBuilder::new());
+        *self = f(builder);
+        self
+    }
+
     pub fn sender(mut self, s: std::sync::mpsc::Sender<T>) -> Self {
-        self.sender = Some(TrayIconSender::Std(s));
+        self.sender = Some(TrayIconSender::User(TrayIconChannel::Std(s)));
         self
     }
 
     /// Optional feature, requires `winit` feature
     #[cfg(feature = "winit")]
     pub fn sender_winit(mut self, s: winit::event_loop::EventLoopProxy<T>) -> Self {
-        self.sender = Some(TrayIconSender::Winit(s));
+        self.sender = Some(TrayIconSender::User(TrayIconChannel::Winit(s)));
+        self
+    }
+
+    /// Optional feature, requires `winit` feature
+    ///
+    /// The events are mapped to the user event type of the event loop, e.g.
+    /// when the tray events are wrapped in a variant of a larger enum.
+    #[cfg(feature = "winit")]
+    pub fn sender_winit_mapped<U, F>(
+        mut self,
+        s: winit::event_loop::EventLoopProxy<U>,
+        f: F,
+    ) -> Self
+    where
+        U: Send + 'static,
+        F: Fn(T) -> U + Send + Sync + 'static,
+    {
+        // Proxy is not `Sync` on all platforms
+        let s = std::sync::Mutex::new(s);
+        self.sender = Some(TrayIconSender::User(TrayIconChannel::Callback(Arc::new(
+            move |e| {
+                let e = f(e);
+                if let Ok(s) = s.lock() {
+                    let _ = s.send_event(e);
+                }
+            },
+        ))));
         self
     }
 
     /// Optional feature, requires `crossbeam-channel` feature
     #[cfg(feature = "crossbeam-channel")]
     pub fn sender_crossbeam(mut self, s: crossbeam_channel::Sender<T>) -> Self {
-        self.sender = Some(TrayIconSender::Crossbeam(s));
+        self.sender = Some(TrayIconSender::User(TrayIconChannel::Crossbeam(s)));
+        self
+    }
+
+    /// Optional feature, requires `tokio` feature
+    ///
+    /// Events can be awaited with `recv` in the async runtime, without a
+    /// thread in between.
+    #[cfg(feature = "tokio")]
+    pub fn sender_tokio(mut self, s: tokio::sync::mpsc::UnboundedSender<T>) -> Self {
+        self.sender = Some(TrayIconSender::User(TrayIconChannel::Tokio(s)));
+        self
+    }
+
+    /// Call the function with the events, without a channel
+    ///
+    /// The function is called in the thread that runs the tray icon, that is
+    /// the thread that built it on Windows and macOS.
+    pub fn on_event<F>(mut self, f: F) -> Self
+    where
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        self.sender = Some(TrayIconSender::User(TrayIconChannel::Callback(Arc::new(f))));
+        self
+    }
+
+    pub fn sender_events(mut self, s: std::sync::mpsc::Sender<TrayIconEvent<T>>) -> Self {
+        self.sender = Some(TrayIconSender::Events(TrayIconChannel::Std(s)));
+        self
+    }
+
+    /// Optional feature, requires `winit` feature
+    #[cfg(feature = "winit")]
+    pub fn sender_events_winit(
+        mut self,
+        s: winit::event_loop::EventLoopProxy<TrayIconEvent<T>>,
+    ) -> Self {
+        self.sender = Some(TrayIconSender::Events(TrayIconChannel::Winit(s)));
         self
     }
 
+    /// Optional feature, requires `winit` feature
+    #[cfg(feature = "winit")]
+    pub fn sender_events_winit_mapped<U, F>(
+        mut self,
+        s: winit::event_loop::EventLoopProxy<U>,
+        f: F,
+    ) -> Self
+    where
+        U: Send + 'static,
+        F: Fn(TrayIconEvent<T>) -> U + Send + Sync + 'static,
+    {
+        // Proxy is not `Sync` on all platforms
+        let s = std::sync::Mutex::new(s);
+        self.sender = Some(TrayIconSender::Events(TrayIconChannel::Callback(Arc::new(
+            move |e| {
+                let e = f(e);
+                if let Ok(s) = s.lock() {
+                    let _ = s.send_event(e);
+                }
+            },
+        ))));
+        self
+    }
+
+    /// Optional feature, requires `crossbeam-channel` feature
+    #[cfg(feature = "crossbeam-channel")]
+    pub fn sender_events_crossbeam(
+        mut self,
+        s: crossbeam_channel::Sender<TrayIconEvent<T>>,
+    ) -> Self {
+        self.sender = Some(TrayIconSender::Events(TrayIconChannel::Crossbeam(s)));
+        self
+    }
+
+    /// Optional feature, requires `tokio` feature
+    #[cfg(feature = "tokio")]
+    pub fn sender_events_tokio(
+        mut self,
+        s: tokio::sync::mpsc::UnboundedSender<TrayIconEvent<T>>,
+    ) -> Self {
+        self.sender = Some(TrayIconSender::Events(TrayIconChannel::Tokio(s)));
+        self
+    }
+
+    /// Call the function with the `TrayIconEvent`, without a channel
+    pub fn on_tray_icon_event<F>(mut self, f: F) -> Self
+    where
+        F: Fn(TrayIconEvent<T>) + Send + Sync + 'static,
+    {
+        self.sender = Some(TrayIconSender::Events(TrayIconChannel::Callback(Arc::new(
+            f,
+        ))));
+        self
+    }
+
+    /// Tooltip shown when hovering the tray icon
+    ///
+    /// In Windows the tooltip is truncated to 127 UTF-16 characters.
     pub fn tooltip(mut self, tooltip: &str) -> Self {
         self.tooltip = Some(tooltip.to_string());
         self
@@ -116,11 +436,481 @@ where
         self
     }
 
+    pub fn on_middle_click(mut self, id: T) -> Self {
+        self.on_middle_click = Some(id);
+        self
+    }
+
+    /// Mouse cursor moved over the tray icon
+    ///
+    /// Not sent on Linux, StatusNotifierItem has no hover events.
+    pub fn on_mouse_enter(mut self, id: T) -> Self {
+        self.on_mouse_enter = Some(id);
+        self
+    }
+
+    /// Mouse cursor moved away from the tray icon
+    ///
+    /// Not sent on Linux, StatusNotifierItem has no hover events.
+    pub fn on_mouse_leave(mut self, id: T) -> Self {
+        self.on_mouse_leave = Some(id);
+        self
+    }
+
+    /// Tray icon selected with keyboard, e.g. with Win+B and Enter
+    ///
+    /// Defaults to the `on_click` event, so that keyboard users can trigger
+    /// the same action as the mouse users.
+    pub fn on_key_select(mut self, id: T) -> Self {
+        self.on_key_select = Some(id);
+        self
+    }
+
+    /// Menu is about to be shown
+    ///
+    /// Sent before the lazily built menu is built, and before the menu set
+    /// with the `menu_updater` is applied, so the menu can be updated for
+    /// the current state. Not sent on Linux, there the host requests the
+    /// menu also when it's not shown.
+    pub fn on_menu_open(mut self, id: T) -> Self {
+        self.on_menu_open = Some(id);
+        self
+    }
+
+    /// Menu was closed without choosing an item
+    ///
+    /// E.g. for resuming the work paused by `on_menu_open`, when no item
+    /// event follows. Only sent on Windows.
+    pub fn on_menu_dismissed(mut self, id: T) -> Self {
+        self.on_menu_dismissed = Some(id);
+        self
+    }
+
+    /// Menu item was highlighted
+    ///
+    /// With `sender_events` the `TrayIconEvent::MenuHover` has the event of
+    /// the item and its description. Only sent on Windows.
+    pub fn on_menu_hover(mut self, id: T) -> Self {
+        self.on_menu_hover = Some(id);
+        self
+    }
+
+    /// Theme of the taskbar or the menu bar changed, see `system_theme`
+    ///
+    /// E.g. for switching between the light and dark icons. Not sent on
+    /// Linux.
+    pub fn on_theme_changed(mut self, id: T) -> Self {
+        self.on_theme_changed = Some(id);
+        self
+    }
+
+    /// Contents of the clipboard changed
+    ///
+    /// The hidden window of the tray icon listens to the clipboard only when
+    /// this is set, e.g. for clipboard managers. Only sent on Windows.
+    pub fn on_clipboard_changed(mut self, id: T) -> Self {
+        self.on_clipboard_changed = Some(id);
+        self
+    }
+
+    /// Workstation was locked, e.g. with Win+L
+    ///
+    /// E.g. for pausing the work while the user is away. Only sent on
+    /// Windows.
+    pub fn on_session_lock(mut self, id: T) -> Self {
+        self.on_session_lock = Some(id);
+        self
+    }
+
+    /// Workstation was unlocked, only sent on Windows
+    pub fn on_session_unlock(mut self, id: T) -> Self {
+        self.on_session_unlock = Some(id);
+        self
+    }
+
+    /// System is suspending
+    ///
+    /// Sent from the window procedure, so the work should be paused quickly
+    /// after receiving it. Only sent on Windows.
+    pub fn on_suspend(mut self, id: T) -> Self {
+        self.on_suspend = Some(id);
+        self
+    }
+
+    /// System resumed from the suspend, only sent on Windows
+    pub fn on_resume(mut self, id: T) -> Self {
+        self.on_resume = Some(id);
+        self
+    }
+
+    /// User is logging off or the system is shutting down
+    ///
+    /// The shutdown is not blocked, the state should be saved right away.
+    /// Only sent on Windows.
+    pub fn on_end_session(mut self, id: T) -> Self {
+        self.on_end_session = Some(id);
+        self
+    }
+
+    /// Resolution changed, or a monitor was added or removed
+    ///
+    /// E.g. for positioning the popup again with `TrayIcon::rect`. Only sent
+    /// on Windows.
+    pub fn on_display_changed(mut self, id: T) -> Self {
+        self.on_display_changed = Some(id);
+        self
+    }
+
+    /// Handle the messages of the hidden window before the tray icon
+    ///
+    /// Called with the message, `wparam` and `lparam`, e.g. for `WM_COPYDATA`
+    /// or a message registered with `RegisterWindowMessageW`. Returning an
+    /// event sends it and the message is not handled further, returning
+    /// `None` lets the tray icon handle the message. Only on Windows.
+    #[cfg(target_os = "windows")]
+    pub fn on_raw_message<F>(mut self, f: F) -> Self
+    where
+        F: Fn(u32, usize, isize) -> Option<T> + Send + Sync + 'static,
+    {
+        self.on_raw_message = Some(RawMessageFn(Arc::new(f)));
+        self
+    }
+
+    /// Called with the errors which can't be returned
+    ///
+    /// E.g. `Error::HandlerPanicked`, when the event handler or the sender
+    /// panics while the tray icon handles a message. Unwinding out of the
+    /// window procedure is undefined behavior, so the panic is caught and the
+    /// tray icon keeps running. Only on Windows.
+    #[cfg(target_os = "windows")]
+    pub fn on_error<F>(mut self, f: F) -> Self
+    where
+        F: Fn(Error) + Send + Sync + 'static,
+    {
+        self.on_error = Some(ErrorFn(Arc::new(f)));
+        self
+    }
+
+    /// Another instance forwarded its arguments with `SingleInstance::forward`
+    ///
+    /// The hidden window of the tray icon receives the arguments, the event
+    /// is built from them, e.g. for opening the files given to the later
+    /// instance. The instance should be the first one, and kept until the
+    /// app exits.
+    #[cfg(target_os = "windows")]
+    pub fn on_second_instance<F>(mut self, instance: &SingleInstance, f: F) -> Self
+    where
+        F: Fn(Vec<String>) -> T + Send + Sync + 'static,
+    {
+        self.on_second_instance =
+            Some((instance.name().to_string(), SecondInstanceFn(Arc::new(f))));
+        self
+    }
+
+    /// Left click shows the window in the foreground, or hides it if it's
+    /// shown
+    ///
+    /// For the "minimize to tray, click to restore" pattern, with the `HWND` of
+    /// the app window. Minimized window is restored. The shell lets the app
+    /// take the foreground only right after the click, so this is done before
+    /// the `on_click` event is handled. Use `single_click_delay` to keep the
+    /// double click from toggling it twice. Only on Windows.
+    #[cfg(target_os = "windows")]
+    pub fn toggle_window_on_click(mut self, hwnd: isize) -> Self {
+        self.toggle_window = Some(hwnd);
+        self
+    }
+
+    /// Hide the window to the tray instead of minimizing or closing it
+    ///
+    /// The window with the `HWND` is subclassed, so minimizing it or closing
+    /// it hides it. Clicking the tray icon restores it, and sends
+    /// `on_restore_requested`. Closing is blocked until the tray icon is
+    /// dropped, so e.g. winit doesn't send the close request, quit from the
+    /// menu of the tray icon instead. The tray icon must be built on the
+    /// thread of the window. Only on Windows.
+    #[cfg(target_os = "windows")]
+    pub fn minimize_to_tray(mut self, hwnd: isize) -> Self {
+        self.minimize_to_tray = Some(hwnd);
+        self
+    }
+
+    /// Create the hidden window as a message-only window
+    ///
+    /// Message-only window is not a top-level window, so it's not listed by
+    /// the tools enumerating the windows, and the hooks of e.g. alt-tab don't
+    /// see it. It doesn't receive the broadcasts either, so the restart of the
+    /// taskbar and the change of the theme are polled every second instead,
+    /// and `on_display_changed`, `on_suspend`, `on_resume` and
+    /// `on_end_session` are not sent. Only on Windows.
+    #[cfg(target_os = "windows")]
+    pub fn message_only_window(mut self, message_only: bool) -> Self {
+        self.message_only_window = message_only;
+        self
+    }
+
+    /// Class name of the hidden window, e.g. for finding it with `FindWindowW`
+    ///
+    /// By default the name is unique for each event type. The class is
+    /// registered with the window procedure of the event type, so building
+    /// tray icons with different event types and the same class name fails.
+    /// Only on Windows.
+    #[cfg(target_os = "windows")]
+    pub fn class_name(mut self, class_name: &str) -> Self {
+        self.class_name = Some(class_name.to_string());
+        self
+    }
+
+    /// Identifier of the notify icon, the `uID` of `NOTIFYICONDATAW`
+    ///
+    /// Other processes can target the icon by the window and this identifier.
+    /// By default a number unique in the process is picked. Only on Windows.
+    #[cfg(target_os = "windows")]
+    pub fn uid(mut self, uid: u32) -> Self {
+        self.uid = Some(uid);
+        self
+    }
+
+    /// Window hidden with `minimize_to_tray` was restored by clicking the
+    /// tray icon
+    ///
+    /// Only sent on Windows.
+    pub fn on_restore_requested(mut self, id: T) -> Self {
+        self.on_restore_requested = Some(id);
+        self
+    }
+
+    /// Taskbar was restarted, e.g. after explorer.exe crashed
+    ///
+    /// The tray icon is added again with its tooltip, visibility and the
+    /// notification that was still shown. Only sent on Windows.
+    pub fn on_taskbar_restarted(mut self, id: T) -> Self {
+        self.on_taskbar_restarted = Some(id);
+        self
+    }
+
+    /// Icon was added after all, adding it failed when the tray icon was built
+    ///
+    /// E.g. the app was started at the sign in before the taskbar existed.
+    /// Adding is retried for about half a minute, with a longer delay each
+    /// time. Not sent when the icon is added right away. Only sent on Windows.
+    pub fn on_icon_added(mut self, id: T) -> Self {
+        self.on_icon_added = Some(id);
+        self
+    }
+
+    /// Retrying to add the icon gave up, the icon is not shown
+    ///
+    /// The icon is added again when the taskbar is restarted, see
+    /// `on_taskbar_restarted`. Only sent on Windows.
+    pub fn on_icon_add_failed(mut self, id: T) -> Self {
+        self.on_icon_add_failed = Some(id);
+        self
+    }
+
+    /// User moved the icon between the taskbar and the overflow area
+    ///
+    /// The shell doesn't tell this, so it's polled every two seconds while
+    /// the event is set. See `TrayIcon::is_visible_in_tray` for the current
+    /// state. Only sent on Windows.
+    pub fn on_tray_visibility_changed(mut self, id: T) -> Self {
+        self.on_tray_visibility_changed = Some(id);
+        self
+    }
+
+    /// Notification shown with `show_notification` was clicked
+    ///
+    /// Not sent on Linux, notifications are not supported there.
+    pub fn on_notification_clicked(mut self, id: T) -> Self {
+        self.on_notification_clicked = Some(id);
+        self
+    }
+
+    /// Notification timed out, or the user closed it
+    ///
+    /// Only sent on Windows, from `NIN_BALLOONTIMEOUT`.
+    pub fn on_notification_closed(mut self, id: T) -> Self {
+        self.on_notification_closed = Some(id);
+        self
+    }
+
+    /// Notification was hidden without the user, e.g. the tray icon was
+    /// hidden or another notification replaced it
+    ///
+    /// Only sent on Windows, from `NIN_BALLOONHIDE`.
+    pub fn on_notification_hidden(mut self, id: T) -> Self {
+        self.on_notification_hidden = Some(id);
+        self
+    }
+
+    /// Show an own popup window instead of the tooltip, e.g. volume or
+    /// network style flyouts
+    ///
+    /// The tooltip is not shown, `on_popup_open` and `on_popup_close` are
+    /// sent instead, and `TrayIcon::position_flyout` moves the popup window
+    /// next to the tray icon. Only on Windows, with `NOTIFYICON_VERSION_4`.
+    /// Ignored under Wine and on ReactOS, their shells don't send the popup
+    /// messages, so the tooltip is shown instead.
+    pub fn rich_popup(mut self, rich_popup: bool) -> Self {
+        self.rich_popup = rich_popup;
+        self
+    }
+
+    /// Behavior of the notification icon in Windows, `V3` by default
+    ///
+    /// If the shell doesn't support the version, an older one is used, see
+    /// `capabilities`. The `rich_popup` always uses `V4`. Under Wine and on
+    /// ReactOS at most `V3` is used. Ignored on other platforms.
+    pub fn notify_icon_version(mut self, version: NotifyIconVersion) -> Self {
+        self.notify_icon_version = version;
+        self
+    }
+
+    /// Rich popup should be shown, see `rich_popup`
+    ///
+    /// Only sent on Windows.
+    pub fn on_popup_open(mut self, id: T) -> Self {
+        self.on_popup_open = Some(id);
+        self
+    }
+
+    /// Rich popup should be hidden, see `rich_popup`
+    ///
+    /// Only sent on Windows.
+    pub fn on_popup_close(mut self, id: T) -> Self {
+        self.on_popup_close = Some(id);
+        self
+    }
+
+    /// Stable identity of the tray icon, e.g. `0x8c1b6a2e_5d3f_4e7a_9b21_0c4d6e8f1a3b`
+    ///
+    /// In Windows this is the `guidItem` of the notification icon, so Windows
+    /// remembers if the user chose to always show the icon, even after the
+    /// restart. The GUID is tied to the path of the executable, unless it's
+    /// signed, and adding the icon fails if the same GUID is used from another
+    /// path. Ghost icon left with the GUID by a crashed instance is removed
+    /// when the icon is added. In macOS this is the autosave name of the
+    /// status item, which retains the position in the menu bar. Ignored on
+    /// Linux.
+    pub fn guid(mut self, guid: u128) -> Self {
+        self.guid = Some(guid);
+        self
+    }
+
+    /// Show the menu on left click too, not only on right click
+    ///
+    /// The `on_click` event is still sent. Ignored on Linux, there the host
+    /// decides how the menu is opened.
+    pub fn menu_on_left_click(mut self, menu_on_left_click: bool) -> Self {
+        self.menu_on_left_click = menu_on_left_click;
+        self
+    }
+
+    /// Delay the `on_click` event until a double click can't follow
+    ///
+    /// Same as `ClickBehavior::SingleWithDelay` with the given delay instead
+    /// of the double click time of the system. The menu shown with
+    /// `menu_on_left_click` is delayed too. Ignored on Linux, there double
+    /// clicks are not reported.
+    pub fn single_click_delay(mut self, delay: Duration) -> Self {
+        self.single_click_delay = Some(delay);
+        self.click_behavior = ClickBehavior::SingleWithDelay;
+        self
+    }
+
+    /// Which of the left clicks are sent, by default both single and double
+    ///
+    /// By default a double click sends `on_click` before `on_double_click`.
+    /// With `SingleWithDelay` the click waits for the double click time of
+    /// the system, e.g. for toggling a window only once on a double click.
+    /// Ignored on Linux, there double clicks are not reported.
+    pub fn click_behavior(mut self, click_behavior: ClickBehavior) -> Self {
+        self.click_behavior = click_behavior;
+        self
+    }
+
+    /// Register the accelerators of the menu items as global hotkeys
+    ///
+    /// The hotkey sends the event of the item also when the menu is closed,
+    /// see `MenuBuilder::item_with_accelerator`. Hotkeys taken by other apps
+    /// are skipped. Only on Windows.
+    pub fn menu_hotkeys(mut self, menu_hotkeys: bool) -> Self {
+        self.menu_hotkeys = menu_hotkeys;
+        self
+    }
+
+    /// Toggle the checkable menu items when they are clicked
+    ///
+    /// The check mark is updated in place, and the event is sent after it. In
+    /// the `sender_events` mode `TrayIconEvent::MenuItemToggled` with the new
+    /// state is sent instead of `MenuItem`. The menu of the `TrayIcon` is
+    /// updated too, so `set_menu` compares against the toggled state.
+    pub fn auto_toggle_checkables(mut self, auto_toggle_checkables: bool) -> Self {
+        self.auto_toggle_checkables = auto_toggle_checkables;
+        self
+    }
+
+    /// Lay out the menu and the tooltip right to left, e.g. for Arabic or Hebrew
+    ///
+    /// By default this follows the reading layout of the user's locale. Only
+    /// on Windows.
+    pub fn rtl(mut self, rtl: bool) -> Self {
+        self.rtl = Some(rtl);
+        self
+    }
+
     pub fn icon(mut self, icon: Icon) -> Self {
         self.icon = Ok(icon);
         self
     }
 
+    /// Icon shown with the light theme, usually a dark glyph
+    ///
+    /// The icon is switched when the theme of the taskbar or the menu bar
+    /// changes, see `system_theme`. Without `icon` this is the icon on Linux,
+    /// where the theme is not known. Setting the icon with `set_icon` stops
+    /// the switching.
+    pub fn icon_light(mut self, icon: Icon) -> Self {
+        if self.icon.is_err() {
+            self.icon = Ok(icon.clone());
+        }
+        self.icon_light = Some(icon);
+        self
+    }
+
+    /// Icon shown with the dark theme, usually a light glyph
+    ///
+    /// See `icon_light`.
+    pub fn icon_dark(mut self, icon: Icon) -> Self {
+        if self.icon.is_err() {
+            self.icon = Ok(icon.clone());
+        }
+        self.icon_dark = Some(icon);
+        self
+    }
+
+    /// Named states of the icon and the tooltip, starting in the state
+    ///
+    /// Switch the state with `TrayIcon::set_state`. Building fails with
+    /// `Error::StateNotFound` if the state is not in the set.
+    pub fn icon_set(mut self, icon_set: IconSet, state: &str) -> Self {
+        match icon_set.get(state) {
+            Some(initial) => {
+                self.icon = Ok(initial.icon.clone());
+                if initial.tooltip.is_some() {
+                    self.tooltip = initial.tooltip.clone();
+                }
+                self.state = Some(state.to_string());
+            }
+            None => {
+                self.icon = Err(Error::StateNotFound);
+                self.state = None;
+            }
+        }
+        self.icon_set = Some(icon_set);
+        self
+    }
+
     pub fn icon_from_buffer(mut self, buffer: &'static [u8]) -> Self {
         self.icon = Icon::from_buffer(buffer, None, None);
         self
@@ -128,13 +918,244 @@ where
 
     pub fn menu(mut self, menu: MenuBuilder<T>) -> Self
     where
-        T: PartialEq + Clone + 'static,
+        T: Clone + 'static,
     {
         self.menu = Some(menu);
         self
     }
 
-    pub fn build(self) -> Result<TrayIcon<T>, Error> {
+    /// Menu shown instead on Shift+right click, like the taskbar does
+    ///
+    /// E.g. for the debugging and advanced items, without cluttering the
+    /// menu. The events of the items are sent the same as from the menu, but
+    /// the checkables are not toggled by `auto_toggle_checkables`. The
+    /// keyboard opens the normal menu. Only on Windows.
+    #[cfg(target_os = "windows")]
+    pub fn alt_menu(mut self, menu: MenuBuilder<T>) -> Self {
+        self.alt_menu = Some(menu);
+        self
+    }
+
+    /// Modifier keys held for the `alt_menu`, Shift by default
+    #[cfg(target_os = "windows")]
+    pub fn alt_menu_modifiers(mut self, modifiers: Modifiers) -> Self {
+        self.alt_menu_modifiers = modifiers;
+        self
+    }
+
+    /// Build the menu each time it's opened, e.g. for a list of windows
+    ///
+    /// The function is called right before the menu is shown, in the thread
+    /// of the tray icon, and it replaces the menu set with `menu` or
+    /// `set_menu`. On Linux it's called when the host requests the menu.
+    pub fn menu_with<F>(mut self, f: F) -> Self
+    where
+        F: Fn() -> MenuBuilder<T> + Send + Sync + 'static,
+    {
+        self.menu_with = Some(MenuFn(Arc::new(f)));
+        self
+    }
+
+    /// Set the menu from any thread with the updater
+    ///
+    /// The menu is applied before the menu is shown, see `MenuUpdater`.
+    pub fn menu_updater(mut self, updater: MenuUpdater<T>) -> Self {
+        self.menu_updater = Some(updater);
+        self
+    }
+
+    /// Check the configuration, this is done by `build` too
+    ///
+    /// Events need a sender, the stable identifiers of the menu items must be
+    /// unique, and the icon must be at most 256x256.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.sender.is_none() {
+            let events = [
+                ("on_click", &self.on_click),
+                ("on_double_click", &self.on_double_click),
+                ("on_right_click", &self.on_right_click),
+                ("on_middle_click", &self.on_middle_click),
+                ("on_mouse_enter", &self.on_mouse_enter),
+                ("on_mouse_leave", &self.on_mouse_leave),
+                ("on_key_select", &self.on_key_select),
+                ("on_taskbar_restarted", &self.on_taskbar_restarted),
+                ("on_menu_open", &self.on_menu_open),
+                ("on_menu_dismissed", &self.on_menu_dismissed),
+                ("on_menu_hover", &self.on_menu_hover),
+                ("on_notification_clicked", &self.on_notification_clicked),
+                ("on_notification_closed", &self.on_notification_closed),
+                ("on_notification_hidden", &self.on_notification_hidden),
+                ("on_popup_open", &self.on_popup_open),
+                ("on_popup_close", &self.on_popup_close),
+                ("on_theme_changed", &self.on_theme_changed),
+                ("on_clipboard_changed", &self.on_clipboard_changed),
+                ("on_session_lock", &self.on_session_lock),
+                ("on_session_unlock", &self.on_session_unlock),
+                ("on_suspend", &self.on_suspend),
+                ("on_resume", &self.on_resume),
+                ("on_end_session", &self.on_end_session),
+                ("on_display_changed", &self.on_display_changed),
+                ("on_restore_requested", &self.on_restore_requested),
+                ("on_icon_added", &self.on_icon_added),
+                ("on_icon_add_failed", &self.on_icon_add_failed),
+                (
+                    "on_tray_visibility_changed",
+                    &self.on_tray_visibility_changed,
+                ),
+            ];
+            if let Some((event, _)) = events.iter().find(|(_, id)| id.is_some()) {
+                return Err(Error::EventWithoutSender(event));
+            }
+            #[cfg(target_os = "windows")]
+            if self.on_raw_message.is_some() {
+                return Err(Error::EventWithoutSender("on_raw_message"));
+            }
+            #[cfg(target_os = "windows")]
+            if self.on_second_instance.is_some() {
+                return Err(Error::EventWithoutSender("on_second_instance"));
+            }
+            if self.menu.is_some() || self.menu_with.is_some() {
+                return Err(Error::EventWithoutSender("menu"));
+            }
+            #[cfg(target_os = "windows")]
+            if self.alt_menu.is_some() {
+                return Err(Error::EventWithoutSender("alt_menu"));
+            }
+            return Err(Error::SenderMissing);
+        }
+        if let Some(menu) = &self.menu {
+            menu.validate()?;
+        }
+        #[cfg(target_os = "windows")]
+        if let Some(menu) = &self.alt_menu {
+            menu.validate()?;
+        }
+        let icon = self.icon.as_ref()?;
+        let icons = std::iter::once(icon)
+            .chain(self.icon_light.as_ref())
+            .chain(self.icon_dark.as_ref());
+        for icon in icons {
+            if let Some((width, height)) = icon.size() {
+                if width == 0 || height == 0 || width > MAX_ICON_SIZE || height > MAX_ICON_SIZE {
+                    return Err(Error::IconSizeUnsupported(width, height));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Icon for the current theme, or the icon if there is no variant
+    pub(crate) fn themed_icon(&self) -> Result<&Icon, Error> {
+        let variant = match crate::system_theme() {
+            Some(SystemTheme::Light) => self.icon_light.as_ref(),
+            Some(SystemTheme::Dark) => self.icon_dark.as_ref(),
+            None => None,
+        };
+        match variant {
+            Some(icon) => Ok(icon),
+            None => Ok(self.icon.as_ref()?),
+        }
+    }
+
+    #[cfg(not(all(target_os = "linux", not(feature = "mock"))))]
+    pub fn build(mut self) -> Result<TrayIcon<T>, Error> {
+        self.validate()?;
+
+        // Clones of the builder must not share the toggled checkables
+        self.toggled = Toggled::default();
         Ok(TrayIcon::new(crate::build_trayicon(&self)?, self))
     }
+
+    /// Events must be `Send` on Linux, the StatusNotifierItem service runs in
+    /// its own thread
+    #[cfg(all(target_os = "linux", not(feature = "mock")))]
+    pub fn build(mut self) -> Result<TrayIcon<T>, Error>
+    where
+        T: Send,
+    {
+        self.validate()?;
+
+        // Clones of the builder must not share the toggled checkables
+        self.toggled = Toggled::default();
+        Ok(TrayIcon::new(crate::build_trayicon(&self)?, self))
+    }
+
+    /// Where the tray icon records the toggled checkables, if enabled
+    pub(crate) fn toggled_checkables(&self) -> Option<Toggled<T>> {
+        if self.auto_toggle_checkables {
+            Some(self.toggled.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Build the tray icon on a dedicated thread
+    ///
+    /// The thread creates the tray icon and runs its event loop, so this can
+    /// be called from any thread, and the returned handle can be used from
+    /// any thread. Not supported on macOS, status items must be created in
+    /// the main thread.
+    pub fn build_detached(self) -> Result<TrayIconHandle<T>, Error>
+    where
+        T: Send,
+        Self: Send,
+    {
+        TrayIconHandle::detached(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum Events {
+        Click,
+        Item1,
+    }
+
+    #[test]
+    fn test_validate_sender() {
+        let builder = TrayIconBuilder::new().on_click(Events::Click);
+        assert_eq!(
+            builder.validate(),
+            Err(Error::EventWithoutSender("on_click"))
+        );
+
+        let builder = TrayIconBuilder::new().menu(MenuBuilder::new().item("Item 1", Events::Item1));
+        assert_eq!(builder.validate(), Err(Error::EventWithoutSender("menu")));
+
+        #[cfg(target_os = "windows")]
+        {
+            let builder = TrayIconBuilder::new().on_raw_message(|_, _, _| Some(Events::Click));
+            assert_eq!(
+                builder.validate(),
+                Err(Error::EventWithoutSender("on_raw_message"))
+            );
+
+            let builder =
+                TrayIconBuilder::new().alt_menu(MenuBuilder::new().item("Item 1", Events::Item1));
+            assert_eq!(
+                builder.validate(),
+                Err(Error::EventWithoutSender("alt_menu"))
+            );
+        }
+
+        let builder = TrayIconBuilder::<Events>::new();
+        assert_eq!(builder.validate(), Err(Error::SenderMissing));
+
+        let (s, _r) = std::sync::mpsc::channel();
+        let builder = TrayIconBuilder::new().sender(s).on_click(Events::Click);
+        assert_eq!(builder.validate(), Err(Error::IconMissing));
+    }
+
+    #[test]
+    fn test_icon_set_state_not_found() {
+        let (s, _r) = std::sync::mpsc::channel::<Events>();
+        let builder = TrayIconBuilder::new()
+            .sender(s)
+            .icon_set(IconSet::new(), "offline");
+        assert_eq!(builder.validate(), Err(Error::StateNotFound));
+        assert_eq!(builder.state, None);
+    }
 }
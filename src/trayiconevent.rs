@@ -0,0 +1,203 @@
+use crate::SystemTheme;
+
+/// Mouse button of the click event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+/// Which of the left clicks are sent, see `TrayIconBuilder::click_behavior`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ClickBehavior {
+    /// Single and double clicks, a double click is preceded by the single
+    /// click of its first press
+    #[default]
+    Both,
+
+    /// Only single clicks, a double click is sent as the second single click
+    SingleOnly,
+
+    /// Only double clicks, single clicks are ignored, including the menu of
+    /// `menu_on_left_click`
+    DoubleOnly,
+
+    /// Single click is sent when a double click can't follow anymore, after
+    /// the double click time of the system or the `single_click_delay`
+    SingleWithDelay,
+}
+
+/// Cursor position in physical screen coordinates
+///
+/// Origin is at the top left corner of the primary monitor. This can be used
+/// to position a popup window next to the tray icon.
+///
+/// On Linux the position is given by the StatusNotifierItem host, and some
+/// hosts always report zeros.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct PhysicalPosition {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// Modifier keys held during the click
+///
+/// E.g. Ctrl+click for an advanced menu. On Linux the host doesn't report
+/// them, so none are held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+
+    /// Windows key, or Command in macOS
+    pub meta: bool,
+}
+
+impl Modifiers {
+    /// Shift only, e.g. for `TrayIconBuilder::alt_menu_modifiers`
+    pub fn shift() -> Modifiers {
+        Modifiers {
+            shift: true,
+            ..Modifiers::default()
+        }
+    }
+
+    /// All of the keys of the other are held, others may be held too
+    pub fn contains(&self, other: Modifiers) -> bool {
+        (self.shift || !other.shift)
+            && (self.ctrl || !other.ctrl)
+            && (self.alt || !other.alt)
+            && (self.meta || !other.meta)
+    }
+}
+
+/// Tray icon event
+///
+/// Sent instead of the user events when the tray icon is built with one of the
+/// `sender_events` functions. Menu items still use the user event type `T`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrayIconEvent<T>
+where
+    T: Clone + 'static,
+{
+    Click {
+        button: MouseButton,
+        position: PhysicalPosition,
+        modifiers: Modifiers,
+    },
+    DoubleClick {
+        position: PhysicalPosition,
+        modifiers: Modifiers,
+    },
+    MenuItem(T),
+
+    /// Checkable menu item was toggled, with the new checked state
+    ///
+    /// Sent instead of `MenuItem` when `auto_toggle_checkables` is enabled.
+    MenuItemToggled(T, bool),
+
+    /// Notification shown with `show_notification` was clicked
+    ///
+    /// Not sent on Linux, notifications are not supported there.
+    BalloonClicked,
+
+    /// Notification timed out, or the user closed it, only sent on Windows
+    BalloonClosed,
+
+    /// Notification was hidden without the user, only sent on Windows
+    BalloonHidden,
+
+    /// Not sent on Linux, StatusNotifierItem has no hover events
+    MouseEnter,
+
+    /// Not sent on Linux, StatusNotifierItem has no hover events
+    MouseLeave,
+
+    /// Tray icon selected with keyboard, only sent on Windows
+    KeySelect,
+
+    /// Menu is about to be shown, not sent on Linux
+    MenuOpen,
+
+    /// Menu was closed without choosing an item, only sent on Windows
+    MenuDismissed,
+
+    /// Menu item was highlighted, with the description of the item
+    ///
+    /// Only sent on Windows, see `MenuBuilder::description` and
+    /// `TrayIconBuilder::on_menu_hover`.
+    MenuHover(T, Option<String>),
+
+    /// Rich popup should be shown at the position, only sent on Windows with
+    /// `rich_popup`
+    PopupOpen {
+        position: PhysicalPosition,
+    },
+
+    /// Rich popup should be hidden, only sent on Windows with `rich_popup`
+    PopupClose,
+
+    /// Theme of the taskbar or the menu bar changed, not sent on Linux
+    ThemeChanged(SystemTheme),
+
+    /// Contents of the clipboard changed, only sent on Windows with
+    /// `on_clipboard_changed`
+    ClipboardChanged,
+
+    /// Workstation was locked, only sent on Windows
+    SessionLock,
+
+    /// Workstation was unlocked, only sent on Windows
+    SessionUnlock,
+
+    /// System is suspending, only sent on Windows
+    Suspend,
+
+    /// System resumed from the suspend, only sent on Windows
+    Resume,
+
+    /// User is logging off or the system is shutting down, only sent on
+    /// Windows
+    EndSession,
+
+    /// Resolution changed, or a monitor was added or removed, only sent on
+    /// Windows
+    DisplayChanged,
+
+    /// Event returned by `on_raw_message`, only sent on Windows
+    RawMessage(T),
+
+    /// Another instance forwarded its arguments, only sent on Windows with
+    /// `on_second_instance`
+    SecondInstance(Vec<String>),
+
+    /// Taskbar was restarted and the tray icon was added again, only sent on
+    /// Windows
+    TaskbarRestarted,
+
+    /// Window hidden to the tray was restored by clicking the tray icon, only
+    /// sent on Windows with `minimize_to_tray`
+    RestoreRequested,
+
+    /// Icon was added by a retry, after adding it failed when built, only
+    /// sent on Windows
+    IconAdded,
+
+    /// Icon couldn't be added when built or after the taskbar restarted, and
+    /// retrying gave up, only sent on Windows
+    IconAddFailed,
+
+    /// Submenu with `MenuBuilder::on_submenu_open` was opened, only sent on
+    /// Windows
+    SubmenuOpen(T),
+
+    /// Submenu with `MenuBuilder::on_submenu_close` was closed, only sent on
+    /// Windows
+    SubmenuClose(T),
+
+    /// Icon was moved to the taskbar, true, or to the overflow area, false,
+    /// only sent on Windows with `on_tray_visibility_changed`
+    TrayVisibilityChanged(bool),
+}
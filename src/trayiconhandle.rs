@@ -0,0 +1,530 @@
+use crate::{Error, Icon, MenuBuilder, TrayIcon, TrayIconBuilder};
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex, TryLockError, Weak};
+use std::thread::ThreadId;
+use std::time::Duration;
+
+/// Wakes the thread of the tray icon to run the handler
+pub(crate) type Waker = Box<dyn Fn() + Send + Sync>;
+
+/// Runs the commands, returns false when all of the handles are dropped
+pub(crate) type Handler = Box<dyn FnMut() -> bool + Send>;
+
+/// Waker of the handler, and the thread running it
+pub(crate) type Attached = (Waker, ThreadId);
+
+/// Command run on the thread of the tray icon
+type Command<T> = Box<dyn FnOnce(&mut TrayIcon<T>) + Send>;
+
+/// Handles of the process by their ids, in the order of creation
+static INSTANCES: Mutex<Vec<(u64, Weak<dyn Control>)>> = Mutex::new(Vec::new());
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Thread-safe handle to a tray icon
+///
+/// Created with `TrayIconBuilder::build_detached` or `TrayIcon::into_handle`.
+/// The methods send the changes to the thread of the tray icon, and wait for
+/// the result. In Windows the changes are posted as messages to a window of
+/// that thread, so the thread must be running the event loop. Tray icon is
+/// dropped in its thread when all of the clones of the handle are dropped,
+/// or when it's removed. Calls from the thread of the tray icon, e.g. from
+/// the `on_event` callbacks, are run right away instead.
+pub struct TrayIconHandle<T>
+where
+    T: Clone + Send + 'static,
+{
+    inner: Arc<Inner<T>>,
+}
+
+struct Inner<T>
+where
+    T: Clone + Send + 'static,
+{
+    id: u64,
+    commands: Option<Sender<Command<T>>>,
+    wake: Waker,
+
+    /// Handler stops when set, dropping the tray icon
+    removed: Arc<AtomicBool>,
+
+    /// Same as the handler has, the calls from its thread are run with it
+    runner: Arc<Mutex<Runner<T>>>,
+
+    /// Thread running the handler
+    thread: ThreadId,
+}
+
+/// Tray icon and the commands for it, locked by the thread running them
+struct Runner<T>
+where
+    T: Clone + Send + 'static,
+{
+    /// Built in the thread of the handler, and dropped there when it stops
+    tray_icon: Option<Owned<T>>,
+    receiver: Receiver<Command<T>>,
+    removed: Arc<AtomicBool>,
+}
+
+/// Tray icon used only by the thread running the handler
+///
+/// Windows and status items belong to the thread which created them. In
+/// Windows and macOS the handler runs in that thread, it's either attached to
+/// the event loop of the thread or it built the tray icon. Other threads only
+/// send the commands, and `Inner::call` locks the runner only in the thread
+/// of the handler.
+struct Owned<T>(TrayIcon<T>)
+where
+    T: Clone + 'static;
+
+unsafe impl<T> Send for Owned<T> where T: Clone + Send + 'static {}
+
+impl<T> TrayIconHandle<T>
+where
+    T: Clone + Send + 'static,
+{
+    /// Build the tray icon on a dedicated thread
+    pub(crate) fn detached(builder: TrayIconBuilder<T>) -> Result<TrayIconHandle<T>, Error>
+    where
+        TrayIconBuilder<T>: Send,
+    {
+        let (commands, runner) = Runner::new(None);
+        let handler_runner = runner.clone();
+        let attached = crate::spawn_detached(move || {
+            handler_runner.lock().unwrap().tray_icon = Some(Owned(builder.build()?));
+            Ok(handler(handler_runner))
+        })?;
+        Ok(TrayIconHandle::new(commands, runner, attached))
+    }
+
+    /// Move the tray icon to be run by the event loop of the current thread
+    pub(crate) fn attached(tray_icon: TrayIcon<T>) -> Result<TrayIconHandle<T>, Error> {
+        let (commands, runner) = Runner::new(Some(Owned(tray_icon)));
+        let attached = crate::attach_handler(handler(runner.clone()))?;
+        Ok(TrayIconHandle::new(commands, runner, attached))
+    }
+
+    /// Registered to be found by `instances`
+    fn new(
+        commands: Sender<Command<T>>,
+        runner: Arc<Mutex<Runner<T>>>,
+        (wake, thread): Attached,
+    ) -> TrayIconHandle<T> {
+        let removed = runner.lock().unwrap().removed.clone();
+        let inner = Arc::new(Inner {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            commands: Some(commands),
+            wake,
+            removed,
+            runner,
+            thread,
+        });
+        let weak: Weak<dyn Control> = Arc::downgrade(&inner) as _;
+        let mut instances = INSTANCES.lock().unwrap();
+        instances.retain(|(_, instance)| instance.strong_count() > 0);
+        instances.push((inner.id, weak));
+        TrayIconHandle { inner }
+    }
+
+    /// Id of the tray icon, unique in the process
+    pub fn id(&self) -> u64 {
+        self.inner.id
+    }
+
+    /// Remove the tray icon, even if there are other clones of the handle
+    ///
+    /// The methods of the handles return an error afterwards.
+    pub fn remove(&self) {
+        self.inner.remove();
+    }
+
+    /// Run the method on the thread of the tray icon and wait for the result
+    fn call<F>(&self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut TrayIcon<T>) -> Result<(), Error> + Send + 'static,
+    {
+        self.inner.call(f)
+    }
+
+    /// Set the icon if changed
+    pub fn set_icon(&self, icon: &Icon) -> Result<(), Error> {
+        let icon = icon.clone();
+        self.call(move |tray_icon| tray_icon.set_icon(&icon))
+    }
+
+    /// Set the menu
+    pub fn set_menu(&self, menu: &MenuBuilder<T>) -> Result<(), Error> {
+        let menu = menu.clone();
+        self.call(move |tray_icon| tray_icon.replace_menu(&menu))
+    }
+
+    /// Set the tooltip if changed
+    pub fn set_tooltip(&self, tooltip: &str) -> Result<(), Error> {
+        let tooltip = tooltip.to_string();
+        self.call(move |tray_icon| tray_icon.set_tooltip(&tooltip))
+    }
+
+    /// Hide or show the tray icon
+    pub fn set_visible(&self, visible: bool) -> Result<(), Error> {
+        self.call(move |tray_icon| tray_icon.set_visible(visible))
+    }
+
+    /// Show a balloon notification
+    pub fn show_notification(
+        &self,
+        title: &str,
+        body: &str,
+        icon: Option<&Icon>,
+        timeout: Option<Duration>,
+    ) -> Result<(), Error> {
+        let title = title.to_string();
+        let body = body.to_string();
+        let icon = icon.cloned();
+        self.call(move |tray_icon| {
+            tray_icon.show_notification(&title, &body, icon.as_ref(), timeout)
+        })
+    }
+}
+
+/// Runs the commands when woken, until all of the handles are dropped or the
+/// tray icon is removed
+///
+/// Runner is locked by the same thread only while it runs a call inline, the
+/// commands are run by that call then.
+fn handler<T>(runner: Arc<Mutex<Runner<T>>>) -> Handler
+where
+    T: Clone + Send + 'static,
+{
+    Box::new(move || match runner.try_lock() {
+        Ok(mut runner) => runner.run(),
+        Err(TryLockError::WouldBlock) => true,
+        Err(TryLockError::Poisoned(runner)) => {
+            runner.into_inner().tray_icon = None;
+            false
+        }
+    })
+}
+
+impl<T> Runner<T>
+where
+    T: Clone + Send + 'static,
+{
+    fn new(tray_icon: Option<Owned<T>>) -> (Sender<Command<T>>, Arc<Mutex<Runner<T>>>) {
+        let (commands, receiver) = channel::<Command<T>>();
+        let runner = Runner {
+            tray_icon,
+            receiver,
+            removed: Arc::new(AtomicBool::new(false)),
+        };
+        (commands, Arc::new(Mutex::new(runner)))
+    }
+
+    /// Run the pending commands, returns false when the tray icon is dropped
+    fn run(&mut self) -> bool {
+        loop {
+            if self.removed.load(Ordering::SeqCst) {
+                self.tray_icon = None;
+            }
+            let tray_icon = match self.tray_icon.as_mut() {
+                Some(tray_icon) => tray_icon,
+                None => return false,
+            };
+            match self.receiver.try_recv() {
+                Ok(command) => command(&mut tray_icon.0),
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => self.tray_icon = None,
+            }
+        }
+    }
+}
+
+impl<T> Inner<T>
+where
+    T: Clone + Send + 'static,
+{
+    /// Run the method on the thread of the tray icon and wait for the result
+    fn call<F>(&self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut TrayIcon<T>) -> Result<(), Error> + Send + 'static,
+    {
+        if self.removed.load(Ordering::SeqCst) {
+            return Err(Error::TrayIconStopped);
+        }
+
+        // Waiting in the thread of the tray icon would never end
+        let own_thread = std::thread::current().id() == self.thread;
+        if own_thread {
+            match self.runner.try_lock() {
+                Ok(mut runner) => {
+                    if !runner.run() {
+                        return Err(Error::TrayIconStopped);
+                    }
+                    let tray_icon = runner.tray_icon.as_mut().ok_or(Error::TrayIconStopped)?;
+                    return f(&mut tray_icon.0);
+                }
+                Err(TryLockError::Poisoned(_)) => return Err(Error::TrayIconStopped),
+
+                // Called while a command runs, e.g. by the events of a menu
+                // it shows, the call is run after it without the result
+                Err(TryLockError::WouldBlock) => {}
+            }
+        }
+
+        let (result, receiver) = channel();
+        let command: Command<T> = Box::new(move |tray_icon| {
+            let _ = result.send(f(tray_icon));
+        });
+        self.commands
+            .as_ref()
+            .ok_or(Error::TrayIconStopped)?
+            .send(command)
+            .map_err(|_| Error::TrayIconStopped)?;
+        (self.wake)();
+        if own_thread {
+            return Ok(());
+        }
+        receiver.recv().map_err(|_| Error::TrayIconStopped)?
+    }
+
+    fn remove(&self) {
+        self.removed.store(true, Ordering::SeqCst);
+        (self.wake)();
+    }
+}
+
+/// Methods of the handles without the type of the events
+trait Control: Send + Sync {
+    fn id(&self) -> u64;
+    fn is_removed(&self) -> bool;
+    fn remove(&self);
+    fn set_icon(&self, icon: Icon) -> Result<(), Error>;
+    fn set_tooltip(&self, tooltip: String) -> Result<(), Error>;
+    fn set_visible(&self, visible: bool) -> Result<(), Error>;
+    fn show_notification(
+        &self,
+        title: String,
+        body: String,
+        icon: Option<Icon>,
+        timeout: Option<Duration>,
+    ) -> Result<(), Error>;
+}
+
+impl<T> Control for Inner<T>
+where
+    T: Clone + Send + 'static,
+{
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn is_removed(&self) -> bool {
+        self.removed.load(Ordering::SeqCst)
+    }
+
+    fn remove(&self) {
+        Inner::remove(self)
+    }
+
+    fn set_icon(&self, icon: Icon) -> Result<(), Error> {
+        self.call(move |tray_icon| tray_icon.set_icon(&icon))
+    }
+
+    fn set_tooltip(&self, tooltip: String) -> Result<(), Error> {
+        self.call(move |tray_icon| tray_icon.set_tooltip(&tooltip))
+    }
+
+    fn set_visible(&self, visible: bool) -> Result<(), Error> {
+        self.call(move |tray_icon| tray_icon.set_visible(visible))
+    }
+
+    fn show_notification(
+        &self,
+        title: String,
+        body: String,
+        icon: Option<Icon>,
+        timeout: Option<Duration>,
+    ) -> Result<(), Error> {
+        self.call(move |tray_icon| {
+            tray_icon.show_notification(&title, &body, icon.as_ref(), timeout)
+        })
+    }
+}
+
+/// Handles of the live tray icons of the process, the oldest first
+///
+/// For a central controller, e.g. a plugin host or a test harness, changing
+/// the tray icons created by others. Only the tray icons run by a
+/// `TrayIconHandle` are included, the others belong to their threads. The
+/// returned handles keep the tray icons alive like the clones of the handles,
+/// until they are dropped or the tray icons are removed.
+pub fn instances() -> Vec<TrayIconInstance> {
+    INSTANCES
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|(_, instance)| instance.upgrade())
+        .filter(|instance| !instance.is_removed())
+        .map(|inner| TrayIconInstance { inner })
+        .collect()
+}
+
+/// Handle to a tray icon found with `instances`
+///
+/// Same as `TrayIconHandle` without the type of the events, so the menu
+/// can't be changed.
+#[derive(Clone)]
+pub struct TrayIconInstance {
+    inner: Arc<dyn Control>,
+}
+
+impl TrayIconInstance {
+    /// Same as `TrayIconHandle::id`
+    pub fn id(&self) -> u64 {
+        self.inner.id()
+    }
+
+    /// Remove the tray icon, the handles return an error afterwards
+    pub fn remove(&self) {
+        self.inner.remove();
+    }
+
+    /// Set the icon if changed
+    pub fn set_icon(&self, icon: &Icon) -> Result<(), Error> {
+        self.inner.set_icon(icon.clone())
+    }
+
+    /// Set the tooltip if changed
+    pub fn set_tooltip(&self, tooltip: &str) -> Result<(), Error> {
+        self.inner.set_tooltip(tooltip.to_string())
+    }
+
+    /// Hide or show the tray icon
+    pub fn set_visible(&self, visible: bool) -> Result<(), Error> {
+        self.inner.set_visible(visible)
+    }
+
+    /// Show a balloon notification
+    pub fn show_notification(
+        &self,
+        title: &str,
+        body: &str,
+        icon: Option<&Icon>,
+        timeout: Option<Duration>,
+    ) -> Result<(), Error> {
+        self.inner
+            .show_notification(title.to_string(), body.to_string(), icon.cloned(), timeout)
+    }
+}
+
+impl Debug for TrayIconInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TrayIconInstance({})", self.id())
+    }
+}
+
+impl<T> Clone for TrayIconHandle<T>
+where
+    T: Clone + Send + 'static,
+{
+    fn clone(&self) -> Self {
+        TrayIconHandle {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Debug for TrayIconHandle<T>
+where
+    T: Clone + Send + 'static,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TrayIconHandle({})", self.id())
+    }
+}
+
+impl<T> Drop for Inner<T>
+where
+    T: Clone + Send + 'static,
+{
+    /// Disconnected commands stop the thread
+    fn drop(&mut self) {
+        self.commands.take();
+        (self.wake)();
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instances() {
+        let build = || {
+            let (s, _r) = channel::<()>();
+            TrayIconBuilder::new()
+                .sender(s)
+                .icon_from_buffer(&[0])
+                .build()
+                .unwrap()
+                .into_handle()
+                .unwrap()
+        };
+        let first = build();
+        let second = build();
+        let ids = |ids: &[u64]| {
+            instances()
+                .iter()
+                .map(TrayIconInstance::id)
+                .filter(|id| ids.contains(id))
+                .collect::<Vec<_>>()
+        };
+        let both = [first.id(), second.id()];
+        assert_eq!(ids(&both), both);
+
+        let instance = instances()
+            .into_iter()
+            .find(|instance| instance.id() == second.id())
+            .unwrap();
+        instance.set_tooltip("Tooltip").unwrap();
+        instance.remove();
+        assert!(second.set_tooltip("Tooltip").is_err());
+        assert_eq!(ids(&both), [first.id()]);
+
+        drop(first);
+        assert_eq!(ids(&both), []);
+    }
+
+    #[test]
+    fn test_call_from_own_thread() {
+        let (s, _r) = channel::<()>();
+        let tray_icon = TrayIconBuilder::new()
+            .sender(s)
+            .icon_from_buffer(&[0])
+            .build()
+            .unwrap();
+
+        // Handler would run in this thread, so waking it can't help
+        let woken = Arc::new(AtomicBool::new(false));
+        let wake_woken = woken.clone();
+        let (commands, runner) = Runner::new(Some(Owned(tray_icon)));
+        let wake: Waker = Box::new(move || wake_woken.store(true, Ordering::SeqCst));
+        let handle = TrayIconHandle::new(commands, runner, (wake, std::thread::current().id()));
+
+        handle.set_tooltip("Tooltip").unwrap();
+        handle
+            .call(|tray_icon| {
+                assert_eq!(
+                    tray_icon.mock_calls(),
+                    &[crate::MockCall::SetTooltip("Tooltip".to_string())]
+                );
+                Ok(())
+            })
+            .unwrap();
+        assert!(!woken.load(Ordering::SeqCst));
+
+        handle.remove();
+        assert_eq!(handle.set_visible(false), Err(Error::TrayIconStopped));
+    }
+}
@@ -1,35 +1,107 @@
-/// Tray Icon event sender
-#[derive(Debug, Clone)]
-pub enum TrayIconSender<T>
+use crate::TrayIconEvent;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// Channel for sending the events
+#[derive(Clone)]
+pub enum TrayIconChannel<E>
 where
-    T: PartialEq + Clone + 'static,
+    E: 'static,
 {
-    Std(std::sync::mpsc::Sender<T>),
+    Std(std::sync::mpsc::Sender<E>),
 
     #[cfg(feature = "winit")]
-    Winit(winit::event_loop::EventLoopProxy<T>),
+    Winit(winit::event_loop::EventLoopProxy<E>),
 
     #[cfg(feature = "crossbeam-channel")]
-    Crossbeam(crossbeam_channel::Sender<T>),
+    Crossbeam(crossbeam_channel::Sender<E>),
+
+    #[cfg(feature = "tokio")]
+    Tokio(tokio::sync::mpsc::UnboundedSender<E>),
+
+    Callback(Arc<dyn Fn(E) + Send + Sync>),
 }
 
-impl<T> TrayIconSender<T>
+impl<E> Debug for TrayIconChannel<E>
 where
-    T: PartialEq + Clone + 'static,
+    E: 'static,
 {
-    pub fn send(&self, e: &T) {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            TrayIconSender::Std(s) => {
-                let _ = s.send(e.clone());
+            TrayIconChannel::Std(s) => f.debug_tuple("Std").field(s).finish(),
+            #[cfg(feature = "winit")]
+            TrayIconChannel::Winit(s) => f.debug_tuple("Winit").field(s).finish(),
+            #[cfg(feature = "crossbeam-channel")]
+            TrayIconChannel::Crossbeam(s) => f.debug_tuple("Crossbeam").field(s).finish(),
+            #[cfg(feature = "tokio")]
+            TrayIconChannel::Tokio(s) => f.debug_tuple("Tokio").field(s).finish(),
+            TrayIconChannel::Callback(_) => f.write_str("Callback"),
+        }
+    }
+}
+
+impl<E> TrayIconChannel<E>
+where
+    E: 'static,
+{
+    fn send(&self, e: E) {
+        match self {
+            TrayIconChannel::Std(s) => {
+                let _ = s.send(e);
             }
             #[cfg(feature = "winit")]
-            TrayIconSender::Winit(s) => {
-                let _ = s.send_event(e.clone());
+            TrayIconChannel::Winit(s) => {
+                let _ = s.send_event(e);
             }
             #[cfg(feature = "crossbeam-channel")]
-            TrayIconSender::Crossbeam(s) => {
-                let _ = s.try_send(e.clone());
+            TrayIconChannel::Crossbeam(s) => {
+                let _ = s.try_send(e);
+            }
+            #[cfg(feature = "tokio")]
+            TrayIconChannel::Tokio(s) => {
+                let _ = s.send(e);
             }
+            TrayIconChannel::Callback(f) => f(e),
         }
     }
 }
+
+/// Tray Icon event sender
+///
+/// Sends either the user events given in the builder, or the `TrayIconEvent`
+/// with the metadata of the interaction.
+#[derive(Debug, Clone)]
+pub enum TrayIconSender<T>
+where
+    T: Clone + 'static,
+{
+    User(TrayIconChannel<T>),
+    Events(TrayIconChannel<TrayIconEvent<T>>),
+}
+
+impl<T> TrayIconSender<T>
+where
+    T: Clone + 'static,
+{
+    /// Send the user event if given, or the tray icon event
+    pub fn send(&self, user_event: Option<&T>, event: TrayIconEvent<T>) {
+        match self {
+            TrayIconSender::User(s) => {
+                if let Some(e) = user_event {
+                    s.send(e.clone());
+                }
+            }
+            TrayIconSender::Events(s) => s.send(event),
+        }
+    }
+
+    /// Send the menu item event
+    pub fn send_menu_item(&self, e: &T) {
+        self.send(Some(e), TrayIconEvent::MenuItem(e.clone()));
+    }
+
+    /// Send the event of the checkable toggled by the tray icon
+    pub fn send_menu_item_toggled(&self, e: &T, checked: bool) {
+        self.send(Some(e), TrayIconEvent::MenuItemToggled(e.clone(), checked));
+    }
+}